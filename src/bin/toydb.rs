@@ -2,6 +2,9 @@
  * toydb is the toyDB server. It takes configuration via a configuration file, command-line
  * parameters, and environment variables, then starts up a toyDB TCP server that communicates with
  * SQL clients (port 9605) and Raft peers (port 9705).
+ *
+ * The verify subcommand instead verifies the checksums of the data files offline, without
+ * modifying them. It must not be run against a data directory in use by a running server.
  */
 
 #![warn(clippy::all)]
@@ -9,6 +12,8 @@
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version};
 use serde_derive::Deserialize;
 use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
 use toydb::error::{Error, Result};
 use toydb::storage;
 use toydb::Server;
@@ -22,12 +27,23 @@ async fn main() -> Result<()> {
                 .long("config")
                 .help("Configuration file path")
                 .takes_value(true)
-                .default_value("/etc/toydb.yaml"),
+                .default_value("/etc/toydb.yaml")
+                .global(true),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("verify").about("Verifies the data files offline").arg(
+                clap::Arg::with_name("dir")
+                    .help("Data directory, defaults to data_dir from the configuration"),
+            ),
         )
         .get_matches();
     // 获取配置
     let cfg = Config::new(opts.value_of("config").unwrap())?;
 
+    if let Some(opts) = opts.subcommand_matches("verify") {
+        return verify(Path::new(opts.value_of("dir").unwrap_or(&cfg.data_dir)));
+    }
+
     // 设置loglevel
     let loglevel = cfg.log_level.parse::<simplelog::LevelFilter>()?;
     let mut logconfig = simplelog::ConfigBuilder::new();
@@ -36,15 +52,19 @@ async fn main() -> Result<()> {
     }
     simplelog::SimpleLogger::init(loglevel, logconfig.build())?;
 
-    let path = std::path::Path::new(&cfg.data_dir);
+    let path = Path::new(&cfg.data_dir);
     let raft_store: Box<dyn storage::log::Store> = match cfg.storage_raft.as_str() {
-        "hybrid" | "" => Box::new(storage::log::Hybrid::new(path, cfg.sync)?),
+        "hybrid" | "" => Box::new(
+            storage::log::Hybrid::new(path, cfg.sync)?
+                .with_sync_delay(Duration::from_millis(cfg.sync_delay)),
+        ),
         "memory" => Box::new(storage::log::Memory::new()),
         name => return Err(Error::Config(format!("Unknown Raft storage engine {}", name))),
     };
     let sql_store: Box<dyn storage::kv::Store> = match cfg.storage_sql.as_str() {
         "memory" | "" => Box::new(storage::kv::Memory::new()),
         "stdmemory" => Box::new(storage::kv::StdMemory::new()),
+        "bitcask" => Box::new(storage::kv::BitCask::new(path.join("sql-data"), cfg.sync)?),
         name => return Err(Error::Config(format!("Unknown SQL storage engine {}", name))),
     };
 
@@ -57,6 +77,27 @@ async fn main() -> Result<()> {
         .await
 }
 
+/// Verifies the data files in the given directory, printing the status of each file. Errors on
+/// the first corrupt file.
+fn verify(dir: &Path) -> Result<()> {
+    let mut checks = storage::log::Hybrid::verify(dir)?;
+    let path = dir.join("sql-data");
+    if path.exists() {
+        checks.push(storage::kv::BitCask::verify(&path)?);
+    }
+    if checks.is_empty() {
+        return Err(Error::Config(format!("No data files found in {}", dir.display())));
+    }
+    for check in checks {
+        print!("{}: {} entries, {} bytes", check.path.display(), check.entries, check.size);
+        if check.torn > 0 {
+            print!(", {} byte torn write at end (truncated on startup)", check.torn);
+        }
+        println!();
+    }
+    Ok(())
+}
+
 #[derive(Debug, Deserialize)]
 struct Config {
     id: String,
@@ -66,6 +107,7 @@ struct Config {
     log_level: String,
     data_dir: String,
     sync: bool,
+    sync_delay: u64,
     storage_raft: String,
     storage_sql: String,
 }
@@ -80,6 +122,7 @@ impl Config {
         c.set_default("log_level", "info")?;
         c.set_default("data_dir", "/var/lib/toydb")?;
         c.set_default("sync", true)?;
+        c.set_default("sync_delay", 0)?;
         c.set_default("storage_raft", "hybrid")?;
         c.set_default("storage_sql", "memory")?;
 
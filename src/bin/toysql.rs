@@ -6,9 +6,12 @@
 #![warn(clippy::all)]
 
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version};
+use futures::stream::TryStreamExt as _;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{error::ReadlineError, Editor, Modifiers};
 use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+use std::cmp::max;
+use toydb::client::StreamResultSet;
 use toydb::error::{Error, Result};
 use toydb::sql::engine::Mode;
 use toydb::sql::execution::ResultSet;
@@ -100,6 +103,11 @@ impl ToySQL {
         };
 
         match command {
+            "!add-node" => {
+                let args = getargs(2)?;
+                self.client.add_node(args[0], args[1]).await?;
+                println!("Added node {} at {}", args[0], args[1]);
+            }
             "!headers" => match getargs(1)?[0] {
                 "on" => {
                     self.show_headers = true;
@@ -116,13 +124,22 @@ impl ToySQL {
 Enter a SQL statement terminated by a semicolon (;) to execute it and display the result.
 The following commands are also available:
 
-    !headers <on|off>  Enable or disable column headers
-    !help              This help message
-    !status            Display server status
-    !table [table]     Display table schema, if it exists
-    !tables            List tables
+    !add-node <id> <addr>  Add a node with the given Raft address to the cluster
+    !headers <on|off>      Enable or disable column headers
+    !help                  This help message
+    !remove-node <id>      Remove a node from the cluster
+    !status                Display server status
+    !table [table]         Display table schema, if it exists
+    !tables                List tables
+    !transfer-leader <id>  Transfer cluster leadership to a node
+    !vacuum                Garbage collect obsolete data
 "#
             ),
+            "!remove-node" => {
+                let args = getargs(1)?;
+                self.client.remove_node(args[0]).await?;
+                println!("Removed node {}", args[0]);
+            }
             "!status" => {
                 let status = self.client.status().await?;
                 let mut node_logs = status
@@ -132,12 +149,15 @@ The following commands are also available:
                     .map(|(id, index)| format!("{}:{}", id, index))
                     .collect::<Vec<_>>();
                 node_logs.sort();
+                let sync = &status.raft.storage_sync;
                 println!(
                     r#"
 Server:    {server} (leader {leader} in term {term} with {nodes} nodes)
 Raft log:  {committed} committed, {applied} applied, {raft_size} MB ({raft_storage} storage)
+Raft sync: {syncs} fsyncs, {sync_batch} commits/fsync, {sync_avg} ms avg, {sync_max} ms max
 Node logs: {logs}
 SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
+Vacuum:    {reclaimed} MB reclaimed
 "#,
                     server = status.raft.server,
                     leader = status.raft.leader,
@@ -147,10 +167,19 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
                     applied = status.raft.apply_index,
                     raft_storage = status.raft.storage,
                     raft_size = format!("{:.3}", status.raft.storage_size as f64 / 1000.0 / 1000.0),
+                    syncs = sync.syncs,
+                    sync_batch = format!("{:.1}", sync.commits as f64 / max(sync.syncs, 1) as f64),
+                    sync_avg = format!(
+                        "{:.3}",
+                        sync.sync_micros as f64 / max(sync.syncs, 1) as f64 / 1000.0
+                    ),
+                    sync_max = format!("{:.3}", sync.max_sync_micros as f64 / 1000.0),
                     logs = node_logs.join(" "),
                     txns = status.mvcc.txns,
                     txns_active = status.mvcc.txns_active,
-                    sql_storage = status.mvcc.storage
+                    sql_storage = status.mvcc.storage,
+                    reclaimed =
+                        format!("{:.3}", status.mvcc.reclaimed_bytes as f64 / 1000.0 / 1000.0),
                 )
             }
             "!table" => {
@@ -163,6 +192,16 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
                     println!("{}", table)
                 }
             }
+            "!transfer-leader" => {
+                let args = getargs(1)?;
+                self.client.transfer_leader(args[0]).await?;
+                println!("Transferred leadership to {}", args[0]);
+            }
+            "!vacuum" => {
+                getargs(0)?;
+                let reclaimed = self.client.vacuum().await?;
+                println!("Reclaimed {} bytes", reclaimed);
+            }
             c => return Err(Error::Parse(format!("Unknown command {}", c))),
         }
         Ok(())
@@ -170,7 +209,30 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
 
     /// Runs a query and displays the results
     async fn execute_query(&mut self, query: &str) -> Result<()> {
-        match self.client.execute(query).await? {
+        let resultset = match self.client.execute_stream(query).await? {
+            StreamResultSet::Other(resultset) => resultset,
+            // Rows are printed as they are received, to avoid buffering large results.
+            StreamResultSet::Query { columns, mut rows } => {
+                if self.show_headers {
+                    println!(
+                        "{}",
+                        columns
+                            .iter()
+                            .map(|c| c.name.as_deref().unwrap_or("?"))
+                            .collect::<Vec<_>>()
+                            .join("|")
+                    );
+                }
+                while let Some(row) = rows.try_next().await? {
+                    println!(
+                        "{}",
+                        row.into_iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join("|")
+                    );
+                }
+                return Ok(());
+            }
+        };
+        match resultset {
             ResultSet::Begin { id, mode } => match mode {
                 Mode::ReadWrite => println!("Began transaction {}", id),
                 Mode::ReadOnly => println!("Began read-only transaction {}", id),
@@ -186,24 +248,13 @@ SQL txns:  {txns_active} active, {txns} total ({sql_storage} storage)
             ResultSet::Update { count } => println!("Updated {} rows", count),
             ResultSet::CreateTable { name } => println!("Created table {}", name),
             ResultSet::DropTable { name } => println!("Dropped table {}", name),
+            ResultSet::AlterTable { name } => println!("Altered table {}", name),
+            ResultSet::CreateIndex { name } => println!("Created index {}", name),
+            ResultSet::DropIndex { name } => println!("Dropped index {}", name),
+            ResultSet::Set { name, value } => println!("Set {} to {}", name, value),
             ResultSet::Explain(plan) => println!("{}", plan.to_string()),
-            ResultSet::Query { columns, mut rows } => {
-                if self.show_headers {
-                    println!(
-                        "{}",
-                        columns
-                            .iter()
-                            .map(|c| c.name.as_deref().unwrap_or("?"))
-                            .collect::<Vec<_>>()
-                            .join("|")
-                    );
-                }
-                while let Some(row) = rows.next().transpose()? {
-                    println!(
-                        "{}",
-                        row.into_iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join("|")
-                    );
-                }
+            ResultSet::Query { .. } => {
+                return Err(Error::Internal("Unexpected buffered query result".into()))
             }
         }
         Ok(())
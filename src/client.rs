@@ -1,19 +1,23 @@
 use crate::error::{Error, Result};
+use crate::raft::MembershipChange;
 use crate::server::{Request, Response};
 use crate::sql::engine::{Mode, Status};
 use crate::sql::execution::ResultSet;
 use crate::sql::schema::Table;
+use crate::sql::types::{Columns, Row};
 
 use futures::future::FutureExt as _;
 use futures::sink::SinkExt as _;
-use futures::stream::TryStreamExt as _;
+use futures::stream::{Stream, StreamExt as _, TryStreamExt as _};
 use rand::Rng as _;
 use std::cell::Cell;
 use std::future::Future;
 use std::ops::{Deref, Drop};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 type Connection = tokio_serde::Framed<
@@ -54,11 +58,7 @@ impl Client {
     }
 
     /// Call a server method while holding the mutex lock
-    async fn call_locked(
-        &self,
-        conn: &mut MutexGuard<'_, Connection>,
-        request: Request,
-    ) -> Result<Response> {
+    async fn call_locked(&self, conn: &mut Connection, request: Request) -> Result<Response> {
         conn.send(request).await?;
         // 是否重试
         match conn.try_next().await? {
@@ -67,27 +67,24 @@ impl Client {
         }
     }
 
-    /// Executes a query
+    /// Executes a query, buffering any result rows
     pub async fn execute(&self, query: &str) -> Result<ResultSet> {
-        let mut conn = self.conn.lock().await;
-        let mut resultset =
-            match self.call_locked(&mut conn, Request::Execute(query.into())).await? {
-                Response::Execute(rs) => rs,
-                resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
-            };
-        if let ResultSet::Query { columns, .. } = resultset {
-            // FIXME We buffer rows for now to avoid lifetime hassles
-            let mut rows = Vec::new();
-            while let Some(result) = conn.try_next().await? {
-                match result? {
-                    Response::Row(Some(row)) => rows.push(row),
-                    Response::Row(None) => break,
-                    response => {
-                        return Err(Error::Internal(format!("Unexpected response {:?}", response)))
-                    }
-                }
+        match self.execute_stream(query).await? {
+            StreamResultSet::Query { columns, rows } => {
+                let rows: Vec<Row> = rows.try_collect().await?;
+                Ok(ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) })
             }
-            resultset = ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) }
+            StreamResultSet::Other(resultset) => Ok(resultset),
+        }
+    }
+
+    /// Executes a query, streaming any result rows from the server. The client can't be used for
+    /// other requests until the row stream has been consumed or dropped.
+    pub async fn execute_stream(&self, query: &str) -> Result<StreamResultSet> {
+        let mut conn = self.conn.clone().lock_owned().await;
+        let resultset = match self.call_locked(&mut conn, Request::Execute(query.into())).await? {
+            Response::Execute(rs) => rs,
+            resp => return Err(Error::Internal(format!("Unexpected response {:?}", resp))),
         };
         match &resultset {
             ResultSet::Begin { id, mode } => self.txn.set(Some((*id, *mode))),
@@ -95,7 +92,12 @@ impl Client {
             ResultSet::Rollback { .. } => self.txn.set(None),
             _ => {}
         }
-        Ok(resultset)
+        Ok(match resultset {
+            ResultSet::Query { columns, .. } => {
+                StreamResultSet::Query { columns, rows: RowStream { conn: Some(conn) } }
+            }
+            resultset => StreamResultSet::Other(resultset),
+        })
     }
 
     /// Fetches the table schema as SQL
@@ -122,6 +124,40 @@ impl Client {
         }
     }
 
+    /// Adds a node to the cluster
+    pub async fn add_node(&self, id: &str, addr: &str) -> Result<()> {
+        let change = MembershipChange::AddNode { id: id.into(), addr: addr.into() };
+        match self.call(Request::ChangeMembership(change)).await? {
+            Response::ChangeMembership => Ok(()),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Removes a node from the cluster
+    pub async fn remove_node(&self, id: &str) -> Result<()> {
+        let change = MembershipChange::RemoveNode { id: id.into() };
+        match self.call(Request::ChangeMembership(change)).await? {
+            Response::ChangeMembership => Ok(()),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Transfers cluster leadership to a node
+    pub async fn transfer_leader(&self, id: &str) -> Result<()> {
+        match self.call(Request::TransferLeadership(id.into())).await? {
+            Response::TransferLeadership => Ok(()),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
+    /// Garbage collects obsolete data, returning the number of bytes reclaimed
+    pub async fn vacuum(&self) -> Result<u64> {
+        match self.call(Request::Vacuum).await? {
+            Response::Vacuum(reclaimed) => Ok(reclaimed),
+            resp => Err(Error::Value(format!("Unexpected response: {:?}", resp))),
+        }
+    }
+
     /// Returns the transaction status of the client
     pub fn txn(&self) -> Option<(u64, Mode)> {
         self.txn.get()
@@ -160,6 +196,59 @@ impl Client {
     }
 }
 
+/// A result set from Client::execute_stream(), where query result rows are streamed from the server.
+pub enum StreamResultSet {
+    /// A query result, with its rows streamed from the server
+    Query { columns: Columns, rows: RowStream },
+    /// Any other result
+    Other(ResultSet),
+}
+
+/// A stream of query result rows from the server. It holds the client connection until all rows
+/// have been read, and any remaining rows are drained in the background if it is dropped early.
+pub struct RowStream {
+    conn: Option<OwnedMutexGuard<Connection>>,
+}
+
+impl Stream for RowStream {
+    type Item = Result<Row>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let conn = match self.conn.as_mut() {
+            Some(conn) => conn,
+            None => return Poll::Ready(None),
+        };
+        let result = match futures::ready!(conn.poll_next_unpin(cx)) {
+            Some(Ok(Ok(Response::Row(Some(row))))) => return Poll::Ready(Some(Ok(row))),
+            Some(Ok(Ok(Response::Row(None)))) => None,
+            Some(Ok(Ok(resp))) => {
+                Some(Err(Error::Internal(format!("Unexpected response {:?}", resp))))
+            }
+            Some(Ok(Err(err))) => Some(Err(err)),
+            Some(Err(err)) => Some(Err(err.into())),
+            None => Some(Err(Error::Internal("Server disconnected".into()))),
+        };
+        // The server stops sending rows after the end marker or an error, so the connection can
+        // be released.
+        self.conn = None;
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for RowStream {
+    fn drop(&mut self) {
+        if let Some(mut conn) = self.conn.take() {
+            let drain = async move {
+                while let Ok(Some(Ok(Response::Row(Some(_))))) = conn.try_next().await {}
+            };
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => std::mem::drop(runtime.spawn(drain)),
+                Err(_) => futures::executor::block_on(drain),
+            }
+        }
+    }
+}
+
 /// A toyDB client pool
 pub struct Pool {
     clients: Vec<Mutex<Client>>,
@@ -4,7 +4,7 @@ use std::fmt::{self, Display};
 /// Result returning Error
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// toyDB errors. All except Internal are considered user-facing.
+/// toyDB errors. All except Internal and Corruption are considered user-facing.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     Abort,
@@ -14,6 +14,8 @@ pub enum Error {
     ReadOnly,
     Serialization,
     Value(String),
+    /// Corrupt data was found in a storage file, e.g. a checksum mismatch.
+    Corruption(String),
 }
 
 impl std::error::Error for Error {}
@@ -24,6 +26,7 @@ impl Display for Error {
             Error::Config(s) | Error::Internal(s) | Error::Parse(s) | Error::Value(s) => {
                 write!(f, "{}", s)
             }
+            Error::Corruption(s) => write!(f, "Data corruption: {}", s),
             Error::Abort => write!(f, "Operation aborted"),
             Error::Serialization => write!(f, "Serialization failure, retry transaction"),
             Error::ReadOnly => write!(f, "Read-only transaction"),
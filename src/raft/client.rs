@@ -1,6 +1,7 @@
-use super::{Request, Response, Status};
+use super::{MembershipChange, Request, Response, Status};
 use crate::error::{Error, Result};
 
+use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
 
 /// A client for a local Raft server.
@@ -45,6 +46,39 @@ impl Client {
         }
     }
 
+    /// Queries the Raft state machine on the local node, once it has applied at least min_index and
+    /// caught up with the leader's read index. With max_staleness, the query is served without
+    /// contacting the leader, as long as the node has heard from the leader within that duration.
+    pub async fn query_local(
+        &self,
+        command: Vec<u8>,
+        min_index: u64,
+        max_staleness: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let max_staleness =
+            max_staleness.map(|d| (d.as_millis() / super::server::TICK.as_millis()) as u64);
+        match self.request(Request::LocalQuery { command, min_index, max_staleness }).await? {
+            Response::State(response) => Ok(response),
+            resp => Err(Error::Internal(format!("Unexpected Raft query response {:?}", resp))),
+        }
+    }
+
+    /// Changes the Raft cluster membership, once the change has been committed.
+    pub async fn change_membership(&self, change: MembershipChange) -> Result<()> {
+        match self.request(Request::ChangeMembership(change)).await? {
+            Response::State(_) => Ok(()),
+            resp => Err(Error::Internal(format!("Unexpected Raft membership response {:?}", resp))),
+        }
+    }
+
+    /// Transfers leadership to the given node, returning once it has been told to take over.
+    pub async fn transfer_leadership(&self, id: String) -> Result<()> {
+        match self.request(Request::TransferLeadership(id)).await? {
+            Response::State(_) => Ok(()),
+            resp => Err(Error::Internal(format!("Unexpected Raft transfer response {:?}", resp))),
+        }
+    }
+
     /// Fetches Raft node status.
     pub async fn status(&self) -> Result<Status> {
         match self.request(Request::Status).await? {
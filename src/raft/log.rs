@@ -1,3 +1,4 @@
+use super::MembershipChange;
 use crate::error::{Error, Result};
 use crate::storage::log;
 use crate::storage::log::Range;
@@ -5,7 +6,16 @@ use crate::storage::log::Range;
 use ::log::debug;
 use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
-use std::ops::RangeBounds;
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::ops::{RangeBounds, RangeInclusive};
+use std::time::Instant;
+
+/// The current log format version, stored in the log metadata. Logs without a stored version were
+/// written by format version 0, whose entries have no membership field. Such entries are decoded
+/// in that layout, and the version is upgraded once all entries that existed when the log was
+/// opened have been compacted.
+const FORMAT_VERSION: u64 = 1;
 
 /// A replicated log entry
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -16,18 +26,59 @@ pub struct Entry {
     pub term: u64,
     /// The state machine command. None is used to commit noops during leader election.
     pub command: Option<Vec<u8>>,
+    /// A cluster membership change, applied by the Raft nodes themselves once committed.
+    pub membership: Option<MembershipChange>,
+}
+
+/// A log entry in format version 0, i.e. without a membership change.
+#[derive(Serialize, Deserialize)]
+struct EntryV0 {
+    index: u64,
+    term: u64,
+    command: Option<Vec<u8>>,
+}
+
+/// A state machine snapshot, covering all log entries up to and including its index.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The index of the last entry applied to the snapshot.
+    pub index: u64,
+    /// The term of the last entry applied to the snapshot.
+    pub term: u64,
+    /// The state machine snapshot data.
+    pub data: Vec<u8>,
+}
+
+/// The cluster membership as seen by the local node, as of the last applied membership change.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    /// The index of the last applied membership change, or 0 if none.
+    pub index: u64,
+    /// The local node's peers, as node ID and Raft address.
+    pub peers: BTreeMap<String, String>,
+    /// Whether the local node has been removed from the cluster.
+    pub removed: bool,
+    /// The local node's own Raft address, if known from the membership change that added it or
+    /// from a snapshot. Leaders send it to followers along with their peers in snapshots.
+    pub addr: Option<String>,
 }
 
 /// A metadata key
 #[derive(Clone, Debug, PartialEq)]
 pub enum Key {
     TermVote,
+    Snapshot,
+    Membership,
+    Version,
 }
 
 impl Key {
     fn encode(&self) -> Vec<u8> {
         match self {
             Self::TermVote => vec![0x00],
+            Self::Snapshot => vec![0x01],
+            Self::Membership => vec![0x02],
+            Self::Version => vec![0x03],
         }
     }
 }
@@ -47,36 +98,98 @@ pub struct Log {
     pub(super) commit_index: u64,
     /// The term of the last committed entry.
     pub(super) commit_term: u64,
+    /// The last committed entry returned by take_flushed(), i.e. handed to the state machine.
+    pub(super) flush_index: u64,
+    /// The index of the last entry covered by the stored snapshot, if any.
+    pub(super) snapshot_index: u64,
+    /// The term of the last entry covered by the stored snapshot, if any.
+    pub(super) snapshot_term: u64,
+    /// For logs in format version 0, the last index that may contain an entry in that format.
+    /// Once it has been compacted, the log is upgraded to the current format version.
+    legacy_index: Option<u64>,
 }
 
 impl Log {
     /// Creates a new log, using a log::Store for storage.
-    pub fn new(store: Box<dyn log::Store>) -> Result<Self> {
+    pub fn new(mut store: Box<dyn log::Store>) -> Result<Self> {
+        let legacy_index = match store.get_metadata(&Key::Version.encode())? {
+            Some(v) => match Self::deserialize::<u64>(&v)? {
+                FORMAT_VERSION => None,
+                version => {
+                    return Err(Error::Internal(format!(
+                        "Unsupported log format version {}",
+                        version
+                    )))
+                }
+            },
+            None => match store.len() {
+                0 => {
+                    store
+                        .set_metadata(&Key::Version.encode(), Self::serialize(&FORMAT_VERSION)?)?;
+                    None
+                }
+                len => Some(len),
+            },
+        };
+        let legacy = legacy_index.is_some();
+        let (snapshot_index, snapshot_term) = store
+            .get_metadata(&Key::Snapshot.encode())?
+            .map(|v| Self::deserialize::<Snapshot>(&v))
+            .transpose()?
+            .map(|s| (s.index, s.term))
+            .unwrap_or((0, 0));
         let (commit_index, commit_term) = match store.committed() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode_entry(&v, legacy))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal("Committed entry not found".into()))?,
         };
         let (last_index, last_term) = match store.len() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode_entry(&v, legacy))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal("Last entry not found".into()))?,
         };
-        Ok(Self { store, last_index, last_term, commit_index, commit_term })
+        Ok(Self {
+            store,
+            last_index,
+            last_term,
+            commit_index,
+            commit_term,
+            flush_index: commit_index,
+            snapshot_index,
+            snapshot_term,
+            legacy_index,
+        })
     }
 
     /// Appends a command to the log, returning the entry.
     pub fn append(&mut self, term: u64, command: Option<Vec<u8>>) -> Result<Entry> {
+        self.append_entry(term, command, None)
+    }
+
+    /// Appends a membership change to the log, returning the entry.
+    pub fn append_membership(&mut self, term: u64, change: MembershipChange) -> Result<Entry> {
+        self.append_entry(term, None, Some(change))
+    }
+
+    /// Appends an entry to the log.
+    fn append_entry(
+        &mut self,
+        term: u64,
+        command: Option<Vec<u8>>,
+        membership: Option<MembershipChange>,
+    ) -> Result<Entry> {
         // 创建一个log entry
-        let entry = Entry { index: self.last_index + 1, term, command };
+        let entry = Entry { index: self.last_index + 1, term, command, membership };
         debug!("Appending log entry {}: {:?}", entry.index, entry);
         // 序列化后entry
         self.store.append(Self::serialize(&entry)?)?;
@@ -96,28 +209,125 @@ impl Log {
         Ok(index)
     }
 
-    /// Fetches an entry at an index
+    /// Flushes commits buffered by the store for group commit to durable storage.
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.flush()
+    }
+
+    /// Returns the time by which buffered commits must be flushed, if any.
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        self.store.flush_deadline()
+    }
+
+    /// Returns the range of committed entries that have been flushed to durable storage since the
+    /// last call, which can be applied to the state machine. Entries covered by the snapshot are
+    /// skipped, since the state machine is restored from it instead.
+    pub fn take_flushed(&mut self) -> Option<RangeInclusive<u64>> {
+        let index = self.store.flushed();
+        let start = max(self.flush_index, self.snapshot_index) + 1;
+        self.flush_index = max(self.flush_index, index);
+        if index >= start {
+            Some(start..=index)
+        } else {
+            None
+        }
+    }
+
+    /// Compacts the log by storing a snapshot and removing the entries it covers. The snapshot
+    /// must be for a committed entry, and snapshots older than the current one are ignored.
+    pub fn compact(&mut self, snapshot: Snapshot) -> Result<u64> {
+        if snapshot.index > self.commit_index {
+            return Err(Error::Internal(format!(
+                "Cannot compact uncommitted entry {}",
+                snapshot.index
+            )));
+        }
+        if snapshot.index <= self.snapshot_index {
+            return Ok(self.snapshot_index);
+        }
+        debug!("Compacting log entries up to {}", snapshot.index);
+        // The snapshot is saved before the entries are removed, such that a crash in between
+        // only leaves behind entries that are already covered by the snapshot.
+        self.store.set_metadata(&Key::Snapshot.encode(), Self::serialize(&snapshot)?)?;
+        self.store.compact(snapshot.index)?;
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        self.upgrade()?;
+        Ok(self.snapshot_index)
+    }
+
+    /// Installs a snapshot received from the leader. If the log contains the snapshot's last
+    /// entry, the entries following it are retained, otherwise the entire log is replaced by the
+    /// snapshot. Returns the new commit index.
+    pub fn install_snapshot(&mut self, snapshot: Snapshot) -> Result<u64> {
+        if snapshot.index <= self.snapshot_index {
+            return Ok(self.commit_index);
+        }
+        if snapshot.index <= self.last_index && self.has(snapshot.index, snapshot.term)? {
+            if snapshot.index > self.commit_index {
+                self.commit(snapshot.index)?;
+            }
+            self.compact(snapshot)?;
+            return Ok(self.commit_index);
+        }
+        debug!("Replacing log with snapshot at {}", snapshot.index);
+        self.store.set_metadata(&Key::Snapshot.encode(), Self::serialize(&snapshot)?)?;
+        self.store.reset(snapshot.index)?;
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        self.commit_index = snapshot.index;
+        self.commit_term = snapshot.term;
+        self.last_index = snapshot.index;
+        self.last_term = snapshot.term;
+        self.upgrade()?;
+        Ok(self.commit_index)
+    }
+
+    /// Loads the stored snapshot, if any.
+    pub fn load_snapshot(&self) -> Result<Option<Snapshot>> {
+        self.store.get_metadata(&Key::Snapshot.encode())?.map(|v| Self::deserialize(&v)).transpose()
+    }
+
+    /// Fetches an entry at an index. Compacted entries are not returned.
     pub fn get(&self, index: u64) -> Result<Option<Entry>> {
-        self.store.get(index)?.map(|v| Self::deserialize(&v)).transpose()
+        let legacy = self.legacy_index.is_some();
+        self.store.get(index)?.map(|v| Self::decode_entry(&v, legacy)).transpose()
     }
 
-    /// Checks if the log contains an entry
+    /// Checks if the log contains an entry. Compacted entries are committed, and thus known to
+    /// match any entry at the same index in a leader's log.
     pub fn has(&self, index: u64, term: u64) -> Result<bool> {
         match self.get(index)? {
             Some(entry) => Ok(entry.term == term),
             None if index == 0 && term == 0 => Ok(true),
+            None if index == self.snapshot_index => Ok(term == self.snapshot_term),
+            None if index < self.snapshot_index => Ok(true),
             None => Ok(false),
         }
     }
 
+    /// Returns the term of the entry at an index, including the last compacted entry.
+    pub fn term(&self, index: u64) -> Result<Option<u64>> {
+        match index {
+            0 => Ok(Some(0)),
+            i if i == self.snapshot_index => Ok(Some(self.snapshot_term)),
+            i => Ok(self.get(i)?.map(|e| e.term)),
+        }
+    }
+
     /// Iterates over log entries
     pub fn scan(&self, range: impl RangeBounds<u64>) -> Scan {
-        Box::new(self.store.scan(Range::from(range)).map(|r| r.and_then(|v| Self::deserialize(&v))))
+        let legacy = self.legacy_index.is_some();
+        Box::new(
+            self.store
+                .scan(Range::from(range))
+                .map(move |r| r.and_then(|v| Self::decode_entry(&v, legacy))),
+        )
     }
 
     /// Splices a set of entries onto an offset. The entries must be contiguous, and the first entry
     /// must be at most last_index+1. If an entry does not exist, append it. If an existing entry
-    /// has a term mismatch, replace it and all following entries.
+    /// has a term mismatch, replace it and all following entries. Compacted entries are skipped.
     pub fn splice(&mut self, entries: Vec<Entry>) -> Result<u64> {
         for i in 0..entries.len() {
             if i == 0 && entries.get(i).unwrap().index > self.last_index + 1 {
@@ -127,14 +337,15 @@ impl Log {
                 return Err(Error::Internal("Spliced entries must be contiguous".into()));
             }
         }
-        for entry in entries {
+        let snapshot_index = self.snapshot_index;
+        for entry in entries.into_iter().filter(|e| e.index > snapshot_index) {
             if let Some(ref current) = self.get(entry.index)? {
                 if current.term == entry.term {
                     continue;
                 }
                 self.truncate(entry.index - 1)?;
             }
-            self.append(entry.term, entry.command)?;
+            self.append_entry(entry.term, entry.command, entry.membership)?;
         }
         Ok(self.last_index)
     }
@@ -145,10 +356,11 @@ impl Log {
         debug!("Truncating log from entry {}", index);
         let (index, term) = match self.store.truncate(index)? {
             0 => (0, 0),
+            i if i == self.snapshot_index => (self.snapshot_index, self.snapshot_term),
             i => self
                 .store
                 .get(i)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode_entry(&v, self.legacy_index.is_some()))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?,
@@ -176,6 +388,51 @@ impl Log {
         self.store.set_metadata(&Key::TermVote.encode(), Self::serialize(&(term, voted_for))?)
     }
 
+    /// Loads the cluster membership, if it has been saved.
+    pub fn load_membership(&self) -> Result<Option<Membership>> {
+        self.store
+            .get_metadata(&Key::Membership.encode())?
+            .map(|v| Self::deserialize(&v))
+            .transpose()
+    }
+
+    /// Saves the cluster membership.
+    pub fn save_membership(&mut self, membership: &Membership) -> Result<()> {
+        self.store.set_metadata(&Key::Membership.encode(), Self::serialize(membership)?)
+    }
+
+    /// Upgrades a format version 0 log to the current format version, once all entries that may
+    /// be in the old format have been compacted.
+    fn upgrade(&mut self) -> Result<()> {
+        if let Some(legacy_index) = self.legacy_index {
+            if self.snapshot_index >= legacy_index {
+                debug!("Upgrading log to format version {}", FORMAT_VERSION);
+                self.store
+                    .set_metadata(&Key::Version.encode(), Self::serialize(&FORMAT_VERSION)?)?;
+                self.legacy_index = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes a log entry. In a format version 0 log, entries that aren't in the current format
+    /// are decoded in the version 0 format. The formats can't be confused, since the current
+    /// format is a strict extension of version 0, and a version 0 entry is too short for it.
+    fn decode_entry(bytes: &[u8], legacy: bool) -> Result<Entry> {
+        match Self::deserialize(bytes) {
+            Err(_) if legacy => {
+                let entry: EntryV0 = Self::deserialize(bytes)?;
+                Ok(Entry {
+                    index: entry.index,
+                    term: entry.term,
+                    command: entry.command,
+                    membership: None,
+                })
+            }
+            result => result,
+        }
+    }
+
     /// Serializes a value for the log store.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -191,6 +448,7 @@ impl Log {
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use std::time::Duration;
 
     fn setup() -> Result<(Log, Box<log::Test>)> {
         let store = Box::new(log::Test::new());
@@ -215,10 +473,13 @@ mod tests {
         assert_eq!(Ok(None), l.get(1));
 
         assert_eq!(
-            Entry { index: 1, term: 3, command: Some(vec![0x01]) },
+            Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None },
             l.append(3, Some(vec![0x01]))?
         );
-        assert_eq!(Some(Entry { index: 1, term: 3, command: Some(vec![0x01]) }), l.get(1)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
         assert_eq!(None, l.get(2)?);
 
         assert_eq!(1, l.last_index);
@@ -231,8 +492,73 @@ mod tests {
     #[test]
     fn append_none() -> Result<()> {
         let (mut l, _) = setup()?;
-        assert_eq!(Entry { index: 1, term: 3, command: None }, l.append(3, None)?);
-        assert_eq!(Some(Entry { index: 1, term: 3, command: None }), l.get(1)?);
+        assert_eq!(
+            Entry { index: 1, term: 3, command: None, membership: None },
+            l.append(3, None)?
+        );
+        assert_eq!(Some(Entry { index: 1, term: 3, command: None, membership: None }), l.get(1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn append_membership() -> Result<()> {
+        let (mut l, store) = setup()?;
+        let change = MembershipChange::AddNode { id: "b".into(), addr: "b:9705".into() };
+        let entry = Entry { index: 1, term: 3, command: None, membership: Some(change.clone()) };
+        assert_eq!(entry, l.append_membership(3, change)?);
+        assert_eq!(Some(entry.clone()), l.get(1)?);
+
+        // Membership changes must survive splicing.
+        let (mut l2, _) = setup()?;
+        l2.splice(vec![entry.clone()])?;
+        assert_eq!(Some(entry.clone()), l2.get(1)?);
+
+        let l = Log::new(store)?;
+        assert_eq!(Some(entry), l.get(1)?);
+        Ok(())
+    }
+
+    #[test]
+    // Logs written before format version 1 have entries without a membership field. These are
+    // decoded in the old format, and the log is upgraded once they have been compacted.
+    fn format_version() -> Result<()> {
+        use crate::storage::log::Store as _;
+        let (_, store) = setup()?;
+        let version = || store.get_metadata(&Key::Version.encode());
+        assert_eq!(Some(Log::serialize(&FORMAT_VERSION)?), version()?);
+
+        let mut store = Box::new(log::Test::new());
+        for (index, term, command) in [(1, 1, Some(vec![0x01])), (2, 1, None)] {
+            store.append(Log::serialize(&EntryV0 { index, term, command })?)?;
+        }
+        store.commit(1)?;
+        let mut l = Log::new(store.clone())?;
+        assert_eq!((1, 1), (l.commit_index, l.commit_term));
+        assert_eq!((2, 1), (l.last_index, l.last_term));
+        let change = MembershipChange::RemoveNode { id: "b".into() };
+        l.append_membership(2, change.clone())?;
+        assert_eq!(
+            vec![
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 1, command: None, membership: None },
+                Entry { index: 3, term: 2, command: None, membership: Some(change) },
+            ],
+            l.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        let version = || store.get_metadata(&Key::Version.encode());
+        assert_eq!(None, version()?);
+
+        // Compacting the old entries upgrades the log.
+        l.commit(3)?;
+        l.compact(Snapshot { index: 1, term: 1, data: vec![] })?;
+        assert_eq!(None, version()?);
+        l.compact(Snapshot { index: 2, term: 1, data: vec![] })?;
+        assert_eq!(Some(Log::serialize(&FORMAT_VERSION)?), version()?);
+        assert_eq!(None, Log::new(store.clone())?.legacy_index);
+
+        // Logs from future format versions are rejected.
+        store.set_metadata(&Key::Version.encode(), Log::serialize(&(FORMAT_VERSION + 1))?)?;
+        assert!(matches!(Log::new(store), Err(Error::Internal(_))));
         Ok(())
     }
 
@@ -244,9 +570,15 @@ mod tests {
         l.append(2, Some(vec![0x03]))?;
 
         let l = Log::new(store)?;
-        assert_eq!(Some(Entry { index: 1, term: 1, command: Some(vec![0x01]) }), l.get(1)?);
-        assert_eq!(Some(Entry { index: 2, term: 2, command: None }), l.get(2)?);
-        assert_eq!(Some(Entry { index: 3, term: 2, command: Some(vec![0x03]) }), l.get(3)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
+        assert_eq!(Some(Entry { index: 2, term: 2, command: None, membership: None }), l.get(2)?);
+        assert_eq!(
+            Some(Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None }),
+            l.get(3)?
+        );
         Ok(())
     }
 
@@ -290,13 +622,157 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Committed entries are only handed out for applying once the store has flushed them.
+    fn take_flushed() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let store =
+            log::Hybrid::new(dir.as_ref(), true)?.with_sync_delay(Duration::from_secs(3600));
+        let mut l = Log::new(Box::new(store))?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.append(2, Some(vec![0x03]))?;
+
+        l.commit(1)?;
+        l.commit(2)?;
+        assert_eq!(2, l.commit_index);
+        assert!(l.flush_deadline().is_some());
+        assert_eq!(None, l.take_flushed());
+
+        l.flush()?;
+        assert_eq!(None, l.flush_deadline());
+        assert_eq!(Some(1..=2), l.take_flushed());
+        assert_eq!(None, l.take_flushed());
+
+        l.commit(3)?;
+        l.flush()?;
+        assert_eq!(Some(3..=3), l.take_flushed());
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> Result<()> {
+        let (mut l, store) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(3)?;
+
+        let snapshot = Snapshot { index: 2, term: 2, data: vec![0xaf] };
+        assert_eq!(2, l.compact(snapshot.clone())?);
+        assert_eq!(None, l.get(1)?);
+        assert_eq!(None, l.get(2)?);
+        assert_eq!(
+            Some(Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None }),
+            l.get(3)?
+        );
+        assert_eq!(Some(2), l.term(2)?);
+        assert_eq!(None, l.term(1)?);
+        assert_eq!(true, l.has(1, 1)?);
+        assert_eq!(true, l.has(2, 2)?);
+        assert_eq!(false, l.has(2, 1)?);
+        assert_eq!(Some(snapshot.clone()), l.load_snapshot()?);
+
+        // Older snapshots are ignored.
+        assert_eq!(2, l.compact(Snapshot { index: 1, term: 1, data: vec![] })?);
+        assert_eq!(Some(snapshot), l.load_snapshot()?);
+
+        // The snapshot and compacted log must be persisted.
+        let l = Log::new(store)?;
+        assert_eq!((2, 2), (l.snapshot_index, l.snapshot_term));
+        assert_eq!((3, 2), (l.commit_index, l.commit_term));
+        assert_eq!((3, 2), (l.last_index, l.last_term));
+        Ok(())
+    }
+
+    #[test]
+    fn compact_all() -> Result<()> {
+        let (mut l, store) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.commit(2)?;
+        assert_eq!(2, l.compact(Snapshot { index: 2, term: 2, data: vec![] })?);
+        assert!(l.scan(..).collect::<Result<Vec<_>>>()?.is_empty());
+
+        // Appending continues from the snapshot, and a reloaded log picks up the snapshot position.
+        let mut l = Log::new(store)?;
+        assert_eq!((2, 2), (l.commit_index, l.commit_term));
+        assert_eq!((2, 2), (l.last_index, l.last_term));
+        assert_eq!(3, l.append(3, None)?.index);
+        Ok(())
+    }
+
+    #[test]
+    fn compact_uncommitted() -> Result<()> {
+        let (mut l, _) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, None)?;
+        l.commit(1)?;
+        assert_eq!(
+            Err(Error::Internal("Cannot compact uncommitted entry 2".into())),
+            l.compact(Snapshot { index: 2, term: 2, data: vec![] })
+        );
+        assert_eq!(None, l.load_snapshot()?);
+        Ok(())
+    }
+
+    #[test]
+    fn install_snapshot_replace() -> Result<()> {
+        let (mut l, store) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(1, Some(vec![0x02]))?;
+        l.commit(1)?;
+
+        let snapshot = Snapshot { index: 4, term: 3, data: vec![0xaf] };
+        assert_eq!(4, l.install_snapshot(snapshot.clone())?);
+        assert_eq!((4, 3), (l.commit_index, l.commit_term));
+        assert_eq!((4, 3), (l.last_index, l.last_term));
+        assert_eq!(None, l.get(1)?);
+        assert_eq!(true, l.has(4, 3)?);
+        assert_eq!(Some(snapshot), l.load_snapshot()?);
+        assert_eq!(5, l.append(3, None)?.index);
+
+        let l = Log::new(store)?;
+        assert_eq!((4, 3), (l.snapshot_index, l.snapshot_term));
+        assert_eq!((4, 3), (l.commit_index, l.commit_term));
+        assert_eq!((5, 3), (l.last_index, l.last_term));
+        Ok(())
+    }
+
+    #[test]
+    fn install_snapshot_retain() -> Result<()> {
+        let (mut l, _) = setup()?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(1)?;
+
+        // Entries following the snapshot are retained if the log contains the snapshot's entry.
+        assert_eq!(2, l.install_snapshot(Snapshot { index: 2, term: 2, data: vec![] })?);
+        assert_eq!((2, 2), (l.commit_index, l.commit_term));
+        assert_eq!((3, 2), (l.last_index, l.last_term));
+        assert_eq!(None, l.get(2)?);
+        assert_eq!(
+            Some(Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None }),
+            l.get(3)?
+        );
+
+        // Older snapshots are ignored.
+        assert_eq!(2, l.install_snapshot(Snapshot { index: 1, term: 1, data: vec![] })?);
+        assert_eq!((3, 2), (l.last_index, l.last_term));
+        Ok(())
+    }
+
     #[test]
     fn get() -> Result<()> {
         let (mut l, _) = setup()?;
         assert_eq!(None, l.get(1)?);
 
         l.append(3, Some(vec![0x01]))?;
-        assert_eq!(Some(Entry { index: 1, term: 3, command: Some(vec![0x01]) }), l.get(1)?);
+        assert_eq!(
+            Some(Entry { index: 1, term: 3, command: Some(vec![0x01]), membership: None }),
+            l.get(1)?
+        );
         assert_eq!(None, l.get(2)?);
         Ok(())
     }
@@ -325,16 +801,16 @@ mod tests {
 
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 1, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 1, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(0..).collect::<Result<Vec<_>>>()?
         );
         assert_eq!(
             vec![
-                Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 1, command: Some(vec![0x03]) },
+                Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 1, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(2..).collect::<Result<Vec<_>>>()?
         );
@@ -363,6 +839,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn load_save_membership() -> Result<()> {
+        let (mut l, store) = setup()?;
+        assert_eq!(None, l.load_membership()?);
+
+        let membership = Membership {
+            index: 3,
+            peers: vec![("b".to_string(), "b:9705".to_string())].into_iter().collect(),
+            removed: false,
+            addr: Some("a:9705".into()),
+        };
+        l.save_membership(&membership)?;
+        let l = Log::new(store)?;
+        assert_eq!(Some(membership), l.load_membership()?);
+        Ok(())
+    }
+
     #[test]
     fn splice() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -373,16 +866,16 @@ mod tests {
         assert_eq!(
             4,
             l.splice(vec![
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -391,6 +884,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Spliced entries are durable before they're acknowledged, including truncations, such that
+    // a follower restart doesn't lose entries it has accepted.
+    fn splice_persistence() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let mut l = Log::new(Box::new(log::Hybrid::new(dir.as_ref(), true)?))?;
+        l.append(1, Some(vec![0x01]))?;
+        l.append(2, Some(vec![0x02]))?;
+        l.append(2, Some(vec![0x03]))?;
+        l.commit(1)?;
+        l.splice(vec![
+            Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 3, command: Some(vec![0x13]), membership: None },
+        ])?;
+        drop(l);
+
+        let l = Log::new(Box::new(log::Hybrid::new(dir.as_ref(), true)?))?;
+        assert_eq!(1, l.commit_index);
+        assert_eq!(3, l.last_index);
+        assert_eq!(3, l.last_term);
+        assert_eq!(
+            vec![
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x13]), membership: None },
+            ],
+            l.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
     #[test]
     fn splice_all() -> Result<()> {
         let (mut l, _) = setup()?;
@@ -401,14 +925,14 @@ mod tests {
         assert_eq!(
             2,
             l.splice(vec![
-                Entry { index: 1, term: 4, command: Some(vec![0x0a]) },
-                Entry { index: 2, term: 4, command: Some(vec![0x0b]) },
+                Entry { index: 1, term: 4, command: Some(vec![0x0a]), membership: None },
+                Entry { index: 2, term: 4, command: Some(vec![0x0b]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 4, command: Some(vec![0x0a]) },
-                Entry { index: 2, term: 4, command: Some(vec![0x0b]) },
+                Entry { index: 1, term: 4, command: Some(vec![0x0a]), membership: None },
+                Entry { index: 2, term: 4, command: Some(vec![0x0b]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -426,16 +950,16 @@ mod tests {
         assert_eq!(
             4,
             l.splice(vec![
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
-                Entry { index: 4, term: 4, command: Some(vec![0x04]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
+                Entry { index: 4, term: 4, command: Some(vec![0x04]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -455,15 +979,15 @@ mod tests {
         assert_eq!(
             3,
             l.splice(vec![
-                Entry { index: 2, term: 3, command: Some(vec![0x0b]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x0c]) }
+                Entry { index: 2, term: 3, command: Some(vec![0x0b]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x0c]), membership: None }
             ])?
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 3, command: Some(vec![0x0b]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x0c]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 3, command: Some(vec![0x0b]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x0c]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -482,15 +1006,15 @@ mod tests {
         assert_eq!(
             Err(Error::Internal("Spliced entries must be contiguous".into())),
             l.splice(vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ])
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -507,15 +1031,15 @@ mod tests {
         assert_eq!(
             Err(Error::Internal("Spliced entries cannot begin past last index".into())),
             l.splice(vec![
-                Entry { index: 5, term: 3, command: Some(vec![0x05]) },
-                Entry { index: 6, term: 3, command: Some(vec![0x06]) },
+                Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                Entry { index: 6, term: 3, command: Some(vec![0x06]), membership: None },
             ])
         );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -529,12 +1053,20 @@ mod tests {
         l.append(2, Some(vec![0x02]))?;
         l.append(3, Some(vec![0x03]))?;
 
-        assert_eq!(3, l.splice(vec![Entry { index: 2, term: 2, command: Some(vec![0x02]) },])?);
+        assert_eq!(
+            3,
+            l.splice(vec![Entry {
+                index: 2,
+                term: 2,
+                command: Some(vec![0x02]),
+                membership: None
+            },])?
+        );
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -551,8 +1083,8 @@ mod tests {
         assert_eq!(2, l.truncate(2)?);
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
@@ -569,9 +1101,9 @@ mod tests {
         assert_eq!(3, l.truncate(4)?);
         assert_eq!(
             vec![
-                Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                Entry { index: 2, term: 2, command: Some(vec![0x02]) },
-                Entry { index: 3, term: 3, command: Some(vec![0x03]) },
+                Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                Entry { index: 2, term: 2, command: Some(vec![0x02]), membership: None },
+                Entry { index: 3, term: 3, command: Some(vec![0x03]), membership: None },
             ],
             l.scan(..).collect::<Result<Vec<_>>>()?
         );
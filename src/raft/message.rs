@@ -2,6 +2,7 @@ use super::{Entry, Status};
 use crate::error::Result;
 
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// A message address.
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
@@ -40,6 +41,8 @@ pub enum Event {
         commit_index: u64,
         /// The term of the leader's last committed log entry.
         commit_term: u64,
+        /// The leader's logical clock when the heartbeat was sent, echoed back in ConfirmLeader.
+        sent_tick: u64,
     },
     /// Followers confirm loyalty to leader after heartbeats.
     ConfirmLeader {
@@ -49,6 +52,9 @@ pub enum Event {
         /// If false, the follower does not have the entry at commit_index
         /// and would like the leader to replicate it.
         has_committed: bool,
+        /// The sent_tick of the original leader heartbeat. Leader leases are measured from when
+        /// the heartbeat was sent, since the follower can't have heard from the leader before.
+        sent_tick: u64,
     },
     /// Candidates solicit votes from all peers.
     SolicitVote {
@@ -56,9 +62,24 @@ pub enum Event {
         last_index: u64,
         // The term of the candidate's last stored log entry
         last_term: u64,
+        // If true, the leader asked the candidate to campaign via TimeoutNow, so followers may
+        // vote for it even though they recently heard from the leader.
+        transfer: bool,
     },
     /// Followers may grant votes to candidates.
     GrantVote,
+    /// Candidates solicit pre-votes from all peers before starting an election, to check that they
+    /// could win it. Sent with the term of the prospective election, but the candidate's own term
+    /// is only incremented once a quorum has granted pre-votes.
+    SolicitPreVote {
+        // The index of the candidate's last stored log entry
+        last_index: u64,
+        // The term of the candidate's last stored log entry
+        last_term: u64,
+    },
+    /// Followers may grant pre-votes to candidates that have an up-to-date log, unless they have
+    /// recently heard from a leader. Sent with the term of the prospective election.
+    GrantPreVote,
     /// Leaders replicate a set of log entries to followers.
     ReplicateEntries {
         /// The index of the log entry immediately preceding the submitted commands.
@@ -73,8 +94,49 @@ pub enum Event {
         /// The index of the last log entry.
         last_index: u64,
     },
-    /// Followers may also reject a set of log entries from a leader.
-    RejectEntries,
+    /// Followers may also reject a set of log entries from a leader, if they don't have the base
+    /// entry. The rejection carries a hint about the conflict, so that the leader can skip past an
+    /// entire term of conflicting entries rather than probing them one at a time.
+    RejectEntries {
+        /// The term of the follower's entry at the base index, or 0 if its log is shorter.
+        conflict_term: u64,
+        /// The first index of the conflicting term in the follower's log, or the follower's last
+        /// index + 1 if its log is shorter.
+        conflict_index: u64,
+    },
+    /// Leaders send a chunk of a state machine snapshot to followers that have fallen behind
+    /// the start of the leader's log.
+    InstallSnapshot {
+        /// The index of the last entry applied to the snapshot.
+        index: u64,
+        /// The term of the last entry applied to the snapshot.
+        term: u64,
+        /// The byte offset of the chunk in the snapshot.
+        offset: u64,
+        /// The snapshot chunk.
+        data: Vec<u8>,
+        /// If true, this is the last chunk of the snapshot.
+        done: bool,
+        /// The leader's peers as of its commit index, as node ID and Raft address.
+        peers: BTreeMap<String, String>,
+    },
+    /// Followers acknowledge snapshot chunks, requesting the chunk at the given offset.
+    AcceptSnapshot {
+        /// The index of the snapshot.
+        index: u64,
+        /// The number of snapshot bytes received, or the full snapshot size once installed.
+        offset: u64,
+    },
+    /// Leaders transferring leadership tell the caught-up target to start an election immediately,
+    /// instead of waiting for its election timeout.
+    TimeoutNow,
+    /// The local state machine took a snapshot, which can be used to compact the log.
+    Snapshot {
+        /// The index of the last entry applied to the snapshot.
+        index: u64,
+        /// The state machine snapshot.
+        data: Vec<u8>,
+    },
     /// A client request.
     ClientRequest {
         /// The request ID.
@@ -91,6 +153,14 @@ pub enum Event {
     },
 }
 
+impl Event {
+    /// Returns true for pre-vote events. These are sent with the term of a prospective election,
+    /// and must not change the recipient's term or leader.
+    pub fn is_prevote(&self) -> bool {
+        matches!(self, Event::SolicitPreVote { .. } | Event::GrantPreVote)
+    }
+}
+
 /// A client request.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Request {
@@ -98,6 +168,30 @@ pub enum Request {
     // 写请求
     Mutate(Vec<u8>),
     Status,
+    ChangeMembership(MembershipChange),
+    /// Transfers leadership to the node with the given ID.
+    TransferLeadership(String),
+    /// Queries the state machine on the local node, once its applied index reaches both min_index
+    /// and the leader's read index. If max_staleness is given, and the leader has been heard from
+    /// within that many ticks, the local commit index is used instead of the read index.
+    LocalQuery {
+        command: Vec<u8>,
+        min_index: u64,
+        max_staleness: Option<u64>,
+    },
+    /// Fetches the leader's read index, i.e. its commit index once leadership has been confirmed
+    /// by a quorum.
+    ReadIndex,
+}
+
+/// A cluster membership change. Changes are made one node at a time, and take effect once
+/// committed, such that the old and new majorities always overlap.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    /// Adds a node with the given ID and Raft address.
+    AddNode { id: String, addr: String },
+    /// Removes the node with the given ID.
+    RemoveNode { id: String },
 }
 
 /// A client response.
@@ -105,4 +199,5 @@ pub enum Request {
 pub enum Response {
     State(Vec<u8>),
     Status(Status),
+    ReadIndex(u64),
 }
@@ -6,9 +6,9 @@ mod server;
 mod state;
 
 // 导出的结构
-pub use self::log::{Entry, Log, Scan};
+pub use self::log::{Entry, Log, Membership, Scan, Snapshot};
 pub use client::Client;
-pub use message::{Address, Event, Message, Request, Response};
+pub use message::{Address, Event, MembershipChange, Message, Request, Response};
 pub use node::{Node, Status};
 pub use server::Server;
 pub use state::{Driver, Instruction, State};
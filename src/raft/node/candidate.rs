@@ -14,21 +14,47 @@ pub struct Candidate {
     election_timeout: u64,
     /// Votes received (including ourself).
     votes: u64,
+    /// If true, the candidate is soliciting pre-votes for the next term, and only starts the
+    /// election once a quorum has granted them. This avoids disrupting a healthy leader with
+    /// term increments from nodes that can't win an election, e.g. after a network partition.
+    prevote: bool,
 }
 
 impl Candidate {
-    /// Creates a new candidate role.
-    pub fn new() -> Self {
+    /// Creates a new candidate role, soliciting either pre-votes or votes.
+    pub fn new(prevote: bool) -> Self {
         Self {
             votes: 1, // We always start with a vote for ourselves.
             election_ticks: 0,
             election_timeout: rand::thread_rng()
                 .gen_range(ELECTION_TIMEOUT_MIN..=ELECTION_TIMEOUT_MAX),
+            prevote,
         }
     }
 }
 
 impl RoleNode<Candidate> {
+    /// Starts campaigning for the next term, by soliciting pre-votes without incrementing the term,
+    /// or by incrementing the term and soliciting votes. Transfer is set for elections requested by
+    /// the leader via TimeoutNow.
+    pub fn campaign(&mut self, prevote: bool, transfer: bool) -> Result<()> {
+        self.role = Candidate::new(prevote);
+        let (last_index, last_term) = (self.log.last_index, self.log.last_term);
+        if prevote {
+            info!("Starting pre-vote for term {}", self.term + 1);
+            self.send_with_term(
+                self.term + 1,
+                Address::Peers,
+                Event::SolicitPreVote { last_index, last_term },
+            )
+        } else {
+            info!("Starting election for term {}", self.term + 1);
+            self.term += 1;
+            self.log.save_term(self.term, None)?;
+            self.send(Address::Peers, Event::SolicitVote { last_index, last_term, transfer })
+        }
+    }
+
     /// Transition to follower role.
     fn become_follower(mut self, term: u64, leader: &str) -> Result<RoleNode<Follower>> {
         info!("Discovered leader {} for term {}, following", leader, term);
@@ -45,18 +71,12 @@ impl RoleNode<Candidate> {
     /// Transition to leader role.
     fn become_leader(self) -> Result<RoleNode<Leader>> {
         info!("Won election for term {}, becoming leader", self.term);
-        let peers = self.peers.clone();
+        let peers = self.peers.keys().cloned().collect();
         // 获取自己的最后一个index
         let last_index = self.log.last_index;
         let mut node = self.become_role(Leader::new(peers, last_index))?;
         // 发送自己的log心跳日志
-        node.send(
-            Address::Peers,
-            Event::Heartbeat {
-                commit_index: node.log.commit_index,
-                commit_term: node.log.commit_term,
-            },
-        )?;
+        node.heartbeat()?;
         // 每个node的任期一上任就开始一个空的command
         node.append(None)?;
         node.abort_proxied()?;
@@ -71,7 +91,7 @@ impl RoleNode<Candidate> {
             return Ok(self.into());
         }
         // 投票的任期，大于自己的任期
-        if msg.term > self.term {
+        if msg.term > self.term && !msg.event.is_prevote() {
             if let Address::Peer(from) = &msg.from {
                 // 变成follower
                 return self.become_follower(msg.term, from)?.step(msg);
@@ -90,7 +110,7 @@ impl RoleNode<Candidate> {
 
             // 获取投票信息
             // 投票消息
-            Event::GrantVote => {
+            Event::GrantVote if !self.role.prevote => {
                 debug!("Received term {} vote from {:?}", self.term, msg.from);
                 // 投票 + 1
                 self.role.votes += 1;
@@ -110,6 +130,23 @@ impl RoleNode<Candidate> {
                 }
             }
 
+            // Once a quorum has granted pre-votes, we start the actual election.
+            Event::GrantPreVote => {
+                if self.role.prevote && msg.term == self.term + 1 {
+                    debug!("Received term {} pre-vote from {:?}", msg.term, msg.from);
+                    self.role.votes += 1;
+                    if self.role.votes >= self.quorum() {
+                        self.campaign(false, false)?;
+                    }
+                }
+            }
+
+            // Other candidates may also have lost contact with the leader, so we grant them
+            // pre-votes if their log is up-to-date, to avoid pre-vote livelocks.
+            Event::SolicitPreVote { last_index, last_term } => {
+                self.respond_prevote(msg.from, msg.term, last_index, last_term)?
+            }
+
             // 客户端请求
             Event::ClientRequest { .. } => self.queued_reqs.push((msg.from, msg.event)),
 
@@ -122,34 +159,31 @@ impl RoleNode<Candidate> {
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
-            // Ignore other candidates when we're also campaigning
-            Event::SolicitVote { .. } => {}
+            Event::Snapshot { index, data } => self.compact(index, data)?,
+
+            // Ignore other candidates when we're also campaigning, and stray votes from a previous
+            // election while we're soliciting pre-votes.
+            Event::SolicitVote { .. } | Event::GrantVote => {}
 
             // 收到合适的消息
             Event::ConfirmLeader { .. }
             | Event::ReplicateEntries { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. } => warn!("Received unexpected message {:?}", msg),
+            | Event::RejectEntries { .. }
+            | Event::InstallSnapshot { .. }
+            | Event::AcceptSnapshot { .. }
+            | Event::TimeoutNow => warn!("Received unexpected message {:?}", msg),
         }
         Ok(self.into())
     }
 
     /// Processes a logical clock tick.
     pub fn tick(mut self) -> Result<Node> {
-        // If the election times out, start a new one for the next term.
+        // If the pre-vote or election times out, start over with a new pre-vote for the next term.
         self.role.election_ticks += 1;
         if self.role.election_ticks >= self.role.election_timeout {
-            info!("Election timed out, starting new election for term {}", self.term + 1);
-            self.term += 1;
-            self.log.save_term(self.term, None)?;
-            self.role = Candidate::new();
-            self.send(
-                Address::Peers,
-                Event::SolicitVote {
-                    last_index: self.log.last_index,
-                    last_term: self.log.last_term,
-                },
-            )?;
+            info!("Election timed out");
+            self.campaign(true, false)?;
         }
         Ok(self.into())
     }
@@ -158,7 +192,7 @@ impl RoleNode<Candidate> {
 #[cfg(test)]
 mod tests {
     use super::super::super::{Entry, Instruction, Log, Request};
-    use super::super::tests::{assert_messages, assert_node};
+    use super::super::tests::{assert_messages, assert_node, peer_map};
     use super::*;
     use crate::storage::log;
     use futures::FutureExt;
@@ -178,18 +212,19 @@ mod tests {
         log.append(1, Some(vec![0x02]))?;
         log.append(2, Some(vec![0x03]))?;
         log.commit(2)?;
+        log.take_flushed(); // the committed entries have been applied
         log.save_term(3, None)?;
 
         let mut node = RoleNode {
             id: "a".into(),
-            peers: vec!["b".into(), "c".into(), "d".into(), "e".into()],
+            peers: peer_map(&["b", "c", "d", "e"]),
             term: 3,
             log,
             node_tx,
             state_tx,
             queued_reqs: Vec::new(),
             proxied_reqs: HashMap::new(),
-            role: Candidate::new(),
+            role: Candidate::new(false),
         };
         node = match node.step(Message {
             from: Address::Client,
@@ -213,7 +248,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3);
         assert_messages(
@@ -232,7 +267,11 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
                 },
             ],
         );
@@ -249,7 +288,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(4);
         assert_messages(
@@ -268,7 +307,11 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 4,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
                 },
             ],
         );
@@ -284,7 +327,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 1, commit_term: 1, sent_tick: 0 },
         })?;
         assert_node(&node).is_candidate().term(3);
         assert_messages(&mut node_rx, vec![]);
@@ -295,7 +338,7 @@ mod tests {
     #[test]
     fn step_grantvote() -> Result<()> {
         let (candidate, mut node_rx, mut state_rx) = setup()?;
-        let peers = candidate.peers.clone();
+        let peers = candidate.peers.keys().cloned().collect::<Vec<_>>();
         let mut node = Node::Candidate(candidate);
 
         // The first vote is not sufficient for a quorum (3 votes including self)
@@ -324,7 +367,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
             })),
         );
 
@@ -338,7 +381,7 @@ mod tests {
                     event: Event::ReplicateEntries {
                         base_index: 3,
                         base_term: 2,
-                        entries: vec![Entry { index: 4, term: 3, command: None }],
+                        entries: vec![Entry { index: 4, term: 3, command: None, membership: None }],
                     },
                 }))
             )
@@ -351,7 +394,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
             }],
         );
         assert_messages(
@@ -382,15 +425,103 @@ mod tests {
             assert_node(&node).is_candidate().term(3);
             node = node.tick()?;
         }
-        assert_node(&node).is_candidate().term(4);
+        assert_node(&node).is_candidate().term(3);
+
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 4,
+                event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // A quorum of pre-votes for the next term starts an election, other votes are ignored.
+    fn step_grantprevote() -> Result<()> {
+        let (mut candidate, mut node_rx, mut state_rx) = setup()?;
+        candidate.campaign(true, false)?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 4,
+                event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+            }],
+        );
+        let mut node = Node::Candidate(candidate);
 
+        for (from, term, event) in vec![
+            ("b", 3, Event::GrantPreVote),
+            ("c", 3, Event::GrantVote),
+            ("d", 4, Event::GrantPreVote),
+        ] {
+            node = node.step(Message {
+                from: Address::Peer(from.into()),
+                to: Address::Peer("a".into()),
+                term,
+                event,
+            })?;
+            assert_node(&node).is_candidate().term(3);
+            assert_messages(&mut node_rx, vec![]);
+        }
+
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::GrantPreVote,
+        })?;
+        assert_node(&node).is_candidate().term(4);
         assert_messages(
             &mut node_rx,
             vec![Message {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 4,
-                event: Event::SolicitVote { last_index: 3, last_term: 2 },
+                event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Pre-votes are granted for future terms if the log is up-to-date, without changing our term.
+    fn step_solicitprevote() -> Result<()> {
+        let (candidate, mut node_rx, mut state_rx) = setup()?;
+        let mut node = Node::Candidate(candidate);
+
+        for (term, last_index, last_term) in vec![(3, 3, 2), (4, 2, 2), (4, 5, 1)] {
+            node = node.step(Message {
+                from: Address::Peer("b".into()),
+                to: Address::Peer("a".into()),
+                term,
+                event: Event::SolicitPreVote { last_index, last_term },
+            })?;
+            assert_node(&node).is_candidate().term(3);
+            assert_messages(&mut node_rx, vec![]);
+        }
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+        })?;
+        assert_node(&node).is_candidate().term(3);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 4,
+                event: Event::GrantPreVote,
             }],
         );
         assert_messages(&mut state_rx, vec![]);
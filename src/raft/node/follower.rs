@@ -1,9 +1,10 @@
-use super::super::{Address, Event, Instruction, Message, Response};
+use super::super::{Address, Event, Instruction, Membership, Message, Request, Response, Snapshot};
 use super::{Candidate, Node, RoleNode, ELECTION_TIMEOUT_MAX, ELECTION_TIMEOUT_MIN};
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 use ::log::{debug, info, warn};
 use rand::Rng as _;
+use std::collections::{BTreeMap, HashMap};
 
 // A follower replicates state from a leader.
 #[derive(Debug)]
@@ -16,6 +17,11 @@ pub struct Follower {
     leader_seen_timeout: u64,
     /// The node we voted for in the current term, if any.
     voted_for: Option<String>,
+    /// A snapshot that is being received from the leader, if any.
+    snapshot: Option<Snapshot>,
+    /// Local queries waiting for a read index from the leader, by request ID, with the client
+    /// address, query command, and minimum index.
+    reads: HashMap<Vec<u8>, (Address, Vec<u8>, u64)>,
 }
 
 impl Follower {
@@ -29,26 +35,24 @@ impl Follower {
             // 获取leader超时时间
             leader_seen_timeout: rand::thread_rng()
                 .gen_range(ELECTION_TIMEOUT_MIN..=ELECTION_TIMEOUT_MAX),
+            snapshot: None,
+            reads: HashMap::new(),
         }
     }
 }
 
 impl RoleNode<Follower> {
-    /// Transforms the node into a candidate.
-    fn become_candidate(self) -> Result<RoleNode<Candidate>> {
-        info!("Starting election for term {}", self.term + 1);
-        let mut node = self.become_role(Candidate::new())?;
-        node.term += 1;
-        node.log.save_term(node.term, None)?;
-        node.send(
-            Address::Peers,
-            Event::SolicitVote { last_index: node.log.last_index, last_term: node.log.last_term },
-        )?;
+    /// Transforms the node into a candidate, campaigning with either a pre-vote or an election.
+    fn become_candidate(mut self, prevote: bool, transfer: bool) -> Result<RoleNode<Candidate>> {
+        self.abort_reads()?;
+        let mut node = self.become_role(Candidate::new(prevote))?;
+        node.campaign(prevote, transfer)?;
         Ok(node)
     }
 
     /// Transforms the node into a follower for a new leader.
     fn become_follower(mut self, leader: &str, term: u64) -> Result<RoleNode<Follower>> {
+        self.abort_reads()?;
         let mut voted_for = None;
         if term > self.term {
             info!("Discovered new term {}, following leader {}", term, leader);
@@ -64,19 +68,179 @@ impl RoleNode<Follower> {
         Ok(self)
     }
 
+    /// Aborts any local queries waiting for a read index.
+    fn abort_reads(&mut self) -> Result<()> {
+        for (id, (address, _, _)) in std::mem::take(&mut self.role.reads) {
+            self.send(address, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+        }
+        Ok(())
+    }
+
+    /// Serves a local query. If the leader has been heard from within max_staleness ticks, the
+    /// query runs once the local commit index has been applied, otherwise the leader is asked for
+    /// a read index to wait for.
+    fn local_query(
+        &mut self,
+        id: Vec<u8>,
+        address: Address,
+        command: Vec<u8>,
+        min_index: u64,
+        max_staleness: Option<u64>,
+    ) -> Result<()> {
+        match (&self.role.leader, max_staleness) {
+            (Some(_), Some(ticks)) if self.role.leader_seen_ticks <= ticks => {
+                let index = min_index.max(self.log.commit_index);
+                self.query_at(id, address, command, index)
+            }
+            (Some(leader), _) => {
+                let leader = Address::Peer(leader.clone());
+                self.role.reads.insert(id.clone(), (address, command, min_index));
+                self.send(leader, Event::ClientRequest { id, request: Request::ReadIndex })
+            }
+            (None, _) => {
+                let request = Request::LocalQuery { command, min_index, max_staleness };
+                self.queued_reqs.push((address, Event::ClientRequest { id, request }));
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles the leader's response to a read index request for a local query, running the query
+    /// once the read index has been applied.
+    fn read_index_response(&mut self, id: Vec<u8>, response: Result<Response>) -> Result<()> {
+        if let Some((address, command, min_index)) = self.role.reads.remove(&id) {
+            match response {
+                Ok(Response::ReadIndex(index)) => {
+                    self.query_at(id, address, command, index.max(min_index))?
+                }
+                Ok(response) => {
+                    let error =
+                        Error::Internal(format!("Unexpected read index response {:?}", response));
+                    self.send(address, Event::ClientResponse { id, response: Err(error) })?
+                }
+                Err(error) => {
+                    self.send(address, Event::ClientResponse { id, response: Err(error) })?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Submits a query to the state machine, to run once the given index has been applied.
+    fn query_at(&self, id: Vec<u8>, address: Address, command: Vec<u8>, index: u64) -> Result<()> {
+        Ok(self.state_tx.send(Instruction::Query {
+            id,
+            address,
+            command,
+            term: 0,
+            index,
+            quorum: 0,
+        })?)
+    }
+
     /// Checks if an address is the current leader
     fn is_leader(&self, from: &Address) -> bool {
         matches!((&self.role.leader, from), (Some(leader), Address::Peer(from)) if leader == from)
     }
 
+    /// Builds a conflict hint for rejected entries at the given base index: the term of our entry
+    /// at the base index and the first index of that term, or 0 and our last index + 1 if we don't
+    /// have the base entry. Committed entries always match the leader, so we don't look past them.
+    fn conflict_hint(&self, base_index: u64) -> Result<(u64, u64)> {
+        let term = match self.log.term(base_index)? {
+            Some(term) if base_index <= self.log.last_index => term,
+            _ => return Ok((0, self.log.last_index + 1)),
+        };
+        let mut index = base_index;
+        while index > self.log.commit_index + 1 && self.log.term(index - 1)? == Some(term) {
+            index -= 1;
+        }
+        Ok((term, index))
+    }
+
+    /// Receives a snapshot chunk from the leader, and installs the snapshot once all chunks have
+    /// been received. Chunks are expected in order: duplicate chunks are acknowledged without
+    /// being applied, and out-of-order chunks make the leader resend from the received offset.
+    /// The snapshot does not cover membership changes, so the peers are taken from the leader. Our
+    /// existing address for the leader takes precedence over the one it sent, like configured
+    /// addresses do, and it's an error if neither is known.
+    #[allow(clippy::too_many_arguments)]
+    fn receive_snapshot(
+        &mut self,
+        leader: Address,
+        index: u64,
+        term: u64,
+        offset: u64,
+        data: Vec<u8>,
+        done: bool,
+        mut peers: BTreeMap<String, String>,
+    ) -> Result<()> {
+        if offset == 0 {
+            self.role.snapshot = Some(Snapshot { index, term, data: Vec::new() });
+        }
+        let received = match &mut self.role.snapshot {
+            Some(snapshot) if snapshot.index == index && snapshot.data.len() as u64 == offset => {
+                snapshot.data.extend(data);
+                snapshot.data.len() as u64
+            }
+            Some(snapshot) if snapshot.index == index => {
+                debug!("Ignoring snapshot chunk at offset {} for index {}", offset, index);
+                let received = snapshot.data.len() as u64;
+                return self.send(leader, Event::AcceptSnapshot { index, offset: received });
+            }
+            _ => return self.send(leader, Event::AcceptSnapshot { index, offset: 0 }),
+        };
+        if done {
+            let snapshot = self.role.snapshot.take().unwrap();
+            if snapshot.index > self.log.commit_index {
+                info!("Installing snapshot at index {} from leader", snapshot.index);
+                let mut addr = self.log.load_membership()?.and_then(|m| m.addr);
+                if let Some(own) = peers.remove(&self.id) {
+                    addr = Some(own);
+                }
+                if let Address::Peer(leader) = &leader {
+                    let leader_addr =
+                        self.peers.get(leader).or_else(|| peers.get(leader)).cloned().ok_or_else(
+                            || Error::Internal(format!("No address known for leader {}", leader)),
+                        )?;
+                    peers.insert(leader.clone(), leader_addr);
+                }
+                self.log.install_snapshot(snapshot.clone())?;
+                self.log.save_membership(&Membership {
+                    index: snapshot.index,
+                    peers: peers.clone(),
+                    removed: false,
+                    addr,
+                })?;
+                self.peers = peers;
+                self.state_tx.send(Instruction::Restore { snapshot })?;
+            }
+        }
+        self.send(leader, Event::AcceptSnapshot { index, offset: received })
+    }
+
     /// Processes a message.
     pub fn step(mut self, msg: Message) -> Result<Node> {
         if let Err(err) = self.validate(&msg) {
             warn!("Ignoring invalid message: {}", err);
             return Ok(self.into());
         }
+        // Votes are ignored while the leader is alive, without stepping up the term, since the
+        // leader may hold a lease during which no other leader must be elected. Leadership
+        // transfers are exempt, since the leader gives up its lease before sending TimeoutNow.
+        // Votes from the node we follow are not ignored, since we follow a candidate when its
+        // vote request steps up our term.
+        if let Event::SolicitVote { transfer: false, .. } = msg.event {
+            if self.role.leader.is_some()
+                && !self.is_leader(&msg.from)
+                && self.role.leader_seen_ticks < ELECTION_TIMEOUT_MIN
+            {
+                debug!("Ignoring vote request from {:?}, leader is alive", msg.from);
+                return Ok(self.into());
+            }
+        }
         if let Address::Peer(from) = &msg.from {
-            if msg.term > self.term || self.role.leader.is_none() {
+            if (msg.term > self.term || self.role.leader.is_none()) && !msg.event.is_prevote() {
                 return self.become_follower(from, msg.term)?.step(msg);
             }
         }
@@ -88,39 +252,32 @@ impl RoleNode<Follower> {
 
         match msg.event {
             // 获取commit index 和任期
-            Event::Heartbeat { commit_index, commit_term } => {
+            Event::Heartbeat { commit_index, commit_term, sent_tick } => {
                 // 自己是leader
                 if self.is_leader(&msg.from) {
                     // 是否已经提交了
                     let has_committed = self.log.has(commit_index, commit_term)?;
                     if has_committed && commit_index > self.log.commit_index {
-                        // 老的commit_index
-                        let old_commit_index = self.log.commit_index;
                         // 提交
                         self.log.commit(commit_index)?;
-                        // 迭代器
-                        let mut scan = self.log.scan((old_commit_index + 1)..=commit_index);
-                        // 转换成entry
-                        while let Some(entry) = scan.next().transpose()? {
-                            // 应用日志
-                            self.state_tx.send(Instruction::Apply { entry })?;
-                        }
+                        // 应用日志
+                        self.apply_committed()?;
                     }
                     // 发送even
-                    self.send(msg.from, Event::ConfirmLeader { commit_index, has_committed })?;
+                    self.send(
+                        msg.from,
+                        Event::ConfirmLeader { commit_index, has_committed, sent_tick },
+                    )?;
                 }
             }
 
-            Event::SolicitVote { last_index, last_term } => {
+            Event::SolicitVote { last_index, last_term, .. } => {
                 if let Some(voted_for) = &self.role.voted_for {
                     if msg.from != Address::Peer(voted_for.clone()) {
                         return Ok(self.into());
                     }
                 }
-                if last_term < self.log.last_term {
-                    return Ok(self.into());
-                }
-                if last_term == self.log.last_term && last_index < self.log.last_index {
+                if !self.is_log_up_to_date(last_index, last_term) {
                     return Ok(self.into());
                 }
                 if let Address::Peer(from) = msg.from {
@@ -131,11 +288,23 @@ impl RoleNode<Follower> {
                 }
             }
 
+            // Pre-votes are only granted if we haven't heard from the leader recently, such that
+            // nodes rejoining after a partition can't disrupt a healthy leader.
+            Event::SolicitPreVote { last_index, last_term } => {
+                if self.role.leader.is_some() && self.role.leader_seen_ticks < ELECTION_TIMEOUT_MIN
+                {
+                    debug!("Ignoring pre-vote from {:?}, leader is alive", msg.from);
+                } else {
+                    self.respond_prevote(msg.from, msg.term, last_index, last_term)?;
+                }
+            }
+
             Event::ReplicateEntries { base_index, base_term, entries } => {
                 if self.is_leader(&msg.from) {
                     if base_index > 0 && !self.log.has(base_index, base_term)? {
                         debug!("Rejecting log entries at base {}", base_index);
-                        self.send(msg.from, Event::RejectEntries)?
+                        let (conflict_term, conflict_index) = self.conflict_hint(base_index)?;
+                        self.send(msg.from, Event::RejectEntries { conflict_term, conflict_index })?
                     } else {
                         let last_index = self.log.splice(entries)?;
                         self.send(msg.from, Event::AcceptEntries { last_index })?
@@ -143,6 +312,25 @@ impl RoleNode<Follower> {
                 }
             }
 
+            Event::InstallSnapshot { index, term, offset, data, done, peers } => {
+                if self.is_leader(&msg.from) {
+                    self.receive_snapshot(msg.from, index, term, offset, data, done, peers)?;
+                }
+            }
+
+            // The leader is transferring leadership to us, so we campaign right away.
+            Event::TimeoutNow => {
+                if self.is_leader(&msg.from) {
+                    info!("Leader is transferring leadership to us");
+                    return Ok(self.become_candidate(false, true)?.into());
+                }
+            }
+
+            Event::ClientRequest {
+                id,
+                request: Request::LocalQuery { command, min_index, max_staleness },
+            } => self.local_query(id, msg.from, command, min_index, max_staleness)?,
+
             Event::ClientRequest { ref id, .. } => {
                 if let Some(leader) = self.role.leader.as_deref() {
                     self.proxied_reqs.insert(id.clone(), msg.from);
@@ -152,6 +340,10 @@ impl RoleNode<Follower> {
                 }
             }
 
+            Event::ClientResponse { id, response } if self.role.reads.contains_key(&id) => {
+                self.read_index_response(id, response)?
+            }
+
             Event::ClientResponse { id, mut response } => {
                 if let Ok(Response::Status(ref mut status)) = response {
                     status.server = self.id.clone();
@@ -160,21 +352,28 @@ impl RoleNode<Follower> {
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
+            Event::Snapshot { index, data } => self.compact(index, data)?,
+
             // Ignore votes which are usually strays from the previous election that we lost.
-            Event::GrantVote => {}
+            Event::GrantVote | Event::GrantPreVote => {}
 
             Event::ConfirmLeader { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. } => warn!("Received unexpected message {:?}", msg),
+            | Event::RejectEntries { .. }
+            | Event::AcceptSnapshot { .. } => warn!("Received unexpected message {:?}", msg),
         };
         Ok(self.into())
     }
 
     /// Processes a logical clock tick.
     pub fn tick(mut self) -> Result<Node> {
+        // A follower without peers has been removed from the cluster, and must not campaign.
+        if self.peers.is_empty() {
+            return Ok(self.into());
+        }
         self.role.leader_seen_ticks += 1;
         if self.role.leader_seen_ticks >= self.role.leader_seen_timeout {
-            Ok(self.become_candidate()?.into())
+            Ok(self.become_candidate(true, false)?.into())
         } else {
             Ok(self.into())
         }
@@ -183,8 +382,8 @@ impl RoleNode<Follower> {
 
 #[cfg(test)]
 pub mod tests {
-    use super::super::super::{Entry, Log, Request};
-    use super::super::tests::{assert_messages, assert_node};
+    use super::super::super::{Entry, Log, MembershipChange, Request};
+    use super::super::tests::{assert_messages, assert_node, peer_map};
     use super::*;
     use crate::error::Error;
     use crate::storage::log;
@@ -212,11 +411,12 @@ pub mod tests {
         log.append(1, Some(vec![0x02]))?;
         log.append(2, Some(vec![0x03]))?;
         log.commit(2)?;
+        log.take_flushed(); // the committed entries have been applied
         log.save_term(3, None)?;
 
         let node = RoleNode {
             id: "a".into(),
-            peers: vec!["b".into(), "c".into(), "d".into(), "e".into()],
+            peers: peer_map(&["b", "c", "d", "e"]),
             term: 3,
             log,
             node_tx,
@@ -236,7 +436,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(3);
         assert_messages(
@@ -245,18 +445,143 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader { commit_index: 3, has_committed: true, sent_tick: 0 },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
     }
 
+    #[test]
+    // Heartbeats that commit membership changes apply them, and a removed follower stops
+    // campaigning.
+    fn step_heartbeat_membership() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let timeout = follower.role.leader_seen_timeout;
+        let mut node: Node = follower.into();
+
+        let add = Entry {
+            index: 4,
+            term: 3,
+            command: None,
+            membership: Some(MembershipChange::AddNode { id: "f".into(), addr: "f:9705".into() }),
+        };
+        let remove = Entry {
+            index: 5,
+            term: 3,
+            command: None,
+            membership: Some(MembershipChange::RemoveNode { id: "c".into() }),
+        };
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries {
+                base_index: 3,
+                base_term: 2,
+                entries: vec![add.clone(), remove.clone()],
+            },
+        })?;
+        assert_node(&node)
+            .is_follower()
+            .last(5)
+            .committed(2)
+            .peers(peer_map(&["b", "c", "d", "e"]));
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, sent_tick: 0 },
+        })?;
+        assert_node(&node).is_follower().committed(5).peers(peer_map(&["b", "d", "e", "f"]));
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Apply {
+                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                },
+                Instruction::Apply { entry: add },
+                Instruction::Apply { entry: remove },
+            ],
+        );
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index: 5 },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 5,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
+                },
+            ],
+        );
+
+        // Once the follower itself is removed, it has no peers and never campaigns.
+        let removed = Entry {
+            index: 6,
+            term: 3,
+            command: None,
+            membership: Some(MembershipChange::RemoveNode { id: "a".into() }),
+        };
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries { base_index: 5, base_term: 3, entries: vec![removed] },
+        })?;
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::Heartbeat { commit_index: 6, commit_term: 3, sent_tick: 0 },
+        })?;
+        assert_node(&node).is_follower().committed(6).peers(BTreeMap::new());
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index: 6 },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 6,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
+                },
+            ],
+        );
+
+        for _ in 0..(2 * timeout) {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_follower().term(3);
+        assert_messages(&mut node_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // Heartbeat from current leader with conflicting commit_term
     fn step_heartbeat_conflict_commit_term() -> Result<()> {
@@ -265,7 +590,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 3, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -274,7 +599,7 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: false },
+                event: Event::ConfirmLeader { commit_index: 3, has_committed: false, sent_tick: 0 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -289,7 +614,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -298,7 +623,7 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 5, has_committed: false },
+                event: Event::ConfirmLeader { commit_index: 5, has_committed: false, sent_tick: 0 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -313,7 +638,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -330,7 +655,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("c")).voted_for(None).committed(3);
         assert_messages(
@@ -339,13 +664,13 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("c".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader { commit_index: 3, has_committed: true, sent_tick: 0 },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -359,7 +684,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+            event: Event::Heartbeat { commit_index: 1, commit_term: 1, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(
@@ -368,7 +693,7 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::ConfirmLeader { commit_index: 1, has_committed: true },
+                event: Event::ConfirmLeader { commit_index: 1, has_committed: true, sent_tick: 0 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -383,7 +708,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("c")).voted_for(None);
         assert_messages(
@@ -392,13 +717,13 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("c".into()),
                 term: 4,
-                event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                event: Event::ConfirmLeader { commit_index: 3, has_committed: true, sent_tick: 0 },
             }],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -412,7 +737,7 @@ pub mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -420,17 +745,97 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // TimeoutNow from the leader makes the follower campaign immediately
+    fn step_timeoutnow() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+
+        // TimeoutNow from a non-leader is ignored.
+        let mut node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::TimeoutNow,
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&mut node_rx, vec![]);
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::TimeoutNow,
+        })?;
+        assert_node(&node).is_candidate().term(4);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 4,
+                event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: true },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // SolicitPreVote is only granted if the leader hasn't been heard from recently, and doesn't
+    // change our term or leader.
+    fn step_solicitprevote() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let solicit = Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
+        };
+
+        let mut node = follower.step(solicit.clone())?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(&mut node_rx, vec![]);
+
+        if let Node::Follower(ref mut follower) = node {
+            follower.role.leader_seen_ticks = ELECTION_TIMEOUT_MIN;
+        }
+        node = node.step(solicit)?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 4,
+                event: Event::GrantPreVote,
+            }],
+        );
+
+        // Pre-votes for candidates with outdated logs are ignored.
+        node = node.step(Message {
+            from: Address::Peer("d".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 2, last_term: 2 },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // SolicitVote is granted for the first solicitor, otherwise ignored.
     fn step_solicitvote() -> Result<()> {
-        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.role.leader_seen_ticks = ELECTION_TIMEOUT_MIN;
 
         // The first vote request in this term yields a vote response.
         let mut node = follower.step(Message {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(
@@ -449,7 +854,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(
@@ -468,7 +873,7 @@ pub mod tests {
             from: Address::Peer("d".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 2 },
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(Some("c"));
         assert_messages(&mut node_rx, vec![]);
@@ -476,6 +881,44 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // SolicitVote is ignored while the leader is alive, without stepping up the term, unless the
+    // leader is transferring leadership to the candidate.
+    fn step_solicitvote_leader_alive() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = follower.into();
+        for _ in 0..ELECTION_TIMEOUT_MIN - 1 {
+            node = node.step(Message {
+                from: Address::Peer("c".into()),
+                to: Address::Peer("a".into()),
+                term: 4,
+                event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: false },
+            })?;
+            assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
+            assert_messages(&mut node_rx, vec![]);
+            node = node.tick()?;
+        }
+
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::SolicitVote { last_index: 3, last_term: 2, transfer: true },
+        })?;
+        assert_node(&node).is_follower().term(4).leader(Some("c")).voted_for(Some("c"));
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("c".into()),
+                term: 4,
+                event: Event::GrantVote,
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // GrantVote messages are ignored
     fn step_grantvote_noop() -> Result<()> {
@@ -500,7 +943,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 2, last_term: 2 },
+            event: Event::SolicitVote { last_index: 2, last_term: 2, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
         assert_messages(&mut node_rx, vec![]);
@@ -516,7 +959,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::SolicitVote { last_index: 3, last_term: 1 },
+            event: Event::SolicitVote { last_index: 3, last_term: 1, transfer: false },
         })?;
         assert_node(&node).is_follower().term(3).leader(Some("b")).voted_for(None);
         assert_messages(&mut node_rx, vec![]);
@@ -536,15 +979,15 @@ pub mod tests {
                 base_index: 0,
                 base_term: 0,
                 entries: vec![
-                    Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-                    Entry { index: 2, term: 1, command: Some(vec![0x02]) },
+                    Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+                    Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -571,17 +1014,17 @@ pub mod tests {
                 base_index: 3,
                 base_term: 2,
                 entries: vec![
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
-                    Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
-            Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+            Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -608,17 +1051,17 @@ pub mod tests {
                 base_index: 1,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-                    Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -645,16 +1088,16 @@ pub mod tests {
                 base_index: 2,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 3, term: 3, command: Some(vec![0x04]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x05]) },
+                    Entry { index: 3, term: 3, command: Some(vec![0x04]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x05]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 3, command: Some(vec![0x04]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x05]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 3, command: Some(vec![0x04]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x05]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -681,16 +1124,16 @@ pub mod tests {
                 base_index: 2,
                 base_term: 1,
                 entries: vec![
-                    Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-                    Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                    Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 ],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
-            Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+            Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -716,13 +1159,18 @@ pub mod tests {
             event: Event::ReplicateEntries {
                 base_index: 5,
                 base_term: 2,
-                entries: vec![Entry { index: 6, term: 3, command: Some(vec![0x04]) }],
+                entries: vec![Entry {
+                    index: 6,
+                    term: 3,
+                    command: Some(vec![0x04]),
+                    membership: None,
+                }],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -730,7 +1178,7 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::RejectEntries,
+                event: Event::RejectEntries { conflict_term: 0, conflict_index: 4 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -748,13 +1196,18 @@ pub mod tests {
             event: Event::ReplicateEntries {
                 base_index: 1,
                 base_term: 2,
-                entries: vec![Entry { index: 2, term: 3, command: Some(vec![0x04]) }],
+                entries: vec![Entry {
+                    index: 2,
+                    term: 3,
+                    command: Some(vec![0x04]),
+                    membership: None,
+                }],
             },
         })?;
         assert_node(&node).is_follower().term(3).entries(vec![
-            Entry { index: 1, term: 1, command: Some(vec![0x01]) },
-            Entry { index: 2, term: 1, command: Some(vec![0x02]) },
-            Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+            Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+            Entry { index: 2, term: 1, command: Some(vec![0x02]), membership: None },
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
         ]);
         assert_messages(
             &mut node_rx,
@@ -762,7 +1215,33 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 3,
-                event: Event::RejectEntries,
+                event: Event::RejectEntries { conflict_term: 1, conflict_index: 1 },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // ReplicateEntries rejections hint at the first index of the conflicting term
+    fn step_replicateentries_reject_conflict_hint() -> Result<()> {
+        let (mut follower, mut node_rx, mut state_rx) = setup()?;
+        follower.log.append(2, Some(vec![0x04]))?;
+        follower.log.append(2, Some(vec![0x05]))?;
+        let node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries { base_index: 5, base_term: 3, entries: vec![] },
+        })?;
+        assert_node(&node).is_follower().term(3).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::RejectEntries { conflict_term: 2, conflict_index: 3 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
@@ -827,6 +1306,163 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    // A local query within the staleness bound is served at the local commit index, otherwise a
+    // read index is fetched from the leader.
+    fn step_clientrequest_localquery_stale() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = Node::Follower(follower);
+        let local_query = |id: u8, min_index: u64| Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![id],
+                request: Request::LocalQuery {
+                    command: vec![0xaf],
+                    min_index,
+                    max_staleness: Some(1),
+                },
+            },
+        };
+
+        node = node.step(local_query(0x01, 0))?;
+        node = node.step(local_query(0x02, 3))?;
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Query {
+                    id: vec![0x01],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 0,
+                    index: 2,
+                    quorum: 0,
+                },
+                Instruction::Query {
+                    id: vec![0x02],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 0,
+                    index: 3,
+                    quorum: 0,
+                },
+            ],
+        );
+
+        node = node.tick()?.tick()?;
+        node = node.step(local_query(0x03, 0))?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).proxied(vec![]);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::ClientRequest { id: vec![0x03], request: Request::ReadIndex },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // A local query fetches a read index from the leader, and then runs once it's applied.
+    fn step_clientrequest_localquery_readindex() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node = Node::Follower(follower);
+        for (id, min_index) in [(0x01, 0), (0x02, 5), (0x03, 0)] {
+            node = node.step(Message {
+                from: Address::Client,
+                to: Address::Local,
+                term: 0,
+                event: Event::ClientRequest {
+                    id: vec![id],
+                    request: Request::LocalQuery {
+                        command: vec![0xaf],
+                        min_index,
+                        max_staleness: None,
+                    },
+                },
+            })?;
+            assert_messages(
+                &mut node_rx,
+                vec![Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ClientRequest { id: vec![id], request: Request::ReadIndex },
+                }],
+            );
+        }
+        assert_messages(&mut state_rx, vec![]);
+
+        // The read index and the minimum index are both respected.
+        for id in [0x01, 0x02] {
+            node = node.step(Message {
+                from: Address::Peer("b".into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ClientResponse { id: vec![id], response: Ok(Response::ReadIndex(4)) },
+            })?;
+        }
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Query {
+                    id: vec![0x01],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 0,
+                    index: 4,
+                    quorum: 0,
+                },
+                Instruction::Query {
+                    id: vec![0x02],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 0,
+                    index: 5,
+                    quorum: 0,
+                },
+            ],
+        );
+
+        // Pending reads are aborted when a new leader is discovered.
+        node = node.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 4,
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
+        })?;
+        assert_node(&node).is_follower().term(4).leader(Some("c"));
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse { id: vec![0x03], response: Err(Error::Abort) },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("c".into()),
+                    term: 4,
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
+                },
+            ],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // ClientRequest is queued when there is no leader, and forwarded when a leader appears.
     fn step_clientrequest_queued() -> Result<()> {
@@ -852,7 +1488,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node)
             .is_follower()
@@ -876,14 +1512,18 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("c".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 3,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
                 },
             ],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
@@ -926,7 +1566,7 @@ pub mod tests {
             from: Address::Peer("c".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("c")).proxied(vec![]).queued(vec![]);
         assert_messages(
@@ -942,19 +1582,245 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("c".into()),
                     term: 4,
-                    event: Event::ConfirmLeader { commit_index: 3, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 3,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
                 },
             ],
         );
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
             }],
         );
         Ok(())
     }
 
+    #[test]
+    // Snapshot from the local state machine compacts the log
+    fn step_snapshot() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let node = follower.step(Message {
+            from: Address::Local,
+            to: Address::Local,
+            term: 0,
+            event: Event::Snapshot { index: 2, data: vec![0xaf] },
+        })?;
+        assert_node(&node).is_follower().term(3).committed(2).last(3).snapshot(2).entries(vec![
+            Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+        ]);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Snapshot chunks from the leader are buffered, and installed once all have been received
+    fn step_installsnapshot() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = follower.into();
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 0,
+                data: vec![0x01, 0x02],
+                done: false,
+                peers: peer_map(&["a", "c", "d", "e"]),
+            },
+        })?;
+        assert_node(&node).is_follower().term(3).committed(2).last(3).snapshot(0);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptSnapshot { index: 5, offset: 2 },
+            }],
+        );
+
+        // Out-of-order chunks are acknowledged with the received offset.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 4,
+                data: vec![0x05],
+                done: true,
+                peers: peer_map(&["a", "c", "d", "e"]),
+            },
+        })?;
+        assert_node(&node).is_follower().committed(2).last(3).snapshot(0);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptSnapshot { index: 5, offset: 2 },
+            }],
+        );
+
+        // The final chunk installs the snapshot.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 2,
+                data: vec![0x03],
+                done: true,
+                peers: peer_map(&["a", "c", "d", "f"]),
+            },
+        })?;
+        assert_node(&node)
+            .is_follower()
+            .term(3)
+            .committed(5)
+            .last(5)
+            .snapshot(5)
+            .entries(vec![])
+            .peers(peer_map(&["b", "c", "d", "f"]));
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptSnapshot { index: 5, offset: 3 },
+            }],
+        );
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Restore {
+                snapshot: Snapshot { index: 5, term: 3, data: vec![0x01, 0x02, 0x03] },
+            }],
+        );
+
+        // Replication continues after the snapshot.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ReplicateEntries {
+                base_index: 5,
+                base_term: 3,
+                entries: vec![Entry {
+                    index: 6,
+                    term: 3,
+                    command: Some(vec![0x06]),
+                    membership: None,
+                }],
+            },
+        })?;
+        assert_node(&node).is_follower().committed(5).last(6);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            }],
+        );
+        Ok(())
+    }
+
+    #[test]
+    // A leader that isn't in our peers is added with the address it sent along with the snapshot,
+    // and we learn our own address from it. Without any known leader address, it's an error.
+    fn step_installsnapshot_leader_addr() -> Result<()> {
+        let (mut follower, mut node_rx, _state_rx) = setup()?;
+        follower.peers.remove("b");
+        let mut peers = peer_map(&["a", "b", "c"]);
+        peers.insert("b".into(), "b:1".into());
+        let node = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 0,
+                data: vec![0x01],
+                done: true,
+                peers: peers.clone(),
+            },
+        })?;
+        peers.remove("a");
+        assert_node(&node).is_follower().snapshot(5).peers(peers.clone());
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::AcceptSnapshot { index: 5, offset: 1 },
+            }],
+        );
+        match node {
+            Node::Follower(follower) => assert_eq!(
+                Some(Membership { index: 5, peers, removed: false, addr: Some("a:9705".into()) }),
+                follower.log.load_membership()?
+            ),
+            _ => panic!("Expected follower"),
+        }
+
+        let (mut follower, _node_rx, _state_rx) = setup()?;
+        follower.peers.remove("b");
+        let result = follower.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 0,
+                data: vec![0x01],
+                done: true,
+                peers: peer_map(&["c", "d"]),
+            },
+        });
+        assert!(matches!(result, Err(Error::Internal(_))));
+        Ok(())
+    }
+
+    #[test]
+    // Snapshot chunks from a non-leader are ignored
+    fn step_installsnapshot_fake_leader() -> Result<()> {
+        let (follower, mut node_rx, mut state_rx) = setup()?;
+        let node = follower.step(Message {
+            from: Address::Peer("c".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::InstallSnapshot {
+                index: 5,
+                term: 3,
+                offset: 0,
+                data: vec![0x01],
+                done: true,
+                peers: peer_map(&["a", "c", "d", "e"]),
+            },
+        })?;
+        assert_node(&node).is_follower().term(3).leader(Some("b")).committed(2).snapshot(0);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     fn tick() -> Result<()> {
         let (follower, mut node_rx, mut state_rx) = setup()?;
@@ -970,7 +1836,7 @@ pub mod tests {
                 from: Address::Peer("b".into()),
                 to: Address::Peer("a".into()),
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
             })?;
             assert_messages(
                 &mut node_rx,
@@ -978,16 +1844,21 @@ pub mod tests {
                     from: Address::Local,
                     to: Address::Peer("b".into()),
                     term: 3,
-                    event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
                 }],
             )
         }
 
+        // The election timeout starts a pre-vote for the next term, without changing our term.
         for _ in 0..timeout {
             assert_node(&node).is_follower().term(3).leader(Some("b"));
             node = node.tick()?;
         }
-        assert_node(&node).is_candidate().term(4);
+        assert_node(&node).is_candidate().term(3);
 
         assert_messages(
             &mut node_rx,
@@ -995,7 +1866,7 @@ pub mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 4,
-                event: Event::SolicitVote { last_index: 3, last_term: 2 },
+                event: Event::SolicitPreVote { last_index: 3, last_term: 2 },
             }],
         );
         assert_messages(&mut state_rx, vec![]);
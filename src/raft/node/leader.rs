@@ -1,9 +1,15 @@
-use super::super::{Address, Event, Instruction, Message, Request, Response, Status};
-use super::{Follower, Node, RoleNode, HEARTBEAT_INTERVAL};
+use super::super::{
+    Address, Event, Instruction, MembershipChange, Message, Request, Response, Snapshot, Status,
+};
+use super::{
+    Follower, Node, RoleNode, ELECTION_TIMEOUT_MAX, HEARTBEAT_INTERVAL, LEASE_DURATION,
+    MAX_BATCH_SIZE, MAX_INFLIGHT, SNAPSHOT_CHUNK_SIZE,
+};
 use crate::error::{Error, Result};
 
 use ::log::{debug, info, warn};
-use std::collections::HashMap;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 // A leader serves requests and replicates the log to followers.
 #[derive(Debug)]
@@ -15,6 +21,38 @@ pub struct Leader {
     peer_next_index: HashMap<String, u64>,
     /// The last index known to be replicated on a peer.
     peer_last_index: HashMap<String, u64>,
+    /// The last index of each unacknowledged ReplicateEntries message sent to a peer, oldest first.
+    peer_inflight: HashMap<String, VecDeque<u64>>,
+    /// Peers whose log position is unknown, e.g. after rejecting entries. These are only sent one
+    /// message at a time until they accept entries, instead of pipelining a full window.
+    peer_probing: HashSet<String>,
+    /// The offset of the last snapshot chunk sent to peers that are receiving a snapshot.
+    peer_snapshot_offset: HashMap<String, u64>,
+    /// The most recently sent snapshot, cached to avoid reloading it for every chunk.
+    snapshot: Option<Snapshot>,
+    /// A leadership transfer in progress, if any. Boxed, since it's rarely used.
+    transfer: Option<Box<Transfer>>,
+    /// Peers that have responded to us since the last quorum check.
+    peer_active: HashSet<String>,
+    /// Number of ticks since the last quorum check.
+    quorum_ticks: u64,
+    /// The leader's logical clock, in ticks since it became leader.
+    ticks: u64,
+    /// The tick at which we sent the latest heartbeat each peer has confirmed, for leader leases.
+    peer_seen: HashMap<String, u64>,
+}
+
+/// A leadership transfer. The leader holds back writes, catches up the target's log, and then
+/// sends it TimeoutNow to make it campaign immediately, responding to the client. The transfer is
+/// aborted if the target hasn't taken over within an election timeout.
+#[derive(Debug)]
+struct Transfer {
+    /// The node to transfer leadership to.
+    target: String,
+    /// Number of ticks since the transfer started.
+    ticks: u64,
+    /// The ID and client address of the transfer request, until it has been responded to.
+    request: Option<(Vec<u8>, Address)>,
 }
 
 impl Leader {
@@ -24,10 +62,21 @@ impl Leader {
             heartbeat_ticks: 0,
             peer_next_index: HashMap::new(),
             peer_last_index: HashMap::new(),
+            peer_inflight: HashMap::new(),
+            peer_probing: HashSet::new(),
+            peer_snapshot_offset: HashMap::new(),
+            snapshot: None,
+            transfer: None,
+            peer_active: HashSet::new(),
+            quorum_ticks: 0,
+            ticks: 0,
+            peer_seen: HashMap::new(),
         };
         for peer in peers {
             leader.peer_next_index.insert(peer.clone(), last_index + 1);
             leader.peer_last_index.insert(peer.clone(), 0);
+            leader.peer_inflight.insert(peer.clone(), VecDeque::new());
+            leader.peer_probing.insert(peer);
         }
         // 返回leader
         leader
@@ -43,8 +92,33 @@ impl RoleNode<Leader> {
         self.term = term;
         self.log.save_term(term, None)?;
         self.state_tx.send(Instruction::Abort)?;
+        if let Some((id, address)) = self.role.transfer.take().and_then(|t| t.request) {
+            self.send(address, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+        }
         // 将自己的状态转化成为follower
-        self.become_role(Follower::new(Some(leader), None))
+        let mut node = self.become_role(Follower::new(Some(leader), None))?;
+        node.forward_queued(Address::Peer(leader.to_string()))?;
+        Ok(node)
+    }
+
+    /// Steps down after losing contact with a quorum, becoming a follower without a leader. We
+    /// voted for ourself in this term, so we can't vote for anyone else.
+    fn step_down(mut self) -> Result<RoleNode<Follower>> {
+        info!("Lost contact with quorum, stepping down in term {}", self.term);
+        self.log.save_term(self.term, Some(&self.id))?;
+        self.state_tx.send(Instruction::Abort)?;
+        if let Some((id, address)) = self.role.transfer.take().and_then(|t| t.request) {
+            self.send(address, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+        }
+        let id = self.id.clone();
+        self.become_role(Follower::new(None, Some(&id)))
+    }
+
+    /// Steps down after being removed from the cluster by a committed membership change.
+    fn become_removed(self) -> Result<RoleNode<Follower>> {
+        info!("Removed from cluster, stepping down");
+        self.state_tx.send(Instruction::Abort)?;
+        self.become_role(Follower::new(None, None))
     }
 
     /// Appends an entry to the log and replicates it to peers.
@@ -52,16 +126,176 @@ impl RoleNode<Leader> {
         // log append
         let entry = self.log.append(self.term, command)?;
         // 迭代每个follower 开始复制
-        for peer in self.peers.iter() {
-            self.replicate(peer)?;
+        for peer in self.peers.keys().cloned().collect::<Vec<_>>() {
+            self.replicate(&peer)?;
         }
         // 返回entry的index
         Ok(entry.index)
     }
 
-    /// Commits any pending log entries.
+    /// Appends a membership change to the log and replicates it to peers.
+    fn append_membership(&mut self, change: MembershipChange) -> Result<u64> {
+        let entry = self.log.append_membership(self.term, change)?;
+        for peer in self.peers.keys().cloned().collect::<Vec<_>>() {
+            self.replicate(&peer)?;
+        }
+        Ok(entry.index)
+    }
+
+    /// Checks whether a membership change can be made. Changes are made one at a time, and only
+    /// once the leader has committed an entry in its own term, such that any changes appended by
+    /// previous leaders have been committed or discarded.
+    fn validate_membership(&self, change: &MembershipChange) -> Result<()> {
+        if self.log.commit_term != self.term {
+            return Err(Error::Value("Leader has not yet committed an entry in its term".into()));
+        }
+        let mut scan = self.log.scan((self.log.commit_index + 1)..);
+        while let Some(entry) = scan.next().transpose()? {
+            if entry.membership.is_some() {
+                return Err(Error::Value("A membership change is already in progress".into()));
+            }
+        }
+        match change {
+            MembershipChange::AddNode { id, .. }
+                if id == &self.id || self.peers.contains_key(id) =>
+            {
+                Err(Error::Value(format!("Node {} is already a cluster member", id)))
+            }
+            MembershipChange::RemoveNode { id } if id == &self.id => {
+                Err(Error::Value(format!("Cannot remove leader {} from cluster", id)))
+            }
+            MembershipChange::RemoveNode { id } if !self.peers.contains_key(id) => {
+                Err(Error::Value(format!("Node {} is not a cluster member", id)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a leadership transfer to the given peer, which must be a cluster member.
+    fn start_transfer(&mut self, target: String, id: Vec<u8>, address: Address) -> Result<()> {
+        if let Some(transfer) = &self.role.transfer {
+            return Err(Error::Value(format!(
+                "Leadership transfer to {} is already in progress",
+                transfer.target
+            )));
+        }
+        if !self.peers.contains_key(&target) {
+            return Err(Error::Value(format!("Node {} is not a cluster member", target)));
+        }
+        info!("Transferring leadership to {}", target);
+        self.role.transfer = Some(Box::new(Transfer {
+            target: target.clone(),
+            ticks: 0,
+            request: Some((id, address)),
+        }));
+        self.replicate(&target)?;
+        self.transfer_caught_up()
+    }
+
+    /// Once the leadership transfer target's log has caught up with ours, responds to the client
+    /// and sends TimeoutNow to the target. The response is sent first, since a proxying follower
+    /// would abort the request once it sees the target campaign.
+    fn transfer_caught_up(&mut self) -> Result<()> {
+        let target = match self.role.transfer.as_deref() {
+            Some(Transfer { target, request: Some(_), .. }) => target.clone(),
+            _ => return Ok(()),
+        };
+        if self.role.peer_last_index.get(&target).copied().unwrap_or(0) >= self.log.last_index {
+            debug!("Leadership transfer target {} caught up, sending TimeoutNow", target);
+            if let Some((id, address)) = self.role.transfer.as_mut().and_then(|t| t.request.take())
+            {
+                let response = Ok(Response::State(vec![]));
+                self.send(address, Event::ClientResponse { id, response })?;
+            }
+            self.send(Address::Peer(target), Event::TimeoutNow)?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether we hold a leader lease, i.e. a quorum has confirmed a heartbeat we sent
+    /// within the lease duration, such that no other leader can have been elected and reads can
+    /// be served without confirming leadership first. The lease also requires an entry from our
+    /// term to have been committed, so that the commit index is current, and is given up during
+    /// leadership transfers, since the target campaigns without a pre-vote.
+    fn has_lease(&self) -> bool {
+        if self.role.transfer.is_some() || self.log.commit_term != self.term {
+            return false;
+        }
+        let ticks = self.role.ticks;
+        let active =
+            self.role.peer_seen.values().filter(|seen| ticks < *seen + LEASE_DURATION).count();
+        active as u64 + 1 >= self.quorum()
+    }
+
+    /// Confirms leadership for pending queries at the commit index, by voting for them and
+    /// soliciting votes from peers via heartbeats.
+    fn confirm_leadership(&mut self) -> Result<()> {
+        // 投票投自己
+        self.state_tx.send(Instruction::Vote {
+            term: self.term,
+            index: self.log.commit_index,
+            address: Address::Local,
+        })?;
+        if !self.peers.is_empty() {
+            self.heartbeat()?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts a heartbeat to all peers, stamped with our logical clock.
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.send(
+            // 广播所有的节点
+            Address::Peers,
+            // 心跳内容
+            Event::Heartbeat {
+                commit_index: self.log.commit_index,
+                commit_term: self.log.commit_term,
+                sent_tick: self.role.ticks,
+            },
+        )
+    }
+
+    /// Starts or stops tracking replication progress for peers that have been added to or
+    /// removed from the cluster, and starts replicating to any added peers.
+    fn sync_peers(&mut self) -> Result<()> {
+        let removed: Vec<String> = self
+            .role
+            .peer_next_index
+            .keys()
+            .filter(|peer| !self.peers.contains_key(*peer))
+            .cloned()
+            .collect();
+        for peer in removed {
+            self.role.peer_next_index.remove(&peer);
+            self.role.peer_last_index.remove(&peer);
+            self.role.peer_inflight.remove(&peer);
+            self.role.peer_probing.remove(&peer);
+            self.role.peer_snapshot_offset.remove(&peer);
+            self.role.peer_active.remove(&peer);
+            self.role.peer_seen.remove(&peer);
+        }
+        let added: Vec<String> = self
+            .peers
+            .keys()
+            .filter(|peer| !self.role.peer_next_index.contains_key(*peer))
+            .cloned()
+            .collect();
+        for peer in added {
+            self.role.peer_next_index.insert(peer.clone(), self.log.last_index + 1);
+            self.role.peer_last_index.insert(peer.clone(), 0);
+            self.role.peer_inflight.insert(peer.clone(), VecDeque::new());
+            self.role.peer_probing.insert(peer.clone());
+            self.replicate(&peer)?;
+        }
+        Ok(())
+    }
+
+    /// Commits any pending log entries. Returns true if the leader was removed from the cluster by
+    /// a committed membership change, which can only happen if it was appended by a previous
+    /// leader.
     /// 用来commit
-    fn commit(&mut self) -> Result<u64> {
+    fn commit(&mut self) -> Result<bool> {
         let mut last_indexes = vec![self.log.last_index];
         last_indexes.extend(self.role.peer_last_index.values());
         last_indexes.sort_unstable();
@@ -73,46 +307,139 @@ impl RoleNode<Leader> {
             if let Some(entry) = self.log.get(quorum_index)? {
                 // 如果任期term
                 if entry.term == self.term {
-                    let old_commit_index = self.log.commit_index;
                     self.log.commit(quorum_index)?;
-                    // 扫描这之间的log
-                    let mut scan = self.log.scan((old_commit_index + 1)..=self.log.commit_index);
-                    while let Some(entry) = scan.next().transpose()? {
-                        // 给状态机执行
-                        self.state_tx.send(Instruction::Apply { entry })?;
-                    }
                 }
             }
         }
-        Ok(self.log.commit_index)
+        // 扫描这之间的log, 给状态机执行.
+        // With group commit, this waits for the log to be flushed.
+        if self.apply_committed()? {
+            return Ok(true);
+        }
+        self.sync_peers()?;
+        Ok(false)
+    }
+
+    /// Flushes commits buffered by the log for group commit, and applies them.
+    pub fn flush(mut self) -> Result<Node> {
+        self.log.flush()?;
+        if self.commit()? {
+            return Ok(self.become_removed()?.into());
+        }
+        Ok(self.into())
     }
 
-    /// Replicates the log to a peer.
-    fn replicate(&self, peer: &str) -> Result<()> {
+    /// Replicates the log to a peer. If the peer needs entries that have been compacted, the
+    /// snapshot is sent instead, resuming at the last chunk sent if a transfer is in progress.
+    ///
+    /// Entries are sent in batches of up to MAX_BATCH_SIZE bytes, pipelining up to MAX_INFLIGHT
+    /// unacknowledged messages (or one, while probing the peer's log position). The next index is
+    /// advanced optimistically as batches are sent, and reset if the peer rejects them. If there
+    /// are no entries to send, an empty message is sent to probe the peer once the window is empty.
+    fn replicate(&mut self, peer: &str) -> Result<()> {
         // 复制偏移量
-        let peer_next = self
+        let mut peer_next = self
             .role
             .peer_next_index
             .get(peer)
             .cloned()
             .ok_or_else(|| Error::Internal(format!("Unknown peer {}", peer)))?;
-        // 上一个index
-        let base_index = if peer_next > 0 { peer_next - 1 } else { 0 };
-        // 上一个任期
-        let base_term = match self.log.get(base_index)? {
-            Some(base) => base.term,
-            None if base_index == 0 => 0,
-            None => return Err(Error::Internal(format!("Missing base entry {}", base_index))),
-        };
+        let window = if self.role.peer_probing.contains(peer) { 1 } else { MAX_INFLIGHT };
+        loop {
+            // 上一个index
+            let base_index = if peer_next > 0 { peer_next - 1 } else { 0 };
+            if base_index < self.log.snapshot_index {
+                self.role.peer_inflight.entry(peer.to_string()).or_default().clear();
+                let offset = self.role.peer_snapshot_offset.get(peer).copied().unwrap_or(0);
+                return self.send_snapshot(peer, offset);
+            }
+            let inflight = self.role.peer_inflight.get(peer).map(|i| i.len()).unwrap_or(0);
+            if inflight >= window || (peer_next > self.log.last_index && inflight > 0) {
+                return Ok(());
+            }
+            // 上一个任期
+            let base_term = self
+                .log
+                .term(base_index)?
+                .ok_or_else(|| Error::Internal(format!("Missing base entry {}", base_index)))?;
 
-        // 多个entries
-        let entries = self.log.scan(peer_next..).collect::<Result<Vec<_>>>()?;
-        debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
-        self.send(
-            Address::Peer(peer.to_string()),
-            Event::ReplicateEntries { base_index, base_term, entries },
-        )?;
-        Ok(())
+            // 多个entries, limited by the batch size
+            let mut entries = Vec::new();
+            let mut size = 0;
+            let mut scan = self.log.scan(peer_next..);
+            while let Some(entry) = scan.next().transpose()? {
+                let entry_size = entry.command.as_ref().map(|c| c.len()).unwrap_or(0);
+                if !entries.is_empty() && size + entry_size > MAX_BATCH_SIZE {
+                    break;
+                }
+                size += entry_size;
+                entries.push(entry);
+            }
+            drop(scan);
+
+            debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
+            peer_next += entries.len() as u64;
+            self.role.peer_next_index.insert(peer.to_string(), peer_next);
+            self.role.peer_inflight.entry(peer.to_string()).or_default().push_back(peer_next - 1);
+            self.send(
+                Address::Peer(peer.to_string()),
+                Event::ReplicateEntries { base_index, base_term, entries },
+            )?;
+            if peer_next > self.log.last_index {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Finds the next index to replicate to a peer that rejected entries, from its conflict hint.
+    /// If we have the peer's first entry in the conflicting term, the peer's log matches ours up
+    /// to our last entry in that term. Otherwise, the peer's entire term is skipped. The result
+    /// never moves past the current next index, nor back past the peer's last known index.
+    fn conflict_next_index(&self, peer: &str, term: u64, index: u64) -> Result<u64> {
+        let mut next = index;
+        if term > 0 && self.log.has(index, term)? {
+            let mut scan = self.log.scan(index..);
+            while let Some(entry) = scan.next().transpose()? {
+                if entry.term != term {
+                    break;
+                }
+                next = entry.index + 1;
+            }
+        }
+        let peer_next = self.role.peer_next_index.get(peer).copied().unwrap_or(1);
+        let peer_last = self.role.peer_last_index.get(peer).copied().unwrap_or(0);
+        Ok(max(min(next, peer_next), peer_last + 1))
+    }
+
+    /// Returns the current snapshot, loading it from the log if the cached one is outdated.
+    fn snapshot(&mut self) -> Result<&Snapshot> {
+        if self.role.snapshot.as_ref().map(|s| s.index) != Some(self.log.snapshot_index) {
+            self.role.snapshot = self.log.load_snapshot()?;
+        }
+        self.role.snapshot.as_ref().ok_or_else(|| Error::Internal("Snapshot not found".into()))
+    }
+
+    /// Sends the snapshot chunk at the given offset to a peer, along with our peers and our own
+    /// address if known, such that the peer can reach the rest of the cluster once installed.
+    fn send_snapshot(&mut self, peer: &str, offset: u64) -> Result<()> {
+        let mut peers = self.peers.clone();
+        if let Some(addr) = self.log.load_membership()?.and_then(|m| m.addr) {
+            peers.insert(self.id.clone(), addr);
+        }
+        let snapshot = self.snapshot()?;
+        let start = min(offset as usize, snapshot.data.len());
+        let end = min(start + SNAPSHOT_CHUNK_SIZE, snapshot.data.len());
+        let event = Event::InstallSnapshot {
+            index: snapshot.index,
+            term: snapshot.term,
+            offset: start as u64,
+            data: snapshot.data[start..end].to_vec(),
+            done: end == snapshot.data.len(),
+            peers,
+        };
+        debug!("Sending snapshot at index {} offset {} to {}", snapshot.index, start, peer);
+        self.role.peer_snapshot_offset.insert(peer.to_string(), start as u64);
+        self.send(Address::Peer(peer.to_string()), event)
     }
 
     /// Processes a message.
@@ -122,26 +449,53 @@ impl RoleNode<Leader> {
             return Ok(self.into());
         }
         // 消息的term大于本地节点的term
-        if msg.term > self.term {
+        if msg.term > self.term && !msg.event.is_prevote() {
             if let Address::Peer(from) = &msg.from {
                 // 自己成为follow
                 return self.become_follower(msg.term, from)?.step(msg);
             }
         }
+        // Replication responses show that a peer is still in contact with us, for quorum checks.
+        // They may also arrive from peers that have been removed, which are ignored.
+        if let Address::Peer(from) = &msg.from {
+            if matches!(
+                msg.event,
+                Event::ConfirmLeader { .. }
+                    | Event::AcceptEntries { .. }
+                    | Event::RejectEntries { .. }
+                    | Event::AcceptSnapshot { .. }
+            ) {
+                if !self.peers.contains_key(from) {
+                    debug!("Ignoring message from non-peer {}: {:?}", from, msg.event);
+                    return Ok(self.into());
+                }
+                self.role.peer_active.insert(from.clone());
+            }
+        }
 
         // 消息的事件
         match msg.event {
             // 如果是confirm leader
-            Event::ConfirmLeader { commit_index, has_committed } => {
+            Event::ConfirmLeader { commit_index, has_committed, sent_tick } => {
                 // 获取消息的来源
                 if let Address::Peer(from) = msg.from.clone() {
+                    // The lease is measured from when the heartbeat was sent, since responses may
+                    // be delayed or reordered.
+                    let seen = self.role.peer_seen.entry(from.clone()).or_default();
+                    *seen = max(*seen, sent_tick);
                     // 返回响应
                     self.state_tx.send(Instruction::Vote {
                         term: msg.term,
                         index: commit_index,
                         address: msg.from,
                     })?;
-                    if !has_committed {
+                    // If the peer is missing committed entries, or is lagging with entries in
+                    // flight, some messages may have been lost. We free up a slot in its window to
+                    // keep replicating, eventually probing it once the window is empty.
+                    let peer_last = self.role.peer_last_index.get(&from).copied().unwrap_or(0);
+                    let inflight = self.role.peer_inflight.entry(from.clone()).or_default();
+                    if !has_committed || (peer_last < self.log.last_index && !inflight.is_empty()) {
+                        inflight.pop_front();
                         self.replicate(&from)?;
                     }
                 }
@@ -150,30 +504,56 @@ impl RoleNode<Leader> {
             // 采纳来该entry
             Event::AcceptEntries { last_index } => {
                 // 获取节点
-                if let Address::Peer(from) = msg.from {
-                    // 对应节点已经accept了
-                    self.role.peer_last_index.insert(from.clone(), last_index);
-                    // 预期的index
-                    self.role.peer_next_index.insert(from, last_index + 1);
+                let from = match msg.from {
+                    Address::Peer(from) => from,
+                    _ => return Ok(self.into()),
+                };
+                // 对应节点已经accept了
+                let peer_last = self.role.peer_last_index.get(&from).copied().unwrap_or(0);
+                self.role.peer_last_index.insert(from.clone(), max(peer_last, last_index));
+                // 预期的index. Entries may already be in flight past it.
+                let peer_next = self.role.peer_next_index.get(&from).copied().unwrap_or(0);
+                self.role.peer_next_index.insert(from.clone(), max(peer_next, last_index + 1));
+                if let Some(inflight) = self.role.peer_inflight.get_mut(&from) {
+                    while inflight.front().map(|i| *i <= last_index).unwrap_or(false) {
+                        inflight.pop_front();
+                    }
                 }
+                self.role.peer_probing.remove(&from);
+                self.transfer_caught_up()?;
                 // 能够提交，将能够提交的部分持久化
-                self.commit()?;
+                if self.commit()? {
+                    return Ok(self.become_removed()?.into());
+                }
+                // Keep the pipeline full if there are more entries to send.
+                if self.peers.contains_key(&from)
+                    && max(peer_next, last_index + 1) <= self.log.last_index
+                {
+                    self.replicate(&from)?;
+                }
             }
 
             // 拒绝来该entry
-            Event::RejectEntries => {
+            // Any entries in flight to the peer will also be rejected, so we discard them and
+            // resume from the conflict hint, probing the peer until it accepts entries.
+            Event::RejectEntries { conflict_term, conflict_index } => {
                 if let Address::Peer(from) = msg.from {
-                    self.role.peer_next_index.entry(from.clone()).and_modify(|i| {
-                        if *i > 1 {
-                            *i -= 1
-                        }
-                    });
+                    let next = self.conflict_next_index(&from, conflict_term, conflict_index)?;
+                    debug!("Peer {} rejected entries, resuming replication at {}", from, next);
+                    self.role.peer_next_index.insert(from.clone(), next);
+                    self.role.peer_inflight.entry(from.clone()).or_default().clear();
+                    self.role.peer_probing.insert(from.clone());
                     self.replicate(&from)?;
                 }
             }
 
-            // 客户端请求
-            Event::ClientRequest { id, request: Request::Query(command) } => {
+            // 客户端请求. Local queries are served as regular queries on the leader.
+            Event::ClientRequest {
+                id,
+                request: Request::Query(command) | Request::LocalQuery { command, .. },
+            } => {
+                // With a lease, the query only has to wait for the commit index to be applied.
+                let lease = self.has_lease();
                 self.state_tx.send(Instruction::Query {
                     id,
                     address: msg.from,
@@ -183,27 +563,37 @@ impl RoleNode<Leader> {
                     // 当前commit index
                     index: self.log.commit_index,
                     // 法定人数
-                    quorum: self.quorum(),
+                    quorum: if lease { 0 } else { self.quorum() },
                 })?;
-                // 投票投自己
-                self.state_tx.send(Instruction::Vote {
+                if !lease {
+                    self.confirm_leadership()?;
+                }
+            }
+
+            // Followers fetch a read index for local queries, which is confirmed like a query.
+            Event::ClientRequest { id, request: Request::ReadIndex } if self.has_lease() => {
+                let response = Ok(Response::ReadIndex(self.log.commit_index));
+                self.send(msg.from, Event::ClientResponse { id, response })?;
+            }
+
+            Event::ClientRequest { id, request: Request::ReadIndex } => {
+                self.state_tx.send(Instruction::ReadIndex {
+                    id,
+                    address: msg.from,
                     term: self.term,
                     index: self.log.commit_index,
-                    address: Address::Local,
+                    quorum: self.quorum(),
                 })?;
-                if !self.peers.is_empty() {
-                    self.send(
-                        // 广播所有的节点
-                        Address::Peers,
-                        // 心跳内容
-                        Event::Heartbeat {
-                            commit_index: self.log.commit_index,
-                            commit_term: self.log.commit_term,
-                        },
-                    )?;
-                }
+                self.confirm_leadership()?;
             }
 
+            // Writes are held back during leadership transfers, so that the target can catch up.
+            // They're forwarded to the new leader, or processed here if the transfer is aborted.
+            Event::ClientRequest {
+                request: Request::Mutate(_) | Request::ChangeMembership(_),
+                ..
+            } if self.role.transfer.is_some() => self.queued_reqs.push((msg.from, msg.event)),
+
             // 客户端的请求，写请求
             Event::ClientRequest { id, request: Request::Mutate(command) } => {
                 // append 命令
@@ -215,6 +605,32 @@ impl RoleNode<Leader> {
                 }
             }
 
+            Event::ClientRequest { id, request: Request::ChangeMembership(change) } => {
+                match self.validate_membership(&change) {
+                    Ok(()) => {
+                        let index = self.append_membership(change)?;
+                        self.state_tx.send(Instruction::Notify { id, address: msg.from, index })?;
+                        if self.peers.is_empty() {
+                            self.commit()?;
+                        }
+                    }
+                    Err(err) => {
+                        self.send(msg.from, Event::ClientResponse { id, response: Err(err) })?
+                    }
+                }
+            }
+
+            Event::ClientRequest { id, request: Request::TransferLeadership(target) } => {
+                if target == self.id {
+                    self.send(
+                        msg.from,
+                        Event::ClientResponse { id, response: Ok(Response::State(vec![])) },
+                    )?
+                } else if let Err(err) = self.start_transfer(target, id.clone(), msg.from.clone()) {
+                    self.send(msg.from, Event::ClientResponse { id, response: Err(err) })?
+                }
+            }
+
             // 查询状态
             Event::ClientRequest { id, request: Request::Status } => {
                 let mut status = Box::new(Status {
@@ -226,6 +642,7 @@ impl RoleNode<Leader> {
                     apply_index: 0,
                     storage: self.log.store.to_string(),
                     storage_size: self.log.store.size(),
+                    storage_sync: self.log.store.sync_stats(),
                 });
                 status.node_last_index.insert(self.id.clone(), self.log.last_index);
                 self.state_tx.send(Instruction::Status { id, address: msg.from, status })?
@@ -239,14 +656,44 @@ impl RoleNode<Leader> {
                 self.send(Address::Client, Event::ClientResponse { id, response })?;
             }
 
+            // Peers that need compacted entries are sent the snapshot instead.
+            Event::Snapshot { index, data } => self.compact(index, data)?,
+
+            // Once the peer has installed the snapshot, we continue replicating entries after it.
+            // If we've taken a newer snapshot in the meanwhile, we start over with that one.
+            Event::AcceptSnapshot { index, offset } => {
+                if let Address::Peer(from) = msg.from {
+                    if let Some(sent) = self.role.peer_snapshot_offset.get(&from).copied() {
+                        if index != self.log.snapshot_index {
+                            self.send_snapshot(&from, 0)?;
+                        } else if offset >= self.snapshot()?.data.len() as u64 {
+                            self.role.peer_snapshot_offset.remove(&from);
+                            let last_index = self.role.peer_last_index.get(&from).copied();
+                            self.role
+                                .peer_last_index
+                                .insert(from.clone(), max(last_index.unwrap_or(0), index));
+                            self.role.peer_next_index.insert(from.clone(), index + 1);
+                            self.role.peer_probing.remove(&from);
+                            self.replicate(&from)?;
+                        } else if offset != sent {
+                            self.send_snapshot(&from, offset)?;
+                        }
+                    }
+                }
+            }
+
             // We ignore these messages, since they are typically additional votes from the previous
-            // election that we won after a quorum.
-            Event::SolicitVote { .. } | Event::GrantVote => {}
+            // election that we won after a quorum. Pre-votes are ignored since we're alive.
+            Event::SolicitVote { .. }
+            | Event::GrantVote
+            | Event::SolicitPreVote { .. }
+            | Event::GrantPreVote => {}
 
             // 心跳
-            Event::Heartbeat { .. } | Event::ReplicateEntries { .. } => {
-                warn!("Received unexpected message {:?}", msg)
-            }
+            Event::Heartbeat { .. }
+            | Event::ReplicateEntries { .. }
+            | Event::InstallSnapshot { .. }
+            | Event::TimeoutNow => warn!("Received unexpected message {:?}", msg),
         }
 
         Ok(self.into())
@@ -255,34 +702,69 @@ impl RoleNode<Leader> {
     /// Processes a logical clock tick.
     /// 逻辑时钟处理
     pub fn tick(mut self) -> Result<Node> {
+        self.role.ticks += 1;
         // 心跳协议
         if !self.peers.is_empty() {
             self.role.heartbeat_ticks += 1;
             if self.role.heartbeat_ticks >= HEARTBEAT_INTERVAL {
                 self.role.heartbeat_ticks = 0;
-                self.send(
-                    Address::Peers,
-                    // 自己节点信息
-                    Event::Heartbeat {
-                        commit_index: self.log.commit_index,
-                        commit_term: self.log.commit_term,
-                    },
-                )?;
+                // 自己节点信息
+                self.heartbeat()?;
+            }
+        }
+        // If we haven't heard from a quorum within an election timeout, we may have been
+        // partitioned from the majority, which can elect a new leader. We step down to stop
+        // serving clients.
+        if !self.peers.is_empty() {
+            self.role.quorum_ticks += 1;
+            if self.role.quorum_ticks >= ELECTION_TIMEOUT_MAX {
+                if (self.role.peer_active.len() as u64) + 1 < self.quorum() {
+                    return Ok(self.step_down()?.into());
+                }
+                self.role.quorum_ticks = 0;
+                self.role.peer_active.clear();
+            }
+        }
+        if let Some(transfer) = self.role.transfer.as_mut() {
+            transfer.ticks += 1;
+            if transfer.ticks >= ELECTION_TIMEOUT_MAX {
+                return self.abort_transfer();
             }
         }
         // 转成Node类型
         Ok(self.into())
     }
+
+    /// Aborts a leadership transfer that timed out, and processes any writes held back by it.
+    fn abort_transfer(mut self) -> Result<Node> {
+        if let Some(transfer) = self.role.transfer.take() {
+            warn!("Leadership transfer to {} timed out", transfer.target);
+            if let Some((id, address)) = transfer.request {
+                let message = format!("Leadership transfer to {} timed out", transfer.target);
+                let response = Err(Error::Value(message));
+                self.send(address, Event::ClientResponse { id, response })?;
+            }
+        }
+        let queued = std::mem::take(&mut self.queued_reqs);
+        let mut node: Node = self.into();
+        for (from, event) in queued {
+            node = node.step(Message { from, to: Address::Local, term: 0, event })?;
+        }
+        Ok(node)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::super::{Entry, Log};
-    use super::super::tests::{assert_messages, assert_node};
+    use super::super::super::{Entry, Log, Membership};
+    use super::super::tests::{assert_messages, assert_node, peer_map};
+    use super::super::ELECTION_TIMEOUT_MIN;
     use super::*;
     use crate::storage::log;
     use futures::FutureExt;
     use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
     use tokio::sync::mpsc;
 
     #[allow(clippy::type_complexity)]
@@ -302,11 +784,12 @@ mod tests {
         log.append(3, Some(vec![0x04]))?;
         log.append(3, Some(vec![0x05]))?;
         log.commit(2)?;
+        log.take_flushed(); // the committed entries have been applied
         log.save_term(3, None)?;
 
         let node = RoleNode {
             id: "a".into(),
-            peers: peers.clone(),
+            peers: peer_map(&["b", "c", "d", "e"]),
             term: 3,
             role: Leader::new(peers, log.last_index),
             log,
@@ -328,7 +811,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::ConfirmLeader { commit_index: 2, has_committed: true },
+            event: Event::ConfirmLeader { commit_index: 2, has_committed: true, sent_tick: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -349,7 +832,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::ConfirmLeader { commit_index: 2, has_committed: false },
+            event: Event::ConfirmLeader { commit_index: 2, has_committed: false, sent_tick: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(
@@ -378,7 +861,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 3,
-            event: Event::Heartbeat { commit_index: 5, commit_term: 3 },
+            event: Event::Heartbeat { commit_index: 5, commit_term: 3, sent_tick: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -396,7 +879,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 4,
-            event: Event::Heartbeat { commit_index: 7, commit_term: 4 },
+            event: Event::Heartbeat { commit_index: 7, commit_term: 4, sent_tick: 0 },
         })?;
         assert_node(&node).is_follower().term(4).leader(Some("b")).committed(2);
         assert_messages(
@@ -405,7 +888,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 4,
-                event: Event::ConfirmLeader { commit_index: 7, has_committed: false },
+                event: Event::ConfirmLeader { commit_index: 7, has_committed: false, sent_tick: 0 },
             }],
         );
         assert_messages(&mut state_rx, vec![Instruction::Abort]);
@@ -422,7 +905,7 @@ mod tests {
             from: Address::Peer("b".into()),
             to: Address::Peer("a".into()),
             term: 2,
-            event: Event::Heartbeat { commit_index: 3, commit_term: 2 },
+            event: Event::Heartbeat { commit_index: 3, commit_term: 2, sent_tick: 0 },
         })?;
         assert_node(&node).is_leader().term(3).committed(2);
         assert_messages(&mut node_rx, vec![]);
@@ -457,10 +940,10 @@ mod tests {
             &mut state_rx,
             vec![
                 Instruction::Apply {
-                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
                 },
                 Instruction::Apply {
-                    entry: Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                    entry: Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
                 },
             ],
         );
@@ -476,7 +959,7 @@ mod tests {
         assert_messages(
             &mut state_rx,
             vec![Instruction::Apply {
-                entry: Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                entry: Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
             }],
         );
 
@@ -504,11 +987,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Replication responses from non-peers, e.g. removed nodes, should be ignored.
+    fn step_acceptentries_nonpeer() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        for event in vec![
+            Event::AcceptEntries { last_index: 5 },
+            Event::RejectEntries { conflict_term: 0, conflict_index: 1 },
+        ] {
+            for peer in vec!["x", "y"] {
+                node = node.step(Message {
+                    from: Address::Peer(peer.into()),
+                    to: Address::Peer("a".into()),
+                    term: 3,
+                    event: event.clone(),
+                })?;
+            }
+        }
+        assert_node(&node).is_leader().term(3).committed(2);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
     #[test]
     // AcceptEntries quorum for entry in past term should not trigger commit
     fn step_acceptentries_past_term() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
-        let peers = leader.peers.clone();
+        let peers = leader.peers.keys().cloned().collect::<Vec<_>>();
         let mut node: Node = leader.into();
 
         for peer in peers.into_iter() {
@@ -529,7 +1037,7 @@ mod tests {
     // AcceptEntries quorum for missing future entry
     fn step_acceptentries_future_index() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
-        let peers = leader.peers.clone();
+        let peers = leader.peers.keys().cloned().collect::<Vec<_>>();
         let mut node: Node = leader.into();
 
         for (i, peer) in peers.into_iter().enumerate() {
@@ -549,13 +1057,28 @@ mod tests {
                     &mut state_rx,
                     vec![
                         Instruction::Apply {
-                            entry: Entry { index: 3, term: 2, command: Some(vec![0x03]) },
+                            entry: Entry {
+                                index: 3,
+                                term: 2,
+                                command: Some(vec![0x03]),
+                                membership: None,
+                            },
                         },
                         Instruction::Apply {
-                            entry: Entry { index: 4, term: 3, command: Some(vec![0x04]) },
+                            entry: Entry {
+                                index: 4,
+                                term: 3,
+                                command: Some(vec![0x04]),
+                                membership: None,
+                            },
                         },
                         Instruction::Apply {
-                            entry: Entry { index: 5, term: 3, command: Some(vec![0x05]) },
+                            entry: Entry {
+                                index: 5,
+                                term: 3,
+                                command: Some(vec![0x05]),
+                                membership: None,
+                            },
                         },
                     ],
                 );
@@ -567,21 +1090,28 @@ mod tests {
     }
 
     #[test]
+    // RejectEntries resumes replication from the conflict hint, skipping entire terms.
     fn step_rejectentries() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
         let entries = leader.log.scan(0..).collect::<Result<Vec<_>>>()?;
         let mut node: Node = leader.into();
 
-        for i in 0..(entries.len() + 3) {
+        for (conflict_term, conflict_index, base_index) in [
+            (0, 3, 2), // peer log ends at 2
+            (2, 3, 3), // peer has our entry 3 in term 2
+            (1, 1, 2), // peer has our entries 1-2 in term 1
+            (4, 4, 3), // we don't have the peer's term 4, so it's skipped
+            (0, 9, 5), // never past our next index
+            (0, 1, 0), // peer log is empty
+        ] {
             node = node.step(Message {
                 from: Address::Peer("b".into()),
                 to: Address::Peer("a".into()),
                 term: 3,
-                event: Event::RejectEntries,
+                event: Event::RejectEntries { conflict_term, conflict_index },
             })?;
             assert_node(&node).is_leader().term(3).committed(2);
-            let index = if i >= entries.len() { 0 } else { entries.len() - i - 1 };
-            let replicate = entries.get(index..).unwrap().to_vec();
+            let index = base_index as usize;
             assert_messages(
                 &mut node_rx,
                 vec![Message {
@@ -589,13 +1119,9 @@ mod tests {
                     to: Address::Peer("b".into()),
                     term: 3,
                     event: Event::ReplicateEntries {
-                        base_index: index as u64,
-                        base_term: if index > 0 {
-                            entries.get(index - 1).map(|e| e.term).unwrap()
-                        } else {
-                            0
-                        },
-                        entries: replicate,
+                        base_index,
+                        base_term: if index > 0 { entries[index - 1].term } else { 0 },
+                        entries: entries[index..].to_vec(),
                     },
                 }],
             );
@@ -604,6 +1130,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Entries are replicated in batches of limited size, pipelining a window of messages.
+    fn step_acceptentries_pipeline() -> Result<()> {
+        let (mut leader, mut node_rx, _) = setup()?;
+        let command = vec![0xaf; MAX_BATCH_SIZE / 2];
+        for _ in 0..(2 * MAX_INFLIGHT + 2) {
+            leader.log.append(3, Some(command.clone()))?;
+        }
+        let mut node: Node = leader.into();
+
+        // Returns the base index and entry indexes of replicated messages.
+        let replicated = |node_rx: &mut mpsc::UnboundedReceiver<Message>| {
+            let mut batches = Vec::new();
+            while let Some(Some(msg)) = node_rx.recv().now_or_never() {
+                match msg.event {
+                    Event::ReplicateEntries { base_index, entries, .. } => batches.push((
+                        base_index,
+                        entries.into_iter().map(|e| e.index).collect::<Vec<_>>(),
+                    )),
+                    event => panic!("Unexpected event {:?}", event),
+                }
+            }
+            batches
+        };
+        let accept = |node: Node, last_index| {
+            node.step(Message {
+                from: Address::Peer("b".into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index },
+            })
+        };
+
+        // Once the peer accepts entries, a full window of batches is sent.
+        node = accept(node, 5)?;
+        let expect: Vec<(u64, Vec<u64>)> =
+            (0..MAX_INFLIGHT as u64).map(|i| (5 + 2 * i, vec![6 + 2 * i, 7 + 2 * i])).collect();
+        assert_eq!(replicated(&mut node_rx), expect);
+
+        // Each acknowledgement frees up a slot for the next batch, and duplicates are ignored.
+        let last = 5 + 2 * MAX_INFLIGHT as u64;
+        node = accept(node, 7)?;
+        node = accept(node, 7)?;
+        assert_eq!(replicated(&mut node_rx), vec![(last, vec![last + 1, last + 2])]);
+
+        // A rejection discards in-flight batches, and probes the peer one batch at a time.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::RejectEntries { conflict_term: 0, conflict_index: 10 },
+        })?;
+        assert_eq!(replicated(&mut node_rx), vec![(9, vec![10, 11])]);
+        node = accept(node, 11)?;
+        assert_eq!(
+            replicated(&mut node_rx),
+            (0..MAX_INFLIGHT as u64)
+                .map(|i| (11 + 2 * i, vec![12 + 2 * i, 13 + 2 * i]))
+                .filter(|(base, _)| *base < last + 2)
+                .collect::<Vec<_>>()
+        );
+        assert_node(&node).is_leader().term(3).committed(2);
+        Ok(())
+    }
+
     #[test]
     // Sending a client query request will pass it to the state machine and trigger heartbeats.
     fn step_clientrequest_query() -> Result<()> {
@@ -623,7 +1214,7 @@ mod tests {
                 from: Address::Local,
                 to: Address::Peers,
                 term: 3,
-                event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
             }],
         );
         assert_messages(
@@ -644,75 +1235,898 @@ mod tests {
     }
 
     #[test]
-    // Sending a mutate request should append it to log, replicate it to peers, and register notification.
-    fn step_clientrequest_mutate() -> Result<()> {
+    // A read index request from a follower is confirmed like a query, and so are local queries.
+    fn step_clientrequest_readindex() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
-        let peers = leader.peers.clone();
+        let quorum = leader.quorum();
         let mut node: Node = leader.into();
-
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::ReadIndex },
+        })?;
         node = node.step(Message {
             from: Address::Client,
             to: Address::Local,
             term: 0,
-            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+            event: Event::ClientRequest {
+                id: vec![0x02],
+                request: Request::LocalQuery {
+                    command: vec![0xaf],
+                    min_index: 0,
+                    max_staleness: Some(10),
+                },
+            },
         })?;
-        assert_node(&node).is_leader().term(3).committed(2).last(6).entry(Entry {
-            index: 6,
+        assert_node(&node).is_leader().term(3).committed(2).last(5);
+        let heartbeat = Message {
+            from: Address::Local,
+            to: Address::Peers,
             term: 3,
-            command: Some(vec![0xaf]),
-        });
-
-        for peer in peers.iter().cloned() {
-            assert_eq!(
-                node_rx.recv().now_or_never(),
-                Some(Some(Message {
-                    from: Address::Local,
-                    to: Address::Peer(peer),
-                    term: 3,
-                    event: Event::ReplicateEntries {
-                        base_index: 5,
-                        base_term: 3,
-                        entries: vec![Entry { index: 6, term: 3, command: Some(vec![0xaf]) },]
-                    },
-                }))
-            )
-        }
-        assert_messages(&mut node_rx, vec![]);
+            event: Event::Heartbeat { commit_index: 2, commit_term: 1, sent_tick: 0 },
+        };
+        assert_messages(&mut node_rx, vec![heartbeat.clone(), heartbeat]);
         assert_messages(
             &mut state_rx,
-            vec![Instruction::Notify { id: vec![0x01], address: Address::Client, index: 6 }],
+            vec![
+                Instruction::ReadIndex {
+                    id: vec![0x01],
+                    address: Address::Peer("b".into()),
+                    term: 3,
+                    index: 2,
+                    quorum,
+                },
+                Instruction::Vote { term: 3, index: 2, address: Address::Local },
+                Instruction::Query {
+                    id: vec![0x02],
+                    address: Address::Client,
+                    command: vec![0xaf],
+                    term: 3,
+                    index: 2,
+                    quorum,
+                },
+                Instruction::Vote { term: 3, index: 2, address: Address::Local },
+            ],
         );
-
         Ok(())
     }
 
-    #[test]
-    // Sending a status request should pass it on to state machine, to add status.
-    fn step_clientrequest_status() -> Result<()> {
-        let (leader, mut node_rx, mut state_rx) = setup()?;
-        let mut node: Node = leader.into();
-
+    /// Sends a query to the leader, and asserts whether it was served under a lease or had to
+    /// confirm leadership with a quorum first.
+    fn assert_query_lease(
+        mut node: Node,
+        node_rx: &mut mpsc::UnboundedReceiver<Message>,
+        state_rx: &mut mpsc::UnboundedReceiver<Instruction>,
+        lease: bool,
+    ) -> Result<Node> {
+        let quorum = match &node {
+            Node::Leader(leader) => leader.quorum(),
+            _ => panic!("Expected leader"),
+        };
         node = node.step(Message {
             from: Address::Client,
             to: Address::Local,
             term: 0,
-            event: Event::ClientRequest { id: vec![0x01], request: Request::Status },
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Query(vec![0xaf]) },
         })?;
-        assert_node(&node).is_leader().term(3).committed(2).last(5);
-        assert_messages(&mut node_rx, vec![]);
-        assert_messages(
-            &mut state_rx,
-            vec![Instruction::Status {
-                id: vec![0x01],
-                address: Address::Client,
-                status: Box::new(Status {
-                    server: "a".into(),
-                    leader: "a".into(),
+        let (commit_index, commit_term, sent_tick) = match &node {
+            Node::Leader(leader) => {
+                (leader.log.commit_index, leader.log.commit_term, leader.role.ticks)
+            }
+            _ => panic!("Expected leader"),
+        };
+        let query = Instruction::Query {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xaf],
+            term: 3,
+            index: commit_index,
+            quorum: if lease { 0 } else { quorum },
+        };
+        if lease {
+            assert_messages(node_rx, vec![]);
+            assert_messages(state_rx, vec![query]);
+        } else {
+            assert_messages(
+                node_rx,
+                vec![Message {
+                    from: Address::Local,
+                    to: Address::Peers,
                     term: 3,
-                    node_last_index: vec![
-                        ("a".into(), 5),
-                        ("b".into(), 0),
-                        ("c".into(), 0),
+                    event: Event::Heartbeat { commit_index, commit_term, sent_tick },
+                }],
+            );
+            assert_messages(
+                state_rx,
+                vec![
+                    query,
+                    Instruction::Vote { term: 3, index: commit_index, address: Address::Local },
+                ],
+            );
+        }
+        Ok(node)
+    }
+
+    #[test]
+    // A leader holds a lease while a quorum has responded within the lease duration, and has
+    // committed an entry in its term. Partitioned peers don't extend the lease.
+    fn step_clientrequest_query_lease() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let drain = |node_rx: &mut mpsc::UnboundedReceiver<Message>,
+                     state_rx: &mut mpsc::UnboundedReceiver<Instruction>| {
+            while node_rx.recv().now_or_never().is_some() {}
+            while state_rx.recv().now_or_never().is_some() {}
+        };
+
+        // Peers respond, but nothing has been committed in our term yet.
+        for peer in ["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader { commit_index: 2, has_committed: true, sent_tick: 0 },
+            })?;
+        }
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+
+        // Once the quorum has accepted our entries, they're committed and we hold a lease.
+        for peer in ["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(5);
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, true)?;
+
+        // Read index requests are answered right away.
+        node = node.step(Message {
+            from: Address::Peer("d".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ClientRequest { id: vec![0x02], request: Request::ReadIndex },
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("d".into()),
+                term: 3,
+                event: Event::ClientResponse {
+                    id: vec![0x02],
+                    response: Ok(Response::ReadIndex(5)),
+                },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+
+        // If the peers are partitioned away, the lease expires after the lease duration.
+        for _ in 0..LEASE_DURATION - 1 {
+            node = node.tick()?;
+        }
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, true)?;
+        node = node.tick()?;
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+
+        // A single peer is not a quorum, but the lease is regained once a quorum responds.
+        node = node.step(Message {
+            from: Address::Peer("d".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader {
+                commit_index: 5,
+                has_committed: true,
+                sent_tick: LEASE_DURATION,
+            },
+        })?;
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+        node = node.step(Message {
+            from: Address::Peer("e".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader {
+                commit_index: 5,
+                has_committed: true,
+                sent_tick: LEASE_DURATION,
+            },
+        })?;
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, true)?;
+
+        // The lease is given up during leadership transfers.
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![0x03],
+                request: Request::TransferLeadership("d".into()),
+            },
+        })?;
+        drain(&mut node_rx, &mut state_rx);
+        assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+        Ok(())
+    }
+
+    #[test]
+    // The lease is measured from when a heartbeat was sent, not from when its confirmation was
+    // received, so delayed confirmations don't extend it past the point where the followers may
+    // have elected a new leader.
+    fn step_clientrequest_query_lease_delayed() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let drain = |node_rx: &mut mpsc::UnboundedReceiver<Message>,
+                     state_rx: &mut mpsc::UnboundedReceiver<Instruction>| {
+            while node_rx.recv().now_or_never().is_some() {}
+            while state_rx.recv().now_or_never().is_some() {}
+        };
+        for peer in ["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(5);
+
+        // The heartbeat sent at tick 1 is confirmed by a quorum, but the confirmations are
+        // delayed until the followers could have elected a new leader.
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            node = node.tick()?;
+        }
+        for peer in ["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader { commit_index: 5, has_committed: true, sent_tick: 1 },
+            })?;
+        }
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+
+        // A timely confirmation of the latest heartbeat grants a lease. Reordered delayed
+        // confirmations don't move it back.
+        let ticks = ELECTION_TIMEOUT_MIN;
+        for peer in ["b", "c"] {
+            for sent_tick in [ticks, 1] {
+                node = node.step(Message {
+                    from: Address::Peer(peer.into()),
+                    to: Address::Peer("a".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader { commit_index: 5, has_committed: true, sent_tick },
+                })?;
+            }
+        }
+        drain(&mut node_rx, &mut state_rx);
+        node = assert_query_lease(node, &mut node_rx, &mut state_rx, true)?;
+
+        // Once the lease duration has passed since that heartbeat was sent, the lease expires.
+        for _ in 0..LEASE_DURATION {
+            node = node.tick()?;
+        }
+        drain(&mut node_rx, &mut state_rx);
+        assert_query_lease(node, &mut node_rx, &mut state_rx, false)?;
+        Ok(())
+    }
+
+    #[test]
+    // Once partitioned away from the followers that confirmed its lease, the leader loses the lease
+    // before any of them will vote for a new leader, so it can't serve reads after a newer leader
+    // has been elected.
+    fn lease_newer_leader() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        for peer in ["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader { commit_index: 2, has_committed: true, sent_tick: 0 },
+            })?;
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        let has_lease = |node: &Node| match node {
+            Node::Leader(leader) => leader.has_lease(),
+            _ => false,
+        };
+        assert!(has_lease(&node));
+
+        // The followers heard from the leader when confirming its heartbeat.
+        let mut followers = Vec::new();
+        for (id, peers) in [("b", ["a", "c", "d", "e"]), ("c", ["a", "b", "d", "e"])] {
+            let (node_tx, node_rx) = mpsc::unbounded_channel();
+            let (state_tx, _) = mpsc::unbounded_channel();
+            let mut log = Log::new(Box::new(log::Test::new()))?;
+            log.append(1, Some(vec![0x01]))?;
+            log.append(1, Some(vec![0x02]))?;
+            log.append(2, Some(vec![0x03]))?;
+            log.append(3, Some(vec![0x04]))?;
+            log.append(3, Some(vec![0x05]))?;
+            log.save_term(3, None)?;
+            let follower = RoleNode {
+                id: id.into(),
+                peers: peer_map(&peers),
+                term: 3,
+                log,
+                node_tx,
+                state_tx,
+                proxied_reqs: HashMap::new(),
+                queued_reqs: Vec::new(),
+                role: Follower::new(Some("a"), None),
+            };
+            followers.push((Some(Node::from(follower)), node_rx));
+        }
+
+        // e campaigns for term 4 every tick. Any new leader needs a vote from b or c, and the
+        // leader must have lost its lease by the time either of them votes.
+        let mut voted = false;
+        for _ in 0..ELECTION_TIMEOUT_MAX {
+            node = node.tick()?;
+            while node_rx.recv().now_or_never().is_some() {}
+            while state_rx.recv().now_or_never().is_some() {}
+            for (follower, follower_rx) in followers.iter_mut() {
+                let mut n = follower.take().unwrap().tick()?;
+                n = n.step(Message {
+                    from: Address::Peer("e".into()),
+                    to: Address::Peers,
+                    term: 4,
+                    event: Event::SolicitVote { last_index: 5, last_term: 3, transfer: false },
+                })?;
+                *follower = Some(n);
+                while let Some(Some(msg)) = follower_rx.recv().now_or_never() {
+                    if msg.event == Event::GrantVote {
+                        assert!(!has_lease(&node), "Vote granted while leader holds lease");
+                        voted = true;
+                    }
+                }
+            }
+        }
+        assert!(voted, "Expected a vote once the leader was considered dead");
+        Ok(())
+    }
+
+    #[test]
+    // Sending a mutate request should append it to log, replicate it to peers, and register
+    // notification.
+    fn step_clientrequest_mutate() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let peers = leader.peers.keys().cloned().collect::<Vec<_>>();
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2).last(6).entry(Entry {
+            index: 6,
+            term: 3,
+            command: Some(vec![0xaf]),
+            membership: None,
+        });
+
+        for peer in peers.iter().cloned() {
+            assert_eq!(
+                node_rx.recv().now_or_never(),
+                Some(Some(Message {
+                    from: Address::Local,
+                    to: Address::Peer(peer),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 5,
+                        base_term: 3,
+                        entries: vec![Entry {
+                            index: 6,
+                            term: 3,
+                            command: Some(vec![0xaf]),
+                            membership: None
+                        },]
+                    },
+                }))
+            )
+        }
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Notify { id: vec![0x01], address: Address::Client, index: 6 }],
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    // With group commit, committed entries are only applied once the log has been flushed.
+    fn flush_group_commit() -> Result<()> {
+        let (node_tx, mut node_rx) = mpsc::unbounded_channel();
+        let (state_tx, mut state_rx) = mpsc::unbounded_channel();
+        let dir = tempdir::TempDir::new("toydb")?;
+        let store =
+            log::Hybrid::new(dir.as_ref(), true)?.with_sync_delay(Duration::from_secs(3600));
+        let mut node: Node = RoleNode {
+            id: "a".into(),
+            peers: peer_map(&[]),
+            term: 1,
+            role: Leader::new(vec![], 0),
+            log: Log::new(Box::new(store))?,
+            node_tx,
+            state_tx,
+            proxied_reqs: HashMap::new(),
+            queued_reqs: Vec::new(),
+        }
+        .into();
+        assert_eq!(node.flush_deadline(), None);
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_node(&node).is_leader().committed(1);
+        assert!(node.flush_deadline().is_some());
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Notify { id: vec![0x01], address: Address::Client, index: 1 }],
+        );
+
+        node = node.flush()?;
+        assert_node(&node).is_leader().committed(1);
+        assert_eq!(node.flush_deadline(), None);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Apply {
+                entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
+            }],
+        );
+        Ok(())
+    }
+
+    // Sends a membership change request to the node, returning any error response.
+    fn change_membership(
+        node: Node,
+        node_rx: &mut mpsc::UnboundedReceiver<Message>,
+        change: MembershipChange,
+    ) -> Result<(Node, Option<Error>)> {
+        let node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![0x01],
+                request: Request::ChangeMembership(change),
+            },
+        })?;
+        let error = match node_rx.recv().now_or_never() {
+            Some(Some(Message {
+                to: Address::Client,
+                event: Event::ClientResponse { response: Err(error), .. },
+                ..
+            })) => Some(error),
+            Some(msg) => panic!("Unexpected message {:?}", msg),
+            None => None,
+        };
+        Ok((node, error))
+    }
+
+    #[test]
+    // Membership changes are validated, appended and replicated, and applied once committed.
+    fn step_clientrequest_changemembership() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        // Changes are rejected until the leader has committed an entry in its term.
+        let (n, error) =
+            change_membership(node, &mut node_rx, MembershipChange::RemoveNode { id: "e".into() })?;
+        node = n;
+        assert_eq!(
+            error,
+            Some(Error::Value("Leader has not yet committed an entry in its term".into()))
+        );
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 5 },
+            })?;
+        }
+        assert_node(&node).committed(5);
+        while state_rx.recv().now_or_never().is_some() {}
+
+        // Invalid changes are rejected.
+        for (invalid, message) in vec![
+            (
+                MembershipChange::AddNode { id: "c".into(), addr: "c:9705".into() },
+                "Node c is already a cluster member",
+            ),
+            (
+                MembershipChange::RemoveNode { id: "a".into() },
+                "Cannot remove leader a from cluster",
+            ),
+            (MembershipChange::RemoveNode { id: "x".into() }, "Node x is not a cluster member"),
+        ] {
+            let (n, error) = change_membership(node, &mut node_rx, invalid)?;
+            node = n;
+            assert_eq!(error, Some(Error::Value(message.into())));
+        }
+
+        // A valid change is appended and replicated, but not applied until committed.
+        let add = MembershipChange::AddNode { id: "f".into(), addr: "f:9705".into() };
+        let entry = Entry { index: 6, term: 3, command: None, membership: Some(add.clone()) };
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::ChangeMembership(add) },
+        })?;
+        assert_node(&node)
+            .is_leader()
+            .committed(5)
+            .last(6)
+            .entry(entry.clone())
+            .peers(peer_map(&["b", "c", "d", "e"]));
+        for peer in vec!["b", "c", "d", "e"] {
+            assert_eq!(
+                node_rx.recv().now_or_never(),
+                Some(Some(Message {
+                    from: Address::Local,
+                    to: Address::Peer(peer.into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index: 5,
+                        base_term: 3,
+                        entries: vec![entry.clone()],
+                    },
+                }))
+            )
+        }
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Notify { id: vec![0x01], address: Address::Client, index: 6 }],
+        );
+
+        // Only one change can be in progress at a time.
+        let (n, error) =
+            change_membership(node, &mut node_rx, MembershipChange::RemoveNode { id: "b".into() })?;
+        node = n;
+        assert_eq!(error, Some(Error::Value("A membership change is already in progress".into())));
+
+        // Once committed, the change is applied and the new peer is replicated to.
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            })?;
+        }
+        assert_node(&node).is_leader().committed(6).peers(peer_map(&["b", "c", "d", "e", "f"]));
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("f".into()),
+                term: 3,
+                event: Event::ReplicateEntries { base_index: 6, base_term: 3, entries: vec![] },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![Instruction::Apply { entry }]);
+        Ok(())
+    }
+
+    #[test]
+    // A leader that commits its own removal, appended by a previous leader, steps down.
+    fn step_acceptentries_removed() -> Result<()> {
+        let (mut leader, _, mut state_rx) = setup()?;
+        leader.log.append_membership(3, MembershipChange::RemoveNode { id: "a".into() })?;
+        let mut node: Node = leader.into();
+
+        for peer in vec!["b", "c"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 6 },
+            })?;
+        }
+        assert_node(&node).is_follower().term(3).leader(None).committed(6).peers(BTreeMap::new());
+        assert_messages(
+            &mut state_rx,
+            vec![
+                Instruction::Apply {
+                    entry: Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                },
+                Instruction::Apply {
+                    entry: Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                },
+                Instruction::Apply {
+                    entry: Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                },
+                Instruction::Apply {
+                    entry: Entry {
+                        index: 6,
+                        term: 3,
+                        command: None,
+                        membership: Some(MembershipChange::RemoveNode { id: "a".into() }),
+                    },
+                },
+                Instruction::Abort,
+            ],
+        );
+        Ok(())
+    }
+
+    #[test]
+    // Leadership transfers catch up the target, hold back writes, and hand off via TimeoutNow.
+    fn step_clientrequest_transferleadership() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        let transfer = |id: u8, target: &str| Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![id],
+                request: Request::TransferLeadership(target.into()),
+            },
+        };
+
+        // Transfers to unknown nodes are rejected, and transfers to ourself are noops.
+        node = node.step(transfer(0x01, "x"))?;
+        node = node.step(transfer(0x01, "a"))?;
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Err(Error::Value("Node x is not a cluster member".into())),
+                    },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::State(vec![])),
+                    },
+                },
+            ],
+        );
+
+        // Starting a transfer replicates to the target, and rejects other transfers.
+        node = node.step(transfer(0x02, "b"))?;
+        node = node.step(transfer(0x03, "c"))?;
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::ReplicateEntries { base_index: 5, base_term: 3, entries: vec![] },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse {
+                        id: vec![0x03],
+                        response: Err(Error::Value(
+                            "Leadership transfer to b is already in progress".into(),
+                        )),
+                    },
+                },
+            ],
+        );
+
+        // Writes are held back during the transfer.
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x04], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        assert_node(&node).is_leader().last(5);
+        assert_messages(&mut node_rx, vec![]);
+
+        // Once the target has caught up, the client is responded to and the target told to
+        // campaign.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptEntries { last_index: 5 },
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 3,
+                    event: Event::ClientResponse {
+                        id: vec![0x02],
+                        response: Ok(Response::State(vec![])),
+                    },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 3,
+                    event: Event::TimeoutNow,
+                },
+            ],
+        );
+
+        // When the target solicits votes, we step down and forward the held back writes to it.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peers,
+            term: 4,
+            event: Event::SolicitVote { last_index: 5, last_term: 3, transfer: true },
+        })?;
+        assert_node(&node).is_follower().term(4).last(5);
+        assert_messages(
+            &mut node_rx,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 0,
+                    event: Event::ClientRequest {
+                        id: vec![0x04],
+                        request: Request::Mutate(vec![0xaf]),
+                    },
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Peer("b".into()),
+                    term: 4,
+                    event: Event::GrantVote,
+                },
+            ],
+        );
+        assert_messages(&mut state_rx, vec![Instruction::Abort]);
+        Ok(())
+    }
+
+    #[test]
+    // Leadership transfers time out after an election timeout, processing held back writes.
+    fn tick_transfer_timeout() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest {
+                id: vec![0x01],
+                request: Request::TransferLeadership("b".into()),
+            },
+        })?;
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x02], request: Request::Mutate(vec![0xaf]) },
+        })?;
+        for _ in 1..ELECTION_TIMEOUT_MAX {
+            node = node.tick()?;
+        }
+        assert_node(&node).is_leader().last(5);
+        while node_rx.recv().now_or_never().is_some() {}
+
+        // Keep in contact with a quorum, to avoid stepping down.
+        for peer in vec!["c", "d"] {
+            node = node.step(Message {
+                from: Address::Peer(peer.into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader { commit_index: 2, has_committed: true, sent_tick: 0 },
+            })?;
+        }
+        while state_rx.recv().now_or_never().is_some() {}
+
+        node = node.tick()?;
+        assert_node(&node).is_leader().last(6);
+        let mut msgs = vec![
+            Message {
+                from: Address::Local,
+                to: Address::Peers,
+                term: 3,
+                event: Event::Heartbeat {
+                    commit_index: 2,
+                    commit_term: 1,
+                    sent_tick: ELECTION_TIMEOUT_MAX,
+                },
+            },
+            Message {
+                from: Address::Local,
+                to: Address::Client,
+                term: 3,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Err(Error::Value("Leadership transfer to b timed out".into())),
+                },
+            },
+        ];
+        // The transfer target still has an unacknowledged probe in flight, so it must wait.
+        for peer in vec!["c", "d", "e"] {
+            msgs.push(Message {
+                from: Address::Local,
+                to: Address::Peer(peer.into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 5,
+                    base_term: 3,
+                    entries: vec![Entry {
+                        index: 6,
+                        term: 3,
+                        command: Some(vec![0xaf]),
+                        membership: None,
+                    }],
+                },
+            });
+        }
+        assert_messages(&mut node_rx, msgs);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Notify { id: vec![0x02], address: Address::Client, index: 6 }],
+        );
+        Ok(())
+    }
+
+    #[test]
+    // Sending a status request should pass it on to state machine, to add status.
+    fn step_clientrequest_status() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        node = node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest { id: vec![0x01], request: Request::Status },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2).last(5);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Status {
+                id: vec![0x01],
+                address: Address::Client,
+                status: Box::new(Status {
+                    server: "a".into(),
+                    leader: "a".into(),
+                    term: 3,
+                    node_last_index: vec![
+                        ("a".into(), 5),
+                        ("b".into(), 0),
+                        ("c".into(), 0),
                         ("d".into(), 0),
                         ("e".into(), 0),
                     ]
@@ -721,7 +2135,8 @@ mod tests {
                     commit_index: 2,
                     apply_index: 0,
                     storage: "test".into(),
-                    storage_size: 130,
+                    storage_size: 135,
+                    storage_sync: Default::default(),
                 }),
             }],
         );
@@ -729,11 +2144,167 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Snapshots compact the log, and are sent in chunks to peers that need compacted entries,
+    // along with our peers and our own address.
+    fn step_snapshot() -> Result<()> {
+        let (mut leader, mut node_rx, mut state_rx) = setup()?;
+        leader.role.peer_next_index.insert("b".into(), 2);
+        leader.log.save_membership(&Membership {
+            index: 0,
+            peers: leader.peers.clone(),
+            removed: false,
+            addr: Some("a:9705".into()),
+        })?;
+        let mut node: Node = leader.into();
+        let data = vec![0xaf; SNAPSHOT_CHUNK_SIZE + 1];
+
+        node = node.step(Message {
+            from: Address::Local,
+            to: Address::Local,
+            term: 0,
+            event: Event::Snapshot { index: 2, data: data.clone() },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2).last(5).snapshot(2);
+        assert_messages(&mut node_rx, vec![]);
+
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::ConfirmLeader { commit_index: 2, has_committed: false, sent_tick: 0 },
+        })?;
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::InstallSnapshot {
+                    index: 2,
+                    term: 1,
+                    offset: 0,
+                    data: data[..SNAPSHOT_CHUNK_SIZE].to_vec(),
+                    done: false,
+                    peers: peer_map(&["a", "b", "c", "d", "e"]),
+                },
+            }],
+        );
+        assert_messages(
+            &mut state_rx,
+            vec![Instruction::Vote { term: 3, index: 2, address: Address::Peer("b".into()) }],
+        );
+
+        // Acknowledging a chunk sends the next one, but duplicate acknowledgements are ignored.
+        for _ in 0..2 {
+            node = node.step(Message {
+                from: Address::Peer("b".into()),
+                to: Address::Peer("a".into()),
+                term: 3,
+                event: Event::AcceptSnapshot { index: 2, offset: SNAPSHOT_CHUNK_SIZE as u64 },
+            })?;
+        }
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::InstallSnapshot {
+                    index: 2,
+                    term: 1,
+                    offset: SNAPSHOT_CHUNK_SIZE as u64,
+                    data: vec![0xaf],
+                    done: true,
+                    peers: peer_map(&["a", "b", "c", "d", "e"]),
+                },
+            }],
+        );
+
+        // Once the snapshot is installed, we replicate the remaining entries.
+        node = node.step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peer("a".into()),
+            term: 3,
+            event: Event::AcceptSnapshot { index: 2, offset: data.len() as u64 },
+        })?;
+        assert_node(&node).is_leader().term(3).committed(2).snapshot(2);
+        assert_messages(
+            &mut node_rx,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("b".into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 2,
+                    base_term: 1,
+                    entries: vec![
+                        Entry { index: 3, term: 2, command: Some(vec![0x03]), membership: None },
+                        Entry { index: 4, term: 3, command: Some(vec![0x04]), membership: None },
+                        Entry { index: 5, term: 3, command: Some(vec![0x05]), membership: None },
+                    ],
+                },
+            }],
+        );
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // Pre-votes for future terms are ignored, and don't make the leader step down.
+    fn step_solicitprevote() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let node = Node::Leader(leader).step(Message {
+            from: Address::Peer("b".into()),
+            to: Address::Peers,
+            term: 4,
+            event: Event::SolicitPreVote { last_index: 5, last_term: 3 },
+        })?;
+        assert_node(&node).is_leader().term(3);
+        assert_messages(&mut node_rx, vec![]);
+        assert_messages(&mut state_rx, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    // The leader steps down if it hasn't heard from a quorum within an election timeout.
+    fn tick_checkquorum() -> Result<()> {
+        let (leader, mut node_rx, mut state_rx) = setup()?;
+        let mut node: Node = leader.into();
+
+        for peers in vec![vec!["b", "c"], vec!["d", "e"], vec!["c"]] {
+            for peer in peers {
+                node = node.step(Message {
+                    from: Address::Peer(peer.into()),
+                    to: Address::Peer("a".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        sent_tick: 0,
+                    },
+                })?;
+            }
+            for _ in 0..ELECTION_TIMEOUT_MAX {
+                assert_node(&node).is_leader().term(3);
+                node = node.tick()?;
+            }
+        }
+        assert_node(&node).is_follower().term(3).leader(None).voted_for(Some("a"));
+        while node_rx.recv().now_or_never().is_some() {}
+        let mut last = None;
+        while let Some(Some(instruction)) = state_rx.recv().now_or_never() {
+            last = Some(instruction);
+        }
+        assert_eq!(last, Some(Instruction::Abort));
+        Ok(())
+    }
+
     #[test]
     fn tick() -> Result<()> {
         let (leader, mut node_rx, mut state_rx) = setup()?;
         let mut node: Node = leader.into();
-        for _ in 0..5 {
+        for i in 1..=5 {
             for _ in 0..HEARTBEAT_INTERVAL {
                 assert_messages(&mut node_rx, vec![]);
                 assert_messages(&mut state_rx, vec![]);
@@ -747,7 +2318,11 @@ mod tests {
                     from: Address::Local,
                     to: Address::Peers,
                     term: 3,
-                    event: Event::Heartbeat { commit_index: 2, commit_term: 1 },
+                    event: Event::Heartbeat {
+                        commit_index: 2,
+                        commit_term: 1,
+                        sent_tick: i * HEARTBEAT_INTERVAL,
+                    },
                 }))
             );
         }
@@ -2,15 +2,20 @@ mod candidate;
 mod follower;
 mod leader;
 
-use super::{Address, Driver, Event, Instruction, Log, Message, State};
+use super::{
+    Address, Driver, Event, Instruction, Log, Membership, MembershipChange, Message, Snapshot,
+    State,
+};
 use crate::error::{Error, Result};
+use crate::storage::log::SyncStats;
 use candidate::Candidate;
 use follower::Follower;
 use leader::Leader;
 
 use ::log::{debug, info};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
 use tokio::sync::mpsc;
 
 /// The interval between leader heartbeats, in ticks.
@@ -22,6 +27,25 @@ const ELECTION_TIMEOUT_MIN: u64 = 8 * HEARTBEAT_INTERVAL;
 /// The maximum election timeout, in ticks.
 const ELECTION_TIMEOUT_MAX: u64 = 15 * HEARTBEAT_INTERVAL;
 
+/// The maximum clock drift between nodes during a leader lease, in ticks, including message delays.
+const LEASE_DRIFT: u64 = 2 * HEARTBEAT_INTERVAL;
+
+/// The duration of a leader lease, in ticks since sending the latest heartbeat confirmed by a
+/// quorum. Followers ignore pre-votes and votes for ELECTION_TIMEOUT_MIN after hearing from the
+/// leader, except for leadership transfers during which the leader holds no lease, so no other
+/// leader can be elected before then, less any clock drift.
+const LEASE_DURATION: u64 = ELECTION_TIMEOUT_MIN - LEASE_DRIFT;
+
+/// The maximum number of unacknowledged ReplicateEntries messages in flight to a peer.
+const MAX_INFLIGHT: usize = 8;
+
+/// The maximum size of entry commands sent in a single ReplicateEntries message, in bytes. At least
+/// one entry is always sent, regardless of its size.
+const MAX_BATCH_SIZE: usize = 256 * 1024;
+
+/// The maximum size of a snapshot chunk sent to followers, in bytes.
+const SNAPSHOT_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Node status
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -33,10 +57,12 @@ pub struct Status {
     pub apply_index: u64,
     pub storage: String,
     pub storage_size: u64,
+    pub storage_sync: SyncStats,
 }
 
 /// The local Raft node state machine.
 /// 节点类型
+#[allow(clippy::large_enum_variant)]
 pub enum Node {
     Candidate(RoleNode<Candidate>),
     Follower(RoleNode<Follower>),
@@ -44,15 +70,17 @@ pub enum Node {
 }
 
 impl Node {
-    /// Creates a new Raft node, starting as a follower, or leader if no peers.
+    /// Creates a new Raft node, starting as a follower, or leader if no peers. The given peers
+    /// (node ID and Raft address) are only used as the initial cluster membership, and are
+    /// superseded by any membership changes in the log, except for their addresses.
     pub async fn new(
         id: &str,
-        peers: Vec<String>,
-        log: Log,
+        peers: BTreeMap<String, String>,
+        mut log: Log,
         mut state: Box<dyn State>,
         node_tx: mpsc::UnboundedSender<Message>,
     ) -> Result<Self> {
-        let applied_index = state.applied_index();
+        let mut applied_index = state.applied_index();
         // 应用的index 大于commit日志
         if applied_index > log.commit_index {
             return Err(Error::Internal(format!(
@@ -64,16 +92,37 @@ impl Node {
         let (state_tx, state_rx) = mpsc::unbounded_channel();
         // 创建driver
         let mut driver = Driver::new(state_rx, node_tx.clone());
+        if let Some(snapshot) = log.load_snapshot()? {
+            if snapshot.index > applied_index {
+                applied_index = snapshot.index;
+                driver.restore(&mut *state, snapshot)?;
+            }
+        }
         if log.commit_index > applied_index {
             info!("Replaying log entries {} to {}", applied_index + 1, log.commit_index);
             driver.replay(&mut *state, log.scan((applied_index + 1)..=log.commit_index))?;
         };
+        // All committed entries have now been applied.
+        log.take_flushed();
         tokio::spawn(driver.drive(state));
 
+        let membership = match log.load_membership()? {
+            Some(mut membership) => {
+                for (id, addr) in membership.peers.iter_mut() {
+                    if let Some(configured) = peers.get(id) {
+                        *addr = configured.clone();
+                    }
+                }
+                membership
+            }
+            None => Membership { index: 0, peers, removed: false, addr: None },
+        };
+        log.save_membership(&membership)?;
+
         let (term, voted_for) = log.load_term()?;
-        let node = RoleNode {
+        let mut node = RoleNode {
             id: id.to_owned(),
-            peers,
+            peers: membership.peers,
             term,
             log,
             node_tx,
@@ -82,7 +131,24 @@ impl Node {
             proxied_reqs: HashMap::new(),
             role: Follower::new(None, voted_for.as_deref()),
         };
-        if node.peers.is_empty() {
+        // Apply any committed membership changes that weren't applied before shutting down.
+        let mut changes = Vec::new();
+        let mut scan = node.log.scan((membership.index + 1)..=node.log.commit_index);
+        while let Some(entry) = scan.next().transpose()? {
+            if let Some(change) = entry.membership {
+                changes.push((entry.index, change));
+            }
+        }
+        std::mem::drop(scan);
+        let mut removed = membership.removed;
+        for (index, change) in changes {
+            removed |= node.apply_membership(index, change)?;
+        }
+
+        if removed {
+            info!("Removed from cluster, not participating");
+            Ok(node.into())
+        } else if node.peers.is_empty() {
             info!("No peers specified, starting as leader");
             let last_index = node.log.last_index;
             Ok(node.become_role(Leader::new(vec![], last_index))?.into())
@@ -100,6 +166,15 @@ impl Node {
         }
     }
 
+    /// Returns the node's current peers, as node ID and Raft address.
+    pub fn peers(&self) -> &BTreeMap<String, String> {
+        match self {
+            Node::Candidate(n) => &n.peers,
+            Node::Follower(n) => &n.peers,
+            Node::Leader(n) => &n.peers,
+        }
+    }
+
     /// Processes a message.
     pub fn step(self, msg: Message) -> Result<Self> {
         debug!("Stepping {:?}", msg);
@@ -120,6 +195,32 @@ impl Node {
             Node::Leader(n) => n.tick(),
         }
     }
+
+    /// Flushes commits buffered by the log for group commit, and applies them.
+    pub fn flush(self) -> Result<Self> {
+        match self {
+            Node::Candidate(mut n) => {
+                n.log.flush()?;
+                n.apply_committed()?;
+                Ok(n.into())
+            }
+            Node::Follower(mut n) => {
+                n.log.flush()?;
+                n.apply_committed()?;
+                Ok(n.into())
+            }
+            Node::Leader(n) => n.flush(),
+        }
+    }
+
+    /// Returns the time by which the log must be flushed, if it has buffered commits.
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        match self {
+            Node::Candidate(n) => n.log.flush_deadline(),
+            Node::Follower(n) => n.log.flush_deadline(),
+            Node::Leader(n) => n.log.flush_deadline(),
+        }
+    }
 }
 
 impl From<RoleNode<Candidate>> for Node {
@@ -146,7 +247,8 @@ impl From<RoleNode<Leader>> for Node {
 pub struct RoleNode<R> {
     id: String,
     // 对方的节点
-    peers: Vec<String>,
+    /// The node's peers, as node ID and Raft address.
+    peers: BTreeMap<String, String>,
     term: u64,
     log: Log,
     node_tx: mpsc::UnboundedSender<Message>,
@@ -204,15 +306,112 @@ impl<R> RoleNode<R> {
         Ok(())
     }
 
+    /// Compacts the log using a state machine snapshot taken at the given applied index. Stale
+    /// snapshots are ignored.
+    fn compact(&mut self, index: u64, data: Vec<u8>) -> Result<()> {
+        if let Some(term) = self.log.term(index)? {
+            self.log.compact(Snapshot { index, term, data })?;
+        }
+        Ok(())
+    }
+
+    /// Sends committed entries that have been flushed to durable storage to the state machine,
+    /// and applies any membership changes among them. Returns true if the local node was removed
+    /// from the cluster. Entries are only applied once flushed, such that the state machine can't
+    /// get ahead of the log if commits are lost in a crash.
+    fn apply_committed(&mut self) -> Result<bool> {
+        let range = match self.log.take_flushed() {
+            Some(range) => range,
+            None => return Ok(false),
+        };
+        let mut changes = Vec::new();
+        let mut scan = self.log.scan(range);
+        while let Some(entry) = scan.next().transpose()? {
+            if let Some(change) = &entry.membership {
+                changes.push((entry.index, change.clone()));
+            }
+            self.state_tx.send(Instruction::Apply { entry })?;
+        }
+        std::mem::drop(scan);
+
+        let mut removed = false;
+        for (index, change) in changes {
+            removed |= self.apply_membership(index, change)?;
+        }
+        Ok(removed)
+    }
+
+    /// Applies a committed membership change to the node's peers, and saves the new membership.
+    /// Changes that have already been applied are ignored, as are any changes once the local node
+    /// has been removed. Returns true if the change removed the local node from the cluster.
+    fn apply_membership(&mut self, index: u64, change: MembershipChange) -> Result<bool> {
+        let mut membership = match self.log.load_membership()? {
+            Some(membership) => membership,
+            None => Membership { index: 0, peers: self.peers.clone(), removed: false, addr: None },
+        };
+        if index <= membership.index || membership.removed {
+            return Ok(false);
+        }
+        match change {
+            MembershipChange::AddNode { id, addr } if id != self.id => {
+                info!("Adding node {} at {} to cluster", id, addr);
+                membership.peers.insert(id, addr);
+            }
+            MembershipChange::AddNode { addr, .. } => membership.addr = Some(addr),
+            MembershipChange::RemoveNode { id } if id == self.id => {
+                info!("Removed from cluster");
+                membership.peers.clear();
+                membership.removed = true;
+            }
+            MembershipChange::RemoveNode { id } => {
+                info!("Removing node {} from cluster", id);
+                membership.peers.remove(&id);
+            }
+        }
+        membership.index = index;
+        self.log.save_membership(&membership)?;
+        self.peers = membership.peers;
+        Ok(membership.removed)
+    }
+
     /// Returns the quorum size of the cluster.
     fn quorum(&self) -> u64 {
         (self.peers.len() as u64 + 1) / 2 + 1
     }
 
+    /// Checks whether a candidate's log, given by its last index and term, is at least as
+    /// up-to-date as ours, which is required to grant it a vote.
+    fn is_log_up_to_date(&self, last_index: u64, last_term: u64) -> bool {
+        last_term > self.log.last_term
+            || (last_term == self.log.last_term && last_index >= self.log.last_index)
+    }
+
+    /// Responds to a pre-vote solicitation for the given prospective term, granting it if the term
+    /// is ahead of ours and the candidate's log is up-to-date. Unlike votes, pre-votes are not
+    /// recorded, and don't change our term.
+    fn respond_prevote(
+        &self,
+        from: Address,
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    ) -> Result<()> {
+        if term > self.term && self.is_log_up_to_date(last_index, last_term) {
+            debug!("Granting pre-vote to {:?} for term {}", from, term);
+            self.send_with_term(term, from, Event::GrantPreVote)?;
+        }
+        Ok(())
+    }
+
     /// Sends an event
     fn send(&self, to: Address, event: Event) -> Result<()> {
+        self.send_with_term(self.term, to, event)
+    }
+
+    /// Sends an event with the given term, e.g. a pre-vote for a prospective term.
+    fn send_with_term(&self, term: u64, to: Address, event: Event) -> Result<()> {
         // 构建消息
-        let msg = Message { term: self.term, from: Address::Local, to, event };
+        let msg = Message { term, from: Address::Local, to, event };
         debug!("Sending {:?}", msg);
         // 发送消息
         Ok(self.node_tx.send(msg)?)
@@ -222,16 +421,22 @@ impl<R> RoleNode<R> {
     fn validate(&self, msg: &Message) -> Result<()> {
         match msg.from {
             Address::Peers => return Err(Error::Internal("Message from broadcast address".into())),
-            Address::Local => return Err(Error::Internal("Message from local node".into())),
+            Address::Local if !matches!(msg.event, Event::Snapshot { .. }) => {
+                return Err(Error::Internal("Message from local node".into()));
+            }
             Address::Client if !matches!(msg.event, Event::ClientRequest { .. }) => {
                 return Err(Error::Internal("Non-request message from client".into()));
             }
             _ => {}
         }
 
-        // Allowing requests and responses form past terms is fine, since they don't rely on it
+        // Allowing requests and responses form past terms is fine, since they don't rely on it.
+        // Local snapshots don't have a term.
         if msg.term < self.term
-            && !matches!(msg.event, Event::ClientRequest { .. } | Event::ClientResponse { .. })
+            && !matches!(
+                msg.event,
+                Event::ClientRequest { .. } | Event::ClientResponse { .. } | Event::Snapshot { .. }
+            )
         {
             return Err(Error::Internal(format!("Message from past term {}", msg.term)));
         }
@@ -350,6 +555,11 @@ mod tests {
             self
         }
 
+        pub fn peers(self, peers: BTreeMap<String, String>) -> Self {
+            assert_eq!(&peers, self.node.peers(), "Unexpected peers");
+            self
+        }
+
         pub fn proxied(self, proxied: Vec<(Vec<u8>, Address)>) -> Self {
             assert_eq!(
                 &proxied.into_iter().collect::<HashMap<Vec<u8>, Address>>(),
@@ -374,6 +584,11 @@ mod tests {
             self
         }
 
+        pub fn snapshot(self, index: u64) -> Self {
+            assert_eq!(index, self.log().snapshot_index, "Unexpected snapshot index");
+            self
+        }
+
         pub fn term(self, term: u64) -> Self {
             assert_eq!(
                 term,
@@ -418,12 +633,17 @@ mod tests {
         NodeAsserter::new(node)
     }
 
+    /// Builds a peer map for the given node IDs, with dummy addresses.
+    pub fn peer_map(ids: &[&str]) -> BTreeMap<String, String> {
+        ids.iter().map(|id| (id.to_string(), format!("{}:9705", id))).collect()
+    }
+
     fn setup_rolenode() -> Result<(RoleNode<()>, mpsc::UnboundedReceiver<Message>)> {
-        setup_rolenode_peers(vec!["b".into(), "c".into()])
+        setup_rolenode_peers(peer_map(&["b", "c"]))
     }
 
     fn setup_rolenode_peers(
-        peers: Vec<String>,
+        peers: BTreeMap<String, String>,
     ) -> Result<(RoleNode<()>, mpsc::UnboundedReceiver<Message>)> {
         let (node_tx, node_rx) = mpsc::unbounded_channel();
         let (state_tx, _) = mpsc::unbounded_channel();
@@ -446,7 +666,7 @@ mod tests {
         let (node_tx, _) = mpsc::unbounded_channel();
         let node = Node::new(
             "a",
-            vec!["b".into(), "c".into()],
+            peer_map(&["b", "c"]),
             Log::new(Box::new(log::Test::new()))?,
             Box::new(TestState::new(0)),
             node_tx,
@@ -456,7 +676,7 @@ mod tests {
             Node::Follower(rolenode) => {
                 assert_eq!(rolenode.id, "a".to_owned());
                 assert_eq!(rolenode.term, 0);
-                assert_eq!(rolenode.peers, vec!["b".to_owned(), "c".to_owned()]);
+                assert_eq!(rolenode.peers, peer_map(&["b", "c"]));
             }
             _ => panic!("Expected node to start as follower"),
         }
@@ -470,7 +690,7 @@ mod tests {
         Log::new(store.clone())?.save_term(3, Some("c"))?;
         let node = Node::new(
             "a",
-            vec!["b".into(), "c".into()],
+            peer_map(&["b", "c"]),
             Log::new(store)?,
             Box::new(TestState::new(0)),
             node_tx,
@@ -494,7 +714,7 @@ mod tests {
         log.append(2, Some(vec![0x03]))?;
         let state = Box::new(TestState::new(0));
 
-        Node::new("a", vec!["b".into(), "c".into()], log, state.clone(), node_tx).await?;
+        Node::new("a", peer_map(&["b", "c"]), log, state.clone(), node_tx).await?;
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         assert_eq!(state.list(), vec![vec![0x01], vec![0x02]]);
         assert_eq!(state.applied_index(), 3);
@@ -512,13 +732,35 @@ mod tests {
         log.append(2, Some(vec![0x03]))?;
         let state = Box::new(TestState::new(2));
 
-        Node::new("a", vec!["b".into(), "c".into()], log, state.clone(), node_tx).await?;
+        Node::new("a", peer_map(&["b", "c"]), log, state.clone(), node_tx).await?;
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         assert_eq!(state.list(), vec![vec![0x02]]);
         assert_eq!(state.applied_index(), 3);
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn new_state_restore_snapshot() -> Result<()> {
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let mut log = Log::new(Box::new(log::Test::new()))?;
+        log.append(1, Some(vec![0x01]))?;
+        log.append(2, None)?;
+        log.append(2, Some(vec![0x02]))?;
+        log.commit(3)?;
+        log.compact(Snapshot {
+            index: 2,
+            term: 2,
+            data: bincode::serialize(&vec![vec![0xaf_u8]])?,
+        })?;
+        let state = Box::new(TestState::new(0));
+
+        Node::new("a", peer_map(&["b", "c"]), log, state.clone(), node_tx).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(state.list(), vec![vec![0xaf], vec![0x02]]);
+        assert_eq!(state.applied_index(), 3);
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn new_state_apply_missing() -> Result<()> {
         let (node_tx, _) = mpsc::unbounded_channel();
@@ -531,7 +773,7 @@ mod tests {
         let state = Box::new(TestState::new(4));
 
         assert_eq!(
-            Node::new("a", vec!["b".into(), "c".into()], log, state.clone(), node_tx).await.err(),
+            Node::new("a", peer_map(&["b", "c"]), log, state.clone(), node_tx).await.err(),
             Some(Error::Internal(
                 "State machine applied index 4 greater than log committed index 3".into()
             ))
@@ -539,12 +781,45 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn new_membership() -> Result<()> {
+        // The saved membership takes precedence over the given peers, except for their addresses,
+        // and committed membership changes are applied.
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let mut log = Log::new(Box::new(log::Test::new()))?;
+        log.save_membership(&Membership {
+            index: 0,
+            peers: peer_map(&["b", "x"]),
+            removed: false,
+            addr: None,
+        })?;
+        log.append_membership(1, MembershipChange::RemoveNode { id: "x".into() })?;
+        log.append_membership(1, MembershipChange::AddNode { id: "d".into(), addr: "d:1".into() })?;
+        log.commit(1)?;
+        let mut peers = peer_map(&["b", "c"]);
+        peers.insert("b".into(), "b:1".into());
+        let node = Node::new("a", peers, log, Box::new(TestState::new(0)), node_tx).await?;
+        let mut expect = BTreeMap::new();
+        expect.insert("b".to_string(), "b:1".to_string());
+        assert_node(&node).is_follower().peers(expect);
+
+        // A removed node starts as a follower, rather than as a single-node leader.
+        let (node_tx, _) = mpsc::unbounded_channel();
+        let mut log = Log::new(Box::new(log::Test::new()))?;
+        log.append_membership(1, MembershipChange::RemoveNode { id: "a".into() })?;
+        log.commit(1)?;
+        let node =
+            Node::new("a", BTreeMap::new(), log, Box::new(TestState::new(0)), node_tx).await?;
+        assert_node(&node).is_follower().peers(BTreeMap::new());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn new_single() -> Result<()> {
         let (node_tx, _) = mpsc::unbounded_channel();
         let node = Node::new(
             "a",
-            vec![],
+            BTreeMap::new(),
             Log::new(Box::new(log::Test::new()))?,
             Box::new(TestState::new(0)),
             node_tx,
@@ -567,7 +842,7 @@ mod tests {
         let new = node.become_role("role")?;
         assert_eq!(new.id, "a".to_owned());
         assert_eq!(new.term, 1);
-        assert_eq!(new.peers, vec!["b".to_owned(), "c".to_owned()]);
+        assert_eq!(new.peers, peer_map(&["b", "c"]));
         assert_eq!(new.role, "role");
         Ok(())
     }
@@ -576,8 +851,8 @@ mod tests {
     fn quorum() -> Result<()> {
         let quorums = vec![(1, 1), (2, 2), (3, 2), (4, 3), (5, 3), (6, 4), (7, 4), (8, 5)];
         for (size, quorum) in quorums.into_iter() {
-            let peers: Vec<String> =
-                (0..(size as u8 - 1)).map(|i| (i as char).to_string()).collect();
+            let peers: BTreeMap<String, String> =
+                (0..(size as u8 - 1)).map(|i| ((i as char).to_string(), String::new())).collect();
             assert_eq!(peers.len(), size as usize - 1);
             let (node, _) = setup_rolenode_peers(peers)?;
             assert_eq!(node.quorum(), quorum);
@@ -588,14 +863,17 @@ mod tests {
     #[test]
     fn send() -> Result<()> {
         let (node, mut rx) = setup_rolenode()?;
-        node.send(Address::Peer("b".into()), Event::Heartbeat { commit_index: 1, commit_term: 1 })?;
+        node.send(
+            Address::Peer("b".into()),
+            Event::Heartbeat { commit_index: 1, commit_term: 1, sent_tick: 0 },
+        )?;
         assert_messages(
             &mut rx,
             vec![Message {
                 from: Address::Local,
                 to: Address::Peer("b".into()),
                 term: 1,
-                event: Event::Heartbeat { commit_index: 1, commit_term: 1 },
+                event: Event::Heartbeat { commit_index: 1, commit_term: 1, sent_tick: 0 },
             }],
         );
         Ok(())
@@ -2,9 +2,10 @@ use super::{Address, Event, Log, Message, Node, Request, Response, State};
 use crate::error::{Error, Result};
 
 use ::log::{debug, error};
+use futures::future::RemoteHandle;
 use futures::{sink::SinkExt as _, FutureExt as _};
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{hash_map, BTreeMap, HashMap};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream, UnboundedReceiverStream};
@@ -13,12 +14,21 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use uuid::Uuid;
 
 /// The duration of a Raft tick, the unit of time for e.g. heartbeats and elections.
-const TICK: Duration = Duration::from_millis(100);
+pub(super) const TICK: Duration = Duration::from_millis(100);
+
+/// An outbound connection to a Raft peer.
+struct PeerSender {
+    /// The peer's Raft address.
+    addr: String,
+    /// Sends messages to the peer's connection task.
+    tx: mpsc::Sender<Message>,
+    /// The peer's connection task, which is cancelled when dropped.
+    _handle: RemoteHandle<()>,
+}
 
 /// A Raft server.
 pub struct Server {
     node: Node,
-    peers: HashMap<String, String>,
     node_rx: mpsc::UnboundedReceiver<Message>,
 }
 
@@ -39,14 +49,13 @@ impl Server {
             node: Node::new(
                 id,
                 // 节点信息
-                peers.iter().map(|(k, _)| k.to_string()).collect(),
+                peers.into_iter().collect(),
                 log,
                 state,
                 // producer channel
                 node_tx,
             )
             .await?,
-            peers,
             node_rx,
         })
     }
@@ -61,6 +70,8 @@ impl Server {
         let (tcp_in_tx, tcp_in_rx) = mpsc::unbounded_channel::<Message>();
         // 输出
         let (tcp_out_tx, tcp_out_rx) = mpsc::unbounded_channel::<Message>();
+        // 成员变更
+        let (peers_tx, peers_rx) = mpsc::unbounded_channel::<BTreeMap<String, String>>();
         // 用来创建tcp listener
         // 是一个future
         // 流量输入
@@ -69,13 +80,14 @@ impl Server {
         // 客户端输出
         tokio::spawn(task);
         let (task, tcp_sender) =
-            Self::tcp_send(self.node.id(), self.peers, tcp_out_rx).remote_handle();
+            Self::tcp_send(self.node.id(), self.node.peers().clone(), tcp_out_rx, peers_rx)
+                .remote_handle();
         tokio::spawn(task);
 
         // 产生来task
         // 事件循环
         let (task, eventloop) =
-            Self::eventloop(self.node, self.node_rx, client_rx, tcp_in_rx, tcp_out_tx)
+            Self::eventloop(self.node, self.node_rx, client_rx, tcp_in_rx, tcp_out_tx, peers_tx)
                 .remote_handle();
         // 可以在另外的一个goroutine中执行
         tokio::spawn(task);
@@ -85,7 +97,7 @@ impl Server {
         Ok(())
     }
 
-    /// Runs the event loop.
+    /// Runs the event loop. Changes to the node's peers are passed on to the TCP sender.
     /// 事件循环
     async fn eventloop(
         mut node: Node,
@@ -93,6 +105,7 @@ impl Server {
         client_rx: mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response>>)>,
         tcp_rx: mpsc::UnboundedReceiver<Message>,
         tcp_tx: mpsc::UnboundedSender<Message>,
+        peers_tx: mpsc::UnboundedSender<BTreeMap<String, String>>,
     ) -> Result<()> {
         let mut node_rx = UnboundedReceiverStream::new(node_rx);
         let mut tcp_rx = UnboundedReceiverStream::new(tcp_rx);
@@ -101,11 +114,21 @@ impl Server {
         // 每个ticker
         let mut ticker = tokio::time::interval(TICK);
         let mut requests = HashMap::<Vec<u8>, oneshot::Sender<Result<Response>>>::new();
+        let mut peers = node.peers().clone();
         loop {
+            if node.peers() != &peers {
+                peers = node.peers().clone();
+                peers_tx.send(peers.clone())?;
+            }
+            let flush_deadline = node.flush_deadline();
             tokio::select! {
                 // 时间到了
                 _ = ticker.tick() => node = node.tick()?,
 
+                // Group commits are flushed once their delay has passed.
+                _ = tokio::time::sleep_until(flush_deadline.unwrap_or_else(Instant::now).into()),
+                    if flush_deadline.is_some() => node = node.flush()?,
+
                 // 收到消息
                 Some(msg) = tcp_rx.next() => node = node.step(msg)?,
 
@@ -114,6 +137,7 @@ impl Server {
                     match msg {
                         Message{to: Address::Peer(_), ..} => tcp_tx.send(msg)?,
                         Message{to: Address::Peers, ..} => tcp_tx.send(msg)?,
+                        Message{to: Address::Local, ..} => node = node.step(msg)?,
                         Message{to: Address::Client, event: Event::ClientResponse{ id, response }, ..} => {
                             if let Some(response_tx) = requests.remove(&id) {
                                 response_tx
@@ -183,49 +207,73 @@ impl Server {
         Ok(())
     }
 
-    /// Sends outbound messages to peers via TCP.
+    /// Sends outbound messages to peers via TCP, connecting to and disconnecting from peers as
+    /// the cluster membership changes.
     async fn tcp_send(
         node_id: String,
-        peers: HashMap<String, String>,
+        peers: BTreeMap<String, String>,
         out_rx: mpsc::UnboundedReceiver<Message>,
+        peers_rx: mpsc::UnboundedReceiver<BTreeMap<String, String>>,
     ) -> Result<()> {
         let mut out_rx = UnboundedReceiverStream::new(out_rx);
-        let mut peer_txs: HashMap<String, mpsc::Sender<Message>> = HashMap::new();
+        let mut peers_rx = UnboundedReceiverStream::new(peers_rx);
+        let mut peer_txs: HashMap<String, PeerSender> = HashMap::new();
+        Self::tcp_connect_peers(peers, &mut peer_txs);
 
-        for (id, addr) in peers.into_iter() {
-            let (tx, rx) = mpsc::channel::<Message>(1000);
-            peer_txs.insert(id, tx);
-            tokio::spawn(Self::tcp_send_peer(addr, rx));
-        }
+        loop {
+            tokio::select! {
+                Some(peers) = peers_rx.next() => Self::tcp_connect_peers(peers, &mut peer_txs),
 
-        while let Some(mut message) = out_rx.next().await {
-            if message.from == Address::Local {
-                message.from = Address::Peer(node_id.clone())
-            }
-            let to = match &message.to {
-                Address::Peers => peer_txs.keys().cloned().collect(),
-                Address::Peer(peer) => vec![peer.to_string()],
-                addr => {
-                    error!("Received outbound message for non-TCP address {:?}", addr);
-                    continue;
-                }
-            };
-            for id in to {
-                match peer_txs.get_mut(&id) {
-                    Some(tx) => match tx.try_send(message.clone()) {
-                        Ok(()) => {}
-                        Err(mpsc::error::TrySendError::Full(_)) => {
-                            debug!("Full send buffer for peer {}, discarding message", id)
+                Some(message) = out_rx.next() => {
+                    let mut message = message;
+                    if message.from == Address::Local {
+                        message.from = Address::Peer(node_id.clone())
+                    }
+                    let to = match &message.to {
+                        Address::Peers => peer_txs.keys().cloned().collect(),
+                        Address::Peer(peer) => vec![peer.to_string()],
+                        addr => {
+                            error!("Received outbound message for non-TCP address {:?}", addr);
+                            continue;
+                        }
+                    };
+                    for id in to {
+                        match peer_txs.get_mut(&id) {
+                            Some(peer) => match peer.tx.try_send(message.clone()) {
+                                Ok(()) => {}
+                                Err(mpsc::error::TrySendError::Full(_)) => {
+                                    debug!("Full send buffer for peer {}, discarding message", id)
+                                }
+                                Err(error) => return Err(error.into()),
+                            },
+                            None => error!("Received outbound message for unknown peer {}", id),
                         }
-                        Err(error) => return Err(error.into()),
-                    },
-                    None => error!("Received outbound message for unknown peer {}", id),
+                    }
                 }
+
+                else => break,
             }
         }
         Ok(())
     }
 
+    /// Updates the outbound peer connections to match the given peers. Dropping a peer sender
+    /// cancels its connection task.
+    fn tcp_connect_peers(
+        peers: BTreeMap<String, String>,
+        peer_txs: &mut HashMap<String, PeerSender>,
+    ) {
+        peer_txs.retain(|id, peer| peers.get(id) == Some(&peer.addr));
+        for (id, addr) in peers.into_iter() {
+            if let hash_map::Entry::Vacant(entry) = peer_txs.entry(id) {
+                let (tx, rx) = mpsc::channel::<Message>(1000);
+                let (task, handle) = Self::tcp_send_peer(addr.clone(), rx).remote_handle();
+                tokio::spawn(task);
+                entry.insert(PeerSender { addr, tx, _handle: handle });
+            }
+        }
+    }
+
     /// Sends outbound messages to a peer, continuously reconnecting.
     async fn tcp_send_peer(addr: String, out_rx: mpsc::Receiver<Message>) {
         let mut out_rx = ReceiverStream::new(out_rx);
@@ -1,7 +1,7 @@
-use super::{Address, Entry, Event, Message, Response, Scan, Status};
+use super::{Address, Entry, Event, Message, Response, Scan, Snapshot, Status};
 use crate::error::{Error, Result};
 
-use log::{debug, error};
+use log::{debug, error, info};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -18,8 +18,18 @@ pub trait State: Send {
 
     /// Queries the state machine. All errors are propagated to the caller.
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Takes a snapshot of the state machine, containing all applied state.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Restores the state machine from a snapshot taken at the given applied index, replacing any
+    /// existing state.
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()>;
 }
 
+/// The number of applied log entries between state machine snapshots.
+const SNAPSHOT_INTERVAL: u64 = 1000;
+
 #[derive(Debug, PartialEq)]
 /// A driver instruction.
 pub enum Instruction {
@@ -34,10 +44,14 @@ pub enum Instruction {
     /// 查询指令
     /// Query the state machine when the given term and index has been confirmed by vote.
     Query { id: Vec<u8>, address: Address, command: Vec<u8>, term: u64, index: u64, quorum: u64 },
+    /// Respond with the given read index when it has been confirmed by vote, like a query.
+    ReadIndex { id: Vec<u8>, address: Address, term: u64, index: u64, quorum: u64 },
     /// 给出服务器指令
     /// Extend the given server status and return it to the given address.
     Status { id: Vec<u8>, address: Address, status: Box<Status> },
 
+    /// Restores the state machine from a snapshot, replacing its current state.
+    Restore { snapshot: Snapshot },
     /// Votes for queries at the given term and commit index.
     /// 投票指令
     Vote { term: u64, index: u64, address: Address },
@@ -47,8 +61,10 @@ pub enum Instruction {
 struct Query {
     id: Vec<u8>,
     term: u64,
+    index: u64,
     address: Address,
-    command: Vec<u8>,
+    /// The query command, or None for read index requests.
+    command: Option<Vec<u8>>,
     quorum: u64,
     votes: HashSet<Address>,
 }
@@ -60,6 +76,10 @@ pub struct Driver {
     // 给node节点发送消息
     node_tx: mpsc::UnboundedSender<Message>,
     applied_index: u64,
+    /// The applied index of the last snapshot.
+    snapshot_index: u64,
+    /// The number of applied entries between snapshots.
+    snapshot_interval: u64,
     /// Notify clients when their mutation is applied. <index, (client, id)>
     notify: HashMap<u64, (Address, Vec<u8>)>,
     /// Execute client queries when they receive a quorum. <index, <id, query>>
@@ -76,6 +96,8 @@ impl Driver {
             state_rx: UnboundedReceiverStream::new(state_rx),
             node_tx,
             applied_index: 0,
+            snapshot_index: 0,
+            snapshot_interval: SNAPSHOT_INTERVAL,
             notify: HashMap::new(),
             // 创建btree
             queries: BTreeMap::new(),
@@ -97,6 +119,15 @@ impl Driver {
         Ok(())
     }
 
+    /// Synchronously restores the state machine from a snapshot, for initial sync.
+    pub fn restore(&mut self, state: &mut dyn State, snapshot: Snapshot) -> Result<()> {
+        info!("Restoring state machine snapshot at index {}", snapshot.index);
+        state.restore(snapshot.index, snapshot.data)?;
+        self.applied_index = snapshot.index;
+        self.snapshot_index = snapshot.index;
+        Ok(())
+    }
+
     /// Synchronously (re)plays a set of log entries, for initial sync.
     pub fn replay<'a>(&mut self, state: &mut dyn State, mut scan: Scan<'a>) -> Result<()> {
         while let Some(entry) = scan.next().transpose()? {
@@ -121,7 +152,7 @@ impl Driver {
                 self.query_abort()?;
             }
 
-            Instruction::Apply { entry: Entry { index, command, .. } } => {
+            Instruction::Apply { entry: Entry { index, command, membership, .. } } => {
                 if let Some(command) = command {
                     debug!("Applying state machine command {}: {:?}", index, command);
                     // 用来改变index
@@ -129,6 +160,10 @@ impl Driver {
                         Err(error @ Error::Internal(_)) => return Err(error),
                         result => self.notify_applied(index, result)?,
                     };
+                } else if membership.is_some() {
+                    // Membership changes are applied by the Raft node itself, so we only have to
+                    // notify the client that requested it.
+                    self.notify_applied(index, Ok(vec![]))?;
                 }
                 // We have to track applied_index here, separately from the state machine, because
                 // no-op log entries are significant for whether a query should be executed.
@@ -136,6 +171,7 @@ impl Driver {
                 // Try to execute any pending queries, since they may have been submitted for a
                 // commit_index which hadn't been applied yet.
                 self.query_execute(state)?;
+                self.snapshot(state)?;
             }
 
             Instruction::Notify { id, address, index } => {
@@ -147,10 +183,33 @@ impl Driver {
             }
 
             Instruction::Query { id, address, command, index, term, quorum } => {
-                self.queries.entry(index).or_default().insert(
-                    id.clone(),
-                    Query { id, term, address, command, quorum, votes: HashSet::new() },
-                );
+                self.query_add(Query {
+                    id,
+                    term,
+                    index,
+                    address,
+                    command: Some(command),
+                    quorum,
+                    votes: HashSet::new(),
+                });
+                // Local queries don't need votes, and may be executable right away.
+                self.query_execute(state)?;
+            }
+
+            Instruction::ReadIndex { id, address, index, term, quorum } => {
+                self.query_add(Query {
+                    id,
+                    term,
+                    index,
+                    address,
+                    command: None,
+                    quorum,
+                    votes: HashSet::new(),
+                });
+            }
+
+            Instruction::Restore { snapshot } => {
+                tokio::task::block_in_place(|| self.restore(state, snapshot))?;
             }
 
             Instruction::Status { id, address, mut status } => {
@@ -169,6 +228,18 @@ impl Driver {
         Ok(())
     }
 
+    /// Takes a snapshot of the state machine if enough entries have been applied since the last
+    /// one, and passes it to the local node for log compaction.
+    fn snapshot(&mut self, state: &mut dyn State) -> Result<()> {
+        if self.applied_index < self.snapshot_index + self.snapshot_interval {
+            return Ok(());
+        }
+        debug!("Taking state machine snapshot at index {}", self.applied_index);
+        let data = tokio::task::block_in_place(|| state.snapshot())?;
+        self.snapshot_index = self.applied_index;
+        self.send(Address::Local, Event::Snapshot { index: self.applied_index, data })
+    }
+
     /// Aborts all pending notifications.
     fn notify_abort(&mut self) -> Result<()> {
         for (_, (address, id)) in std::mem::take(&mut self.notify) {
@@ -198,18 +269,26 @@ impl Driver {
         Ok(())
     }
 
+    /// Adds a pending query.
+    fn query_add(&mut self, query: Query) {
+        self.queries.entry(query.index).or_default().insert(query.id.clone(), query);
+    }
+
     /// Executes any queries that are ready.
     fn query_execute(&mut self, state: &mut dyn State) -> Result<()> {
         for query in self.query_ready(self.applied_index) {
-            debug!("Executing query {:?}", query.command);
-            let result = state.query(query.command);
-            if let Err(error @ Error::Internal(_)) = result {
-                return Err(error);
-            }
-            self.send(
-                query.address,
-                Event::ClientResponse { id: query.id, response: result.map(Response::State) },
-            )?
+            let response = match query.command {
+                Some(command) => {
+                    debug!("Executing query {:?}", command);
+                    let result = state.query(command);
+                    if let Err(error @ Error::Internal(_)) = result {
+                        return Err(error);
+                    }
+                    result.map(Response::State)
+                }
+                None => Ok(Response::ReadIndex(query.index)),
+            };
+            self.send(query.address, Event::ClientResponse { id: query.id, response })?
         }
         Ok(())
     }
@@ -261,6 +340,7 @@ impl Driver {
 
 #[cfg(test)]
 pub mod tests {
+    use super::super::MembershipChange;
     use super::*;
     use pretty_assertions::assert_eq;
     use std::sync::{Arc, Mutex};
@@ -301,6 +381,18 @@ pub mod tests {
             self.commands.lock()?.push(command.clone());
             Ok(command)
         }
+
+        // Serializes the internal commands list.
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(bincode::serialize(&*self.commands.lock()?)?)
+        }
+
+        // Replaces the internal commands list.
+        fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+            *self.commands.lock()? = bincode::deserialize(&snapshot)?;
+            *self.applied_index.lock()? = index;
+            Ok(())
+        }
     }
 
     async fn setup() -> Result<(
@@ -369,9 +461,11 @@ pub mod tests {
             index: 2,
             address: Address::Client,
         })?;
-        state_tx.send(Instruction::Apply { entry: Entry { index: 1, term: 1, command: None } })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 2, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: None, membership: None },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: Some(vec![0xaf]), membership: None },
         })?;
         std::mem::drop(state_tx);
 
@@ -394,6 +488,115 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_apply_membership() -> Result<()> {
+        let (state, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::Notify {
+            id: vec![0x01],
+            index: 1,
+            address: Address::Client,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry {
+                index: 1,
+                term: 1,
+                command: None,
+                membership: Some(MembershipChange::RemoveNode { id: "b".into() }),
+            },
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Client,
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Ok(Response::State(vec![]))
+                }
+            }]
+        );
+        assert_eq!(state.list(), Vec::<Vec<u8>>::new());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_snapshot() -> Result<()> {
+        let state = Box::new(TestState::new(0));
+        let (state_tx, state_rx) = mpsc::unbounded_channel();
+        let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let mut driver = Driver::new(state_rx, node_tx);
+        driver.snapshot_interval = 2;
+        tokio::spawn(driver.drive(state.clone()));
+
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: None, membership: None },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 3, term: 1, command: Some(vec![0x03]), membership: None },
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Local,
+                term: 0,
+                event: Event::Snapshot {
+                    index: 2,
+                    data: bincode::serialize(&vec![vec![0x01_u8]])?
+                },
+            }]
+        );
+
+        let mut restored = TestState::new(0);
+        let mut driver = Driver::new(mpsc::unbounded_channel().1, mpsc::unbounded_channel().0);
+        driver.restore(
+            &mut restored,
+            Snapshot { index: 2, term: 1, data: bincode::serialize(&vec![vec![0x01_u8]])? },
+        )?;
+        assert_eq!(restored.list(), vec![vec![0x01]]);
+        assert_eq!(restored.applied_index(), 2);
+        assert_eq!(driver.applied_index, 2);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_restore() -> Result<()> {
+        let (state, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0x01]), membership: None },
+        })?;
+        state_tx.send(Instruction::Restore {
+            snapshot: Snapshot {
+                index: 3,
+                term: 2,
+                data: bincode::serialize(&vec![vec![0x02_u8], vec![0x03_u8]])?,
+            },
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 4, term: 2, command: Some(vec![0x04]), membership: None },
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(node_rx.collect::<Vec<_>>().await, vec![]);
+        assert_eq!(state.list(), vec![vec![0x02], vec![0x03], vec![0x04]]);
+        assert_eq!(state.applied_index(), 4);
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn driver_query() -> Result<()> {
         let (_, state_tx, node_rx) = setup().await?;
@@ -407,7 +610,7 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 2, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 2, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 2, index: 1, address: Address::Local })?;
         state_tx.send(Instruction::Vote {
@@ -448,7 +651,7 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 2, index: 1, address: Address::Local })?;
         state_tx.send(Instruction::Vote {
@@ -476,7 +679,7 @@ pub mod tests {
             quorum: 2,
         })?;
         state_tx.send(Instruction::Apply {
-            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]) },
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
         })?;
         state_tx.send(Instruction::Vote { term: 1, index: 1, address: Address::Local })?;
         std::mem::drop(state_tx);
@@ -486,4 +689,100 @@ pub mod tests {
 
         Ok(())
     }
+
+    // A query with no quorum is executed once the index has been applied, without votes.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_query_local() -> Result<()> {
+        let (_, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::Query {
+            id: vec![0x01],
+            address: Address::Client,
+            command: vec![0xf0],
+            term: 0,
+            index: 2,
+            quorum: 0,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 1, command: Some(vec![0xaf]), membership: None },
+        })?;
+        state_tx.send(Instruction::Query {
+            id: vec![0x02],
+            address: Address::Client,
+            command: vec![0xf1],
+            term: 0,
+            index: 1,
+            quorum: 0,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 2, term: 1, command: Some(vec![0xbf]), membership: None },
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x02],
+                        response: Ok(Response::State(vec![0xf1]))
+                    }
+                },
+                Message {
+                    from: Address::Local,
+                    to: Address::Client,
+                    term: 0,
+                    event: Event::ClientResponse {
+                        id: vec![0x01],
+                        response: Ok(Response::State(vec![0xf0]))
+                    }
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn driver_read_index() -> Result<()> {
+        let (_, state_tx, node_rx) = setup().await?;
+
+        state_tx.send(Instruction::ReadIndex {
+            id: vec![0x01],
+            address: Address::Peer("a".into()),
+            term: 2,
+            index: 1,
+            quorum: 2,
+        })?;
+        state_tx.send(Instruction::Apply {
+            entry: Entry { index: 1, term: 2, command: Some(vec![0xaf]), membership: None },
+        })?;
+        state_tx.send(Instruction::Vote { term: 2, index: 1, address: Address::Local })?;
+        state_tx.send(Instruction::Vote {
+            term: 2,
+            index: 1,
+            address: Address::Peer("a".into()),
+        })?;
+        std::mem::drop(state_tx);
+
+        let node_rx = UnboundedReceiverStream::new(node_rx);
+        assert_eq!(
+            node_rx.collect::<Vec<_>>().await,
+            vec![Message {
+                from: Address::Local,
+                to: Address::Peer("a".into()),
+                term: 0,
+                event: Event::ClientResponse {
+                    id: vec![0x01],
+                    response: Ok(Response::ReadIndex(1))
+                }
+            }]
+        );
+
+        Ok(())
+    }
 }
@@ -7,16 +7,20 @@ use crate::sql::schema::{Catalog as _, Table};
 use crate::sql::types::Row;
 use crate::storage::{kv, log};
 
-use ::log::{error, info};
+use ::log::{debug, error, info};
 use futures::sink::SinkExt as _;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::TcpListenerStream;
 use tokio_stream::StreamExt as _;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+/// The interval between MVCC garbage collection runs.
+const VACUUM_INTERVAL: Duration = Duration::from_secs(60);
+
 /// A toyDB server.
 pub struct Server {
     raft: raft::Server,
@@ -74,17 +78,43 @@ impl Server {
         let (raft_tx, raft_rx) = mpsc::unbounded_channel();
 
         // sql engine
-        let sql_engine = sql::engine::Raft::new(raft::Client::new(raft_tx));
+        let raft_client = raft::Client::new(raft_tx);
+        let sql_engine = sql::engine::Raft::new(raft_client.clone());
 
         tokio::try_join!(
             // 等待futur结束
             self.raft.serve(raft_listener, raft_rx),
             // 执行sql
-            Self::serve_sql(sql_listener, sql_engine),
+            Self::serve_sql(sql_listener, sql_engine.clone()),
+            Self::vacuum(raft_client, sql_engine),
         )?;
         Ok(())
     }
 
+    /// Periodically garbage collects obsolete MVCC data while this node is the Raft leader.
+    /// Errors, e.g. due to leader changes, are logged and retried on the next run.
+    async fn vacuum(client: raft::Client, engine: sql::engine::Raft) -> Result<()> {
+        let mut interval = tokio::time::interval(VACUUM_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let status = match client.status().await {
+                Ok(status) => status,
+                Err(error) => {
+                    error!("Failed to fetch Raft status for vacuum: {}", error);
+                    continue;
+                }
+            };
+            if status.leader != status.server {
+                continue;
+            }
+            match tokio::task::block_in_place(|| engine.vacuum()) {
+                Ok(reclaimed) => debug!("Vacuumed {} bytes of MVCC data", reclaimed),
+                Err(error) => error!("Vacuum failed: {}", error),
+            }
+        }
+    }
+
     /// Serves SQL clients.
     async fn serve_sql(listener: TcpListener, engine: sql::engine::Raft) -> Result<()> {
         let mut listener = TcpListenerStream::new(listener);
@@ -110,6 +140,9 @@ pub enum Request {
     GetTable(String),
     ListTables,
     Status,
+    ChangeMembership(raft::MembershipChange),
+    TransferLeadership(String),
+    Vacuum,
 }
 
 /// A server response.
@@ -120,6 +153,9 @@ pub enum Response {
     GetTable(Table),
     ListTables(Vec<String>),
     Status(sql::engine::Status),
+    ChangeMembership,
+    TransferLeadership,
+    Vacuum(u64),
 }
 
 /// A client session coupled to a SQL session.
@@ -165,9 +201,17 @@ impl Session {
                         .fuse(),
                 );
             }
+            // Rows may be fetched lazily from Raft, and dropping them may roll back an implicit
+            // transaction, so this must be done in blocking sections.
+            let mut rows =
+                std::iter::from_fn(move || tokio::task::block_in_place(|| rows.next())).map(Ok);
             // 发回响应
-            stream.send(response).await?;
-            stream.send_all(&mut tokio_stream::iter(rows.map(Ok))).await?;
+            let result = match stream.send(response).await {
+                Ok(()) => stream.send_all(&mut tokio_stream::iter(&mut rows)).await,
+                Err(err) => Err(err),
+            };
+            tokio::task::block_in_place(|| std::mem::drop(rows));
+            result?;
         }
         Ok(())
     }
@@ -185,6 +229,15 @@ impl Session {
                 })?)
             }
             Request::Status => Response::Status(self.engine.status()?),
+            Request::ChangeMembership(change) => {
+                self.engine.change_membership(change)?;
+                Response::ChangeMembership
+            }
+            Request::TransferLeadership(id) => {
+                self.engine.transfer_leadership(id)?;
+                Response::TransferLeadership
+            }
+            Request::Vacuum => Response::Vacuum(self.engine.vacuum()?),
         })
     }
 }
@@ -1,13 +1,15 @@
-use super::super::schema::{Catalog, Table, Tables};
+use super::super::schema::{Alteration, Catalog, Index, Indexes, Table, Tables};
 use super::super::types::{Expression, Row, Value};
 use super::Transaction as _;
 use crate::error::{Error, Result};
 use crate::storage::kv;
 
 use serde::{Deserialize, Serialize};
+use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::clone::Clone;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
 
 /// A SQL engine based on an underlying MVCC key/value store
 pub struct KV {
@@ -61,6 +63,53 @@ fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
     Ok(bincode::deserialize(bytes)?)
 }
 
+/// A page of scan results, as returned by the paginated scans used by the Raft engine.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Page<T> {
+    /// The items in the page
+    pub items: Vec<T>,
+    /// The key of the last scanned item, if there may be further items. This is used as a
+    /// continuation token, i.e. as the exclusive start (or end, for reverse scans) of the next page.
+    pub next: Option<Vec<u8>>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from a key/value scan, scanning up to limit entries and mapping them to items.
+    /// The mapping can skip entries by returning None, but they still count towards the limit.
+    fn build<F>(mut scan: kv::Scan, reverse: bool, limit: usize, mut f: F) -> Result<Self>
+    where
+        F: FnMut(&[u8], Vec<u8>) -> Result<Option<T>>,
+    {
+        let mut next = || if reverse { scan.next_back() } else { scan.next() };
+        let mut page = Page { items: Vec::new(), next: None };
+        let mut last = None;
+        for _ in 0..limit {
+            match next().transpose()? {
+                Some((key, value)) => {
+                    if let Some(item) = f(&key, value)? {
+                        page.items.push(item);
+                    }
+                    last = Some(key);
+                }
+                None => return Ok(page),
+            }
+        }
+        if next().transpose()?.is_some() {
+            page.next = last;
+        }
+        Ok(page)
+    }
+}
+
+/// Evaluates a filter predicate for a row.
+fn matches(filter: &Expression, row: &Row) -> Result<bool> {
+    match filter.evaluate(Some(row))? {
+        Value::Boolean(b) => Ok(b),
+        Value::Null => Ok(false),
+        v => Err(Error::Value(format!("Filter returned {}, expected boolean", v))),
+    }
+}
+
 /// An SQL transaction based on an MVCC key/value transaction
 pub struct Transaction {
     txn: kv::mvcc::Transaction,
@@ -72,31 +121,263 @@ impl Transaction {
         Self { txn }
     }
 
-    /// Loads an index entry
-    fn index_load(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
+    /// Loads the primary keys of an index entry, by scanning the per-entry keys along with any
+    /// legacy index entry.
+    fn index_load(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Vec<Value>>> {
+        let mut pks = self.index_load_legacy(table, column, value)?;
+        let mut scan = self.txn.scan_prefix(
+            &Key::Index(table.into(), column.into(), Some(value.into()), None).encode(),
+        )?;
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::Index(_, _, _, Some(pk)) => pks.insert(pk.into_owned()),
+                _ => return Err(Error::Internal("Invalid index key".into())),
+            };
+        }
+        Ok(pks)
+    }
+
+    /// Loads a legacy index entry, if any. These predate composite primary keys, so they contain
+    /// single-value primary keys.
+    fn index_load_legacy(
+        &self,
+        table: &str,
+        column: &str,
+        value: &Value,
+    ) -> Result<HashSet<Vec<Value>>> {
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), Some(value.into())).encode())?
-            .map(|v| deserialize(&v))
+            .get(&Key::LegacyIndex(table.into(), column.into(), Some(value.into())).encode())?
+            .map(|v| deserialize::<HashSet<Value>>(&v))
             .transpose()?
-            .unwrap_or_else(HashSet::new))
+            .unwrap_or_else(HashSet::new)
+            .into_iter()
+            .map(|pk| vec![pk])
+            .collect())
     }
 
-    /// Saves an index entry.
-    fn index_save(
+    /// Migrates a legacy index entry to per-entry keys, if it exists. This is done before writing
+    /// to an index entry, so that legacy entries are migrated as they are modified.
+    fn index_migrate(&mut self, table: &str, column: &str, value: &Value) -> Result<()> {
+        let pks = self.index_load_legacy(table, column, value)?;
+        if pks.is_empty() {
+            return Ok(());
+        }
+        for pk in pks {
+            self.txn.set(
+                &Key::Index(table.into(), column.into(), Some(value.into()), Some(pk.into()))
+                    .encode(),
+                vec![],
+            )?;
+        }
+        self.txn.delete(&Key::LegacyIndex(table.into(), column.into(), Some(value.into())).encode())
+    }
+
+    /// Adds a primary key to an index entry.
+    fn index_insert(
         &mut self,
         table: &str,
         column: &str,
         value: &Value,
-        index: HashSet<Value>,
+        pk: &[Value],
     ) -> Result<()> {
-        let key = Key::Index(table.into(), column.into(), Some(value.into())).encode();
-        if index.is_empty() {
-            self.txn.delete(&key)
-        } else {
-            self.txn.set(&key, serialize(&index)?)
+        self.index_migrate(table, column, value)?;
+        self.txn.set(
+            &Key::Index(table.into(), column.into(), Some(value.into()), Some(pk.into())).encode(),
+            vec![],
+        )
+    }
+
+    /// Removes a primary key from an index entry.
+    fn index_remove(
+        &mut self,
+        table: &str,
+        column: &str,
+        value: &Value,
+        pk: &[Value],
+    ) -> Result<()> {
+        self.index_migrate(table, column, value)?;
+        self.txn.delete(
+            &Key::Index(table.into(), column.into(), Some(value.into()), Some(pk.into())).encode(),
+        )
+    }
+
+    /// Reads an index schema, if it exists.
+    fn read_index_schema(&self, index: &str) -> Result<Option<Index>> {
+        self.txn
+            .get(&Key::IndexSchema(Some(index.into())).encode())?
+            .map(|v| deserialize(&v))
+            .transpose()
+    }
+
+    /// Loads the multi-column indexes of a table. Single-column indexes are instead flagged on the
+    /// column itself.
+    fn composite_indexes(&self, table: &str) -> Result<Vec<Index>> {
+        Ok(self.scan_indexes()?.filter(|i| i.table == table && i.columns.len() > 1).collect())
+    }
+
+    /// Returns a row's values for a multi-column index.
+    fn composite_index_values(table: &Table, index: &Index, row: &[Value]) -> Result<Vec<Value>> {
+        index.columns.iter().map(|c| Ok(row[table.get_column_index(c)?].clone())).collect()
+    }
+
+    /// Adds a primary key to a multi-column index entry, enforcing uniqueness if necessary.
+    /// Values containing NULL are never considered equal, like for UNIQUE columns.
+    fn composite_index_insert(
+        &mut self,
+        index: &Index,
+        values: &[Value],
+        pk: &[Value],
+    ) -> Result<()> {
+        if index.unique && !values.contains(&Value::Null) {
+            let mut pks = self.read_index_prefix(&index.name, values)?;
+            pks.remove(pk);
+            if !pks.is_empty() {
+                return Err(Error::Value(format!(
+                    "Unique value {} already exists for index {}",
+                    Value::format_tuple(values),
+                    index.name
+                )));
+            }
+        }
+        self.txn.set(
+            &Key::CompositeIndex((&index.name).into(), Some(values.into()), Some(pk.into()))
+                .encode(),
+            serialize(&pk)?,
+        )
+    }
+
+    /// Removes a primary key from a multi-column index entry.
+    fn composite_index_remove(
+        &mut self,
+        index: &Index,
+        values: &[Value],
+        pk: &[Value],
+    ) -> Result<()> {
+        self.txn.delete(
+            &Key::CompositeIndex((&index.name).into(), Some(values.into()), Some(pk.into()))
+                .encode(),
+        )
+    }
+
+    /// Moves all keys with the given prefix to a new prefix, keeping their values. This is used to
+    /// rename tables and columns, since their names are part of the keys.
+    fn move_prefix(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        let entries = self.txn.scan_prefix(from)?.collect::<Result<Vec<_>>>()?;
+        for (key, value) in entries {
+            self.txn.delete(&key)?;
+            self.txn.set(&[to, &key[from.len()..]].concat(), value)?;
+        }
+        Ok(())
+    }
+
+    /// Moves a column's index entries, including legacy entries, to a new table and column name.
+    fn move_index(
+        &mut self,
+        table: &str,
+        column: &str,
+        to_table: &str,
+        to_column: &str,
+    ) -> Result<()> {
+        self.move_prefix(
+            &Key::Index(table.into(), column.into(), None, None).encode(),
+            &Key::Index(to_table.into(), to_column.into(), None, None).encode(),
+        )?;
+        self.move_prefix(
+            &Key::LegacyIndex(table.into(), column.into(), None).encode(),
+            &Key::LegacyIndex(to_table.into(), to_column.into(), None).encode(),
+        )
+    }
+
+    /// Runs the given mutation atomically, undoing any writes it made if it fails.
+    fn atomic<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        self.txn.savepoint();
+        match f(self) {
+            Ok(()) => {
+                self.txn.release_savepoint();
+                Ok(())
+            }
+            Err(err) => {
+                self.txn.rollback_to_savepoint()?;
+                Err(err)
+            }
         }
     }
+
+    /// Scans a page of a table's rows whose primary key starts with the given prefix, within the
+    /// given key range (as given by a previous page's continuation key). Up to limit rows are
+    /// scanned, in reverse order if requested, and those matching the filter are returned.
+    pub fn scan_page(
+        &self,
+        table: &str,
+        prefix: &[Value],
+        filter: Option<Expression>,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Page<Row>> {
+        let table = self.must_read_table(table)?;
+        let scan = self.txn.scan_prefix_range(
+            &Key::Row((&table.name).into(), Some(prefix.into())).encode(),
+            range,
+        )?;
+        Page::build(scan, reverse, limit, |_, value| {
+            let row: Row = deserialize(&value)?;
+            match &filter {
+                Some(filter) if !matches(filter, &row)? => Ok(None),
+                _ => Ok(Some(row)),
+            }
+        })
+    }
+
+    /// Scans a page of a column's index entries as (value, primary key) pairs, within the given key
+    /// range. Indexes that still have legacy entries are returned in a single page, since these
+    /// must be merged with the per-entry keys.
+    pub fn scan_index_page(
+        &self,
+        table: &str,
+        column: &str,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Page<(Value, Vec<Value>)>> {
+        let table = self.must_read_table(table)?;
+        let column = table.get_column(column)?;
+        if !column.index {
+            return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
+        }
+        if self
+            .txn
+            .scan_prefix(
+                &Key::LegacyIndex((&table.name).into(), (&column.name).into(), None).encode(),
+            )?
+            .next()
+            .is_some()
+        {
+            let mut items = Vec::new();
+            for r in self.scan_index(&table.name, &column.name)? {
+                let (value, pks) = r?;
+                items.extend(pks.into_iter().map(|pk| (value.clone(), pk)));
+            }
+            if reverse {
+                items.reverse();
+            }
+            return Ok(Page { items, next: None });
+        }
+        let scan = self.txn.scan_prefix_range(
+            &Key::Index((&table.name).into(), (&column.name).into(), None, None).encode(),
+            range,
+        )?;
+        Page::build(scan, reverse, limit, |key, _| match Key::decode(key)? {
+            Key::Index(_, _, Some(value), Some(pk)) => {
+                Ok(Some((value.into_owned(), pk.into_owned())))
+            }
+            _ => Err(Error::Internal("Invalid index key".into())),
+        })
+    }
 }
 
 impl super::Transaction for Transaction {
@@ -123,7 +404,8 @@ impl super::Transaction for Transaction {
         if self.read(&table.name, &id)?.is_some() {
             return Err(Error::Value(format!(
                 "Primary key {} already exists for table {}",
-                id, table.name
+                Value::format_tuple(&id),
+                table.name
             )));
         }
         self.txn.set(
@@ -133,14 +415,20 @@ impl super::Transaction for Transaction {
 
         // Update indexes
         for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-            let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-            index.insert(id.clone());
-            self.index_save(&table.name, &column.name, &row[i], index)?;
+            self.index_insert(&table.name, &column.name, &row[i], &id)?;
+        }
+        for index in self.composite_indexes(&table.name)? {
+            let values = Self::composite_index_values(&table, &index, &row)?;
+            self.composite_index_insert(&index, &values, &id)?;
         }
         Ok(())
     }
 
-    fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
+    fn create_rows(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        self.atomic(|txn| rows.into_iter().try_for_each(|row| txn.create(table, row)))
+    }
+
+    fn delete(&mut self, table: &str, id: &[Value]) -> Result<()> {
         let table = self.must_read_table(table)?;
         for (t, cs) in self.table_references(&table.name, true)? {
             let t = self.must_read_table(&t)?;
@@ -151,10 +439,14 @@ impl super::Transaction for Transaction {
             let mut scan = self.scan(&t.name, None)?;
             while let Some(row) = scan.next().transpose()? {
                 for (i, c) in &cs {
-                    if &row[*i] == id && (table.name != t.name || id != &table.get_row_key(&row)?) {
+                    if std::slice::from_ref(&row[*i]) == id
+                        && (table.name != t.name || id != table.get_row_key(&row)?)
+                    {
                         return Err(Error::Value(format!(
                             "Primary key {} is referenced by table {} column {}",
-                            id, t.name, c
+                            Value::format_tuple(id),
+                            t.name,
+                            c
                         )));
                     }
                 }
@@ -162,32 +454,53 @@ impl super::Transaction for Transaction {
         }
 
         let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
-        if !indexes.is_empty() {
+        let composite = self.composite_indexes(&table.name)?;
+        if !indexes.is_empty() || !composite.is_empty() {
             if let Some(row) = self.read(&table.name, id)? {
                 for (i, column) in indexes {
-                    let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-                    index.remove(id);
-                    self.index_save(&table.name, &column.name, &row[i], index)?;
+                    self.index_remove(&table.name, &column.name, &row[i], id)?;
+                }
+                for index in composite {
+                    let values = Self::composite_index_values(&table, &index, &row)?;
+                    self.composite_index_remove(&index, &values, id)?;
                 }
             }
         }
         self.txn.delete(&Key::Row(table.name.into(), Some(id.into())).encode())
     }
 
-    fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
+    fn delete_rows(&mut self, table: &str, ids: Vec<Vec<Value>>) -> Result<()> {
+        self.atomic(|txn| ids.into_iter().try_for_each(|id| txn.delete(table, &id)))
+    }
+
+    fn read(&self, table: &str, id: &[Value]) -> Result<Option<Row>> {
         self.txn
             .get(&Key::Row(table.into(), Some(id.into())).encode())?
             .map(|v| deserialize(&v))
             .transpose()
     }
 
-    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
+    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Vec<Value>>> {
         if !self.must_read_table(table)?.get_column(column)?.index {
             return Err(Error::Value(format!("No index on {}.{}", table, column)));
         }
         self.index_load(table, column, value)
     }
 
+    fn read_index_prefix(&self, index: &str, prefix: &[Value]) -> Result<HashSet<Vec<Value>>> {
+        match self.read_index_schema(index)? {
+            Some(i) if i.columns.len() > 1 => {}
+            Some(_) => {
+                return Err(Error::Value(format!("Index {} is not a multi-column index", index)))
+            }
+            None => return Err(Error::Value(format!("Index {} does not exist", index))),
+        }
+        self.txn
+            .scan_prefix(&Key::CompositeIndex(index.into(), Some(prefix.into()), None).encode())?
+            .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+            .collect()
+    }
+
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<super::Scan> {
         let table = self.must_read_table(table)?;
         Ok(Box::new(
@@ -196,13 +509,9 @@ impl super::Transaction for Transaction {
                 .map(|r| r.and_then(|(_, v)| deserialize(&v)))
                 .filter_map(move |r| match r {
                     Ok(row) => match &filter {
-                        Some(filter) => match filter.evaluate(Some(&row)) {
-                            Ok(Value::Boolean(b)) if b => Some(Ok(row)),
-                            Ok(Value::Boolean(_)) | Ok(Value::Null) => None,
-                            Ok(v) => Some(Err(Error::Value(format!(
-                                "Filter returned {}, expected boolean",
-                                v
-                            )))),
+                        Some(filter) => match matches(filter, &row) {
+                            Ok(true) => Some(Ok(row)),
+                            Ok(false) => None,
                             Err(err) => Some(Err(err)),
                         },
                         None => Some(Ok(row)),
@@ -212,32 +521,75 @@ impl super::Transaction for Transaction {
         ))
     }
 
+    fn scan_prefix(&self, table: &str, prefix: &[Value]) -> Result<super::Scan> {
+        let table = self.must_read_table(table)?;
+        Ok(Box::new(
+            self.txn
+                .scan_prefix(&Key::Row((&table.name).into(), Some(prefix.into())).encode())?
+                .map(|r| r.and_then(|(_, v)| deserialize(&v))),
+        ))
+    }
+
     fn scan_index(&self, table: &str, column: &str) -> Result<super::IndexScan> {
         let table = self.must_read_table(table)?;
         let column = table.get_column(column)?;
         if !column.index {
             return Err(Error::Value(format!("No index for {}.{}", table.name, column.name)));
         }
-        Ok(Box::new(
+        let scan = IndexGroups::new(Box::new(
             self.txn
                 .scan_prefix(
-                    &Key::Index((&table.name).into(), (&column.name).into(), None).encode(),
+                    &Key::Index((&table.name).into(), (&column.name).into(), None, None).encode(),
                 )?
-                .map(|r| -> Result<(Value, HashSet<Value>)> {
-                    let (k, v) = r?;
-                    let value = match Key::decode(&k)? {
-                        Key::Index(_, _, Some(pk)) => pk.into_owned(),
-                        _ => return Err(Error::Internal("Invalid index key".into())),
-                    };
-                    Ok((value, deserialize(&v)?))
+                .map(|r| -> Result<(Value, Vec<Value>)> {
+                    match Key::decode(&r?.0)? {
+                        Key::Index(_, _, Some(value), Some(pk)) => {
+                            Ok((value.into_owned(), pk.into_owned()))
+                        }
+                        _ => Err(Error::Internal("Invalid index key".into())),
+                    }
                 }),
-        ))
+        ));
+
+        // If there are any unmigrated legacy index entries, merge them into the scan. This is
+        // buffered, but only happens for indexes that were written before per-entry keys.
+        let mut legacy = self
+            .txn
+            .scan_prefix(
+                &Key::LegacyIndex((&table.name).into(), (&column.name).into(), None).encode(),
+            )?
+            .peekable();
+        if legacy.peek().is_none() {
+            return Ok(Box::new(scan));
+        }
+        let mut entries = BTreeMap::new();
+        for r in legacy {
+            let (k, v) = r?;
+            let value = match Key::decode(&k)? {
+                Key::LegacyIndex(_, _, Some(value)) => value.into_owned(),
+                _ => return Err(Error::Internal("Invalid index key".into())),
+            };
+            let pks: HashSet<Value> = deserialize(&v)?;
+            entries.insert(
+                kv::encoding::encode_value(&value),
+                (value, pks.into_iter().map(|pk| vec![pk]).collect::<HashSet<_>>()),
+            );
+        }
+        for r in scan {
+            let (value, pks): (Value, HashSet<Vec<Value>>) = r?;
+            entries
+                .entry(kv::encoding::encode_value(&value))
+                .or_insert_with(|| (value, HashSet::new()))
+                .1
+                .extend(pks);
+        }
+        Ok(Box::new(entries.into_values().map(Ok)))
     }
 
-    fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
+    fn update(&mut self, table: &str, id: &[Value], row: Row) -> Result<()> {
         let table = self.must_read_table(table)?;
         // If the primary key changes we do a delete and create, otherwise we replace the row
-        if id != &table.get_row_key(&row)? {
+        if id != table.get_row_key(&row)? {
             self.delete(&table.name, id)?;
             self.create(&table.name, row)?;
             return Ok(());
@@ -245,25 +597,34 @@ impl super::Transaction for Transaction {
 
         // Update indexes, knowing that the primary key has not changed
         let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect();
-        if !indexes.is_empty() {
+        let composite = self.composite_indexes(&table.name)?;
+        if !indexes.is_empty() || !composite.is_empty() {
             let old = self.read(&table.name, id)?.unwrap();
             for (i, column) in indexes {
                 if old[i] == row[i] {
                     continue;
                 }
-                let mut index = self.index_load(&table.name, &column.name, &old[i])?;
-                index.remove(id);
-                self.index_save(&table.name, &column.name, &old[i], index)?;
-
-                let mut index = self.index_load(&table.name, &column.name, &row[i])?;
-                index.insert(id.clone());
-                self.index_save(&table.name, &column.name, &row[i], index)?;
+                self.index_remove(&table.name, &column.name, &old[i], id)?;
+                self.index_insert(&table.name, &column.name, &row[i], id)?;
+            }
+            for index in composite {
+                let old_values = Self::composite_index_values(&table, &index, &old)?;
+                let values = Self::composite_index_values(&table, &index, &row)?;
+                if old_values == values {
+                    continue;
+                }
+                self.composite_index_remove(&index, &old_values, id)?;
+                self.composite_index_insert(&index, &values, id)?;
             }
         }
 
         table.validate_row(&row, self)?;
         self.txn.set(&Key::Row(table.name.into(), Some(id.into())).encode(), serialize(&row)?)
     }
+
+    fn update_rows(&mut self, table: &str, rows: Vec<(Vec<Value>, Row)>) -> Result<()> {
+        self.atomic(|txn| rows.into_iter().try_for_each(|(id, row)| txn.update(table, &id, row)))
+    }
 }
 
 impl Catalog for Transaction {
@@ -287,9 +648,168 @@ impl Catalog for Transaction {
         while let Some(row) = scan.next().transpose()? {
             self.delete(&table.name, &table.get_row_key(&row)?)?
         }
+        for index in self.scan_indexes()?.filter(|i| i.table == table.name) {
+            self.txn.delete(&Key::IndexSchema(Some(index.name.into())).encode())?;
+        }
         self.txn.delete(&Key::Table(Some(table.name.into())).encode())
     }
 
+    fn alter_table(&mut self, table: &str, alteration: Alteration) -> Result<()> {
+        let mut table = self.must_read_table(table)?;
+        match alteration {
+            Alteration::AddColumn(column) => {
+                if table.get_column(&column.name).is_ok() {
+                    return Err(Error::Value(format!(
+                        "Column {} already exists in table {}",
+                        column.name, table.name
+                    )));
+                }
+                if column.primary_key {
+                    return Err(Error::Value(format!(
+                        "Can't add primary key column {}",
+                        column.name
+                    )));
+                }
+                table.columns.push(column);
+                table.validate(self)?;
+                self.txn
+                    .set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+
+                // Rewrite existing rows with the column's default value, validating them against
+                // the new schema and indexing them if necessary.
+                let column = table.columns.last().unwrap();
+                let rows = self.scan(&table.name, None)?.collect::<Result<Vec<_>>>()?;
+                for mut row in rows {
+                    row.push(column.default.clone().unwrap_or(Value::Null));
+                    table.validate_row(&row, self)?;
+                    let id = table.get_row_key(&row)?;
+                    if column.index {
+                        self.index_insert(&table.name, &column.name, row.last().unwrap(), &id)?;
+                    }
+                    self.txn.set(
+                        &Key::Row((&table.name).into(), Some(id.as_slice().into())).encode(),
+                        serialize(&row)?,
+                    )?;
+                }
+                Ok(())
+            }
+
+            Alteration::DropColumn(name) => {
+                let i = table.get_column_index(&name)?;
+                if table.columns[i].primary_key {
+                    return Err(Error::Value(format!("Can't drop primary key column {}", name)));
+                }
+                // Drop any indexes on the column, then reload the table since dropping a
+                // single-column index changes the column schema.
+                for index in self
+                    .scan_indexes()?
+                    .filter(|index| index.table == table.name && index.columns.contains(&name))
+                {
+                    self.delete_index(&index.name)?;
+                }
+                let mut table = self.must_read_table(&table.name)?;
+                if table.columns[i].index {
+                    let keys = self
+                        .txn
+                        .scan_prefix(
+                            &Key::Index((&table.name).into(), (&name).into(), None, None).encode(),
+                        )?
+                        .chain(self.txn.scan_prefix(
+                            &Key::LegacyIndex((&table.name).into(), (&name).into(), None).encode(),
+                        )?)
+                        .map(|r| r.map(|(k, _)| k))
+                        .collect::<Result<Vec<_>>>()?;
+                    for key in keys {
+                        self.txn.delete(&key)?;
+                    }
+                }
+
+                let rows = self.scan(&table.name, None)?.collect::<Result<Vec<_>>>()?;
+                for mut row in rows {
+                    row.remove(i);
+                    let id = table.get_row_key(&row)?;
+                    self.txn.set(
+                        &Key::Row((&table.name).into(), Some(id.as_slice().into())).encode(),
+                        serialize(&row)?,
+                    )?;
+                }
+                table.columns.remove(i);
+                self.txn.set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)
+            }
+
+            Alteration::RenameColumn { from, to } => {
+                if table.get_column(&to).is_ok() {
+                    return Err(Error::Value(format!(
+                        "Column {} already exists in table {}",
+                        to, table.name
+                    )));
+                }
+                let i = table.get_column_index(&from)?;
+                if table.columns[i].index {
+                    self.move_index(&table.name, &from, &table.name, &to)?;
+                }
+                table.columns[i].name = to.clone();
+                self.txn
+                    .set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+
+                for mut index in self.scan_indexes()?.filter(|index| index.table == table.name) {
+                    if let Some(column) = index.columns.iter_mut().find(|c| **c == from) {
+                        *column = to.clone();
+                        self.txn.set(
+                            &Key::IndexSchema(Some((&index.name).into())).encode(),
+                            serialize(&index)?,
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+
+            Alteration::RenameTable(name) => {
+                if self.read_table(&name)?.is_some() {
+                    return Err(Error::Value(format!("Table {} already exists", name)));
+                }
+                self.move_prefix(
+                    &Key::Row((&table.name).into(), None).encode(),
+                    &Key::Row((&name).into(), None).encode(),
+                )?;
+                for column in table.columns.iter().filter(|c| c.index) {
+                    self.move_index(&table.name, &column.name, &name, &column.name)?;
+                }
+                for mut index in self.scan_indexes()?.filter(|index| index.table == table.name) {
+                    index.table = name.clone();
+                    self.txn.set(
+                        &Key::IndexSchema(Some((&index.name).into())).encode(),
+                        serialize(&index)?,
+                    )?;
+                }
+
+                // Update foreign key references to the table, including self-references.
+                for mut t in self.scan_tables()?.filter(|t| t.name != table.name) {
+                    let mut changed = false;
+                    for column in t.columns.iter_mut() {
+                        if column.references.as_ref() == Some(&table.name) {
+                            column.references = Some(name.clone());
+                            changed = true;
+                        }
+                    }
+                    if changed {
+                        self.txn
+                            .set(&Key::Table(Some((&t.name).into())).encode(), serialize(&t)?)?;
+                    }
+                }
+                for column in table.columns.iter_mut() {
+                    if column.references.as_ref() == Some(&table.name) {
+                        column.references = Some(name.clone());
+                    }
+                }
+
+                self.txn.delete(&Key::Table(Some((&table.name).into())).encode())?;
+                table.name = name;
+                self.txn.set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)
+            }
+        }
+    }
+
     fn read_table(&self, table: &str) -> Result<Option<Table>> {
         self.txn.get(&Key::Table(Some(table.into())).encode())?.map(|v| deserialize(&v)).transpose()
     }
@@ -303,19 +823,257 @@ impl Catalog for Transaction {
                 .into_iter(),
         ))
     }
+
+    fn create_index(&mut self, mut index: Index) -> Result<()> {
+        if self.read_index_schema(&index.name)?.is_some() {
+            return Err(Error::Value(format!("Index {} already exists", index.name)));
+        }
+        let mut table = self.must_read_table(&index.table)?;
+        let column_indexes =
+            index.columns.iter().map(|c| table.get_column_index(c)).collect::<Result<Vec<_>>>()?;
+        if index.columns.iter().enumerate().any(|(i, c)| index.columns[..i].contains(c)) {
+            return Err(Error::Value(format!("Duplicate column in index {}", index.name)));
+        }
+
+        // Multi-column indexes are maintained separately from the table schema, while
+        // single-column indexes are flagged on the column itself.
+        if index.columns.len() > 1 {
+            if self.composite_indexes(&table.name)?.iter().any(|i| i.columns == index.columns) {
+                return Err(Error::Value(format!(
+                    "Columns {} are already indexed",
+                    index.columns.join(", ")
+                )));
+            }
+            let mut seen = HashSet::new();
+            let mut scan = self.scan(&table.name, None)?;
+            while let Some(row) = scan.next().transpose()? {
+                let values = Self::composite_index_values(&table, &index, &row)?;
+                if index.unique && !values.contains(&Value::Null) && !seen.insert(values.clone()) {
+                    return Err(Error::Value(format!(
+                        "Unique value {} already exists for index {}",
+                        Value::format_tuple(&values),
+                        index.name
+                    )));
+                }
+                let pk = table.get_row_key(&row)?;
+                self.txn.set(
+                    &Key::CompositeIndex(
+                        (&index.name).into(),
+                        Some(values.into()),
+                        Some(pk.as_slice().into()),
+                    )
+                    .encode(),
+                    serialize(&pk)?,
+                )?;
+            }
+            return self
+                .txn
+                .set(&Key::IndexSchema(Some((&index.name).into())).encode(), serialize(&index)?);
+        }
+
+        let column_index = column_indexes[0];
+        let single_pk = table.get_primary_key_indexes().len() == 1;
+        let column = &mut table.columns[column_index];
+        if column.primary_key && single_pk {
+            return Err(Error::Value(format!("Can't index primary key column {}", column.name)));
+        }
+        if column.index {
+            return Err(Error::Value(format!("Column {} is already indexed", column.name)));
+        }
+        // Only record the index as unique if it added the constraint, so that dropping it
+        // doesn't remove a UNIQUE constraint declared on the column itself.
+        index.unique = index.unique && !column.unique;
+        column.unique = column.unique || index.unique;
+        column.index = true;
+
+        // Backfill the index with existing rows, checking uniqueness if necessary.
+        let mut seen = HashSet::new();
+        let mut scan = self.scan(&table.name, None)?;
+        while let Some(row) = scan.next().transpose()? {
+            let value = &row[column_index];
+            if index.unique && value != &Value::Null && !seen.insert(value.clone()) {
+                return Err(Error::Value(format!(
+                    "Unique value {} already exists for column {}",
+                    value, index.columns[0]
+                )));
+            }
+            self.index_insert(&table.name, &index.columns[0], value, &table.get_row_key(&row)?)?;
+        }
+
+        self.txn.set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+        self.txn.set(&Key::IndexSchema(Some((&index.name).into())).encode(), serialize(&index)?)
+    }
+
+    fn delete_index(&mut self, index: &str) -> Result<()> {
+        let index = self
+            .read_index_schema(index)?
+            .ok_or_else(|| Error::Value(format!("Index {} does not exist", index)))?;
+
+        let keys = if index.columns.len() > 1 {
+            self.txn
+                .scan_prefix(&Key::CompositeIndex((&index.name).into(), None, None).encode())?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            let mut table = self.must_read_table(&index.table)?;
+            let column_index = table.get_column_index(&index.columns[0])?;
+            let column = &mut table.columns[column_index];
+            column.index = false;
+            column.unique = column.unique && !index.unique;
+            self.txn.set(&Key::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+
+            self.txn
+                .scan_prefix(
+                    &Key::Index((&index.table).into(), (&index.columns[0]).into(), None, None)
+                        .encode(),
+                )?
+                .chain(
+                    self.txn.scan_prefix(
+                        &Key::LegacyIndex((&index.table).into(), (&index.columns[0]).into(), None)
+                            .encode(),
+                    )?,
+                )
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>>>()?
+        };
+        for key in keys {
+            self.txn.delete(&key)?;
+        }
+        self.txn.delete(&Key::IndexSchema(Some(index.name.into())).encode())
+    }
+
+    fn scan_indexes(&self) -> Result<Indexes> {
+        Ok(Box::new(
+            self.txn
+                .scan_prefix(&Key::IndexSchema(None).encode())?
+                .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter(),
+        ))
+    }
+}
+
+/// Groups an ordered scan of (value, primary key) index entries into primary key sets by value.
+pub(super) struct IndexGroups {
+    /// The underlying index entry scan.
+    scan: Box<dyn DoubleEndedIterator<Item = Result<(Value, Vec<Value>)>> + Send>,
+    /// An entry read past the end of the last group returned by next().
+    front: Option<(Value, Vec<Value>)>,
+    /// An entry read past the start of the last group returned by next_back().
+    back: Option<(Value, Vec<Value>)>,
+}
+
+impl IndexGroups {
+    /// Creates a new index group iterator.
+    pub(super) fn new(
+        scan: Box<dyn DoubleEndedIterator<Item = Result<(Value, Vec<Value>)>> + Send>,
+    ) -> Self {
+        Self { scan, front: None, back: None }
+    }
+
+    /// next() with error handling.
+    fn try_next(&mut self) -> Result<Option<(Value, HashSet<Vec<Value>>)>> {
+        let (value, pk) = match self.front.take() {
+            Some(entry) => entry,
+            None => match self.scan.next().transpose()?.or_else(|| self.back.take()) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            },
+        };
+        let mut pks = HashSet::new();
+        pks.insert(pk);
+        loop {
+            match self.scan.next().transpose()? {
+                Some((v, pk)) if v == value => pks.insert(pk),
+                Some(entry) => {
+                    self.front = Some(entry);
+                    break;
+                }
+                None => {
+                    match self.back.take() {
+                        Some((v, pk)) if v == value => pks.insert(pk),
+                        back => {
+                            self.back = back;
+                            false
+                        }
+                    };
+                    break;
+                }
+            };
+        }
+        Ok(Some((value, pks)))
+    }
+
+    /// next_back() with error handling.
+    fn try_next_back(&mut self) -> Result<Option<(Value, HashSet<Vec<Value>>)>> {
+        let (value, pk) = match self.back.take() {
+            Some(entry) => entry,
+            None => match self.scan.next_back().transpose()?.or_else(|| self.front.take()) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            },
+        };
+        let mut pks = HashSet::new();
+        pks.insert(pk);
+        loop {
+            match self.scan.next_back().transpose()? {
+                Some((v, pk)) if v == value => pks.insert(pk),
+                Some(entry) => {
+                    self.back = Some(entry);
+                    break;
+                }
+                None => {
+                    match self.front.take() {
+                        Some((v, pk)) if v == value => pks.insert(pk),
+                        front => {
+                            self.front = front;
+                            false
+                        }
+                    };
+                    break;
+                }
+            };
+        }
+        Ok(Some((value, pks)))
+    }
+}
+
+impl Iterator for IndexGroups {
+    type Item = Result<(Value, HashSet<Vec<Value>>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl DoubleEndedIterator for IndexGroups {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.try_next_back().transpose()
+    }
 }
 
 /// Encodes SQL keys, using an order-preserving encoding - see kv::encoding for details. Options can
 /// be None to get a keyspace prefix. We use table and column names directly as identifiers, to
-/// avoid additional indirection and associated overhead. It is not possible to change names, so
-/// this is ok. Uses Cows since we want to borrow when encoding but return owned when decoding.
+/// avoid additional indirection and associated overhead. Renaming a table or column with ALTER
+/// TABLE therefore rewrites all of its keys. Uses Cows since we want to borrow when encoding but return owned when decoding.
 enum Key<'a> {
     /// A table schema key for the given table name
     Table(Option<Cow<'a, str>>),
-    /// A key for an index entry
-    Index(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, Value>>),
-    /// A key for a row identified by table name and row primary key
-    Row(Cow<'a, str>, Option<Cow<'a, Value>>),
+    /// A legacy key for an index entry, containing the set of primary keys for the value. These
+    /// are migrated to per-entry Index keys when the index entry is written to.
+    LegacyIndex(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, Value>>),
+    /// A row identified by table name and row primary key. The primary key is a tuple of the
+    /// primary key column values, which can be given as a prefix for prefix scans.
+    Row(Cow<'a, str>, Option<Cow<'a, [Value]>>),
+    /// A key for an index entry, identified by table name, column name, value, and primary key.
+    /// The value is empty.
+    Index(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, Value>>, Option<Cow<'a, [Value]>>),
+    /// An index schema key for the given index name
+    IndexSchema(Option<Cow<'a, str>>),
+    /// A key for a multi-column index entry, identified by index name, column values, and primary
+    /// key. The values can be given as a prefix for prefix scans. Since the boundary between the
+    /// values and the primary key is unknown when decoding, the primary key is stored as the value.
+    CompositeIndex(Cow<'a, str>, Option<Cow<'a, [Value]>>, Option<Cow<'a, [Value]>>),
 }
 
 impl<'a> Key<'a> {
@@ -325,10 +1083,10 @@ impl<'a> Key<'a> {
         match self {
             Self::Table(None) => vec![0x01],
             Self::Table(Some(name)) => [&[0x01][..], &encode_string(&name)].concat(),
-            Self::Index(table, column, None) => {
+            Self::LegacyIndex(table, column, None) => {
                 [&[0x02][..], &encode_string(&table), &encode_string(&column)].concat()
             }
-            Self::Index(table, column, Some(value)) => [
+            Self::LegacyIndex(table, column, Some(value)) => [
                 &[0x02][..],
                 &encode_string(&table),
                 &encode_string(&column),
@@ -337,7 +1095,30 @@ impl<'a> Key<'a> {
             .concat(),
             Self::Row(table, None) => [&[0x03][..], &encode_string(&table)].concat(),
             Self::Row(table, Some(pk)) => {
-                [&[0x03][..], &encode_string(&table), &encode_value(&pk)].concat()
+                [&[0x03][..], &encode_string(&table), &encode_values(&pk)].concat()
+            }
+            Self::Index(table, column, value, pk) => {
+                let mut key =
+                    [&[0x04][..], &encode_string(&table), &encode_string(&column)].concat();
+                if let Some(value) = value {
+                    key.extend(encode_value(&value));
+                    if let Some(pk) = pk {
+                        key.extend(encode_values(&pk));
+                    }
+                }
+                key
+            }
+            Self::IndexSchema(None) => vec![0x05],
+            Self::IndexSchema(Some(name)) => [&[0x05][..], &encode_string(&name)].concat(),
+            Self::CompositeIndex(index, values, pk) => {
+                let mut key = [&[0x06][..], &encode_string(&index)].concat();
+                if let Some(values) = values {
+                    key.extend(encode_values(&values));
+                    if let Some(pk) = pk {
+                        key.extend(encode_values(&pk));
+                    }
+                }
+                key
             }
         }
     }
@@ -348,12 +1129,19 @@ impl<'a> Key<'a> {
         let bytes = &mut bytes;
         let key = match take_byte(bytes)? {
             0x01 => Self::Table(Some(take_string(bytes)?.into())),
-            0x02 => Self::Index(
+            0x02 => Self::LegacyIndex(
+                take_string(bytes)?.into(),
+                take_string(bytes)?.into(),
+                Some(take_value(bytes)?.into()),
+            ),
+            0x03 => Self::Row(take_string(bytes)?.into(), Some(take_values(bytes)?.into())),
+            0x04 => Self::Index(
                 take_string(bytes)?.into(),
                 take_string(bytes)?.into(),
                 Some(take_value(bytes)?.into()),
+                Some(take_values(bytes)?.into()),
             ),
-            0x03 => Self::Row(take_string(bytes)?.into(), Some(take_value(bytes)?.into())),
+            0x05 => Self::IndexSchema(Some(take_string(bytes)?.into())),
             b => return Err(Error::Internal(format!("Unknown SQL key prefix {:x?}", b))),
         };
         if !bytes.is_empty() {
@@ -362,3 +1150,333 @@ impl<'a> Key<'a> {
         Ok(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::schema::{Column, Index};
+    use super::super::super::types::DataType;
+    use super::super::{Engine as _, Mode, Transaction as _};
+    use super::*;
+
+    fn setup() -> Result<KV> {
+        let engine = KV::new(kv::MVCC::new(Box::new(kv::Test::new())));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let column = |name: &str, datatype, primary_key, index| Column {
+            name: name.into(),
+            datatype,
+            primary_key,
+            nullable: !primary_key,
+            default: if primary_key { None } else { Some(Value::Null) },
+            unique: primary_key,
+            references: None,
+            index,
+        };
+        txn.create_table(Table::new(
+            "t".into(),
+            vec![
+                column("id", DataType::Integer, true, false),
+                column("v", DataType::String, false, true),
+            ],
+        )?)?;
+        txn.commit()?;
+        Ok(engine)
+    }
+
+    fn row(id: i64, v: &str) -> Row {
+        vec![Value::Integer(id), Value::String(v.into())]
+    }
+
+    fn pks(ids: &[i64]) -> HashSet<Vec<Value>> {
+        ids.iter().map(|id| vec![Value::Integer(*id)]).collect()
+    }
+
+    #[test]
+    fn index_scan() -> Result<()> {
+        let engine = setup()?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        txn.create("t", row(1, "a"))?;
+        txn.create("t", row(2, "b"))?;
+        txn.create("t", row(3, "a"))?;
+        txn.create("t", row(4, "c"))?;
+        txn.create("t", row(5, "b"))?;
+        txn.update("t", &[Value::Integer(5)], row(5, "c"))?;
+        txn.delete("t", &[Value::Integer(4)])?;
+
+        assert_eq!(pks(&[1, 3]), txn.read_index("t", "v", &Value::String("a".into()))?);
+        assert_eq!(pks(&[]), txn.read_index("t", "v", &Value::String("x".into()))?);
+
+        let expect = vec![
+            (Value::String("a".into()), pks(&[1, 3])),
+            (Value::String("b".into()), pks(&[2])),
+            (Value::String("c".into()), pks(&[5])),
+        ];
+        assert_eq!(expect, txn.scan_index("t", "v")?.collect::<Result<Vec<_>>>()?);
+        assert_eq!(
+            expect.iter().rev().cloned().collect::<Vec<_>>(),
+            txn.scan_index("t", "v")?.rev().collect::<Result<Vec<_>>>()?
+        );
+
+        // Iterating from both ends should meet in the middle.
+        let mut scan = txn.scan_index("t", "v")?;
+        assert_eq!(Some(expect[0].clone()), scan.next().transpose()?);
+        assert_eq!(Some(expect[2].clone()), scan.next_back().transpose()?);
+        assert_eq!(Some(expect[1].clone()), scan.next_back().transpose()?);
+        assert_eq!(None, scan.next().transpose()?);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_atomic() -> Result<()> {
+        let engine = setup()?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        txn.create_rows("t", vec![row(1, "a"), row(2, "b")])?;
+
+        let scan = |txn: &Transaction| -> Result<Vec<Row>> {
+            txn.scan("t", None)?.collect::<Result<Vec<_>>>()
+        };
+        let expect = vec![row(1, "a"), row(2, "b")];
+
+        // The duplicate primary key in the middle of the batch must undo the rows before it,
+        // including their index entries.
+        assert_eq!(
+            Err(Error::Value("Primary key 1 already exists for table t".into())),
+            txn.create_rows("t", vec![row(3, "c"), row(1, "x"), row(4, "d")])
+        );
+        assert_eq!(expect, scan(&txn)?);
+        assert_eq!(pks(&[]), txn.read_index("t", "v", &Value::String("c".into()))?);
+
+        // Changing a primary key into an existing one fails after the first row was updated.
+        assert_eq!(
+            Err(Error::Value("Primary key 1 already exists for table t".into())),
+            txn.update_rows(
+                "t",
+                vec![
+                    (vec![Value::Integer(1)], row(1, "y")),
+                    (vec![Value::Integer(2)], row(1, "z")),
+                ]
+            )
+        );
+        assert_eq!(expect, scan(&txn)?);
+        assert_eq!(pks(&[1]), txn.read_index("t", "v", &Value::String("a".into()))?);
+        assert_eq!(pks(&[]), txn.read_index("t", "v", &Value::String("y".into()))?);
+
+        // Successful batches are kept, and the transaction remains usable.
+        txn.delete_rows("t", vec![vec![Value::Integer(1)]])?;
+        txn.create_rows("t", vec![row(3, "c")])?;
+        txn.commit()?;
+
+        let txn = engine.begin(Mode::ReadOnly)?;
+        assert_eq!(vec![row(2, "b"), row(3, "c")], scan(&txn)?);
+        Ok(())
+    }
+
+    #[test]
+    fn scan_page() -> Result<()> {
+        let engine = setup()?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        for (id, v) in [(1, "a"), (2, "b"), (3, "a"), (4, "c"), (5, "b")] {
+            txn.create("t", row(id, v))?;
+        }
+        let unbounded = (Bound::Unbounded, Bound::Unbounded);
+
+        // Pages are continued from the key of the last scanned row, until no rows remain.
+        let page = txn.scan_page("t", &[], None, unbounded.clone(), false, 2)?;
+        assert_eq!(vec![row(1, "a"), row(2, "b")], page.items);
+        let next = page.next.expect("continuation key");
+        let page =
+            txn.scan_page("t", &[], None, (Bound::Excluded(next), Bound::Unbounded), false, 2)?;
+        assert_eq!(vec![row(3, "a"), row(4, "c")], page.items);
+        let next = page.next.expect("continuation key");
+        let page =
+            txn.scan_page("t", &[], None, (Bound::Excluded(next), Bound::Unbounded), false, 2)?;
+        assert_eq!(Page { items: vec![row(5, "b")], next: None }, page);
+
+        // An exactly full last page has no continuation key.
+        assert_eq!(None, txn.scan_page("t", &[], None, unbounded.clone(), false, 5)?.next);
+
+        // Filtered rows count towards the limit, and reverse pages continue from the end.
+        let filter = Expression::Equal(
+            Box::new(Expression::Field(1, None)),
+            Box::new(Expression::Constant(Value::String("b".into()))),
+        );
+        let page = txn.scan_page("t", &[], Some(filter.clone()), unbounded.clone(), true, 2)?;
+        assert_eq!(vec![row(5, "b")], page.items);
+        let next = page.next.expect("continuation key");
+        let page = txn.scan_page(
+            "t",
+            &[],
+            Some(filter),
+            (Bound::Unbounded, Bound::Excluded(next)),
+            true,
+            2,
+        )?;
+        assert_eq!(vec![row(2, "b")], page.items);
+
+        // Primary key prefixes limit the scanned range.
+        let page = txn.scan_page("t", &[Value::Integer(4)], None, unbounded.clone(), false, 2)?;
+        assert_eq!(Page { items: vec![row(4, "c")], next: None }, page);
+
+        // Index pages contain individual entries, in index order.
+        let page = txn.scan_index_page("t", "v", unbounded, false, 3)?;
+        assert_eq!(
+            vec![
+                (Value::String("a".into()), vec![Value::Integer(1)]),
+                (Value::String("a".into()), vec![Value::Integer(3)]),
+                (Value::String("b".into()), vec![Value::Integer(2)]),
+            ],
+            page.items
+        );
+        let next = page.next.expect("continuation key");
+        let page =
+            txn.scan_index_page("t", "v", (Bound::Excluded(next), Bound::Unbounded), false, 3)?;
+        assert_eq!(
+            Page {
+                items: vec![
+                    (Value::String("b".into()), vec![Value::Integer(5)]),
+                    (Value::String("c".into()), vec![Value::Integer(4)]),
+                ],
+                next: None
+            },
+            page
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn index_migrate_legacy() -> Result<()> {
+        let engine = setup()?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        txn.create("t", row(1, "a"))?;
+        txn.create("t", row(2, "a"))?;
+        txn.create("t", row(3, "b"))?;
+
+        // Replace the index entries with legacy entries.
+        for (value, ids) in vec![("a", vec![1, 2]), ("b", vec![3])] {
+            let value = Value::String(value.into());
+            for id in &ids {
+                txn.txn.delete(
+                    &Key::Index(
+                        "t".into(),
+                        "v".into(),
+                        Some((&value).into()),
+                        Some(vec![Value::Integer(*id)].into()),
+                    )
+                    .encode(),
+                )?;
+            }
+            txn.txn.set(
+                &Key::LegacyIndex("t".into(), "v".into(), Some((&value).into())).encode(),
+                serialize(&ids.iter().map(|id| Value::Integer(*id)).collect::<HashSet<_>>())?,
+            )?;
+        }
+        assert_eq!(pks(&[1, 2]), txn.read_index("t", "v", &Value::String("a".into()))?);
+
+        // Writing to a legacy entry migrates it, and scans merge both kinds of entries.
+        txn.create("t", row(4, "a"))?;
+        assert_eq!(
+            None,
+            txn.txn.get(
+                &Key::LegacyIndex("t".into(), "v".into(), Some(Value::String("a".into()).into()))
+                    .encode()
+            )?
+        );
+        assert_eq!(pks(&[1, 2, 4]), txn.read_index("t", "v", &Value::String("a".into()))?);
+        assert_eq!(
+            vec![
+                (Value::String("a".into()), pks(&[1, 2, 4])),
+                (Value::String("b".into()), pks(&[3]))
+            ],
+            txn.scan_index("t", "v")?.collect::<Result<Vec<_>>>()?
+        );
+
+        txn.delete("t", &[Value::Integer(3)])?;
+        txn.update("t", &[Value::Integer(1)], row(1, "b"))?;
+        assert_eq!(
+            vec![(Value::String("a".into()), pks(&[2, 4])), (Value::String("b".into()), pks(&[1]))],
+            txn.scan_index("t", "v")?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn composite_keys() -> Result<()> {
+        let engine = setup()?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let column = |name: &str, datatype, primary_key| Column {
+            name: name.into(),
+            datatype,
+            primary_key,
+            nullable: false,
+            default: None,
+            unique: false,
+            references: None,
+            index: false,
+        };
+        txn.create_table(Table::new(
+            "c".into(),
+            vec![
+                column("a", DataType::String, true),
+                column("b", DataType::Integer, true),
+                column("x", DataType::Integer, false),
+                column("y", DataType::String, false),
+            ],
+        )?)?;
+        txn.create_index(Index {
+            name: "c_xy".into(),
+            table: "c".into(),
+            columns: vec!["x".into(), "y".into()],
+            unique: true,
+        })?;
+        let row = |a: &str, b: i64, x: i64, y: &str| {
+            vec![
+                Value::String(a.into()),
+                Value::Integer(b),
+                Value::Integer(x),
+                Value::String(y.into()),
+            ]
+        };
+        let key = |a: &str, b: i64| vec![Value::String(a.into()), Value::Integer(b)];
+        txn.create("c", row("a", 1, 1, "x"))?;
+        txn.create("c", row("a", 2, 1, "y"))?;
+        txn.create("c", row("ab", 1, 2, "x"))?;
+        txn.create("c", row("b", 1, 2, "y"))?;
+        assert!(txn.create("c", row("a", 1, 9, "z")).is_err());
+        assert!(txn.create("c", row("c", 1, 1, "y")).is_err());
+
+        // Full and prefix lookups by primary key.
+        assert_eq!(Some(row("a", 2, 1, "y")), txn.read("c", &key("a", 2))?);
+        assert_eq!(
+            vec![row("a", 1, 1, "x"), row("a", 2, 1, "y")],
+            txn.scan_prefix("c", &[Value::String("a".into())])?.collect::<Result<Vec<_>>>()?
+        );
+
+        // Full and prefix lookups by index.
+        assert_eq!(
+            vec![key("a", 2)].into_iter().collect::<HashSet<_>>(),
+            txn.read_index_prefix("c_xy", &[Value::Integer(1), Value::String("y".into())])?
+        );
+        assert_eq!(
+            vec![key("ab", 1), key("b", 1)].into_iter().collect::<HashSet<_>>(),
+            txn.read_index_prefix("c_xy", &[Value::Integer(2)])?
+        );
+        txn.update("c", &key("b", 1), row("b", 1, 3, "y"))?;
+        txn.delete("c", &key("ab", 1))?;
+        assert_eq!(HashSet::new(), txn.read_index_prefix("c_xy", &[Value::Integer(2)])?);
+        assert_eq!(
+            vec![key("b", 1)].into_iter().collect::<HashSet<_>>(),
+            txn.read_index_prefix("c_xy", &[Value::Integer(3)])?
+        );
+
+        // Single-value keys encode the same as before composite keys.
+        assert_eq!(
+            [
+                &[0x03][..],
+                &kv::encoding::encode_string("t"),
+                &kv::encoding::encode_value(&1.into())
+            ]
+            .concat(),
+            Key::Row("t".into(), Some(vec![Value::Integer(1)].into())).encode()
+        );
+        Ok(())
+    }
+}
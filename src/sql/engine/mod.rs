@@ -8,7 +8,7 @@ use super::execution::ResultSet;
 use super::parser::{ast, Parser};
 use super::plan::Plan;
 use super::schema::Catalog;
-use super::types::{Expression, Row, Value};
+use super::types::{Expression, Row, Rows, Value};
 use crate::error::{Error, Result};
 
 use std::collections::HashSet;
@@ -16,7 +16,7 @@ use std::collections::HashSet;
 /// The SQL engine interface
 pub trait Engine: Clone {
     /// The transaction type
-    type Transaction: Transaction;
+    type Transaction: Transaction + Send + 'static;
 
     /// Begins a transaction in the given mode
     fn begin(&self, mode: Mode) -> Result<Self::Transaction>;
@@ -28,6 +28,12 @@ pub trait Engine: Clone {
 
     /// Resumes an active transaction with the given ID
     fn resume(&self, id: u64) -> Result<Self::Transaction>;
+
+    /// Changes a session setting, which applies to transactions begun afterwards. Each session
+    /// has its own engine clone, so settings don't affect other sessions.
+    fn set(&mut self, name: &str, _value: Value) -> Result<()> {
+        Err(Error::Value(format!("Unknown setting {}", name)))
+    }
 }
 
 /// An SQL transaction
@@ -43,18 +49,29 @@ pub trait Transaction: Catalog {
 
     /// Creates a new table row
     fn create(&mut self, table: &str, row: Row) -> Result<()>;
+    /// Creates multiple table rows atomically: if any row fails, none of them are created
+    fn create_rows(&mut self, table: &str, rows: Vec<Row>) -> Result<()>;
     /// Deletes a table row
-    fn delete(&mut self, table: &str, id: &Value) -> Result<()>;
+    fn delete(&mut self, table: &str, id: &[Value]) -> Result<()>;
+    /// Deletes multiple table rows atomically: if any row fails, none of them are deleted
+    fn delete_rows(&mut self, table: &str, ids: Vec<Vec<Value>>) -> Result<()>;
     /// Reads a table row, if it exists
-    fn read(&self, table: &str, id: &Value) -> Result<Option<Row>>;
+    fn read(&self, table: &str, id: &[Value]) -> Result<Option<Row>>;
     /// Reads an index entry, if it exists
-    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>>;
+    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Vec<Value>>>;
+    /// Reads the primary keys of a multi-column index's entries with the given value prefix
+    fn read_index_prefix(&self, index: &str, prefix: &[Value]) -> Result<HashSet<Vec<Value>>>;
     /// Scans a table's rows
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan>;
+    /// Scans a table's rows whose primary key starts with the given prefix
+    fn scan_prefix(&self, table: &str, prefix: &[Value]) -> Result<Scan>;
     /// Scans a column's index entries
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan>;
     /// Updates a table row
-    fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()>;
+    fn update(&mut self, table: &str, id: &[Value], row: Row) -> Result<()>;
+    /// Updates multiple table rows atomically, given as primary key and new row: if any row
+    /// fails, none of them are updated
+    fn update_rows(&mut self, table: &str, rows: Vec<(Vec<Value>, Row)>) -> Result<()>;
 }
 
 /// An SQL session, which handles transaction control and simplified query execution
@@ -123,18 +140,32 @@ impl<E: Engine + 'static> Session<E> {
                 }
                 Ok(ResultSet::Rollback { id })
             }
+            ast::Statement::Set { name, value } => {
+                let value = Value::from(value);
+                self.engine.set(&name, value.clone())?;
+                Ok(ResultSet::Set { name, value })
+            }
             ast::Statement::Explain(statement) => self.with_txn(Mode::ReadOnly, |txn| {
                 Ok(ResultSet::Explain(Plan::build(*statement, txn)?.optimize(txn)?.0))
             }),
             statement if self.txn.is_some() => Plan::build(statement, self.txn.as_mut().unwrap())?
                 .optimize(self.txn.as_mut().unwrap())?
                 .execute(self.txn.as_mut().unwrap()),
-            statement @ ast::Statement::Select { .. } => {
+            statement @ ast::Statement::Select { .. }
+            | statement @ ast::Statement::SetOperation { .. }
+            | statement @ ast::Statement::With { .. } => {
                 let mut txn = self.engine.begin(Mode::ReadOnly)?;
-                let result =
-                    Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn);
-                txn.rollback()?;
-                result
+                match Plan::build(statement, &mut txn)?.optimize(&mut txn)?.execute(&mut txn) {
+                    // Rows may be read lazily, so the transaction is kept open until the
+                    // rows have been consumed.
+                    Ok(ResultSet::Query { columns, rows }) => {
+                        Ok(ResultSet::Query { columns, rows: Box::new(TxnRows::new(rows, txn)) })
+                    }
+                    result => {
+                        txn.rollback()?;
+                        result
+                    }
+                }
             }
             statement => {
                 let mut txn = self.engine.begin(Mode::ReadWrite)?;
@@ -172,6 +203,41 @@ impl<E: Engine + 'static> Session<E> {
     }
 }
 
+/// Query result rows read in an implicit read-only transaction, which is rolled back once the rows
+/// have been consumed or dropped.
+struct TxnRows<T: Transaction> {
+    rows: Rows,
+    txn: Option<T>,
+}
+
+impl<T: Transaction> TxnRows<T> {
+    fn new(rows: Rows, txn: T) -> Self {
+        Self { rows, txn: Some(txn) }
+    }
+}
+
+impl<T: Transaction> Iterator for TxnRows<T> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rows.next() {
+            None => match self.txn.take().map(|txn| txn.rollback()) {
+                Some(Err(err)) => Some(Err(err)),
+                _ => None,
+            },
+            item => item,
+        }
+    }
+}
+
+impl<T: Transaction> Drop for TxnRows<T> {
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            txn.rollback().ok();
+        }
+    }
+}
+
 /// The transaction mode
 pub type Mode = crate::storage::kv::mvcc::Mode;
 
@@ -179,4 +245,5 @@ pub type Mode = crate::storage::kv::mvcc::Mode;
 pub type Scan = Box<dyn DoubleEndedIterator<Item = Result<Row>> + Send>;
 
 /// An index scan iterator
-pub type IndexScan = Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Value>)>> + Send>;
+pub type IndexScan =
+    Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Vec<Value>>)>> + Send>;
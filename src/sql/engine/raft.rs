@@ -1,5 +1,6 @@
-use super::super::schema::{Catalog, Table, Tables};
+use super::super::schema::{Alteration, Catalog, Index, Indexes, Table, Tables};
 use super::super::types::{Expression, Row, Value};
+use super::kv::{IndexGroups, Page};
 use super::{Engine as _, IndexScan, Mode, Scan, Transaction as _};
 use crate::error::{Error, Result};
 use crate::raft;
@@ -7,9 +8,12 @@ use crate::storage::kv;
 
 use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::ops::Bound;
+use std::time::Duration;
 
-/// A Raft state machine mutation
+/// A Raft state machine mutation. Variants are encoded by index and persisted in the Raft log, so
+/// new variants must be appended at the end and existing ones must not change.
 #[derive(Clone, Serialize, Deserialize)]
 enum Mutation {
     /// Begins a transaction in the given mode
@@ -30,6 +34,28 @@ enum Mutation {
     CreateTable { txn_id: u64, schema: Table },
     /// Deletes a table
     DeleteTable { txn_id: u64, table: String },
+    /// Creates an index
+    CreateIndex { txn_id: u64, index: Index },
+    /// Deletes an index
+    DeleteIndex { txn_id: u64, index: String },
+
+    /// Creates multiple rows
+    CreateRows { txn_id: u64, table: String, rows: Vec<Row> },
+    /// Deletes multiple rows
+    DeleteRows { txn_id: u64, table: String, ids: Vec<Vec<Value>> },
+    /// Updates multiple rows
+    UpdateRows { txn_id: u64, table: String, rows: Vec<(Vec<Value>, Row)> },
+
+    /// Alters a table
+    AlterTable { txn_id: u64, table: String, alteration: Alteration },
+
+    /// Garbage collects obsolete MVCC data
+    Vacuum,
+
+    /// Deletes a row with a composite primary key
+    DeleteComposite { txn_id: u64, table: String, id: Vec<Value> },
+    /// Updates a row with a composite primary key
+    UpdateComposite { txn_id: u64, table: String, id: Vec<Value>, row: Row },
 }
 
 /// A Raft state machine query
@@ -41,18 +67,37 @@ enum Query {
     Resume(u64),
 
     /// Reads a row
-    Read { txn_id: u64, table: String, id: Value },
+    Read { txn_id: u64, table: String, id: Vec<Value> },
     /// Reads an index entry
     ReadIndex { txn_id: u64, table: String, column: String, value: Value },
-    /// Scans a table's rows
-    Scan { txn_id: u64, table: String, filter: Option<Expression> },
-    /// Scans an index
-    ScanIndex { txn_id: u64, table: String, column: String },
+    /// Reads a multi-column index entry prefix
+    ReadIndexPrefix { txn_id: u64, index: String, prefix: Vec<Value> },
+    /// Scans a page of a table's rows with the given primary key prefix
+    ScanPage {
+        txn_id: u64,
+        table: String,
+        prefix: Vec<Value>,
+        filter: Option<Expression>,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        reverse: bool,
+        limit: usize,
+    },
+    /// Scans a page of an index's entries
+    ScanIndexPage {
+        txn_id: u64,
+        table: String,
+        column: String,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        reverse: bool,
+        limit: usize,
+    },
 
     /// Scans the tables
     ScanTables { txn_id: u64 },
     /// Reads a table
     ReadTable { txn_id: u64, table: String },
+    /// Scans the indexes
+    ScanIndexes { txn_id: u64 },
 }
 
 /// Status for the Raft SQL engine.
@@ -66,12 +111,25 @@ pub struct Status {
 #[derive(Clone)]
 pub struct Raft {
     client: raft::Client,
+    /// Follower read settings for transactions begun by this engine.
+    reads: FollowerReads,
+}
+
+/// Session settings for serving read-only transactions from the local node, even if it's a
+/// follower, rather than from the leader.
+#[derive(Clone, Copy, Debug, Default)]
+struct FollowerReads {
+    /// Whether follower reads are enabled (setting follower_reads).
+    enabled: bool,
+    /// The maximum time since the local node last heard from the leader for it to serve reads
+    /// without fetching the leader's read index first (setting max_staleness, in milliseconds).
+    max_staleness: Option<Duration>,
 }
 
 impl Raft {
     /// Creates a new Raft SQL engine.
     pub fn new(client: raft::Client) -> Self {
-        Self { client }
+        Self { client, reads: FollowerReads::default() }
     }
 
     /// Creates an underlying state machine for a Raft engine.
@@ -89,6 +147,24 @@ impl Raft {
         })
     }
 
+    /// Changes the Raft cluster membership.
+    pub fn change_membership(&self, change: raft::MembershipChange) -> Result<()> {
+        futures::executor::block_on(self.client.change_membership(change))
+    }
+
+    /// Transfers Raft leadership to the given node.
+    pub fn transfer_leadership(&self, id: String) -> Result<()> {
+        futures::executor::block_on(self.client.transfer_leadership(id))
+    }
+
+    /// Garbage collects obsolete MVCC data, returning the number of bytes reclaimed. This runs
+    /// through Raft, such that all replicas remove the same data.
+    pub fn vacuum(&self) -> Result<u64> {
+        Raft::deserialize(&futures::executor::block_on(
+            self.client.mutate(Raft::serialize(&Mutation::Vacuum)?),
+        )?)
+    }
+
     /// Serializes a command for the Raft SQL state machine.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -105,11 +181,26 @@ impl super::Engine for Raft {
     type Transaction = Transaction;
 
     fn begin(&self, mode: Mode) -> Result<Self::Transaction> {
-        Transaction::begin(self.client.clone(), mode)
+        Transaction::begin(self.client.clone(), mode, self.reads)
     }
 
     fn resume(&self, id: u64) -> Result<Self::Transaction> {
-        Transaction::resume(self.client.clone(), id)
+        Transaction::resume(self.client.clone(), id, self.reads)
+    }
+
+    fn set(&mut self, name: &str, value: Value) -> Result<()> {
+        match (name, value) {
+            ("follower_reads", Value::Boolean(enabled)) => self.reads.enabled = enabled,
+            ("max_staleness", Value::Null) => self.reads.max_staleness = None,
+            ("max_staleness", Value::Integer(ms)) if ms >= 0 => {
+                self.reads.max_staleness = Some(Duration::from_millis(ms as u64))
+            }
+            ("follower_reads", value) | ("max_staleness", value) => {
+                return Err(Error::Value(format!("Invalid value {} for setting {}", value, name)))
+            }
+            (name, _) => return Err(Error::Value(format!("Unknown setting {}", name))),
+        }
+        Ok(())
     }
 }
 
@@ -122,23 +213,28 @@ pub struct Transaction {
     id: u64,
     /// The transaction mode
     mode: Mode,
+    /// A Raft log index at which the transaction is known to be active. Local reads wait for the
+    /// local node to apply it, so that the transaction is visible there.
+    index: u64,
+    /// Follower read settings, only used for read-only transactions.
+    reads: FollowerReads,
 }
 
 impl Transaction {
     /// Starts a transaction in the given mode
-    fn begin(client: raft::Client, mode: Mode) -> Result<Self> {
-        let id = Raft::deserialize(&futures::executor::block_on(
+    fn begin(client: raft::Client, mode: Mode, reads: FollowerReads) -> Result<Self> {
+        let (id, index) = Raft::deserialize(&futures::executor::block_on(
             client.mutate(Raft::serialize(&Mutation::Begin(mode))?),
         )?)?;
-        Ok(Self { client, id, mode })
+        Ok(Self { client, id, mode, index, reads })
     }
 
     /// Resumes an active transaction
-    fn resume(client: raft::Client, id: u64) -> Result<Self> {
-        let (id, mode) = Raft::deserialize(&futures::executor::block_on(
+    fn resume(client: raft::Client, id: u64, reads: FollowerReads) -> Result<Self> {
+        let (id, mode, index) = Raft::deserialize(&futures::executor::block_on(
             client.query(Raft::serialize(&Query::Resume(id))?),
         )?)?;
-        Ok(Self { client, id, mode })
+        Ok(Self { client, id, mode, index, reads })
     }
 
     /// Executes a mutation
@@ -146,9 +242,136 @@ impl Transaction {
         futures::executor::block_on(self.client.mutate(Raft::serialize(&mutation)?))
     }
 
-    /// Executes a query
+    /// Executes a query. Read-only transactions are served by the local node if follower reads
+    /// are enabled, otherwise by the leader.
     fn query(&self, query: Query) -> Result<Vec<u8>> {
-        futures::executor::block_on(self.client.query(Raft::serialize(&query)?))
+        let command = Raft::serialize(&query)?;
+        if self.reads.enabled && !self.mode.mutable() {
+            futures::executor::block_on(self.client.query_local(
+                command,
+                self.index,
+                self.reads.max_staleness,
+            ))
+        } else {
+            futures::executor::block_on(self.client.query(command))
+        }
+    }
+
+    /// Scans a table's rows with the given primary key prefix, fetching pages as needed.
+    fn scan_pages(
+        &self,
+        table: &str,
+        prefix: Vec<Value>,
+        filter: Option<Expression>,
+    ) -> Result<Scan> {
+        let txn = self.clone();
+        let table = table.to_string();
+        Ok(Box::new(PageScan::new(move |range, reverse| {
+            Raft::deserialize(&txn.query(Query::ScanPage {
+                txn_id: txn.id,
+                table: table.clone(),
+                prefix: prefix.clone(),
+                filter: filter.clone(),
+                range,
+                reverse,
+                limit: SCAN_PAGE_SIZE,
+            })?)
+        })?))
+    }
+}
+
+/// The maximum number of items scanned per page, when scanning via the Raft state machine.
+const SCAN_PAGE_SIZE: usize = 1000;
+
+/// A page fetcher, given a key range and direction.
+type FetchPage<T> =
+    Box<dyn FnMut((Bound<Vec<u8>>, Bound<Vec<u8>>), bool) -> Result<Page<T>> + Send>;
+
+/// A scan which lazily fetches pages of items from the state machine, from either end. Since
+/// pages are fetched in the scan's transaction, they all see the same MVCC snapshot.
+struct PageScan<T> {
+    /// Fetches a page within the given key range, in reverse order if requested.
+    fetch: FetchPage<T>,
+    /// The key range that remains to be fetched.
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// Items fetched from the front, in ascending order.
+    front: VecDeque<T>,
+    /// Items fetched from the back, in descending order.
+    back: VecDeque<T>,
+    /// Whether the entire range has been fetched.
+    done: bool,
+}
+
+impl<T> PageScan<T> {
+    /// Creates a new page scan, eagerly fetching the first page such that errors (e.g. for
+    /// missing tables) are returned immediately.
+    fn new<F>(fetch: F) -> Result<Self>
+    where
+        F: FnMut((Bound<Vec<u8>>, Bound<Vec<u8>>), bool) -> Result<Page<T>> + Send + 'static,
+    {
+        let mut scan = Self {
+            fetch: Box::new(fetch),
+            range: (Bound::Unbounded, Bound::Unbounded),
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+            done: false,
+        };
+        scan.fetch_page(false)?;
+        Ok(scan)
+    }
+
+    /// Fetches the next page from the front or back of the remaining range.
+    fn fetch_page(&mut self, reverse: bool) -> Result<()> {
+        let page = (self.fetch)(self.range.clone(), reverse)?;
+        match page.next {
+            Some(key) if reverse => self.range.1 = Bound::Excluded(key),
+            Some(key) => self.range.0 = Bound::Excluded(key),
+            None => self.done = true,
+        }
+        if reverse {
+            self.back.extend(page.items)
+        } else {
+            self.front.extend(page.items)
+        }
+        Ok(())
+    }
+
+    /// next() with error handling.
+    fn try_next(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(item) = self.front.pop_front() {
+                return Ok(Some(item));
+            } else if self.done {
+                return Ok(self.back.pop_back());
+            }
+            self.fetch_page(false)?;
+        }
+    }
+
+    /// next_back() with error handling.
+    fn try_next_back(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(item) = self.back.pop_front() {
+                return Ok(Some(item));
+            } else if self.done {
+                return Ok(self.front.pop_back());
+            }
+            self.fetch_page(true)?;
+        }
+    }
+}
+
+impl<T> Iterator for PageScan<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<T> DoubleEndedIterator for PageScan<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.try_next_back().transpose()
     }
 }
 
@@ -177,23 +400,39 @@ impl super::Transaction for Transaction {
         })?)
     }
 
-    fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
-        Raft::deserialize(&self.mutate(Mutation::Delete {
+    fn create_rows(&mut self, table: &str, rows: Vec<Row>) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::CreateRows {
+            txn_id: self.id,
+            table: table.to_string(),
+            rows,
+        })?)
+    }
+
+    fn delete(&mut self, table: &str, id: &[Value]) -> Result<()> {
+        let (txn_id, table) = (self.id, table.to_string());
+        Raft::deserialize(&self.mutate(match id {
+            [id] => Mutation::Delete { txn_id, table, id: id.clone() },
+            id => Mutation::DeleteComposite { txn_id, table, id: id.to_vec() },
+        })?)
+    }
+
+    fn delete_rows(&mut self, table: &str, ids: Vec<Vec<Value>>) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::DeleteRows {
             txn_id: self.id,
             table: table.to_string(),
-            id: id.clone(),
+            ids,
         })?)
     }
 
-    fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
+    fn read(&self, table: &str, id: &[Value]) -> Result<Option<Row>> {
         Raft::deserialize(&self.query(Query::Read {
             txn_id: self.id,
             table: table.to_string(),
-            id: id.clone(),
+            id: id.to_vec(),
         })?)
     }
 
-    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
+    fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Vec<Value>>> {
         Raft::deserialize(&self.query(Query::ReadIndex {
             txn_id: self.id,
             table: table.to_string(),
@@ -202,36 +441,51 @@ impl super::Transaction for Transaction {
         })?)
     }
 
+    fn read_index_prefix(&self, index: &str, prefix: &[Value]) -> Result<HashSet<Vec<Value>>> {
+        Raft::deserialize(&self.query(Query::ReadIndexPrefix {
+            txn_id: self.id,
+            index: index.to_string(),
+            prefix: prefix.to_vec(),
+        })?)
+    }
+
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Scan> {
-        Ok(Box::new(
-            Raft::deserialize::<Vec<_>>(&self.query(Query::Scan {
-                txn_id: self.id,
-                table: table.to_string(),
-                filter,
-            })?)?
-            .into_iter()
-            .map(Ok),
-        ))
+        self.scan_pages(table, Vec::new(), filter)
+    }
+
+    fn scan_prefix(&self, table: &str, prefix: &[Value]) -> Result<Scan> {
+        self.scan_pages(table, prefix.to_vec(), None)
     }
 
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan> {
-        Ok(Box::new(
-            Raft::deserialize::<Vec<_>>(&self.query(Query::ScanIndex {
-                txn_id: self.id,
-                table: table.to_string(),
-                column: column.to_string(),
-            })?)?
-            .into_iter()
-            .map(Ok),
-        ))
+        let txn = self.clone();
+        let (table, column) = (table.to_string(), column.to_string());
+        let scan = PageScan::new(move |range, reverse| {
+            Raft::deserialize(&txn.query(Query::ScanIndexPage {
+                txn_id: txn.id,
+                table: table.clone(),
+                column: column.clone(),
+                range,
+                reverse,
+                limit: SCAN_PAGE_SIZE,
+            })?)
+        })?;
+        Ok(Box::new(IndexGroups::new(Box::new(scan))))
     }
 
-    fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
-        Raft::deserialize(&self.mutate(Mutation::Update {
+    fn update(&mut self, table: &str, id: &[Value], row: Row) -> Result<()> {
+        let (txn_id, table) = (self.id, table.to_string());
+        Raft::deserialize(&self.mutate(match id {
+            [id] => Mutation::Update { txn_id, table, id: id.clone(), row },
+            id => Mutation::UpdateComposite { txn_id, table, id: id.to_vec(), row },
+        })?)
+    }
+
+    fn update_rows(&mut self, table: &str, rows: Vec<(Vec<Value>, Row)>) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::UpdateRows {
             txn_id: self.id,
             table: table.to_string(),
-            id: id.clone(),
-            row,
+            rows,
         })?)
     }
 }
@@ -253,12 +507,37 @@ impl Catalog for Transaction {
         )
     }
 
+    fn alter_table(&mut self, table: &str, alteration: Alteration) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::AlterTable {
+            txn_id: self.id,
+            table: table.to_string(),
+            alteration,
+        })?)
+    }
+
     fn scan_tables(&self) -> Result<Tables> {
         Ok(Box::new(
             Raft::deserialize::<Vec<_>>(&self.query(Query::ScanTables { txn_id: self.id })?)?
                 .into_iter(),
         ))
     }
+
+    fn create_index(&mut self, index: Index) -> Result<()> {
+        Raft::deserialize(&self.mutate(Mutation::CreateIndex { txn_id: self.id, index })?)
+    }
+
+    fn delete_index(&mut self, index: &str) -> Result<()> {
+        Raft::deserialize(
+            &self.mutate(Mutation::DeleteIndex { txn_id: self.id, index: index.to_string() })?,
+        )
+    }
+
+    fn scan_indexes(&self) -> Result<Indexes> {
+        Ok(Box::new(
+            Raft::deserialize::<Vec<_>>(&self.query(Query::ScanIndexes { txn_id: self.id })?)?
+                .into_iter(),
+        ))
+    }
 }
 
 /// The Raft state machine for the Raft-based SQL engine, using a KV SQL engine
@@ -280,10 +559,10 @@ impl State {
         Ok(State { engine, applied_index })
     }
 
-    /// Applies a state machine mutation
-    fn apply(&mut self, mutation: Mutation) -> Result<Vec<u8>> {
+    /// Applies a state machine mutation at the given Raft log index
+    fn apply(&mut self, index: u64, mutation: Mutation) -> Result<Vec<u8>> {
         match mutation {
-            Mutation::Begin(mode) => Raft::serialize(&self.engine.begin(mode)?.id()),
+            Mutation::Begin(mode) => Raft::serialize(&(self.engine.begin(mode)?.id(), index)),
             Mutation::Commit(txn_id) => Raft::serialize(&self.engine.resume(txn_id)?.commit()?),
             Mutation::Rollback(txn_id) => Raft::serialize(&self.engine.resume(txn_id)?.rollback()?),
 
@@ -291,10 +570,10 @@ impl State {
                 Raft::serialize(&self.engine.resume(txn_id)?.create(&table, row)?)
             }
             Mutation::Delete { txn_id, table, id } => {
-                Raft::serialize(&self.engine.resume(txn_id)?.delete(&table, &id)?)
+                Raft::serialize(&self.engine.resume(txn_id)?.delete(&table, &[id])?)
             }
             Mutation::Update { txn_id, table, id, row } => {
-                Raft::serialize(&self.engine.resume(txn_id)?.update(&table, &id, row)?)
+                Raft::serialize(&self.engine.resume(txn_id)?.update(&table, &[id], row)?)
             }
 
             Mutation::CreateTable { txn_id, schema } => {
@@ -303,6 +582,35 @@ impl State {
             Mutation::DeleteTable { txn_id, table } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.delete_table(&table)?)
             }
+            Mutation::CreateIndex { txn_id, index } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_index(index)?)
+            }
+            Mutation::DeleteIndex { txn_id, index } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete_index(&index)?)
+            }
+
+            Mutation::CreateRows { txn_id, table, rows } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.create_rows(&table, rows)?)
+            }
+            Mutation::DeleteRows { txn_id, table, ids } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete_rows(&table, ids)?)
+            }
+            Mutation::UpdateRows { txn_id, table, rows } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.update_rows(&table, rows)?)
+            }
+
+            Mutation::AlterTable { txn_id, table, alteration } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.alter_table(&table, alteration)?)
+            }
+
+            Mutation::Vacuum => Raft::serialize(&self.engine.kv.vacuum()?),
+
+            Mutation::DeleteComposite { txn_id, table, id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.delete(&table, &id)?)
+            }
+            Mutation::UpdateComposite { txn_id, table, id, row } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.update(&table, &id, row)?)
+            }
         }
     }
 }
@@ -316,26 +624,45 @@ impl raft::State for State {
     fn mutate(&mut self, index: u64, command: Vec<u8>) -> Result<Vec<u8>> {
         // We don't check that index == applied_index + 1, since the Raft log commits no-op
         // entries during leader election which we need to ignore.
-        match self.apply(Raft::deserialize(&command)?) {
+        let mutation = Raft::deserialize(&command)?;
+        // The mutation's writes and the applied index are written as a single atomic batch, such
+        // that a crash can't cause the mutation to be applied again.
+        let kv = self.engine.kv.clone();
+        let result = kv.batch(|| match self.apply(index, mutation) {
             // 如果是错误
-            error @ Err(Error::Internal(_)) => error,
+            Err(err @ Error::Internal(_)) => Err(err),
             // 如果是result
             result => {
                 // 设置set_metadata
                 self.engine.set_metadata(b"applied_index", Raft::serialize(&(index))?)?;
-                // 更新index
-                self.applied_index = index;
-                // 返回结果
-                result
+                Ok(result)
             }
-        }
+        })?;
+        // 更新index
+        self.applied_index = index;
+        // 返回结果
+        result
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Raft::serialize(&self.engine.kv.export()?)
+    }
+
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        let data = Raft::deserialize(&snapshot)?;
+        self.engine.kv.batch(|| {
+            self.engine.kv.import(data)?;
+            self.engine.set_metadata(b"applied_index", Raft::serialize(&index)?)
+        })?;
+        self.applied_index = index;
+        Ok(())
     }
 
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
         match Raft::deserialize(&command)? {
             Query::Resume(id) => {
                 let txn = self.engine.resume(id)?;
-                Raft::serialize(&(txn.id(), txn.mode()))
+                Raft::serialize(&(txn.id(), txn.mode(), self.applied_index))
             }
 
             Query::Read { txn_id, table, id } => {
@@ -344,17 +671,25 @@ impl raft::State for State {
             Query::ReadIndex { txn_id, table, column, value } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.read_index(&table, &column, &value)?)
             }
-            // FIXME These need to stream rows somehow
-            Query::Scan { txn_id, table, filter } => Raft::serialize(
-                &self.engine.resume(txn_id)?.scan(&table, filter)?.collect::<Result<Vec<_>>>()?,
-            ),
-            Query::ScanIndex { txn_id, table, column } => Raft::serialize(
-                &self
-                    .engine
-                    .resume(txn_id)?
-                    .scan_index(&table, &column)?
-                    .collect::<Result<Vec<_>>>()?,
-            ),
+            Query::ReadIndexPrefix { txn_id, index, prefix } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.read_index_prefix(&index, &prefix)?)
+            }
+            Query::ScanPage { txn_id, table, prefix, filter, range, reverse, limit } => {
+                Raft::serialize(
+                    &self
+                        .engine
+                        .resume(txn_id)?
+                        .scan_page(&table, &prefix, filter, range, reverse, limit)?,
+                )
+            }
+            Query::ScanIndexPage { txn_id, table, column, range, reverse, limit } => {
+                Raft::serialize(
+                    &self
+                        .engine
+                        .resume(txn_id)?
+                        .scan_index_page(&table, &column, range, reverse, limit)?,
+                )
+            }
             Query::Status => Raft::serialize(&self.engine.kv.status()?),
 
             Query::ReadTable { txn_id, table } => {
@@ -363,6 +698,171 @@ impl raft::State for State {
             Query::ScanTables { txn_id } => {
                 Raft::serialize(&self.engine.resume(txn_id)?.scan_tables()?.collect::<Vec<_>>())
             }
+            Query::ScanIndexes { txn_id } => {
+                Raft::serialize(&self.engine.resume(txn_id)?.scan_indexes()?.collect::<Vec<_>>())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::RangeBounds;
+
+    /// Creates a page scan over the given single-byte keys, with the given page size.
+    fn page_scan(keys: Vec<u8>, limit: usize) -> Result<PageScan<u8>> {
+        PageScan::new(move |(start, end): (Bound<Vec<u8>>, Bound<Vec<u8>>), reverse| {
+            let mut scan: Vec<u8> = keys
+                .iter()
+                .copied()
+                .filter(|k| (start.as_ref().map(|s| s[0]), end.as_ref().map(|e| e[0])).contains(k))
+                .collect();
+            if reverse {
+                scan.reverse();
+            }
+            let items: Vec<u8> = scan.iter().copied().take(limit).collect();
+            let next = if scan.len() > limit { items.last().map(|k| vec![*k]) } else { None };
+            Ok(Page { items, next })
+        })
+    }
+
+    #[test]
+    fn page_scan_forward() -> Result<()> {
+        let keys: Vec<u8> = (1..=10).collect();
+        for limit in 1..=11 {
+            assert_eq!(keys, page_scan(keys.clone(), limit)?.collect::<Result<Vec<_>>>()?);
+        }
+        assert!(page_scan(vec![], 2)?.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn page_scan_reverse() -> Result<()> {
+        let keys: Vec<u8> = (1..=10).collect();
+        let expect: Vec<u8> = keys.iter().rev().copied().collect();
+        for limit in 1..=11 {
+            assert_eq!(expect, page_scan(keys.clone(), limit)?.rev().collect::<Result<Vec<_>>>()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn page_scan_both_ends() -> Result<()> {
+        // Iterating from both ends should meet in the middle, without duplicates or gaps.
+        let keys: Vec<u8> = (1..=10).collect();
+        for limit in 1..=11 {
+            let mut scan = page_scan(keys.clone(), limit)?;
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+            while let Some(k) = scan.next().transpose()? {
+                front.push(k);
+                match scan.next_back().transpose()? {
+                    Some(k) => back.push(k),
+                    None => break,
+                }
+            }
+            front.extend(back.into_iter().rev());
+            assert_eq!(keys, front, "limit {}", limit);
+        }
+        Ok(())
+    }
+    /// A test store whose write batches fail, to check that state machine writes are batched.
+    struct FailingBatch(kv::Test);
+
+    impl std::fmt::Display for FailingBatch {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "failing batch")
+        }
+    }
+
+    impl kv::Store for FailingBatch {
+        fn delete(&mut self, key: &[u8]) -> Result<()> {
+            self.0.delete(key)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.0.flush()
+        }
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.0.get(key)
+        }
+
+        fn scan(&self, range: kv::Range) -> kv::Scan {
+            self.0.scan(range)
+        }
+
+        fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+            self.0.set(key, value)
+        }
+
+        fn write_batch(&mut self, _: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+            Err(Error::Internal("Write batch failed".into()))
+        }
+    }
+
+    #[test]
+    fn mutation_decode_legacy() -> Result<()> {
+        // Mutation::Create { txn_id: 7, table: "t", row: [Integer(1), String("a")] }, as encoded
+        // before any variants were added.
+        let bytes = vec![
+            3, 0, 0, 0, // Create
+            7, 0, 0, 0, 0, 0, 0, 0, // txn_id
+            1, 0, 0, 0, 0, 0, 0, 0, b't', // table
+            2, 0, 0, 0, 0, 0, 0, 0, // row length
+            2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // Integer(1)
+            4, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', // String("a")
+        ];
+        match Raft::deserialize(&bytes)? {
+            Mutation::Create { txn_id, table, row } => {
+                assert_eq!(7, txn_id);
+                assert_eq!("t", table);
+                assert_eq!(vec![Value::Integer(1), Value::String("a".into())], row);
+            }
+            _ => panic!("Expected Mutation::Create"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_decode_legacy_delete() -> Result<()> {
+        // Mutation::Delete { txn_id: 7, table: "t", id: Integer(1) }, as encoded before composite
+        // primary keys were added.
+        let bytes = vec![
+            4, 0, 0, 0, // Delete
+            7, 0, 0, 0, 0, 0, 0, 0, // txn_id
+            1, 0, 0, 0, 0, 0, 0, 0, b't', // table
+            2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // Integer(1)
+        ];
+        match Raft::deserialize(&bytes)? {
+            Mutation::Delete { txn_id, table, id } => {
+                assert_eq!(7, txn_id);
+                assert_eq!("t", table);
+                assert_eq!(Value::Integer(1), id);
+            }
+            _ => panic!("Expected Mutation::Delete"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_atomic() -> Result<()> {
+        use kv::Store as _;
+        let store = kv::Test::new();
+        let mut state = State::new(kv::MVCC::new(Box::new(FailingBatch(store.clone()))))?;
+
+        // If the batch write fails, neither the mutation nor the applied index may be written.
+        assert_eq!(
+            Err(Error::Internal("Write batch failed".into())),
+            raft::State::mutate(&mut state, 1, Raft::serialize(&Mutation::Begin(Mode::ReadWrite))?)
+        );
+        assert_eq!(0, raft::State::applied_index(&state));
+        assert!(store.scan(kv::Range::from(..)).next().is_none());
+
+        let mut state = State::new(kv::MVCC::new(Box::new(store.clone())))?;
+        raft::State::mutate(&mut state, 1, Raft::serialize(&Mutation::Begin(Mode::ReadWrite))?)?;
+        assert_eq!(1, State::new(kv::MVCC::new(Box::new(store.clone())))?.applied_index);
+        assert!(store.scan(kv::Range::from(..)).count() > 1);
+        Ok(())
+    }
+}
@@ -77,7 +77,7 @@ pub trait Accumulator: std::fmt::Debug + Send {
 }
 
 impl dyn Accumulator {
-    fn from(aggregate: &Aggregate) -> Box<dyn Accumulator> {
+    pub fn from(aggregate: &Aggregate) -> Box<dyn Accumulator> {
         match aggregate {
             Aggregate::Average => Box::new(Average::new()),
             Aggregate::Count => Box::new(Count::new()),
@@ -200,3 +200,67 @@ impl<T: Transaction> Executor<T> for HashJoin<T> {
         Err(Error::Internal("Unexpected result set".into()))
     }
 }
+
+/// A semi join executor, which returns the left rows that match any right row using the given
+/// predicate, or for anti joins the left rows that don't match any. Unlike other joins, no right
+/// fields are returned, and each left row is returned at most once.
+pub struct SemiJoin<T: Transaction> {
+    left: Box<dyn Executor<T>>,
+    right: Box<dyn Executor<T>>,
+    predicate: Option<Expression>,
+    anti: bool,
+}
+
+impl<T: Transaction> SemiJoin<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Box<dyn Executor<T>>,
+        predicate: Option<Expression>,
+        anti: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, right, predicate, anti })
+    }
+
+    /// Checks whether a left row matches any of the right rows.
+    fn matches(left_row: &[Value], right: &[Row], predicate: &Option<Expression>) -> Result<bool> {
+        let predicate = match predicate {
+            Some(predicate) => predicate,
+            None => return Ok(!right.is_empty()),
+        };
+        for right_row in right {
+            let mut row = left_row.to_vec();
+            row.extend(right_row.iter().cloned());
+            match predicate.evaluate(Some(&row))? {
+                Value::Boolean(true) => return Ok(true),
+                Value::Boolean(false) | Value::Null => {}
+                value => {
+                    return Err(Error::Value(format!(
+                        "Join predicate returned {}, expected boolean",
+                        value
+                    )))
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<T: Transaction> Executor<T> for SemiJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if let ResultSet::Query { columns, rows } = self.left.execute(txn)? {
+            if let ResultSet::Query { rows: rrows, .. } = self.right.execute(txn)? {
+                let right = rrows.collect::<Result<Vec<_>>>()?;
+                let (predicate, anti) = (self.predicate, self.anti);
+                let rows = Box::new(rows.filter_map(move |res| {
+                    res.and_then(|row| match Self::matches(&row, &right, &predicate)? {
+                        hit if hit != anti => Ok(Some(row)),
+                        _ => Ok(None),
+                    })
+                    .transpose()
+                }));
+                return Ok(ResultSet::Query { columns, rows });
+            }
+        }
+        Err(Error::Internal("Unexpected result set".into()))
+    }
+}
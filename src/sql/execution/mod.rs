@@ -2,15 +2,23 @@ mod aggregation;
 mod join;
 mod mutation;
 mod query;
+mod recursive;
 mod schema;
+mod set;
 mod source;
+mod subquery;
+mod window;
 
 use aggregation::Aggregation;
-use join::{HashJoin, NestedLoopJoin};
+use join::{HashJoin, NestedLoopJoin, SemiJoin};
 use mutation::{Delete, Insert, Update};
 use query::{Filter, Limit, Offset, Order, Projection};
-use schema::{CreateTable, DropTable};
-use source::{IndexLookup, KeyLookup, Nothing, Scan};
+use recursive::RecursiveUnion;
+use schema::{AlterTable, CreateIndex, CreateTable, DropIndex, DropTable};
+use set::{Except, Intersect, Union};
+use source::{IndexLookup, IndexPrefixLookup, KeyLookup, Nothing, Scan, WorkTable};
+use subquery::Apply;
+use window::Window;
 
 use super::engine::{Mode, Transaction};
 use super::plan::Node;
@@ -33,9 +41,18 @@ impl<T: Transaction + 'static> dyn Executor<T> {
             Node::Aggregation { source, aggregates } => {
                 Aggregation::new(Self::build(*source), aggregates)
             }
+            Node::AlterTable { table, alteration } => AlterTable::new(table, alteration),
+            Node::Apply { source, source_size: _, subquery, kind, level } => {
+                Apply::new(Self::build(*source), *subquery, kind, level)
+            }
+            Node::CreateIndex { schema } => CreateIndex::new(schema),
             Node::CreateTable { schema } => CreateTable::new(schema),
             Node::Delete { table, source } => Delete::new(table, Self::build(*source)),
+            Node::DropIndex { name } => DropIndex::new(name),
             Node::DropTable { table } => DropTable::new(table),
+            Node::Except { left, right, distinct } => {
+                Except::new(Self::build(*left), Self::build(*right), distinct)
+            }
             Node::Filter { source, predicate } => Filter::new(Self::build(*source), predicate),
             Node::HashJoin { left, left_field, right, right_field, outer } => HashJoin::new(
                 Self::build(*left),
@@ -47,9 +64,15 @@ impl<T: Transaction + 'static> dyn Executor<T> {
             Node::IndexLookup { table, alias: _, column, values } => {
                 IndexLookup::new(table, column, values)
             }
+            Node::IndexPrefixLookup { table, alias: _, index, values } => {
+                IndexPrefixLookup::new(table, index, values)
+            }
             Node::Insert { table, columns, expressions } => {
                 Insert::new(table, columns, expressions)
             }
+            Node::Intersect { left, right, distinct } => {
+                Intersect::new(Self::build(*left), Self::build(*right), distinct)
+            }
             Node::KeyLookup { table, alias: _, keys } => KeyLookup::new(table, keys),
             Node::Limit { source, limit } => Limit::new(Self::build(*source), limit),
             Node::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
@@ -61,12 +84,23 @@ impl<T: Transaction + 'static> dyn Executor<T> {
             Node::Projection { source, expressions } => {
                 Projection::new(Self::build(*source), expressions)
             }
+            Node::RecursiveUnion { name, anchor, recursive, distinct, limit } => {
+                RecursiveUnion::new(name, Self::build(*anchor), *recursive, distinct, limit)
+            }
             Node::Scan { table, filter, alias: _ } => Scan::new(table, filter),
+            Node::SemiJoin { left, left_size: _, right, predicate, anti } => {
+                SemiJoin::new(Self::build(*left), Self::build(*right), predicate, anti)
+            }
+            Node::Union { left, right, distinct } => {
+                Union::new(Self::build(*left), Self::build(*right), distinct)
+            }
             Node::Update { table, source, expressions } => Update::new(
                 table,
                 Self::build(*source),
                 expressions.into_iter().map(|(i, _, e)| (i, e)).collect(),
             ),
+            Node::Window { source, windows } => Window::new(Self::build(*source), windows),
+            Node::WorkTable { name: _, columns, rows } => WorkTable::new(columns, rows),
         }
     }
 }
@@ -108,6 +142,23 @@ pub enum ResultSet {
     DropTable {
         name: String,
     },
+    // Table altered
+    AlterTable {
+        name: String,
+    },
+    // Index created
+    CreateIndex {
+        name: String,
+    },
+    // Index dropped
+    DropIndex {
+        name: String,
+    },
+    // Session setting changed
+    Set {
+        name: String,
+        value: Value,
+    },
     // Query result
     Query {
         columns: Columns,
@@ -59,7 +59,7 @@ impl Insert {
 impl<T: Transaction> Executor<T> for Insert {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
-        let mut count = 0;
+        let mut rows = Vec::with_capacity(self.rows.len());
         for expressions in self.rows {
             let mut row =
                 expressions.into_iter().map(|expr| expr.evaluate(None)).collect::<Result<_>>()?;
@@ -68,9 +68,11 @@ impl<T: Transaction> Executor<T> for Insert {
             } else {
                 row = Self::make_row(&table, &self.columns, row)?;
             }
-            txn.create(&table.name, row)?;
-            count += 1;
+            rows.push(row);
         }
+        // The rows are written in a single batch, which the Raft engine submits as one command.
+        let count = rows.len() as u64;
+        txn.create_rows(&table.name, rows)?;
         Ok(ResultSet::Create { count })
     }
 }
@@ -98,27 +100,27 @@ impl<T: Transaction> Executor<T> for Update<T> {
             ResultSet::Query { mut rows, .. } => {
                 let table = txn.must_read_table(&self.table)?;
 
-                // The iterator will see our changes, such that the same item may be iterated over
-                // multiple times. We keep track of the primary keys here to avoid that, althought
-                // it may cause ballooning memory usage for large updates.
-                //
-                // FIXME This is not safe for primary key updates, which may still be processed
-                // multiple times - it should be possible to come up with a pathological case that
-                // loops forever (e.g. UPDATE test SET id = id + 1).
+                // The updated rows are collected and written in a single batch once the source is
+                // exhausted, such that the iterator won't see our changes. We still keep track of
+                // the primary keys, in case the source yields the same row multiple times.
                 let mut updated = HashSet::new();
+                let mut updates = Vec::new();
                 while let Some(row) = rows.next().transpose()? {
                     let id = table.get_row_key(&row)?;
-                    if updated.contains(&id) {
+                    if !updated.insert(id.clone()) {
                         continue;
                     }
                     let mut new = row.clone();
                     for (field, expr) in &self.expressions {
                         new[*field] = expr.evaluate(Some(&row))?;
                     }
-                    txn.update(&table.name, &id, new)?;
-                    updated.insert(id);
+                    // The source may append subquery results to the rows, which are dropped.
+                    new.truncate(table.columns.len());
+                    updates.push((id, new));
                 }
-                Ok(ResultSet::Update { count: updated.len() as u64 })
+                let count = updates.len() as u64;
+                txn.update_rows(&table.name, updates)?;
+                Ok(ResultSet::Update { count })
             }
             r => Err(Error::Internal(format!("Unexpected response {:?}", r))),
         }
@@ -140,13 +142,14 @@ impl<T: Transaction> Delete<T> {
 impl<T: Transaction> Executor<T> for Delete<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
-        let mut count = 0;
         match self.source.execute(txn)? {
             ResultSet::Query { mut rows, .. } => {
+                let mut ids = Vec::new();
                 while let Some(row) = rows.next().transpose()? {
-                    txn.delete(&table.name, &table.get_row_key(&row)?)?;
-                    count += 1
+                    ids.push(table.get_row_key(&row)?);
                 }
+                let count = ids.len() as u64;
+                txn.delete_rows(&table.name, ids)?;
                 Ok(ResultSet::Delete { count })
             }
             r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
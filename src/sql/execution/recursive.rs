@@ -0,0 +1,75 @@
+use super::super::engine::Transaction;
+use super::super::plan::{Node, Plan};
+use super::super::types::Row;
+use super::{Executor, ResultSet};
+use crate::error::{Error, Result};
+
+use std::collections::HashSet;
+
+/// The maximum number of iterations of a recursive query, to guard against infinite recursion,
+/// e.g. via cycles with UNION ALL.
+const MAX_ITERATIONS: usize = 1000;
+
+/// A recursive union executor, which evaluates a recursive common table expression. The anchor
+/// rows are emitted first and form the initial work table. The recursive query is then executed
+/// repeatedly with the work table bound to the rows emitted by the previous iteration, until it
+/// no longer emits any rows. With distinct, rows that have already been emitted are discarded,
+/// which also terminates cycles. With a limit, the recursion stops once that many rows have been
+/// emitted, e.g. when the query has an outer LIMIT.
+pub struct RecursiveUnion<T: Transaction> {
+    name: String,
+    anchor: Box<dyn Executor<T>>,
+    recursive: Node,
+    distinct: bool,
+    limit: Option<u64>,
+}
+
+impl<T: Transaction> RecursiveUnion<T> {
+    pub fn new(
+        name: String,
+        anchor: Box<dyn Executor<T>>,
+        recursive: Node,
+        distinct: bool,
+        limit: Option<u64>,
+    ) -> Box<Self> {
+        Box::new(Self { name, anchor, recursive, distinct, limit })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for RecursiveUnion<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let Self { name, anchor, recursive, distinct, limit } = *self;
+        let limit = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+        if let ResultSet::Query { columns, rows } = anchor.execute(txn)? {
+            let mut seen = HashSet::new();
+            let mut keep = |rows: Vec<Row>| -> Vec<Row> {
+                match distinct {
+                    true => rows.into_iter().filter(|row| seen.insert(row.clone())).collect(),
+                    false => rows,
+                }
+            };
+            let mut result = keep(rows.collect::<Result<_>>()?);
+            let mut work = result.clone();
+            let mut iterations = 0;
+            while !work.is_empty() && result.len() < limit {
+                iterations += 1;
+                if iterations > MAX_ITERATIONS {
+                    return Err(Error::Value(format!(
+                        "Recursive query {} exceeded {} iterations",
+                        name, MAX_ITERATIONS
+                    )));
+                }
+                let node = recursive.clone().bind_work_table(&name, &work)?;
+                work = match Plan(node).optimize(txn)?.execute(txn)? {
+                    ResultSet::Query { rows, .. } => keep(rows.collect::<Result<_>>()?),
+                    r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+                };
+                result.extend(work.iter().cloned());
+            }
+            result.truncate(limit);
+            Ok(ResultSet::Query { columns, rows: Box::new(result.into_iter().map(Ok)) })
+        } else {
+            Err(Error::Internal("Unexpected result".into()))
+        }
+    }
+}
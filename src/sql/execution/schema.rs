@@ -1,5 +1,5 @@
 use super::super::engine::Transaction;
-use super::super::schema::Table;
+use super::super::schema::{Alteration, Index, Table};
 use super::{Executor, ResultSet};
 use crate::error::Result;
 
@@ -39,3 +39,64 @@ impl<T: Transaction> Executor<T> for DropTable {
         Ok(ResultSet::DropTable { name: self.table })
     }
 }
+
+/// An ALTER TABLE executor
+pub struct AlterTable {
+    table: String,
+    alteration: Alteration,
+}
+
+impl AlterTable {
+    pub fn new(table: String, alteration: Alteration) -> Box<Self> {
+        Box::new(Self { table, alteration })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AlterTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        // Renaming the table returns the new name.
+        let name = match &self.alteration {
+            Alteration::RenameTable(name) => name.clone(),
+            _ => self.table.clone(),
+        };
+        txn.alter_table(&self.table, self.alteration)?;
+        Ok(ResultSet::AlterTable { name })
+    }
+}
+
+/// A CREATE INDEX executor
+pub struct CreateIndex {
+    index: Index,
+}
+
+impl CreateIndex {
+    pub fn new(index: Index) -> Box<Self> {
+        Box::new(Self { index })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.index.name.clone();
+        txn.create_index(self.index)?;
+        Ok(ResultSet::CreateIndex { name })
+    }
+}
+
+/// A DROP INDEX executor
+pub struct DropIndex {
+    name: String,
+}
+
+impl DropIndex {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.delete_index(&self.name)?;
+        Ok(ResultSet::DropIndex { name: self.name })
+    }
+}
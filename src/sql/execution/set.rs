@@ -0,0 +1,148 @@
+use super::super::engine::Transaction;
+use super::super::types::Row;
+use super::{Executor, ResultSet};
+use crate::error::{Error, Result};
+
+use std::collections::{HashMap, HashSet};
+
+/// A union executor, which emits the rows of the left source followed by the rows of the right
+/// source. With distinct, duplicate rows are only emitted once.
+pub struct Union<T: Transaction> {
+    left: Box<dyn Executor<T>>,
+    right: Box<dyn Executor<T>>,
+    distinct: bool,
+}
+
+impl<T: Transaction> Union<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Box<dyn Executor<T>>,
+        distinct: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, right, distinct })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Union<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if let ResultSet::Query { columns, rows } = self.left.execute(txn)? {
+            if let ResultSet::Query { rows: rrows, .. } = self.right.execute(txn)? {
+                let rows = rows.chain(rrows);
+                if !self.distinct {
+                    return Ok(ResultSet::Query { columns, rows: Box::new(rows) });
+                }
+                let mut seen = HashSet::new();
+                return Ok(ResultSet::Query {
+                    columns,
+                    rows: Box::new(rows.filter(move |r| match r {
+                        Ok(row) => seen.insert(row.clone()),
+                        Err(_) => true,
+                    })),
+                });
+            }
+        }
+        Err(Error::Internal("Unexpected result set".into()))
+    }
+}
+
+/// An intersect executor, which emits the rows of the left source that are also in the right
+/// source. With distinct, each such row is only emitted once, otherwise it is emitted as many
+/// times as it occurs in both sources.
+pub struct Intersect<T: Transaction> {
+    left: Box<dyn Executor<T>>,
+    right: Box<dyn Executor<T>>,
+    distinct: bool,
+}
+
+impl<T: Transaction> Intersect<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Box<dyn Executor<T>>,
+        distinct: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, right, distinct })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Intersect<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if let ResultSet::Query { columns, rows } = self.left.execute(txn)? {
+            if let ResultSet::Query { rows: rrows, .. } = self.right.execute(txn)? {
+                let mut counts = count_rows(rrows)?;
+                let distinct = self.distinct;
+                return Ok(ResultSet::Query {
+                    columns,
+                    rows: Box::new(rows.filter(move |r| match r {
+                        Ok(row) => match counts.get_mut(row) {
+                            Some(count) if *count > 0 => {
+                                *count = if distinct { 0 } else { *count - 1 };
+                                true
+                            }
+                            _ => false,
+                        },
+                        Err(_) => true,
+                    })),
+                });
+            }
+        }
+        Err(Error::Internal("Unexpected result set".into()))
+    }
+}
+
+/// An except executor, which emits the rows of the left source that are not in the right source.
+/// With distinct, each such row is only emitted once, otherwise each occurrence in the right
+/// source removes one occurrence from the left source.
+pub struct Except<T: Transaction> {
+    left: Box<dyn Executor<T>>,
+    right: Box<dyn Executor<T>>,
+    distinct: bool,
+}
+
+impl<T: Transaction> Except<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Box<dyn Executor<T>>,
+        distinct: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, right, distinct })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Except<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if let ResultSet::Query { columns, rows } = self.left.execute(txn)? {
+            if let ResultSet::Query { rows: rrows, .. } = self.right.execute(txn)? {
+                let mut counts = count_rows(rrows)?;
+                let mut seen = HashSet::new();
+                let distinct = self.distinct;
+                return Ok(ResultSet::Query {
+                    columns,
+                    rows: Box::new(rows.filter(move |r| match r {
+                        Ok(row) if distinct => {
+                            !counts.contains_key(row) && seen.insert(row.clone())
+                        }
+                        Ok(row) => match counts.get_mut(row) {
+                            Some(count) if *count > 0 => {
+                                *count -= 1;
+                                false
+                            }
+                            _ => true,
+                        },
+                        Err(_) => true,
+                    })),
+                });
+            }
+        }
+        Err(Error::Internal("Unexpected result set".into()))
+    }
+}
+
+/// Counts the number of occurrences of each row. Rows are compared by value, such that e.g.
+/// NULL values are considered equal to each other.
+fn count_rows(rows: impl Iterator<Item = Result<Row>>) -> Result<HashMap<Row, usize>> {
+    let mut counts = HashMap::new();
+    for row in rows {
+        *counts.entry(row?).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
@@ -27,14 +27,15 @@ impl<T: Transaction> Executor<T> for Scan {
     }
 }
 
-/// A primary key lookup executor
+/// A primary key lookup executor. Keys can be a prefix of a composite primary key, in which case
+/// all rows with that key prefix are returned.
 pub struct KeyLookup {
     table: String,
-    keys: Vec<Value>,
+    keys: Vec<Vec<Value>>,
 }
 
 impl KeyLookup {
-    pub fn new(table: String, keys: Vec<Value>) -> Box<Self> {
+    pub fn new(table: String, keys: Vec<Vec<Value>>) -> Box<Self> {
         Box::new(Self { table, keys })
     }
 }
@@ -42,13 +43,17 @@ impl KeyLookup {
 impl<T: Transaction> Executor<T> for KeyLookup {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
+        let pk_len = table.get_primary_key_indexes().len();
 
         // FIXME Is there a way to pass the txn into an iterator closure instead?
-        let rows = self
-            .keys
-            .into_iter()
-            .filter_map(|key| txn.read(&table.name, &key).transpose())
-            .collect::<Result<Vec<Row>>>()?;
+        let mut rows = Vec::new();
+        for key in self.keys {
+            if key.len() == pk_len {
+                rows.extend(txn.read(&table.name, &key)?);
+            } else {
+                rows.extend(txn.scan_prefix(&table.name, &key)?.collect::<Result<Vec<Row>>>()?);
+            }
+        }
 
         Ok(ResultSet::Query {
             columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
@@ -74,7 +79,7 @@ impl<T: Transaction> Executor<T> for IndexLookup {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.must_read_table(&self.table)?;
 
-        let mut pks: HashSet<Value> = HashSet::new();
+        let mut pks: HashSet<Vec<Value>> = HashSet::new();
         for value in self.values {
             pks.extend(txn.read_index(&self.table, &self.column, &value)?);
         }
@@ -92,6 +97,41 @@ impl<T: Transaction> Executor<T> for IndexLookup {
     }
 }
 
+/// A multi-column index lookup executor, for values that can be a prefix of the index columns
+pub struct IndexPrefixLookup {
+    table: String,
+    index: String,
+    values: Vec<Vec<Value>>,
+}
+
+impl IndexPrefixLookup {
+    pub fn new(table: String, index: String, values: Vec<Vec<Value>>) -> Box<Self> {
+        Box::new(Self { table, index, values })
+    }
+}
+
+impl<T: Transaction> Executor<T> for IndexPrefixLookup {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_read_table(&self.table)?;
+
+        let mut pks: HashSet<Vec<Value>> = HashSet::new();
+        for values in self.values {
+            pks.extend(txn.read_index_prefix(&self.index, &values)?);
+        }
+
+        // FIXME Is there a way to pass the txn into an iterator closure instead?
+        let rows = pks
+            .into_iter()
+            .filter_map(|pk| txn.read(&table.name, &pk).transpose())
+            .collect::<Result<Vec<Row>>>()?;
+
+        Ok(ResultSet::Query {
+            columns: table.columns.iter().map(|c| Column { name: Some(c.name.clone()) }).collect(),
+            rows: Box::new(rows.into_iter().map(Ok)),
+        })
+    }
+}
+
 /// An executor that produces a single empty row
 pub struct Nothing;
 
@@ -109,3 +149,24 @@ impl<T: Transaction> Executor<T> for Nothing {
         })
     }
 }
+
+/// A work table executor, which produces the rows bound to the work table of a recursive query
+pub struct WorkTable {
+    columns: Vec<Option<String>>,
+    rows: Vec<Row>,
+}
+
+impl WorkTable {
+    pub fn new(columns: Vec<Option<String>>, rows: Vec<Row>) -> Box<Self> {
+        Box::new(Self { columns, rows })
+    }
+}
+
+impl<T: Transaction> Executor<T> for WorkTable {
+    fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::Query {
+            columns: self.columns.into_iter().map(|name| Column { name }).collect(),
+            rows: Box::new(self.rows.into_iter().map(Ok)),
+        })
+    }
+}
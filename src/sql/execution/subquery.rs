@@ -0,0 +1,100 @@
+use super::super::engine::Transaction;
+use super::super::plan::{Node, Plan, Subquery};
+use super::super::types::{Column, Expression, Row, Value};
+use super::{Executor, ResultSet};
+use crate::error::{Error, Result};
+
+/// An apply executor, which evaluates a subquery for each source row and appends the result to
+/// the row. Outer field references in the subquery are bound to the source row's values before
+/// it is optimized and executed, and subqueries without such references are only executed once.
+pub struct Apply<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    subquery: Node,
+    kind: Subquery,
+    level: usize,
+}
+
+impl<T: Transaction> Apply<T> {
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        subquery: Node,
+        kind: Subquery,
+        level: usize,
+    ) -> Box<Self> {
+        Box::new(Self { source, subquery, kind, level })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for Apply<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let Self { source, subquery, kind, level } = *self;
+        if let ResultSet::Query { mut columns, rows } = source.execute(txn)? {
+            // The subquery must be executed in the transaction, so we fetch all source rows first.
+            let rows = rows.collect::<Result<Vec<_>>>()?;
+            let mut applied = Vec::with_capacity(rows.len());
+            let mut cached: Option<Vec<Row>> = None;
+            for mut row in rows {
+                let results = match &cached {
+                    Some(results) => results.clone(),
+                    None => {
+                        let (subquery, correlated) = subquery.clone().bind_outer(level, &row)?;
+                        let results = Self::fetch(&kind, subquery, txn)?;
+                        if !correlated {
+                            cached = Some(results.clone());
+                        }
+                        results
+                    }
+                };
+                row.push(Self::evaluate(&kind, &row, results)?);
+                applied.push(row);
+            }
+            columns.push(Column { name: None });
+            Ok(ResultSet::Query { columns, rows: Box::new(applied.into_iter().map(Ok)) })
+        } else {
+            Err(Error::Internal("Unexpected result".into()))
+        }
+    }
+}
+
+impl<T: Transaction + 'static> Apply<T> {
+    /// Optimizes and executes a bound subquery, returning as many rows as are needed to evaluate
+    /// it: one for EXISTS, two for scalar subqueries (to detect multiple rows), and all for IN.
+    fn fetch(kind: &Subquery, subquery: Node, txn: &mut T) -> Result<Vec<Row>> {
+        let limit = match kind {
+            Subquery::Exists => 1,
+            Subquery::In(_) => usize::MAX,
+            Subquery::Scalar => 2,
+        };
+        match Plan(subquery).optimize(txn)?.execute(txn)? {
+            ResultSet::Query { rows, .. } => rows.take(limit).collect(),
+            r => Err(Error::Internal(format!("Unexpected subquery result {:?}", r))),
+        }
+    }
+
+    /// Evaluates the subquery result for a source row.
+    fn evaluate(kind: &Subquery, row: &Row, mut results: Vec<Row>) -> Result<Value> {
+        match kind {
+            Subquery::Exists => Ok(Value::Boolean(!results.is_empty())),
+            Subquery::Scalar => match results.len() {
+                0 => Ok(Value::Null),
+                1 => Ok(results.remove(0).into_iter().next().unwrap_or(Value::Null)),
+                _ => Err(Error::Value("Subquery returned more than one row".into())),
+            },
+            // IN uses the same semantics as a chain of OR'ed equality comparisons, i.e. it
+            // returns NULL rather than false if there is no match but a comparison was NULL.
+            Subquery::In(expr) => {
+                let lhs = Expression::Constant(expr.evaluate(Some(row))?);
+                let mut result = Value::Boolean(false);
+                for value in results.into_iter().filter_map(|r| r.into_iter().next()) {
+                    let rhs = Expression::Constant(value);
+                    match Expression::Equal(lhs.clone().into(), rhs.into()).evaluate(None)? {
+                        Value::Boolean(true) => return Ok(Value::Boolean(true)),
+                        Value::Null => result = Value::Null,
+                        _ => {}
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use super::super::engine::Transaction;
+use super::super::plan::{self, Aggregate, Direction, FrameBound, WindowFunction};
+use super::super::types::{Column, Expression, Row, Value};
+use super::aggregation::Accumulator;
+use super::{Executor, ResultSet};
+use crate::error::{Error, Result};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A window executor, which evaluates window functions over the source rows and appends a column
+/// with the result of each window function to them. Rows are emitted in source order.
+pub struct Window<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    windows: Vec<plan::Window>,
+}
+
+impl<T: Transaction> Window<T> {
+    pub fn new(source: Box<dyn Executor<T>>, windows: Vec<plan::Window>) -> Box<Self> {
+        Box::new(Self { source, windows })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Window<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { mut columns, rows } => {
+                let mut rows = rows.collect::<Result<Vec<_>>>()?;
+                for window in &self.windows {
+                    let values = evaluate(window, &rows)?;
+                    for (row, value) in rows.iter_mut().zip(values) {
+                        row.push(value);
+                    }
+                    columns.push(Column { name: None });
+                }
+                Ok(ResultSet::Query { columns, rows: Box::new(rows.into_iter().map(Ok)) })
+            }
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+}
+
+/// Evaluates a window function for each of the given rows, returning the results in row order.
+fn evaluate(window: &plan::Window, rows: &[Row]) -> Result<Vec<Value>> {
+    // Pre-evaluate the argument and order values of each row, and group the row indexes by
+    // partition, in order of appearance.
+    let mut args = Vec::with_capacity(rows.len());
+    let mut orders = Vec::with_capacity(rows.len());
+    let mut partitions: Vec<Vec<usize>> = Vec::new();
+    let mut partition_index: HashMap<Vec<Value>, usize> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let key =
+            window.partition.iter().map(|e| e.evaluate(Some(row))).collect::<Result<Vec<_>>>()?;
+        let p = *partition_index.entry(key).or_insert_with(|| {
+            partitions.push(Vec::new());
+            partitions.len() - 1
+        });
+        partitions[p].push(i);
+        args.push(window.args.iter().map(|e| e.evaluate(Some(row))).collect::<Result<Vec<_>>>()?);
+        orders.push(
+            window.order.iter().map(|(e, _)| e.evaluate(Some(row))).collect::<Result<Vec<_>>>()?,
+        );
+    }
+
+    let mut results = vec![Value::Null; rows.len()];
+    for mut partition in partitions {
+        let compare = |a: usize, b: usize| compare_order(&window.order, &orders[a], &orders[b]);
+        partition.sort_by(|a, b| compare(*a, *b));
+
+        // Find the first and last peer of each row, i.e. rows with equal order values.
+        let len = partition.len();
+        let mut peer_start = vec![0; len];
+        for pos in 1..len {
+            peer_start[pos] = match compare(partition[pos - 1], partition[pos]) {
+                Ordering::Equal => peer_start[pos - 1],
+                _ => pos,
+            };
+        }
+        let mut peer_end = vec![len - 1; len];
+        for pos in (0..len - 1).rev() {
+            peer_end[pos] = match compare(partition[pos], partition[pos + 1]) {
+                Ordering::Equal => peer_end[pos + 1],
+                _ => pos,
+            };
+        }
+
+        let mut aggregates = match &window.function {
+            WindowFunction::Aggregate(aggregate) => {
+                let frames = (0..len)
+                    .map(|pos| match &window.frame {
+                        Some((start, end)) => frame(pos, len, start, end),
+                        None if window.order.is_empty() => (0, len as i64 - 1),
+                        None => (0, peer_end[pos] as i64),
+                    })
+                    .collect::<Vec<_>>();
+                let (from_start, to_end) = match &window.frame {
+                    Some((start, end)) => (
+                        *start == FrameBound::UnboundedPreceding,
+                        *end == FrameBound::UnboundedFollowing,
+                    ),
+                    None => (true, false),
+                };
+                let values = partition.iter().map(|i| &args[*i][0]).collect::<Vec<_>>();
+                aggregate_frames(aggregate, &values, &frames, from_start, to_end)?
+            }
+            _ => Vec::new(),
+        };
+
+        let mut dense_rank = 0;
+        for (pos, &i) in partition.iter().enumerate() {
+            if peer_start[pos] == pos {
+                dense_rank += 1;
+            }
+            results[i] = match &window.function {
+                WindowFunction::RowNumber => Value::Integer(pos as i64 + 1),
+                WindowFunction::Rank => Value::Integer(peer_start[pos] as i64 + 1),
+                WindowFunction::DenseRank => Value::Integer(dense_rank),
+                WindowFunction::Lag | WindowFunction::Lead => {
+                    let offset = match args[i].get(1) {
+                        Some(Value::Integer(o)) if *o >= 0 => *o as usize,
+                        Some(v) => {
+                            return Err(Error::Value(format!(
+                                "Invalid offset {} for {}",
+                                v, window.function
+                            )))
+                        }
+                        None => 1,
+                    };
+                    let target = match window.function {
+                        WindowFunction::Lag => pos.checked_sub(offset),
+                        _ => pos.checked_add(offset).filter(|t| *t < len),
+                    };
+                    match target {
+                        Some(t) => args[partition[t]][0].clone(),
+                        None => args[i].get(2).cloned().unwrap_or(Value::Null),
+                    }
+                }
+                WindowFunction::Aggregate(_) => {
+                    std::mem::replace(&mut aggregates[pos], Value::Null)
+                }
+            };
+        }
+    }
+    Ok(results)
+}
+
+/// Evaluates an aggregate over the frame of each row in a partition, given the argument values in
+/// partition order and the frame positions of each row. Frames that start at the partition start
+/// only grow with the row position, and frames that end at the partition end only shrink, so
+/// these are evaluated in a single pass with a running accumulator, forwards or backwards
+/// respectively. Other frames slide, and are accumulated from scratch for each row.
+fn aggregate_frames(
+    aggregate: &Aggregate,
+    values: &[&Value],
+    frames: &[(i64, i64)],
+    from_start: bool,
+    to_end: bool,
+) -> Result<Vec<Value>> {
+    let len = values.len() as i64;
+    let mut results = vec![Value::Null; values.len()];
+    if from_start {
+        let mut accumulator = <dyn Accumulator>::from(aggregate);
+        let mut next = 0;
+        for (pos, (_, end)) in frames.iter().enumerate() {
+            while next <= (*end).min(len - 1) {
+                accumulator.accumulate(values[next as usize])?;
+                next += 1;
+            }
+            results[pos] = accumulator.aggregate();
+        }
+    } else if to_end {
+        let mut accumulator = <dyn Accumulator>::from(aggregate);
+        let mut next = len - 1;
+        for (pos, (start, _)) in frames.iter().enumerate().rev() {
+            while next >= (*start).max(0) {
+                accumulator.accumulate(values[next as usize])?;
+                next -= 1;
+            }
+            results[pos] = accumulator.aggregate();
+        }
+    } else {
+        for (pos, (start, end)) in frames.iter().enumerate() {
+            let mut accumulator = <dyn Accumulator>::from(aggregate);
+            for t in (*start).max(0)..=(*end).min(len - 1) {
+                accumulator.accumulate(values[t as usize])?;
+            }
+            results[pos] = accumulator.aggregate();
+        }
+    }
+    Ok(results)
+}
+
+/// Returns the inclusive start and end positions of a row's frame within its partition. These
+/// may be out of bounds, or describe an empty frame.
+fn frame(pos: usize, len: usize, start: &FrameBound, end: &FrameBound) -> (i64, i64) {
+    let position = |bound: &FrameBound| match bound {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(n) => pos as i64 - *n as i64,
+        FrameBound::CurrentRow => pos as i64,
+        FrameBound::Following(n) => pos as i64 + *n as i64,
+        FrameBound::UnboundedFollowing => len as i64 - 1,
+    };
+    (position(start), position(end))
+}
+
+/// Compares the order values of two rows, in the same way as the order executor.
+fn compare_order(order: &[(Expression, Direction)], a: &[Value], b: &[Value]) -> Ordering {
+    for (i, (_, direction)) in order.iter().enumerate() {
+        match a[i].partial_cmp(&b[i]) {
+            Some(Ordering::Equal) | None => {}
+            Some(o) if *direction == Direction::Ascending => return o,
+            Some(o) => return o.reverse(),
+        }
+    }
+    Ordering::Equal
+}
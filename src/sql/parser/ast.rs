@@ -1,4 +1,4 @@
-use super::super::types::DataType;
+use super::super::types::{DataType, Value};
 use crate::error::Result;
 
 use std::collections::BTreeMap;
@@ -15,12 +15,27 @@ pub enum Statement {
     Commit,
     Rollback,
     Explain(Box<Statement>),
+    Set {
+        name: String,
+        value: Literal,
+    },
 
+    AlterTable {
+        name: String,
+        alteration: Alteration,
+    },
     CreateTable {
         name: String,
         columns: Vec<Column>,
     },
     DropTable(String),
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+    },
+    DropIndex(String),
 
     Delete {
         table: String,
@@ -47,6 +62,38 @@ pub enum Statement {
         offset: Option<Expression>,
         limit: Option<Expression>,
     },
+    SetOperation {
+        operator: SetOperator,
+        all: bool,
+        left: Box<Statement>,
+        right: Box<Statement>,
+        order: Vec<(Expression, Order)>,
+        offset: Option<Expression>,
+        limit: Option<Expression>,
+    },
+    With {
+        ctes: Vec<CommonTableExpression>,
+        query: Box<Statement>,
+    },
+}
+
+/// A common table expression, i.e. a named query in a WITH clause
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Option<Vec<String>>,
+    pub query: Box<Statement>,
+    /// Whether the CTE was given in a WITH RECURSIVE clause. If so, and the query is a UNION, the
+    /// right-hand query can reference the CTE itself.
+    pub recursive: bool,
+}
+
+/// A set operator, combining the results of two queries
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
 }
 
 /// A FROM item
@@ -62,6 +109,10 @@ pub enum FromItem {
         r#type: JoinType,
         predicate: Option<Expression>,
     },
+    Subquery {
+        query: Box<Statement>,
+        alias: String,
+    },
 }
 
 /// A JOIN type
@@ -73,6 +124,15 @@ pub enum JoinType {
     Right,
 }
 
+/// A table alteration
+#[derive(Clone, Debug, PartialEq)]
+pub enum Alteration {
+    AddColumn(Column),
+    DropColumn(String),
+    RenameColumn { from: String, to: String },
+    RenameTable(String),
+}
+
 /// A column
 #[derive(Clone, Debug, PartialEq)]
 pub struct Column {
@@ -100,7 +160,10 @@ pub enum Expression {
     Column(usize), // only used during plan building to break off expression subtrees
     Literal(Literal),
     Function(String, Vec<Expression>),
+    Window(String, Vec<Expression>, Window),
     Operation(Operation),
+    Exists(Box<Statement>),
+    Subquery(Box<Statement>),
 }
 
 impl From<Literal> for Expression {
@@ -115,6 +178,25 @@ impl From<Operation> for Expression {
     }
 }
 
+/// A window specification for a window function, i.e. an OVER clause
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    pub partition: Vec<Expression>,
+    pub order: Vec<(Expression, Order)>,
+    /// The ROWS frame as start and end bounds, if given.
+    pub frame: Option<(FrameBound, FrameBound)>,
+}
+
+/// A window frame bound
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Box<Expression>),
+    CurrentRow,
+    Following(Box<Expression>),
+    UnboundedFollowing,
+}
+
 /// Literals
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
@@ -125,6 +207,18 @@ pub enum Literal {
     String(String),
 }
 
+impl From<Literal> for Value {
+    fn from(literal: Literal) -> Self {
+        match literal {
+            Literal::Null => Value::Null,
+            Literal::Boolean(b) => Value::Boolean(b),
+            Literal::Integer(i) => Value::Integer(i),
+            Literal::Float(f) => Value::Float(f),
+            Literal::String(s) => Value::String(s),
+        }
+    }
+}
+
 /// Operations (done by operators)
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operation {
@@ -137,6 +231,8 @@ pub enum Operation {
     Equal(Box<Expression>, Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
     GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    In(Box<Expression>, Vec<Expression>),
+    InSubquery(Box<Expression>, Box<Statement>),
     IsNull(Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
     LessThanOrEqual(Box<Expression>, Box<Expression>),
@@ -203,19 +299,41 @@ impl Expression {
 
             Self::Operation(Assert(expr))
             | Self::Operation(Factorial(expr))
+            | Self::Operation(InSubquery(expr, _))
             | Self::Operation(IsNull(expr))
             | Self::Operation(Negate(expr))
             | Self::Operation(Not(expr)) => {
                 Self::replace_with(expr, |e| e.transform(before, after))?
             }
 
+            Self::Operation(In(expr, list)) => {
+                Self::replace_with(expr, |e| e.transform(before, after))?;
+                for expr in list {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+            }
+
             Self::Function(_, exprs) => {
                 for expr in exprs {
                     Self::replace_with(expr, |e| e.transform(before, after))?;
                 }
             }
 
-            Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => {}
+            Self::Window(_, exprs, window) => {
+                for expr in exprs.iter_mut().chain(window.partition.iter_mut()) {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+                for (expr, _) in window.order.iter_mut() {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+            }
+
+            // Subqueries have their own scope, and are not descended into.
+            Self::Literal(_)
+            | Self::Field(_, _)
+            | Self::Column(_)
+            | Self::Exists(_)
+            | Self::Subquery(_) => {}
         };
         after(self)
     }
@@ -252,10 +370,15 @@ impl Expression {
 
                 Self::Operation(Assert(expr))
                 | Self::Operation(Factorial(expr))
+                | Self::Operation(InSubquery(expr, _))
                 | Self::Operation(IsNull(expr))
                 | Self::Operation(Negate(expr))
                 | Self::Operation(Not(expr)) => expr.walk(visitor),
 
+                Self::Operation(In(expr, list)) => {
+                    expr.walk(visitor) && list.iter().all(|e| e.walk(visitor))
+                }
+
                 Self::Function(_, exprs) => {
                     for expr in exprs {
                         if !expr.walk(visitor) {
@@ -265,7 +388,16 @@ impl Expression {
                     true
                 }
 
-                Self::Literal(_) | Self::Field(_, _) | Self::Column(_) => true,
+                Self::Window(_, exprs, window) => {
+                    exprs.iter().chain(window.partition.iter()).all(|e| e.walk(visitor))
+                        && window.order.iter().all(|(e, _)| e.walk(visitor))
+                }
+
+                Self::Literal(_)
+                | Self::Field(_, _)
+                | Self::Column(_)
+                | Self::Exists(_)
+                | Self::Subquery(_) => true,
             }
     }
 }
@@ -72,34 +72,45 @@ impl From<Keyword> for Token {
 /// Lexer keywords
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keyword {
+    Add,
+    All,
+    Alter,
     And,
     As,
     Asc,
     Begin,
+    Between,
     Bool,
     Boolean,
     By,
     Char,
+    Column,
     Commit,
     Create,
     Cross,
+    Current,
     Default,
     Delete,
     Desc,
     Double,
     Drop,
+    Except,
+    Exists,
     Explain,
     False,
     Float,
+    Following,
     From,
     Group,
     Having,
+    In,
     Index,
     Infinity,
     Inner,
     Insert,
     Int,
     Integer,
+    Intersect,
     Into,
     Is,
     Join,
@@ -117,11 +128,18 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Over,
+    Partition,
+    Preceding,
     Primary,
     Read,
+    Recursive,
     References,
+    Rename,
     Right,
     Rollback,
+    Row,
+    Rows,
     Select,
     Set,
     String,
@@ -129,13 +147,17 @@ pub enum Keyword {
     Table,
     Text,
     Time,
+    To,
     Transaction,
     True,
+    Unbounded,
+    Union,
     Unique,
     Update,
     Values,
     Varchar,
     Where,
+    With,
     Write,
 }
 
@@ -143,34 +165,45 @@ impl Keyword {
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(ident: &str) -> Option<Self> {
         Some(match ident.to_uppercase().as_ref() {
+            "ADD" => Self::Add,
+            "ALL" => Self::All,
+            "ALTER" => Self::Alter,
             "AS" => Self::As,
             "ASC" => Self::Asc,
             "AND" => Self::And,
             "BEGIN" => Self::Begin,
+            "BETWEEN" => Self::Between,
             "BOOL" => Self::Bool,
             "BOOLEAN" => Self::Boolean,
             "BY" => Self::By,
             "CHAR" => Self::Char,
+            "COLUMN" => Self::Column,
             "COMMIT" => Self::Commit,
             "CREATE" => Self::Create,
             "CROSS" => Self::Cross,
+            "CURRENT" => Self::Current,
             "DEFAULT" => Self::Default,
             "DELETE" => Self::Delete,
             "DESC" => Self::Desc,
             "DOUBLE" => Self::Double,
             "DROP" => Self::Drop,
+            "EXCEPT" => Self::Except,
+            "EXISTS" => Self::Exists,
             "EXPLAIN" => Self::Explain,
             "FALSE" => Self::False,
             "FLOAT" => Self::Float,
+            "FOLLOWING" => Self::Following,
             "FROM" => Self::From,
             "GROUP" => Self::Group,
             "HAVING" => Self::Having,
+            "IN" => Self::In,
             "INDEX" => Self::Index,
             "INFINITY" => Self::Infinity,
             "INNER" => Self::Inner,
             "INSERT" => Self::Insert,
             "INT" => Self::Int,
             "INTEGER" => Self::Integer,
+            "INTERSECT" => Self::Intersect,
             "INTO" => Self::Into,
             "IS" => Self::Is,
             "JOIN" => Self::Join,
@@ -188,11 +221,18 @@ impl Keyword {
             "OR" => Self::Or,
             "ORDER" => Self::Order,
             "OUTER" => Self::Outer,
+            "OVER" => Self::Over,
+            "PARTITION" => Self::Partition,
+            "PRECEDING" => Self::Preceding,
             "PRIMARY" => Self::Primary,
             "READ" => Self::Read,
+            "RECURSIVE" => Self::Recursive,
             "REFERENCES" => Self::References,
+            "RENAME" => Self::Rename,
             "RIGHT" => Self::Right,
             "ROLLBACK" => Self::Rollback,
+            "ROW" => Self::Row,
+            "ROWS" => Self::Rows,
             "SELECT" => Self::Select,
             "SET" => Self::Set,
             "STRING" => Self::String,
@@ -200,13 +240,17 @@ impl Keyword {
             "TABLE" => Self::Table,
             "TEXT" => Self::Text,
             "TIME" => Self::Time,
+            "TO" => Self::To,
             "TRANSACTION" => Self::Transaction,
             "TRUE" => Self::True,
+            "UNBOUNDED" => Self::Unbounded,
+            "UNION" => Self::Union,
             "UNIQUE" => Self::Unique,
             "UPDATE" => Self::Update,
             "VALUES" => Self::Values,
             "VARCHAR" => Self::Varchar,
             "WHERE" => Self::Where,
+            "WITH" => Self::With,
             "WRITE" => Self::Write,
             _ => return None,
         })
@@ -214,34 +258,45 @@ impl Keyword {
 
     pub fn to_str(&self) -> &str {
         match self {
+            Self::Add => "ADD",
+            Self::All => "ALL",
+            Self::Alter => "ALTER",
             Self::As => "AS",
             Self::Asc => "ASC",
             Self::And => "AND",
             Self::Begin => "BEGIN",
+            Self::Between => "BETWEEN",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
             Self::Char => "CHAR",
+            Self::Column => "COLUMN",
             Self::Commit => "COMMIT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Current => "CURRENT",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
+            Self::Except => "EXCEPT",
+            Self::Exists => "EXISTS",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
             Self::Float => "FLOAT",
+            Self::Following => "FOLLOWING",
             Self::From => "FROM",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
+            Self::In => "IN",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
             Self::Insert => "INSERT",
             Self::Int => "INT",
             Self::Integer => "INTEGER",
+            Self::Intersect => "INTERSECT",
             Self::Into => "INTO",
             Self::Is => "IS",
             Self::Join => "JOIN",
@@ -257,13 +312,20 @@ impl Keyword {
             Self::On => "ON",
             Self::Only => "ONLY",
             Self::Outer => "OUTER",
+            Self::Over => "OVER",
+            Self::Partition => "PARTITION",
+            Self::Preceding => "PRECEDING",
             Self::Or => "OR",
             Self::Order => "ORDER",
             Self::Primary => "PRIMARY",
             Self::Read => "READ",
+            Self::Recursive => "RECURSIVE",
             Self::References => "REFERENCES",
+            Self::Rename => "RENAME",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
+            Self::Row => "ROW",
+            Self::Rows => "ROWS",
             Self::Select => "SELECT",
             Self::Set => "SET",
             Self::String => "STRING",
@@ -271,13 +333,17 @@ impl Keyword {
             Self::Table => "TABLE",
             Self::Text => "TEXT",
             Self::Time => "TIME",
+            Self::To => "TO",
             Self::Transaction => "TRANSACTION",
             Self::True => "TRUE",
+            Self::Unbounded => "UNBOUNDED",
+            Self::Union => "UNION",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         }
     }
@@ -290,6 +356,7 @@ impl std::fmt::Display for Keyword {
 }
 
 /// A lexer tokenizes an input string as an iterator
+#[derive(Clone)]
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
 }
@@ -300,7 +367,9 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Result<Token>> {
         match self.scan() {
             Ok(Some(token)) => Some(Ok(token)),
-            Ok(None) => self.iter.peek().map(|c| Err(Error::Parse(format!("Unexpected character {}", c)))),
+            Ok(None) => {
+                self.iter.peek().map(|c| Err(Error::Parse(format!("Unexpected character {}", c))))
+            }
             Err(err) => Some(Err(err)),
         }
     }
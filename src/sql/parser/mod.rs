@@ -96,6 +96,13 @@ impl<'a> Parser<'a> {
         self.lexer.peek().cloned().transpose()
     }
 
+    /// Peeks the lexer token after the next one, if any.
+    fn peek_second(&mut self) -> Result<Option<Token>> {
+        let mut lexer = self.lexer.clone();
+        lexer.next();
+        lexer.next().transpose()
+    }
+
     /// Parses an SQL statement
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         match self.peek()? {
@@ -103,6 +110,7 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Commit)) => self.parse_transaction(),
             Some(Token::Keyword(Keyword::Rollback)) => self.parse_transaction(),
 
+            Some(Token::Keyword(Keyword::Alter)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
 
@@ -110,8 +118,10 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Insert)) => self.parse_statement_insert(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_statement_update(),
+            Some(Token::Keyword(Keyword::With)) => self.parse_statement_with(),
 
             Some(Token::Keyword(Keyword::Explain)) => self.parse_statement_explain(),
+            Some(Token::Keyword(Keyword::Set)) => self.parse_statement_set(),
 
             Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
             None => Err(Error::Parse("Unexpected end of input".into())),
@@ -121,41 +131,140 @@ impl<'a> Parser<'a> {
     /// Parses a DDL statement
     fn parse_ddl(&mut self) -> Result<ast::Statement> {
         match self.next()? {
+            Token::Keyword(Keyword::Alter) => match self.next()? {
+                Token::Keyword(Keyword::Table) => self.parse_ddl_alter_table(),
+                token => Err(Error::Parse(format!("Unexpected token {}", token))),
+            },
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_create_index(false),
+                Token::Keyword(Keyword::Unique) => {
+                    self.next_expect(Some(Keyword::Index.into()))?;
+                    self.parse_ddl_create_index(true)
+                }
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             Token::Keyword(Keyword::Drop) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_drop_table(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_drop_index(),
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
         }
     }
 
+    /// Parses an ALTER TABLE DDL statement. The ALTER TABLE prefix has
+    /// already been consumed.
+    fn parse_ddl_alter_table(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        let alteration = match self.next()? {
+            Token::Keyword(Keyword::Add) => {
+                self.next_if_token(Keyword::Column.into());
+                ast::Alteration::AddColumn(self.parse_ddl_columnspec()?)
+            }
+            Token::Keyword(Keyword::Drop) => {
+                self.next_if_token(Keyword::Column.into());
+                ast::Alteration::DropColumn(self.next_ident()?)
+            }
+            Token::Keyword(Keyword::Rename) => {
+                if self.next_if_token(Keyword::To.into()).is_some() {
+                    ast::Alteration::RenameTable(self.next_ident()?)
+                } else {
+                    self.next_if_token(Keyword::Column.into());
+                    let from = self.next_ident()?;
+                    self.next_expect(Some(Keyword::To.into()))?;
+                    ast::Alteration::RenameColumn { from, to: self.next_ident()? }
+                }
+            }
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        Ok(ast::Statement::AlterTable { name, alteration })
+    }
+
     /// Parses a CREATE TABLE DDL statement. The CREATE TABLE prefix has
     /// already been consumed.
     fn parse_ddl_create_table(&mut self) -> Result<ast::Statement> {
         let name = self.next_ident()?;
         self.next_expect(Some(Token::OpenParen))?;
 
-        let mut columns = Vec::new();
+        let mut columns: Vec<ast::Column> = Vec::new();
+        let mut primary_key = None;
         loop {
-            columns.push(self.parse_ddl_columnspec()?);
+            if self.next_if_token(Keyword::Primary.into()).is_some() {
+                self.next_expect(Some(Keyword::Key.into()))?;
+                if primary_key.is_some() {
+                    return Err(Error::Value(format!("Multiple primary keys in table {}", name)));
+                }
+                primary_key = Some(self.parse_ddl_column_list()?);
+            } else {
+                columns.push(self.parse_ddl_columnspec()?);
+            }
             if self.next_if_token(Token::Comma).is_none() {
                 break;
             }
         }
         self.next_expect(Some(Token::CloseParen))?;
+
+        // A PRIMARY KEY table constraint marks its columns as primary key columns. The key's
+        // columns are ordered as in the table, so they must be given in table order.
+        let inline = columns.iter().filter(|c| c.primary_key).count();
+        if inline > 1 || (inline > 0 && primary_key.is_some()) {
+            return Err(Error::Value(format!("Multiple primary keys in table {}", name)));
+        }
+        if let Some(primary_key) = primary_key {
+            let mut last = None;
+            for key in primary_key {
+                let i = columns.iter().position(|c| c.name == key).ok_or_else(|| {
+                    Error::Value(format!("Primary key column {} does not exist", key))
+                })?;
+                if columns[i].primary_key || last.map(|l| i < l).unwrap_or(false) {
+                    return Err(Error::Value(
+                        "Primary key columns must be given once, in table column order".into(),
+                    ));
+                }
+                columns[i].primary_key = true;
+                last = Some(i);
+            }
+        }
         Ok(ast::Statement::CreateTable { name, columns })
     }
 
+    /// Parses a parenthesized, comma-separated list of column names
+    fn parse_ddl_column_list(&mut self) -> Result<Vec<String>> {
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.next_ident()?);
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(columns)
+    }
+
     /// Parses a DROP TABLE DDL statement. The DROP TABLE prefix has
     /// already been consumed.
     fn parse_ddl_drop_table(&mut self) -> Result<ast::Statement> {
         Ok(ast::Statement::DropTable(self.next_ident()?))
     }
 
+    /// Parses a CREATE [UNIQUE] INDEX DDL statement. The CREATE [UNIQUE] INDEX prefix has
+    /// already been consumed.
+    fn parse_ddl_create_index(&mut self, unique: bool) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.next_expect(Some(Keyword::On.into()))?;
+        let table = self.next_ident()?;
+        let columns = self.parse_ddl_column_list()?;
+        Ok(ast::Statement::CreateIndex { name, table, columns, unique })
+    }
+
+    /// Parses a DROP INDEX DDL statement. The DROP INDEX prefix has
+    /// already been consumed.
+    fn parse_ddl_drop_index(&mut self) -> Result<ast::Statement> {
+        Ok(ast::Statement::DropIndex(self.next_ident()?))
+    }
+
     /// Parses a column specification
     fn parse_ddl_columnspec(&mut self) -> Result<ast::Column> {
         let mut column = ast::Column {
@@ -275,26 +384,137 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::Insert { table, columns, values })
     }
 
-    /// Parses a select statement
+    /// Parses a select statement, possibly combined with other select statements via set
+    /// operations. Any ORDER BY, LIMIT, and OFFSET clauses apply to the combined result.
     fn parse_statement_select(&mut self) -> Result<ast::Statement> {
+        let mut statement = self.parse_statement_select_operation(1)?;
+        let (order, offset, limit) = match &mut statement {
+            ast::Statement::Select { order, offset, limit, .. }
+            | ast::Statement::SetOperation { order, offset, limit, .. } => (order, offset, limit),
+            statement => {
+                return Err(Error::Internal(format!("Unexpected statement {:?}", statement)))
+            }
+        };
+        *order = self.parse_clause_order()?;
+        *limit = if self.next_if_token(Keyword::Limit.into()).is_some() {
+            Some(self.parse_expression(0)?)
+        } else {
+            None
+        };
+        *offset = if self.next_if_token(Keyword::Offset.into()).is_some() {
+            Some(self.parse_expression(0)?)
+        } else {
+            None
+        };
+        Ok(statement)
+    }
+
+    /// Parses set operations of select statements using precedence climbing. INTERSECT binds
+    /// tighter than UNION and EXCEPT, which are evaluated left to right.
+    fn parse_statement_select_operation(&mut self, min_prec: u8) -> Result<ast::Statement> {
+        let mut lhs = self.parse_statement_select_single()?;
+        loop {
+            let (operator, prec) = match self.peek()? {
+                Some(Token::Keyword(Keyword::Union)) => (ast::SetOperator::Union, 1),
+                Some(Token::Keyword(Keyword::Except)) => (ast::SetOperator::Except, 1),
+                Some(Token::Keyword(Keyword::Intersect)) => (ast::SetOperator::Intersect, 2),
+                _ => break,
+            };
+            if prec < min_prec {
+                break;
+            }
+            self.next()?;
+            let all = self.next_if_token(Keyword::All.into()).is_some();
+            let rhs = self.parse_statement_select_operation(prec + 1)?;
+            lhs = ast::Statement::SetOperation {
+                operator,
+                all,
+                left: Box::new(lhs),
+                right: Box::new(rhs),
+                order: vec![],
+                offset: None,
+                limit: None,
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// Parses a single select statement, without ORDER BY, LIMIT, and OFFSET clauses
+    fn parse_statement_select_single(&mut self) -> Result<ast::Statement> {
+        match self.peek()? {
+            Some(Token::Keyword(Keyword::Select)) => {}
+            Some(token) => return Err(Error::Parse(format!("Unexpected token {}", token))),
+            None => return Err(Error::Parse("Unexpected end of input".into())),
+        }
         Ok(ast::Statement::Select {
             select: self.parse_clause_select()?,
             from: self.parse_clause_from()?,
             r#where: self.parse_clause_where()?,
             group_by: self.parse_clause_group_by()?,
             having: self.parse_clause_having()?,
-            order: self.parse_clause_order()?,
-            limit: if self.next_if_token(Keyword::Limit.into()).is_some() {
-                Some(self.parse_expression(0)?)
-            } else {
-                None
-            },
-            offset: if self.next_if_token(Keyword::Offset.into()).is_some() {
-                Some(self.parse_expression(0)?)
+            order: vec![],
+            offset: None,
+            limit: None,
+        })
+    }
+
+    /// Parses a query with a WITH clause of common table expressions
+    fn parse_statement_with(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::With.into()))?;
+        let recursive = self.next_if_token(Keyword::Recursive.into()).is_some();
+        let mut ctes = Vec::new();
+        loop {
+            let name = self.next_ident()?;
+            let columns = if self.next_if_token(Token::OpenParen).is_some() {
+                let mut columns = Vec::new();
+                loop {
+                    columns.push(self.next_ident()?);
+                    match self.next()? {
+                        Token::CloseParen => break,
+                        Token::Comma => {}
+                        token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                    }
+                }
+                Some(columns)
             } else {
                 None
-            },
-        })
+            };
+            self.next_expect(Some(Keyword::As.into()))?;
+            self.next_expect(Some(Token::OpenParen))?;
+            let query = Box::new(self.parse_query()?);
+            self.next_expect(Some(Token::CloseParen))?;
+            ctes.push(ast::CommonTableExpression { name, columns, query, recursive });
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        Ok(ast::Statement::With { ctes, query: Box::new(self.parse_query()?) })
+    }
+
+    /// Parses a query, i.e. a SELECT statement with an optional WITH clause
+    fn parse_query(&mut self) -> Result<ast::Statement> {
+        match self.peek()? {
+            Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
+            Some(Token::Keyword(Keyword::With)) => self.parse_statement_with(),
+            Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
+            None => Err(Error::Parse("Unexpected end of input".into())),
+        }
+    }
+
+    /// Checks whether the next token starts a query
+    fn peek_query(&mut self) -> Result<bool> {
+        Ok(matches!(self.peek()?, Some(Token::Keyword(Keyword::Select | Keyword::With))))
+    }
+
+    /// Parses a SET statement for a session setting
+    fn parse_statement_set(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::Set.into()))?;
+        let name = self.next_ident()?;
+        self.next_expect(Some(Token::Equal))?;
+        match self.parse_expression(0)? {
+            ast::Expression::Literal(value) => Ok(ast::Statement::Set { name, value }),
+            expr => Err(Error::Parse(format!("Expected constant value, got {:?}", expr))),
+        }
     }
 
     /// Parses an update statement
@@ -387,7 +607,20 @@ impl<'a> Parser<'a> {
 
     /// Parses a from clause item
     fn parse_clause_from_item(&mut self) -> Result<ast::FromItem> {
-        self.parse_clause_from_table()
+        if self.next_if_token(Token::OpenParen).is_some() {
+            self.parse_clause_from_subquery()
+        } else {
+            self.parse_clause_from_table()
+        }
+    }
+
+    // Parses a from clause subquery, after the opening parenthesis. These must have an alias.
+    fn parse_clause_from_subquery(&mut self) -> Result<ast::FromItem> {
+        let query = Box::new(self.parse_query()?);
+        self.next_expect(Some(Token::CloseParen))?;
+        self.next_if_token(Keyword::As.into());
+        let alias = self.next_ident()?;
+        Ok(ast::FromItem::Subquery { query, alias })
     }
 
     // Parses a from clause table
@@ -518,15 +751,84 @@ impl<'a> Parser<'a> {
         } else {
             self.parse_expression_atom()?
         };
-        while let Some(postfix) = self.next_if_operator::<PostfixOperator>(min_prec)? {
-            lhs = postfix.build(lhs)
-        }
-        while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
-            lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
+        loop {
+            if let Some(postfix) = self.next_if_postfix_operator(min_prec)? {
+                lhs = postfix.build(lhs)
+            } else if let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
+                lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
+            } else {
+                break;
+            }
         }
         Ok(lhs)
     }
 
+    /// Grabs the next postfix operator if it satisfies the precedence. NOT is only a postfix
+    /// operator when followed by IN, which requires an additional token of lookahead.
+    fn next_if_postfix_operator(&mut self, min_prec: u8) -> Result<Option<PostfixOperator>> {
+        if self.peek()? == Some(Keyword::Not.into())
+            && self.peek_second()? != Some(Keyword::In.into())
+        {
+            return Ok(None);
+        }
+        self.next_if_operator(min_prec)
+    }
+
+    /// Parses a subquery, after the opening parenthesis
+    fn parse_expression_subquery(&mut self) -> Result<Box<ast::Statement>> {
+        let query = self.parse_query()?;
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(Box::new(query))
+    }
+
+    /// Parses a window specification, after the OVER keyword
+    fn parse_window(&mut self) -> Result<ast::Window> {
+        self.next_expect(Some(Token::OpenParen))?;
+        let mut partition = Vec::new();
+        if self.next_if_token(Keyword::Partition.into()).is_some() {
+            self.next_expect(Some(Keyword::By.into()))?;
+            loop {
+                partition.push(self.parse_expression(0)?);
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+        }
+        let order = self.parse_clause_order()?;
+        let frame = if self.next_if_token(Keyword::Rows.into()).is_some() {
+            if self.next_if_token(Keyword::Between.into()).is_some() {
+                let start = self.parse_window_frame_bound()?;
+                self.next_expect(Some(Keyword::And.into()))?;
+                Some((start, self.parse_window_frame_bound()?))
+            } else {
+                Some((self.parse_window_frame_bound()?, ast::FrameBound::CurrentRow))
+            }
+        } else {
+            None
+        };
+        self.next_expect(Some(Token::CloseParen))?;
+        Ok(ast::Window { partition, order, frame })
+    }
+
+    /// Parses a window frame bound
+    fn parse_window_frame_bound(&mut self) -> Result<ast::FrameBound> {
+        if self.next_if_token(Keyword::Current.into()).is_some() {
+            self.next_expect(Some(Keyword::Row.into()))?;
+            return Ok(ast::FrameBound::CurrentRow);
+        }
+        let offset = match self.next_if_token(Keyword::Unbounded.into()) {
+            Some(_) => None,
+            None => Some(Box::new(self.parse_expression(0)?)),
+        };
+        Ok(match (self.next()?, offset) {
+            (Token::Keyword(Keyword::Preceding), None) => ast::FrameBound::UnboundedPreceding,
+            (Token::Keyword(Keyword::Preceding), Some(o)) => ast::FrameBound::Preceding(o),
+            (Token::Keyword(Keyword::Following), None) => ast::FrameBound::UnboundedFollowing,
+            (Token::Keyword(Keyword::Following), Some(o)) => ast::FrameBound::Following(o),
+            (token, _) => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        })
+    }
+
     /// Parses an expression atom
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
@@ -544,7 +846,11 @@ impl<'a> Parser<'a> {
                             args.push(self.parse_expression(0)?);
                         }
                     }
-                    ast::Expression::Function(i, args)
+                    if self.next_if_token(Keyword::Over.into()).is_some() {
+                        ast::Expression::Window(i, args, self.parse_window()?)
+                    } else {
+                        ast::Expression::Function(i, args)
+                    }
                 } else {
                     let mut relation = None;
                     let mut field = i;
@@ -562,11 +868,18 @@ impl<'a> Parser<'a> {
                     ast::Literal::Float(n.parse()?).into()
                 }
             }
+            Token::OpenParen if self.peek_query()? => {
+                ast::Expression::Subquery(self.parse_expression_subquery()?)
+            }
             Token::OpenParen => {
                 let expr = self.parse_expression(0)?;
                 self.next_expect(Some(Token::CloseParen))?;
                 expr
             }
+            Token::Keyword(Keyword::Exists) => {
+                self.next_expect(Some(Token::OpenParen))?;
+                ast::Expression::Exists(self.parse_expression_subquery()?)
+            }
             Token::String(s) => ast::Literal::String(s).into(),
             Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
             Token::Keyword(Keyword::Infinity) => ast::Literal::Float(std::f64::INFINITY).into(),
@@ -732,19 +1045,29 @@ enum PostfixOperator {
     IsNull {
         not: bool,
     },
+    In {
+        not: bool,
+        list: Vec<ast::Expression>,
+    },
+    InSubquery {
+        not: bool,
+        query: Box<ast::Statement>,
+    },
 }
 
 impl PostfixOperator {
-    fn build(&self, lhs: ast::Expression) -> ast::Expression {
+    fn build(self, lhs: ast::Expression) -> ast::Expression {
         let lhs = Box::new(lhs);
-        match self {
-            Self::IsNull { not } => match not {
-                true => ast::Operation::Not(Box::new(ast::Operation::IsNull(lhs).into())),
-                false => ast::Operation::IsNull(lhs),
-            },
-            Self::Factorial => ast::Operation::Factorial(lhs),
+        let (op, not) = match self {
+            Self::IsNull { not } => (ast::Operation::IsNull(lhs), not),
+            Self::In { not, list } => (ast::Operation::In(lhs, list), not),
+            Self::InSubquery { not, query } => (ast::Operation::InSubquery(lhs, query), not),
+            Self::Factorial => (ast::Operation::Factorial(lhs), false),
+        };
+        match not {
+            true => ast::Operation::Not(Box::new(op.into())).into(),
+            false => op.into(),
         }
-        .into()
     }
 }
 
@@ -752,13 +1075,14 @@ impl Operator for PostfixOperator {
     fn from(token: &Token) -> Option<Self> {
         match token {
             Token::Exclamation => Some(Self::Factorial),
+            Token::Keyword(Keyword::In) => Some(Self::In { not: false, list: Vec::new() }),
             Token::Keyword(Keyword::Is) => Some(Self::IsNull { not: false }),
+            Token::Keyword(Keyword::Not) => Some(Self::In { not: true, list: Vec::new() }),
             _ => None,
         }
     }
 
     fn augment(mut self, parser: &mut Parser) -> Result<Self> {
-        #[allow(clippy::single_match)]
         match &mut self {
             Self::IsNull { ref mut not } => {
                 if parser.next_if_token(Keyword::Not.into()).is_some() {
@@ -766,7 +1090,24 @@ impl Operator for PostfixOperator {
                 };
                 parser.next_expect(Some(Keyword::Null.into()))?;
             }
-            _ => {}
+            Self::In { not, ref mut list } => {
+                if *not {
+                    parser.next_expect(Some(Keyword::In.into()))?;
+                }
+                parser.next_expect(Some(Token::OpenParen))?;
+                if parser.peek_query()? {
+                    let query = parser.parse_expression_subquery()?;
+                    return Ok(Self::InSubquery { not: *not, query });
+                }
+                loop {
+                    list.push(parser.parse_expression(0)?);
+                    if parser.next_if_token(Token::Comma).is_none() {
+                        break;
+                    }
+                }
+                parser.next_expect(Some(Token::CloseParen))?;
+            }
+            Self::Factorial | Self::InSubquery { .. } => {}
         };
         Ok(self)
     }
@@ -776,7 +1117,10 @@ impl Operator for PostfixOperator {
     }
 
     fn prec(&self) -> u8 {
-        8
+        match self {
+            Self::In { .. } | Self::InSubquery { .. } => 3,
+            Self::Factorial | Self::IsNull { .. } => 8,
+        }
     }
 }
 
@@ -6,11 +6,12 @@ use planner::Planner;
 use super::engine::Transaction;
 use super::execution::{Executor, ResultSet};
 use super::parser::ast;
-use super::schema::{Catalog, Table};
-use super::types::{Expression, Value};
-use crate::error::Result;
+use super::schema::{Alteration, Catalog, Index, Table};
+use super::types::{Expression, Row, Value};
+use crate::error::{Error, Result};
 
 use serde_derive::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::fmt::{self, Display};
 
 /// A query plan
@@ -38,21 +39,37 @@ impl Plan {
     pub fn optimize<C: Catalog>(self, catalog: &mut C) -> Result<Self> {
         let mut root = self.0;
         root = optimizer::ConstantFolder.optimize(root)?;
+        root = optimizer::Decorrelator.optimize(root)?;
         root = optimizer::FilterPushdown.optimize(root)?;
         root = optimizer::IndexLookup::new(catalog).optimize(root)?;
         root = optimizer::NoopCleaner.optimize(root)?;
+        root = optimizer::LimitPushdown.optimize(root)?;
         root = optimizer::JoinType.optimize(root)?;
         Ok(Plan(root))
     }
 }
 
 /// A plan node
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Node {
     Aggregation {
         source: Box<Node>,
         aggregates: Vec<Aggregate>,
     },
+    AlterTable {
+        table: String,
+        alteration: Alteration,
+    },
+    Apply {
+        source: Box<Node>,
+        source_size: usize,
+        subquery: Box<Node>,
+        kind: Subquery,
+        level: usize,
+    },
+    CreateIndex {
+        schema: Index,
+    },
     CreateTable {
         schema: Table,
     },
@@ -60,9 +77,17 @@ pub enum Node {
         table: String,
         source: Box<Node>,
     },
+    DropIndex {
+        name: String,
+    },
     DropTable {
         table: String,
     },
+    Except {
+        left: Box<Node>,
+        right: Box<Node>,
+        distinct: bool,
+    },
     Filter {
         source: Box<Node>,
         predicate: Expression,
@@ -80,15 +105,26 @@ pub enum Node {
         column: String,
         values: Vec<Value>,
     },
+    IndexPrefixLookup {
+        table: String,
+        alias: Option<String>,
+        index: String,
+        values: Vec<Vec<Value>>,
+    },
     Insert {
         table: String,
         columns: Vec<String>,
         expressions: Vec<Vec<Expression>>,
     },
+    Intersect {
+        left: Box<Node>,
+        right: Box<Node>,
+        distinct: bool,
+    },
     KeyLookup {
         table: String,
         alias: Option<String>,
-        keys: Vec<Value>,
+        keys: Vec<Vec<Value>>,
     },
     Limit {
         source: Box<Node>,
@@ -114,16 +150,44 @@ pub enum Node {
         source: Box<Node>,
         expressions: Vec<(Expression, Option<String>)>,
     },
+    RecursiveUnion {
+        name: String,
+        anchor: Box<Node>,
+        recursive: Box<Node>,
+        distinct: bool,
+        limit: Option<u64>,
+    },
     Scan {
         table: String,
         alias: Option<String>,
         filter: Option<Expression>,
     },
+    SemiJoin {
+        left: Box<Node>,
+        left_size: usize,
+        right: Box<Node>,
+        predicate: Option<Expression>,
+        anti: bool,
+    },
+    Union {
+        left: Box<Node>,
+        right: Box<Node>,
+        distinct: bool,
+    },
     Update {
         table: String,
         source: Box<Node>,
         expressions: Vec<(usize, Option<String>, Expression)>,
     },
+    Window {
+        source: Box<Node>,
+        windows: Vec<Window>,
+    },
+    WorkTable {
+        name: String,
+        columns: Vec<Option<String>>,
+        rows: Vec<Row>,
+    },
 }
 
 impl Node {
@@ -135,20 +199,37 @@ impl Node {
     {
         self = before(self)?;
         self = match self {
-            n @ Self::CreateTable { .. }
+            n @ Self::AlterTable { .. }
+            | n @ Self::CreateIndex { .. }
+            | n @ Self::CreateTable { .. }
+            | n @ Self::DropIndex { .. }
             | n @ Self::DropTable { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexPrefixLookup { .. }
             | n @ Self::Insert { .. }
             | n @ Self::KeyLookup { .. }
             | n @ Self::Nothing
-            | n @ Self::Scan { .. } => n,
+            | n @ Self::Scan { .. }
+            | n @ Self::WorkTable { .. } => n,
 
             Self::Aggregation { source, aggregates } => {
                 Self::Aggregation { source: source.transform(before, after)?.into(), aggregates }
             }
+            Self::Apply { source, source_size, subquery, kind, level } => Self::Apply {
+                source: source.transform(before, after)?.into(),
+                source_size,
+                subquery: subquery.transform(before, after)?.into(),
+                kind,
+                level,
+            },
             Self::Delete { table, source } => {
                 Self::Delete { table, source: source.transform(before, after)?.into() }
             }
+            Self::Except { left, right, distinct } => Self::Except {
+                left: left.transform(before, after)?.into(),
+                right: right.transform(before, after)?.into(),
+                distinct,
+            },
             Self::Filter { source, predicate } => {
                 Self::Filter { source: source.transform(before, after)?.into(), predicate }
             }
@@ -159,6 +240,11 @@ impl Node {
                 right_field,
                 outer,
             },
+            Self::Intersect { left, right, distinct } => Self::Intersect {
+                left: left.transform(before, after)?.into(),
+                right: right.transform(before, after)?.into(),
+                distinct,
+            },
             Self::Limit { source, limit } => {
                 Self::Limit { source: source.transform(before, after)?.into(), limit }
             }
@@ -180,9 +266,33 @@ impl Node {
             Self::Projection { source, expressions } => {
                 Self::Projection { source: source.transform(before, after)?.into(), expressions }
             }
+            Self::RecursiveUnion { name, anchor, recursive, distinct, limit } => {
+                Self::RecursiveUnion {
+                    name,
+                    anchor: anchor.transform(before, after)?.into(),
+                    recursive: recursive.transform(before, after)?.into(),
+                    distinct,
+                    limit,
+                }
+            }
+            Self::SemiJoin { left, left_size, right, predicate, anti } => Self::SemiJoin {
+                left: left.transform(before, after)?.into(),
+                left_size,
+                right: right.transform(before, after)?.into(),
+                predicate,
+                anti,
+            },
+            Self::Union { left, right, distinct } => Self::Union {
+                left: left.transform(before, after)?.into(),
+                right: right.transform(before, after)?.into(),
+                distinct,
+            },
             Self::Update { table, source, expressions } => {
                 Self::Update { table, source: source.transform(before, after)?.into(), expressions }
             }
+            Self::Window { source, windows } => {
+                Self::Window { source: source.transform(before, after)?.into(), windows }
+            }
         };
         after(self)
     }
@@ -195,18 +305,39 @@ impl Node {
     {
         Ok(match self {
             n @ Self::Aggregation { .. }
+            | n @ Self::AlterTable { .. }
+            | n @ Self::Apply { kind: Subquery::Exists, .. }
+            | n @ Self::Apply { kind: Subquery::Scalar, .. }
+            | n @ Self::CreateIndex { .. }
             | n @ Self::CreateTable { .. }
             | n @ Self::Delete { .. }
+            | n @ Self::DropIndex { .. }
             | n @ Self::DropTable { .. }
+            | n @ Self::Except { .. }
             | n @ Self::HashJoin { .. }
             | n @ Self::IndexLookup { .. }
+            | n @ Self::IndexPrefixLookup { .. }
+            | n @ Self::Intersect { .. }
             | n @ Self::KeyLookup { .. }
             | n @ Self::Limit { .. }
             | n @ Self::NestedLoopJoin { predicate: None, .. }
             | n @ Self::Nothing
             | n @ Self::Offset { .. }
-            | n @ Self::Scan { filter: None, .. } => n,
+            | n @ Self::RecursiveUnion { .. }
+            | n @ Self::Scan { filter: None, .. }
+            | n @ Self::SemiJoin { predicate: None, .. }
+            | n @ Self::Union { .. }
+            | n @ Self::WorkTable { .. } => n,
 
+            Self::Apply { source, source_size, subquery, kind: Subquery::In(expr), level } => {
+                Self::Apply {
+                    source,
+                    source_size,
+                    subquery,
+                    kind: Subquery::In(expr.transform(before, after)?),
+                    level,
+                }
+            }
             Self::Filter { source, predicate } => {
                 Self::Filter { source, predicate: predicate.transform(before, after)? }
             }
@@ -244,6 +375,15 @@ impl Node {
             Self::Scan { table, alias, filter: Some(filter) } => {
                 Self::Scan { table, alias, filter: Some(filter.transform(before, after)?) }
             }
+            Self::SemiJoin { left, left_size, right, predicate: Some(predicate), anti } => {
+                Self::SemiJoin {
+                    left,
+                    left_size,
+                    right,
+                    predicate: Some(predicate.transform(before, after)?),
+                    anti,
+                }
+            }
             Self::Update { table, source, expressions } => Self::Update {
                 table,
                 source,
@@ -252,9 +392,93 @@ impl Node {
                     .map(|(i, l, e)| e.transform(before, after).map(|e| (i, l, e)))
                     .collect::<Result<_>>()?,
             },
+            Self::Window { source, windows } => Self::Window {
+                source,
+                windows: windows
+                    .into_iter()
+                    .map(|w| w.transform_expressions(before, after))
+                    .collect::<Result<_>>()?,
+            },
         })
     }
 
+    /// Binds outer field references to the given query level to the values of the given row,
+    /// including any in nested subqueries. Returns the bound node, and whether it contained any
+    /// such references.
+    pub fn bind_outer(self, level: usize, row: &[Value]) -> Result<(Self, bool)> {
+        let bound = Cell::new(false);
+        let node = self.transform(
+            &|n| {
+                n.transform_expressions(
+                    &|e| match e {
+                        Expression::Outer(l, i, _) if l == level => {
+                            bound.set(true);
+                            row.get(i).cloned().map(Expression::Constant).ok_or_else(|| {
+                                Error::Internal(format!("Outer field {} out of bounds", i))
+                            })
+                        }
+                        e => Ok(e),
+                    },
+                    &|e| Ok(e),
+                )
+            },
+            &|n| Ok(n),
+        )?;
+        Ok((node, bound.get()))
+    }
+
+    /// Checks whether the node references outer fields of the given query level.
+    pub fn references_outer(&self, level: usize) -> bool {
+        let found = Cell::new(false);
+        self.clone()
+            .transform(
+                &|n| {
+                    n.transform_expressions(
+                        &|e| {
+                            if matches!(e, Expression::Outer(l, _, _) if l == level) {
+                                found.set(true);
+                            }
+                            Ok(e)
+                        },
+                        &|e| Ok(e),
+                    )
+                },
+                &|n| Ok(n),
+            )
+            .ok();
+        found.get()
+    }
+
+    /// Binds the work table of the given recursive query to the given rows.
+    pub fn bind_work_table(self, name: &str, rows: &[Row]) -> Result<Self> {
+        self.transform(
+            &|n| match n {
+                Self::WorkTable { name: n, columns, .. } if n == name => {
+                    Ok(Self::WorkTable { name: n, columns, rows: rows.to_vec() })
+                }
+                n => Ok(n),
+            },
+            &|n| Ok(n),
+        )
+    }
+
+    /// Checks whether the node references the work table of the given recursive query.
+    pub fn references_work_table(&self, name: &str) -> bool {
+        let found = Cell::new(false);
+        self.clone()
+            .transform(
+                &|n| {
+                    if matches!(&n, Self::WorkTable { name: n, .. } if n == name) {
+                        found.set(true);
+                    }
+                    Ok(n)
+                },
+                &|n| Ok(n),
+            )
+            .ok();
+        found.get()
+    }
+
     // Displays the node, where prefix gives the node prefix.
     pub fn format(&self, mut indent: String, root: bool, last: bool) -> String {
         let mut s = indent.clone();
@@ -273,6 +497,22 @@ impl Node {
                 );
                 s += &source.format(indent, false, true);
             }
+            Self::AlterTable { table, alteration } => {
+                s += &format!("AlterTable: {} {}\n", table, alteration);
+            }
+            Self::Apply { source, source_size: _, subquery, kind, level: _ } => {
+                s += &format!("Apply: {}\n", kind);
+                s += &source.format(indent.clone(), false, false);
+                s += &subquery.format(indent, false, true);
+            }
+            Self::CreateIndex { schema } => {
+                s += &format!(
+                    "CreateIndex: {} on {} ({})\n",
+                    schema.name,
+                    schema.table,
+                    schema.columns.join(", ")
+                );
+            }
             Self::CreateTable { schema } => {
                 s += &format!("CreateTable: {}\n", schema.name);
             }
@@ -280,9 +520,17 @@ impl Node {
                 s += &format!("Delete: {}\n", table);
                 s += &source.format(indent, false, true);
             }
+            Self::DropIndex { name } => {
+                s += &format!("DropIndex: {}\n", name);
+            }
             Self::DropTable { table } => {
                 s += &format!("DropTable: {}\n", table);
             }
+            Self::Except { left, right, distinct } => {
+                s += &format!("Except: {}\n", if *distinct { "distinct" } else { "all" });
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
             Self::Filter { source, predicate } => {
                 s += &format!("Filter: {}\n", predicate);
                 s += &source.format(indent, false, true);
@@ -321,9 +569,34 @@ impl Node {
                 }
                 s += "\n";
             }
+            Self::IndexPrefixLookup { table, alias, index, values } => {
+                s += &format!("IndexPrefixLookup: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" index {}", index);
+                if !values.is_empty() && values.len() < 10 {
+                    s += &format!(
+                        " ({})",
+                        values
+                            .iter()
+                            .map(|v| Value::format_tuple(v))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                } else {
+                    s += &format!(" ({} values)", values.len());
+                }
+                s += "\n";
+            }
             Self::Insert { table, columns: _, expressions } => {
                 s += &format!("Insert: {} ({} rows)\n", table, expressions.len());
             }
+            Self::Intersect { left, right, distinct } => {
+                s += &format!("Intersect: {}\n", if *distinct { "distinct" } else { "all" });
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
             Self::KeyLookup { table, alias, keys } => {
                 s += &format!("KeyLookup: {}", table);
                 if let Some(alias) = alias {
@@ -332,7 +605,7 @@ impl Node {
                 if !keys.is_empty() && keys.len() < 10 {
                     s += &format!(
                         " ({})",
-                        keys.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
+                        keys.iter().map(|k| Value::format_tuple(k)).collect::<Vec<_>>().join(", ")
                     );
                 } else {
                     s += &format!(" ({} keys)", keys.len());
@@ -381,6 +654,19 @@ impl Node {
                 );
                 s += &source.format(indent, false, true);
             }
+            Self::RecursiveUnion { name, anchor, recursive, distinct, limit } => {
+                s += &format!(
+                    "RecursiveUnion: {} ({})",
+                    name,
+                    if *distinct { "distinct" } else { "all" }
+                );
+                if let Some(limit) = limit {
+                    s += &format!(" limit {}", limit);
+                }
+                s += "\n";
+                s += &anchor.format(indent.clone(), false, false);
+                s += &recursive.format(indent, false, true);
+            }
             Self::Scan { table, alias, filter } => {
                 s += &format!("Scan: {}", table);
                 if let Some(alias) = alias {
@@ -391,6 +677,20 @@ impl Node {
                 }
                 s += "\n";
             }
+            Self::SemiJoin { left, left_size: _, right, predicate, anti } => {
+                s += &format!("SemiJoin: {}", if *anti { "anti" } else { "semi" });
+                if let Some(expr) = predicate {
+                    s += &format!(" on {}", expr);
+                }
+                s += "\n";
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
+            Self::Union { left, right, distinct } => {
+                s += &format!("Union: {}\n", if *distinct { "distinct" } else { "all" });
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
             Self::Update { source, table, expressions } => {
                 s += &format!(
                     "Update: {} ({})\n",
@@ -407,6 +707,16 @@ impl Node {
                 );
                 s += &source.format(indent, false, true);
             }
+            Self::Window { source, windows } => {
+                s += &format!(
+                    "Window: {}\n",
+                    windows.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(", ")
+                );
+                s += &source.format(indent, false, true);
+            }
+            Self::WorkTable { name, columns: _, rows: _ } => {
+                s += &format!("WorkTable: {}\n", name);
+            }
         };
         if root {
             s = s.trim_end().to_string()
@@ -422,7 +732,7 @@ impl Display for Node {
 }
 
 /// An aggregate operation
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Aggregate {
     Average,
     Count,
@@ -450,7 +760,7 @@ impl Display for Aggregate {
 pub type Aggregates = Vec<Aggregate>;
 
 /// A sort order direction
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Ascending,
     Descending,
@@ -468,3 +778,138 @@ impl Display for Direction {
         )
     }
 }
+
+/// A window function
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WindowFunction {
+    Aggregate(Aggregate),
+    DenseRank,
+    Lag,
+    Lead,
+    Rank,
+    RowNumber,
+}
+
+impl Display for WindowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Aggregate(aggregate) => write!(f, "{}", aggregate),
+            Self::DenseRank => write!(f, "dense_rank"),
+            Self::Lag => write!(f, "lag"),
+            Self::Lead => write!(f, "lead"),
+            Self::Rank => write!(f, "rank"),
+            Self::RowNumber => write!(f, "row_number"),
+        }
+    }
+}
+
+/// A window function evaluated over a window of rows. Rows are split into partitions by the
+/// partition expressions and sorted by the order expressions, and aggregates are computed over
+/// the frame of each row. Without an explicit frame, the frame is the entire partition if there
+/// are no order expressions, otherwise all rows up to and including the current row's peers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub function: WindowFunction,
+    pub args: Vec<Expression>,
+    pub partition: Vec<Expression>,
+    pub order: Vec<(Expression, Direction)>,
+    pub frame: Option<(FrameBound, FrameBound)>,
+}
+
+impl Window {
+    /// Transforms all expressions in the window by calling .transform() on them.
+    fn transform_expressions<B, A>(mut self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        self.args =
+            self.args.into_iter().map(|e| e.transform(before, after)).collect::<Result<_>>()?;
+        self.partition = self
+            .partition
+            .into_iter()
+            .map(|e| e.transform(before, after))
+            .collect::<Result<_>>()?;
+        self.order = self
+            .order
+            .into_iter()
+            .map(|(e, d)| e.transform(before, after).map(|e| (e, d)))
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) over (",
+            self.function,
+            self.args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
+        )?;
+        let mut clauses = Vec::new();
+        if !self.partition.is_empty() {
+            clauses.push(format!(
+                "partition by {}",
+                self.partition.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        if !self.order.is_empty() {
+            clauses.push(format!(
+                "order by {}",
+                self.order
+                    .iter()
+                    .map(|(e, d)| format!("{} {}", e, d))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if let Some((start, end)) = &self.frame {
+            clauses.push(format!("rows between {} and {}", start, end));
+        }
+        write!(f, "{})", clauses.join(" "))
+    }
+}
+
+/// A window frame bound, relative to the current row
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing,
+}
+
+impl Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnboundedPreceding => write!(f, "unbounded preceding"),
+            Self::Preceding(n) => write!(f, "{} preceding", n),
+            Self::CurrentRow => write!(f, "current row"),
+            Self::Following(n) => write!(f, "{} following", n),
+            Self::UnboundedFollowing => write!(f, "unbounded following"),
+        }
+    }
+}
+
+/// A subquery evaluated by an Apply node, appending its result to each source row
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Subquery {
+    // Whether the subquery returns any rows.
+    Exists,
+    // Whether the given expression equals any value returned by the subquery.
+    In(Expression),
+    // The single value returned by the subquery, or NULL if none.
+    Scalar,
+}
+
+impl Display for Subquery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exists => write!(f, "exists"),
+            Self::In(expr) => write!(f, "{} in", expr),
+            Self::Scalar => write!(f, "scalar"),
+        }
+    }
+}
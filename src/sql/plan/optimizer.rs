@@ -1,7 +1,7 @@
 use super::super::schema::Catalog;
 use super::super::types::{Expression, Value};
-use super::Node;
-use crate::error::Result;
+use super::{Node, Subquery};
+use crate::error::{Error, Result};
 
 use std::mem::replace;
 
@@ -19,7 +19,9 @@ impl Optimizer for ConstantFolder {
         node.transform(&|n| Ok(n), &|n| {
             n.transform_expressions(
                 &|e| {
-                    if !e.contains(&|expr| matches!(expr, Expression::Field(_, _))) {
+                    if !e.contains(&|expr| {
+                        matches!(expr, Expression::Field(_, _) | Expression::Outer(_, _, _))
+                    }) {
                         Ok(Expression::Constant(e.evaluate(None)?))
                     } else {
                         Ok(e)
@@ -61,6 +63,10 @@ impl Optimizer for FilterPushdown {
                     let predicate = self.pushdown_join(predicate, &mut left, &mut right, left_size);
                     Ok(Node::NestedLoopJoin { left, left_size, right, predicate, outer })
                 }
+                Node::SemiJoin { left, left_size, mut right, predicate: Some(predicate), anti } => {
+                    let predicate = self.pushdown_semi_join(predicate, &mut right, left_size);
+                    Ok(Node::SemiJoin { left, left_size, right, predicate, anti })
+                }
                 n => Ok(n),
             },
             &|n| Ok(n),
@@ -89,10 +95,55 @@ impl FilterPushdown {
                 *predicate = Expression::And(Box::new(p), Box::new(expression));
                 None
             }
+            // Semi joins return the left rows as-is, so they can be filtered directly.
+            Node::SemiJoin { ref mut left, .. } => self.pushdown(expression, left),
             _ => Some(expression),
         }
     }
 
+    /// Pushes the parts of a semi join predicate that only reference the right source down into
+    /// it, returning any remaining expression. Left-only parts are kept, since anti joins must
+    /// return left rows that don't match them.
+    fn pushdown_semi_join(
+        &self,
+        predicate: Expression,
+        right: &mut Node,
+        boundary: usize,
+    ) -> Option<Expression> {
+        let (push_right, mut cnf): (Vec<Expression>, Vec<Expression>) = predicate
+            .into_cnf_vec()
+            .into_iter()
+            .partition(|e| !e.contains(&|e| matches!(e, Expression::Field(i, _) if i < &boundary)));
+        if let Some(mut push_right) = Expression::from_cnf_vec(push_right) {
+            push_right = push_right
+                .transform(
+                    &|e| match e {
+                        Expression::Field(i, label) => Ok(Expression::Field(i - boundary, label)),
+                        e => Ok(e),
+                    },
+                    &|e| Ok(e),
+                )
+                .unwrap();
+            // Any remainder must be shifted back to the join row.
+            if let Some(remainder) = self.pushdown(push_right, right) {
+                cnf.push(
+                    remainder
+                        .transform(
+                            &|e| match e {
+                                Expression::Field(i, label) => {
+                                    Ok(Expression::Field(i + boundary, label))
+                                }
+                                e => Ok(e),
+                            },
+                            &|e| Ok(e),
+                        )
+                        .unwrap(),
+                )
+            }
+        }
+        Expression::from_cnf_vec(cnf)
+    }
+
     /// Attempts to partition a join predicate and push parts of it down into either source,
     /// returning any remaining expression.
     fn pushdown_join(
@@ -157,6 +208,203 @@ impl FilterPushdown {
     }
 }
 
+/// A subquery decorrelator, which converts EXISTS and IN subqueries that are filter predicates
+/// into semi joins, or anti joins when negated. Rather than executing the subquery for each row,
+/// it is executed once and its correlation predicate (if any) is used as the join predicate. This
+/// requires the correlated fields to only be used in the subquery's top-level WHERE clause or
+/// SELECT expression, other subqueries are left as-is.
+pub struct Decorrelator;
+
+impl Optimizer for Decorrelator {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        // Transform bottom-up, such that nested subqueries are decorrelated first.
+        node.transform(&|n| Ok(n), &|n| match n {
+            Node::Filter { source, predicate } if matches!(*source, Node::Apply { .. }) => {
+                Ok(self.decorrelate(*source, predicate))
+            }
+            // Remove projections that only removed the columns of decorrelated subqueries.
+            Node::Projection { source, expressions } if Self::is_noop(&source, &expressions) => {
+                Ok(*source)
+            }
+            n => Ok(n),
+        })
+    }
+}
+
+impl Decorrelator {
+    /// Decorrelates a chain of subqueries applied below a filter where possible, returning the
+    /// filter with the remaining subqueries and predicate, if any.
+    fn decorrelate(&self, mut source: Node, predicate: Expression) -> Node {
+        let mut applies = Vec::new();
+        while let Node::Apply { source: inner, source_size, subquery, kind, level } = source {
+            applies.push((source_size, *subquery, kind, level));
+            source = *inner;
+        }
+        applies.reverse();
+        let base = applies.first().map(|(size, _, _, _)| *size).unwrap_or(0);
+
+        // Columns of decorrelated subqueries are removed, so we map the original subquery
+        // columns to their new index, if any. Subquery columns used in IN expressions can't be
+        // removed.
+        let mut columns: Vec<Option<usize>> = Vec::new();
+        let remap = |expr: Expression, columns: &[Option<usize>]| {
+            expr.transform(
+                &|e| match e {
+                    Expression::Field(i, label) if i >= base => match columns.get(i - base) {
+                        Some(Some(i)) => Ok(Expression::Field(*i, label)),
+                        _ => Err(Error::Internal(format!("Unexpected subquery column {}", i))),
+                    },
+                    e => Ok(e),
+                },
+                &|e| Ok(e),
+            )
+        };
+        let references = |e: &Expression, column: usize| {
+            e.contains(&|e| matches!(e, Expression::Field(i, _) if *i == column))
+        };
+        let used: Vec<usize> = (base..base + applies.len())
+            .filter(|c| {
+                applies
+                    .iter()
+                    .any(|(_, _, kind, _)| matches!(kind, Subquery::In(e) if references(e, *c)))
+            })
+            .collect();
+
+        let mut cnf = predicate.into_cnf_vec();
+        let mut node = source;
+        let mut width = base;
+        for (column, subquery, kind, level) in applies {
+            let kind = match kind {
+                Subquery::In(expr) => Subquery::In(remap(expr, &columns).unwrap()),
+                kind => kind,
+            };
+            // Look for a single conjunct that is either the subquery column or its negation.
+            let position = match cnf.iter().filter(|e| references(e, column)).count() {
+                1 if !used.contains(&column) && !matches!(kind, Subquery::Scalar) => {
+                    cnf.iter().position(|e| match e {
+                        Expression::Field(i, _) => *i == column,
+                        Expression::Not(e) => matches!(**e, Expression::Field(i, _) if i == column),
+                        _ => false,
+                    })
+                }
+                _ => None,
+            };
+            match position {
+                Some(position) if Self::is_decorrelatable(&subquery, level) => {
+                    let anti = matches!(cnf.remove(position), Expression::Not(_));
+                    let (right, predicate) = Self::build_join(subquery, kind, level, width, anti);
+                    node = Node::SemiJoin {
+                        left: Box::new(node),
+                        left_size: width,
+                        right: Box::new(right),
+                        predicate,
+                        anti,
+                    };
+                    columns.push(None);
+                }
+                _ => {
+                    node = Node::Apply {
+                        source: Box::new(node),
+                        source_size: width,
+                        subquery: Box::new(subquery),
+                        kind,
+                        level,
+                    };
+                    columns.push(Some(width));
+                    width += 1;
+                }
+            }
+        }
+
+        let cnf = cnf.into_iter().map(|e| remap(e, &columns).unwrap()).collect();
+        match Expression::from_cnf_vec(cnf) {
+            Some(predicate) => Node::Filter { source: Box::new(node), predicate },
+            None => node,
+        }
+    }
+
+    /// Checks whether a subquery can be decorrelated, i.e. whether outer fields are only used in
+    /// its top-level projection and filter.
+    fn is_decorrelatable(subquery: &Node, level: usize) -> bool {
+        let mut node = subquery;
+        if let Node::Projection { source, .. } = node {
+            node = source;
+        }
+        if let Node::Filter { source, .. } = node {
+            node = source;
+        }
+        !node.references_outer(level)
+    }
+
+    /// Builds the right side and predicate of a semi join for a decorrelatable subquery, where
+    /// the left side is the source of the subquery with the given size.
+    fn build_join(
+        subquery: Node,
+        kind: Subquery,
+        level: usize,
+        left_size: usize,
+        anti: bool,
+    ) -> (Node, Option<Expression>) {
+        let (right, expressions) = match subquery {
+            Node::Projection { source, expressions } => (*source, Some(expressions)),
+            node => (node, None),
+        };
+        let (right, correlation) = match right {
+            Node::Filter { source, predicate } => (*source, Some(predicate)),
+            node => (node, None),
+        };
+        // Shift the subquery fields past the left fields, and replace outer fields with left
+        // fields.
+        let shift = |expr: Expression| {
+            expr.transform(
+                &|e| match e {
+                    Expression::Field(i, label) => Ok(Expression::Field(left_size + i, label)),
+                    Expression::Outer(l, i, label) if l == level => Ok(Expression::Field(i, label)),
+                    e => Ok(e),
+                },
+                &|e| Ok(e),
+            )
+            .unwrap()
+        };
+        let mut cnf: Vec<Expression> = correlation.into_iter().map(shift).collect();
+        if let Subquery::In(lhs) = kind {
+            let value = match expressions {
+                Some(mut expressions) => shift(expressions.remove(0).0),
+                None => Expression::Field(left_size, None),
+            };
+            let equal = Expression::Equal(lhs.clone().into(), value.clone().into());
+            // NOT IN is false if the value matches any subquery value, but NULL (i.e. false)
+            // if either is NULL, so these are also matches for anti joins.
+            cnf.push(match anti {
+                true => Expression::Or(
+                    Expression::Or(equal.into(), Expression::IsNull(lhs.into()).into()).into(),
+                    Expression::IsNull(value.into()).into(),
+                ),
+                false => equal,
+            });
+        }
+        (right, Expression::from_cnf_vec(cnf))
+    }
+
+    /// Checks whether a projection only passes through the rows of a semi join, possibly via a
+    /// filter, i.e. it is a noop.
+    fn is_noop(source: &Node, expressions: &[(Expression, Option<String>)]) -> bool {
+        let width = match source {
+            Node::SemiJoin { left_size, .. } => *left_size,
+            Node::Filter { source, .. } => match &**source {
+                Node::SemiJoin { left_size, .. } => *left_size,
+                _ => return false,
+            },
+            _ => return false,
+        };
+        expressions.len() == width
+            && expressions
+                .iter()
+                .enumerate()
+                .all(|(i, e)| matches!(e, (Expression::Field(f, None), None) if *f == i))
+    }
+}
+
 /// An index lookup optimizer, which converts table scans to index lookups.
 pub struct IndexLookup<'a, C: Catalog> {
     catalog: &'a mut C,
@@ -175,32 +423,100 @@ impl<'a, C: Catalog> IndexLookup<'a, C> {
             node
         }
     }
+
+    // Looks for lookups on the longest possible prefix of the given fields (e.g. the columns of a
+    // composite key), removing the used expressions from the CNF vector. Returns the cartesian
+    // product of the lookup values as value tuples, or None if the first field has no lookup.
+    fn lookup_prefix(cnf: &mut Vec<Expression>, fields: &[usize]) -> Option<Vec<Vec<Value>>> {
+        let mut tuples: Vec<Vec<Value>> = vec![Vec::new()];
+        let mut matched = 0;
+        for field in fields {
+            let values = match cnf
+                .iter()
+                .enumerate()
+                .find_map(|(i, e)| e.as_lookup(*field).map(|values| (i, values)))
+            {
+                Some((i, values)) => {
+                    cnf.remove(i);
+                    matched += 1;
+                    values
+                }
+                None => break,
+            };
+            tuples = tuples
+                .into_iter()
+                .flat_map(|t| {
+                    values.iter().map(move |v| [&t[..], std::slice::from_ref(v)].concat())
+                })
+                .collect();
+        }
+        if matched == 0 {
+            None
+        } else {
+            Some(tuples)
+        }
+    }
 }
 
 impl<'a, C: Catalog> Optimizer for IndexLookup<'a, C> {
     fn optimize(&self, node: Node) -> Result<Node> {
         node.transform(&|n| Ok(n), &|n| match n {
             Node::Scan { table, alias, filter: Some(filter) } => {
-                let columns = self.catalog.must_read_table(&table)?.columns;
-                let pk = columns.iter().position(|c| c.primary_key).unwrap();
+                let schema = self.catalog.must_read_table(&table)?;
+                let columns = &schema.columns;
+                let composite = self
+                    .catalog
+                    .scan_indexes()?
+                    .filter(|i| i.table == table && i.columns.len() > 1)
+                    .map(|i| {
+                        let fields = i
+                            .columns
+                            .iter()
+                            .map(|c| schema.get_column_index(c))
+                            .collect::<Result<Vec<_>>>()?;
+                        Ok((i, fields))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
 
                 // Convert the filter into conjunctive normal form, and try to convert each
                 // sub-expression into a lookup. If a lookup is found, return a lookup node and then
-                // apply the remaining conjunctions as a filter node, if any.
+                // apply the remaining conjunctions as a filter node, if any. Primary key lookups
+                // are preferred, followed by unique indexes (including ones added via CREATE
+                // UNIQUE INDEX) since they return at most one row per value. Composite keys and
+                // indexes can be used for lookups on a prefix of their columns.
                 let mut cnf = filter.clone().into_cnf_vec();
-                for i in 0..cnf.len() {
-                    if let Some(keys) = cnf[i].as_lookup(pk) {
-                        cnf.remove(i);
-                        return Ok(self.wrap_cnf(Node::KeyLookup { table, alias, keys }, cnf));
+                if let Some(keys) = Self::lookup_prefix(&mut cnf, &schema.get_primary_key_indexes())
+                {
+                    return Ok(self.wrap_cnf(Node::KeyLookup { table, alias, keys }, cnf));
+                }
+                for unique in [true, false] {
+                    for i in 0..cnf.len() {
+                        for (ci, column) in columns
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, c)| c.index && c.unique == unique)
+                        {
+                            if let Some(values) = cnf[i].as_lookup(ci) {
+                                cnf.remove(i);
+                                return Ok(self.wrap_cnf(
+                                    Node::IndexLookup {
+                                        table,
+                                        alias,
+                                        column: column.name.clone(),
+                                        values,
+                                    },
+                                    cnf,
+                                ));
+                            }
+                        }
                     }
-                    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.index) {
-                        if let Some(values) = cnf[i].as_lookup(ci) {
-                            cnf.remove(i);
+                    for (index, fields) in composite.iter().filter(|(i, _)| i.unique == unique) {
+                        if let Some(values) = Self::lookup_prefix(&mut cnf, fields) {
                             return Ok(self.wrap_cnf(
-                                Node::IndexLookup {
+                                Node::IndexPrefixLookup {
                                     table,
                                     alias,
-                                    column: column.name.clone(),
+                                    index: index.name.clone(),
                                     values,
                                 },
                                 cnf,
@@ -262,6 +578,42 @@ impl Optimizer for NoopCleaner {
     }
 }
 
+/// Pushes limits down into recursive unions, such that the recursion stops once enough rows have
+/// been produced instead of running to completion. Limits are only pushed through nodes that emit
+/// exactly one row per source row, in the same order.
+pub struct LimitPushdown;
+
+impl Optimizer for LimitPushdown {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(
+            &|n| match n {
+                Node::Limit { mut source, limit } => {
+                    Self::pushdown(limit, &mut source);
+                    Ok(Node::Limit { source, limit })
+                }
+                n => Ok(n),
+            },
+            &|n| Ok(n),
+        )
+    }
+}
+
+impl LimitPushdown {
+    /// Attempts to push a limit down into a target node.
+    fn pushdown(limit: u64, target: &mut Node) {
+        match target {
+            Node::Projection { source, .. } => Self::pushdown(limit, source),
+            Node::Offset { source, offset } => {
+                Self::pushdown(limit.saturating_add(*offset), source)
+            }
+            Node::RecursiveUnion { limit: existing, .. } => {
+                *existing = Some(existing.map_or(limit, |existing| existing.min(limit)))
+            }
+            _ => {}
+        }
+    }
+}
+
 // Optimizes join types, currently by swapping nested-loop joins with hash joins where appropriate.
 pub struct JoinType;
 
@@ -1,11 +1,11 @@
 use super::super::parser::ast;
-use super::super::schema::{Catalog, Column, Table};
+use super::super::schema::{Alteration, Catalog, Column, Index, Table};
 use super::super::types::{Expression, Value};
-use super::{Aggregate, Direction, Node, Plan};
+use super::{Aggregate, Direction, FrameBound, Node, Plan, Subquery, Window, WindowFunction};
 use crate::error::{Error, Result};
 
 use std::collections::{HashMap, HashSet};
-use std::mem::replace;
+use std::mem::{replace, take};
 
 /// A query plan builder.
 pub struct Planner<'a, C: Catalog> {
@@ -26,7 +26,8 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// Builds a plan node for a statement.
     fn build_statement(&self, statement: ast::Statement) -> Result<Node> {
         Ok(match statement {
-            // Transaction control and explain statements should have been handled by session.
+            // Transaction control, explain, and set statements should have been handled by
+            // session.
             ast::Statement::Begin { .. } | ast::Statement::Commit | ast::Statement::Rollback => {
                 return Err(Error::Internal(format!(
                     "Unexpected transaction statement {:?}",
@@ -38,46 +39,53 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 return Err(Error::Internal("Unexpected explain statement".into()))
             }
 
+            ast::Statement::Set { .. } => {
+                return Err(Error::Internal("Unexpected set statement".into()))
+            }
+
             // DDL statements (schema changes).
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable {
-                schema: Table::new(
-                    name,
-                    columns
-                        .into_iter()
-                        .map(|c| {
-                            let nullable = c.nullable.unwrap_or(!c.primary_key);
-                            let default = match c.default {
-                                Some(expr) => Some(self.evaluate_constant(expr)?),
-                                None if nullable => Some(Value::Null),
-                                None => None,
-                            };
-                            Ok(Column {
-                                name: c.name,
-                                datatype: c.datatype,
-                                primary_key: c.primary_key,
-                                nullable,
-                                default,
-                                index: c.index && !c.primary_key,
-                                unique: c.unique || c.primary_key,
-                                references: c.references,
-                            })
-                        })
-                        .collect::<Result<_>>()?,
-                )?,
-            },
+            ast::Statement::CreateTable { name, columns } => {
+                // Columns of a composite primary key are not unique by themselves.
+                let single_pk = columns.iter().filter(|c| c.primary_key).count() == 1;
+                Node::CreateTable {
+                    schema: Table::new(
+                        name,
+                        columns
+                            .into_iter()
+                            .map(|c| self.build_column(c, single_pk))
+                            .collect::<Result<_>>()?,
+                    )?,
+                }
+            }
 
             ast::Statement::DropTable(table) => Node::DropTable { table },
 
+            ast::Statement::AlterTable { name, alteration } => Node::AlterTable {
+                table: name,
+                alteration: match alteration {
+                    ast::Alteration::AddColumn(c) => {
+                        Alteration::AddColumn(self.build_column(c, false)?)
+                    }
+                    ast::Alteration::DropColumn(name) => Alteration::DropColumn(name),
+                    ast::Alteration::RenameColumn { from, to } => {
+                        Alteration::RenameColumn { from, to }
+                    }
+                    ast::Alteration::RenameTable(name) => Alteration::RenameTable(name),
+                },
+            },
+
+            ast::Statement::CreateIndex { name, table, columns, unique } => {
+                Node::CreateIndex { schema: Index { name, table, columns, unique } }
+            }
+
+            ast::Statement::DropIndex(name) => Node::DropIndex { name },
+
             // DML statements (mutations).
             ast::Statement::Delete { table, r#where } => {
                 let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
                 Node::Delete {
                     table: table.clone(),
-                    source: Box::new(Node::Scan {
-                        table,
-                        alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
-                    }),
+                    source: Box::new(self.build_mutation_source(scope, table, r#where)?),
                 }
             }
 
@@ -97,160 +105,302 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
             ast::Statement::Update { table, set, r#where } => {
                 let scope = &mut Scope::from_table(self.catalog.must_read_table(&table)?)?;
+                let source = self.build_mutation_source(scope, table.clone(), r#where)?;
+                let expressions = set
+                    .into_iter()
+                    .map(|(c, e)| {
+                        Ok((scope.resolve(None, &c)?, Some(c), self.build_expression(scope, e)?))
+                    })
+                    .collect::<Result<_>>()?;
+                // Any subquery columns are appended to the source rows, and ignored by the
+                // executor when writing the updated rows.
                 Node::Update {
-                    table: table.clone(),
-                    source: Box::new(Node::Scan {
-                        table,
-                        alias: None,
-                        filter: r#where.map(|e| self.build_expression(scope, e)).transpose()?,
-                    }),
-                    expressions: set
-                        .into_iter()
-                        .map(|(c, e)| {
-                            Ok((
-                                scope.resolve(None, &c)?,
-                                Some(c),
-                                self.build_expression(scope, e)?,
-                            ))
-                        })
-                        .collect::<Result<_>>()?,
+                    table,
+                    source: Box::new(self.build_apply(scope, source)),
+                    expressions,
                 }
             }
 
             // Queries.
-            ast::Statement::Select {
-                mut select,
-                from,
-                r#where,
-                group_by,
-                mut having,
-                mut order,
-                offset,
-                limit,
-            } => {
-                let scope = &mut Scope::new();
-
-                // Build FROM clause.
-                let mut node = if !from.is_empty() {
-                    self.build_from_clause(scope, from)?
-                } else if select.is_empty() {
-                    return Err(Error::Value("Can't select * without a table".into()));
-                } else {
-                    Node::Nothing
-                };
+            statement @ ast::Statement::Select { .. }
+            | statement @ ast::Statement::SetOperation { .. }
+            | statement @ ast::Statement::With { .. } => {
+                self.build_query(&mut Scope::new(), statement)?
+            }
+        })
+    }
 
-                // Build WHERE clause.
-                if let Some(expr) = r#where {
-                    node = Node::Filter {
-                        source: Box::new(node),
-                        predicate: self.build_expression(scope, expr)?,
-                    };
-                };
+    /// Builds a query, i.e. a SELECT statement, in the given scope. Once built, the scope contains
+    /// the output columns of the query.
+    fn build_query(&self, scope: &mut Scope, statement: ast::Statement) -> Result<Node> {
+        // Make any common table expressions visible to the query, and its subqueries.
+        if let ast::Statement::With { ctes, query } = statement {
+            let mut names = HashSet::new();
+            for cte in ctes {
+                if !names.insert(cte.name.clone()) {
+                    return Err(Error::Value(format!(
+                        "Duplicate common table expression {}",
+                        cte.name
+                    )));
+                }
+                scope.ctes.push(Cte::Query(cte));
+            }
+            return self.build_query(scope, *query);
+        }
 
-                // Build SELECT clause.
-                let mut hidden = 0;
-                if !select.is_empty() {
-                    // Inject hidden SELECT columns for fields and aggregates used in ORDER BY and
-                    // HAVING expressions but not present in existing SELECT output. These will be
-                    // removed again by a later projection.
-                    if let Some(ref mut expr) = having {
-                        hidden += self.inject_hidden(expr, &mut select)?;
-                    }
-                    for (expr, _) in order.iter_mut() {
-                        hidden += self.inject_hidden(expr, &mut select)?;
-                    }
+        // Set operations combine the results of the left and right queries, which are built
+        // independently, using the output columns of the left query.
+        if let ast::Statement::SetOperation { operator, all, left, right, order, offset, limit } =
+            statement
+        {
+            let mut right_scope = scope.clone();
+            let left = Box::new(self.build_query(scope, *left)?);
+            let right = Box::new(self.build_query(&mut right_scope, *right)?);
+            if scope.len() != right_scope.len() {
+                return Err(Error::Value(format!(
+                    "{} queries have {} and {} columns",
+                    match operator {
+                        ast::SetOperator::Union => "UNION",
+                        ast::SetOperator::Intersect => "INTERSECT",
+                        ast::SetOperator::Except => "EXCEPT",
+                    },
+                    scope.len(),
+                    right_scope.len()
+                )));
+            }
+            // The combined columns can't be referenced via the tables of the left query.
+            let expressions: Vec<_> = (0..scope.len())
+                .map(|i| Ok((Expression::Field(i, None), scope.get_label(i)?.map(|(_, l)| l))))
+                .collect::<Result<_>>()?;
+            scope.project(&expressions)?;
+            let distinct = !all;
+            let node = match operator {
+                ast::SetOperator::Union => Node::Union { left, right, distinct },
+                ast::SetOperator::Intersect => Node::Intersect { left, right, distinct },
+                ast::SetOperator::Except => Node::Except { left, right, distinct },
+            };
+            return self.build_order_limit(scope, node, order, offset, limit);
+        }
 
-                    // Extract any aggregate functions and GROUP BY expressions, replacing them with
-                    // Column placeholders. Aggregations are handled by evaluating group expressions
-                    // and aggregate function arguments in a pre-projection, passing the results
-                    // to an aggregation node, and then evaluating the final SELECT expressions
-                    // in the post-projection. For example:
-                    //
-                    // SELECT (MAX(rating * 100) - MIN(rating * 100)) / 100
-                    // FROM movies
-                    // GROUP BY released - 2000
-                    //
-                    // Results in the following nodes:
-                    //
-                    // - Projection: rating * 100, rating * 100, released - 2000
-                    // - Aggregation: max(#0), min(#1) group by #2
-                    // - Projection: (#0 - #1) / 100
-                    let aggregates = self.extract_aggregates(&mut select)?;
-                    let groups = self.extract_groups(&mut select, group_by, aggregates.len())?;
-                    if !aggregates.is_empty() || !groups.is_empty() {
-                        node = self.build_aggregation(scope, node, groups, aggregates)?;
-                    }
+        let (mut select, from, r#where, group_by, mut having, mut order, offset, limit) =
+            match statement {
+                ast::Statement::Select {
+                    select,
+                    from,
+                    r#where,
+                    group_by,
+                    having,
+                    order,
+                    offset,
+                    limit,
+                } => (select, from, r#where, group_by, having, order, offset, limit),
+                statement => {
+                    return Err(Error::Internal(format!("Unexpected statement {:?}", statement)))
+                }
+            };
 
-                    // Build the remaining non-aggregate projection.
-                    let expressions: Vec<(Expression, Option<String>)> = select
-                        .into_iter()
-                        .map(|(e, l)| Ok((self.build_expression(scope, e)?, l)))
-                        .collect::<Result<_>>()?;
-                    scope.project(&expressions)?;
-                    node = Node::Projection { source: Box::new(node), expressions };
-                };
+        // Build FROM clause.
+        let mut node = if !from.is_empty() {
+            self.build_from_clause(scope, from)?
+        } else if select.is_empty() {
+            return Err(Error::Value("Can't select * without a table".into()));
+        } else {
+            Node::Nothing
+        };
 
-                // Build HAVING clause.
-                if let Some(expr) = having {
-                    node = Node::Filter {
-                        source: Box::new(node),
-                        predicate: self.build_expression(scope, expr)?,
-                    };
-                };
+        // Build WHERE clause.
+        if let Some(expr) = r#where {
+            let predicate = self.build_expression(scope, expr)?;
+            node = self.build_filter(scope, node, predicate);
+        };
 
-                // Build ORDER clause.
-                if !order.is_empty() {
-                    node = Node::Order {
-                        source: Box::new(node),
-                        orders: order
-                            .into_iter()
-                            .map(|(e, o)| {
-                                Ok((
-                                    self.build_expression(scope, e)?,
-                                    match o {
-                                        ast::Order::Ascending => Direction::Ascending,
-                                        ast::Order::Descending => Direction::Descending,
-                                    },
-                                ))
-                            })
-                            .collect::<Result<_>>()?,
-                    };
-                }
+        // Build SELECT clause.
+        let mut hidden = 0;
+        if !select.is_empty() {
+            // Inject hidden SELECT columns for fields and aggregates used in ORDER BY and
+            // HAVING expressions but not present in existing SELECT output. These will be
+            // removed again by a later projection.
+            if let Some(ref mut expr) = having {
+                hidden += self.inject_hidden(expr, &mut select)?;
+            }
+            for (expr, _) in order.iter_mut() {
+                hidden += self.inject_hidden(expr, &mut select)?;
+            }
 
-                // Build OFFSET clause.
-                if let Some(expr) = offset {
-                    node = Node::Offset {
-                        source: Box::new(node),
-                        offset: match self.evaluate_constant(expr)? {
-                            Value::Integer(i) if i >= 0 => Ok(i as u64),
-                            v => Err(Error::Value(format!("Invalid offset {}", v))),
-                        }?,
-                    }
-                }
+            // Extract any aggregate functions and GROUP BY expressions, replacing them with
+            // Column placeholders. Aggregations are handled by evaluating group expressions
+            // and aggregate function arguments in a pre-projection, passing the results
+            // to an aggregation node, and then evaluating the final SELECT expressions
+            // in the post-projection. For example:
+            //
+            // SELECT (MAX(rating * 100) - MIN(rating * 100)) / 100
+            // FROM movies
+            // GROUP BY released - 2000
+            //
+            // Results in the following nodes:
+            //
+            // - Projection: rating * 100, rating * 100, released - 2000
+            // - Aggregation: max(#0), min(#1) group by #2
+            // - Projection: (#0 - #1) / 100
+            let aggregates = self.extract_aggregates(&mut select)?;
+            let groups = self.extract_groups(&mut select, group_by, aggregates.len())?;
+            if !aggregates.is_empty() || !groups.is_empty() {
+                node = self.build_aggregation(scope, node, groups, aggregates)?;
+            }
 
-                // Build LIMIT clause.
-                if let Some(expr) = limit {
-                    node = Node::Limit {
-                        source: Box::new(node),
-                        limit: match self.evaluate_constant(expr)? {
-                            Value::Integer(i) if i >= 0 => Ok(i as u64),
-                            v => Err(Error::Value(format!("Invalid limit {}", v))),
-                        }?,
-                    }
+            // Extract any window functions, replacing them with Column references to the columns
+            // appended by the window node. Window functions are evaluated after aggregation, so
+            // they can be applied to aggregates, e.g. RANK() OVER (ORDER BY SUM(x)).
+            let windows = self.extract_windows(&mut select, scope.len())?;
+            if !windows.is_empty() {
+                if having.is_some() {
+                    return Err(Error::Value(
+                        "Window functions can't be combined with HAVING".into(),
+                    ));
                 }
+                node = self.build_window(scope, node, windows)?;
+            }
 
-                // Remove any hidden columns.
-                if hidden > 0 {
-                    node = Node::Projection {
-                        source: Box::new(node),
-                        expressions: (0..(scope.len() - hidden))
-                            .map(|i| (Expression::Field(i, None), None))
-                            .collect(),
-                    }
-                }
+            // Build the remaining non-aggregate projection.
+            let expressions: Vec<(Expression, Option<String>)> = select
+                .into_iter()
+                .map(|(e, l)| Ok((self.build_expression(scope, e)?, l)))
+                .collect::<Result<_>>()?;
+            node = self.build_apply(scope, node);
+            scope.project(&expressions)?;
+            node = Node::Projection { source: Box::new(node), expressions };
+        };
 
-                node
+        // Build HAVING clause.
+        if let Some(expr) = having {
+            let predicate = self.build_expression(scope, expr)?;
+            node = self.build_filter(scope, node, predicate);
+        };
+
+        node = self.build_order_limit(scope, node, order, offset, limit)?;
+
+        // Remove any hidden columns.
+        if hidden > 0 {
+            let expressions: Vec<_> =
+                (0..(scope.len() - hidden)).map(|i| (Expression::Field(i, None), None)).collect();
+            scope.project(&expressions)?;
+            node = Node::Projection { source: Box::new(node), expressions }
+        }
+
+        Ok(node)
+    }
+
+    /// Builds the ORDER BY, OFFSET, and LIMIT clauses of a query.
+    fn build_order_limit(
+        &self,
+        scope: &mut Scope,
+        mut node: Node,
+        order: Vec<(ast::Expression, ast::Order)>,
+        offset: Option<ast::Expression>,
+        limit: Option<ast::Expression>,
+    ) -> Result<Node> {
+        // Build ORDER clause.
+        if !order.is_empty() {
+            let orders = order
+                .into_iter()
+                .map(|(e, o)| {
+                    Ok((
+                        self.build_expression(scope, e)?,
+                        match o {
+                            ast::Order::Ascending => Direction::Ascending,
+                            ast::Order::Descending => Direction::Descending,
+                        },
+                    ))
+                })
+                .collect::<Result<_>>()?;
+            node = self.build_with_subqueries(scope, node, |source| Node::Order { source, orders });
+        }
+
+        // Build OFFSET clause.
+        if let Some(expr) = offset {
+            node = Node::Offset {
+                source: Box::new(node),
+                offset: match self.evaluate_constant(expr)? {
+                    Value::Integer(i) if i >= 0 => Ok(i as u64),
+                    v => Err(Error::Value(format!("Invalid offset {}", v))),
+                }?,
+            }
+        }
+
+        // Build LIMIT clause.
+        if let Some(expr) = limit {
+            node = Node::Limit {
+                source: Box::new(node),
+                limit: match self.evaluate_constant(expr)? {
+                    Value::Integer(i) if i >= 0 => Ok(i as u64),
+                    v => Err(Error::Value(format!("Invalid limit {}", v))),
+                }?,
+            }
+        }
+
+        Ok(node)
+    }
+
+    /// Builds a subquery in a scope nested in the given scope, such that it can reference the
+    /// fields of any enclosing queries.
+    fn build_subquery(&self, scope: &Scope, query: ast::Statement) -> Result<(Node, usize)> {
+        if scope.constant {
+            return Err(Error::Value("Subqueries are not allowed in constant expressions".into()));
+        }
+        let mut nested = scope.nested();
+        let node = self.build_query(&mut nested, query)?;
+        Ok((node, nested.len()))
+    }
+
+    /// Applies any subqueries used by expressions in the scope to a node. Each subquery result is
+    /// appended as a column after the scope columns, where the expressions expect them.
+    fn build_apply(&self, scope: &mut Scope, mut node: Node) -> Node {
+        for (i, (subquery, kind)) in take(&mut scope.subqueries).into_iter().enumerate() {
+            node = Node::Apply {
+                source: Box::new(node),
+                source_size: scope.len() + i,
+                subquery: Box::new(subquery),
+                kind,
+                level: scope.level,
+            };
+        }
+        node
+    }
+
+    /// Builds a node that evaluates expressions in the scope without changing its rows, e.g. a
+    /// filter. Any subqueries are applied first, and their columns are removed again afterwards.
+    fn build_with_subqueries<F>(&self, scope: &mut Scope, node: Node, build: F) -> Node
+    where
+        F: FnOnce(Box<Node>) -> Node,
+    {
+        if scope.subqueries.is_empty() {
+            return build(Box::new(node));
+        }
+        let expressions = (0..scope.len()).map(|i| (Expression::Field(i, None), None)).collect();
+        let node = build(Box::new(self.build_apply(scope, node)));
+        Node::Projection { source: Box::new(node), expressions }
+    }
+
+    /// Builds a filter node for a predicate, see build_with_subqueries().
+    fn build_filter(&self, scope: &mut Scope, node: Node, predicate: Expression) -> Node {
+        self.build_with_subqueries(scope, node, |source| Node::Filter { source, predicate })
+    }
+
+    /// Builds the source rows for a DELETE or UPDATE statement, i.e. a table scan with an optional
+    /// WHERE predicate.
+    fn build_mutation_source(
+        &self,
+        scope: &mut Scope,
+        table: String,
+        r#where: Option<ast::Expression>,
+    ) -> Result<Node> {
+        let predicate = r#where.map(|e| self.build_expression(scope, e)).transpose()?;
+        Ok(match predicate {
+            Some(predicate) if !scope.subqueries.is_empty() => {
+                let scan = Node::Scan { table, alias: None, filter: None };
+                self.build_filter(scope, scan, predicate)
             }
+            filter => Node::Scan { table, alias: None, filter },
         })
     }
 
@@ -285,12 +435,22 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// only be referenced by the query name (so if alias is given, cannot reference by name).
     fn build_from_item(&self, scope: &mut Scope, item: ast::FromItem) -> Result<Node> {
         Ok(match item {
-            ast::FromItem::Table { name, alias } => {
-                scope.add_table(
-                    alias.clone().unwrap_or_else(|| name.clone()),
-                    self.catalog.must_read_table(&name)?,
-                )?;
-                Node::Scan { table: name, alias, filter: None }
+            ast::FromItem::Table { name, alias } => match scope.get_cte(&name) {
+                Some((cte, visible)) => self.build_cte(scope, cte, visible, alias)?,
+                None => {
+                    scope.add_table(
+                        alias.clone().unwrap_or_else(|| name.clone()),
+                        self.catalog.must_read_table(&name)?,
+                    )?;
+                    Node::Scan { table: name, alias, filter: None }
+                }
+            },
+
+            ast::FromItem::Subquery { query, alias } => {
+                let mut derived = scope.derived();
+                let node = self.build_query(&mut derived, *query)?;
+                scope.add_relation(alias, derived.columns.into_iter().map(|(_, l)| l).collect())?;
+                node
             }
 
             ast::FromItem::Join { left, right, r#type, predicate } => {
@@ -304,6 +464,11 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 let left_size = scope.len();
                 let right = Box::new(self.build_from_item(scope, *right)?);
                 let predicate = predicate.map(|e| self.build_expression(scope, e)).transpose()?;
+                if !scope.subqueries.is_empty() {
+                    return Err(Error::Value(
+                        "Subqueries are not supported in join predicates".into(),
+                    ));
+                }
                 let outer = match r#type {
                     ast::JoinType::Cross | ast::JoinType::Inner => false,
                     ast::JoinType::Left | ast::JoinType::Right => true,
@@ -322,6 +487,194 @@ impl<'a, C: Catalog> Planner<'a, C> {
         })
     }
 
+    /// Builds a reference to a common table expression in a FROM clause. Like a subquery, the
+    /// CTE is built wherever it's referenced, but can only reference the CTEs defined before it.
+    /// Recursive CTEs are built as a recursive union of the query and the recursive query, where
+    /// references to the CTE itself in the recursive query read from a work table.
+    fn build_cte(
+        &self,
+        scope: &mut Scope,
+        cte: Cte,
+        visible: Vec<Cte>,
+        alias: Option<String>,
+    ) -> Result<Node> {
+        let (name, labels, node) = match cte {
+            Cte::WorkTable(name, columns) => {
+                let node =
+                    Node::WorkTable { name: name.clone(), columns: columns.clone(), rows: vec![] };
+                (name, columns, node)
+            }
+            Cte::Query(ast::CommonTableExpression { name, columns, query, recursive }) => {
+                // In a recursive CTE, the right-hand side of a top-level UNION is the recursive
+                // query.
+                let (query, recursive) = match *query {
+                    ast::Statement::SetOperation {
+                        operator: ast::SetOperator::Union,
+                        all,
+                        left,
+                        right,
+                        order,
+                        offset: None,
+                        limit: None,
+                    } if recursive && order.is_empty() => (left, Some((right, all))),
+                    query => (Box::new(query), None),
+                };
+                let mut cte_scope = scope.for_cte(visible.clone());
+                let mut node = self.build_query(&mut cte_scope, *query)?;
+                let labels: Vec<Option<String>> = match &columns {
+                    Some(columns) if columns.len() != cte_scope.len() => {
+                        return Err(Error::Value(format!(
+                            "Common table expression {} has {} columns, but {} were given",
+                            name,
+                            cte_scope.len(),
+                            columns.len()
+                        )))
+                    }
+                    Some(columns) => columns.iter().cloned().map(Some).collect(),
+                    None => cte_scope.columns.into_iter().map(|(_, l)| l).collect(),
+                };
+                if let Some((recursive, all)) = recursive {
+                    let mut recursive_scope = scope.for_cte(visible);
+                    recursive_scope.ctes.push(Cte::WorkTable(name.clone(), labels.clone()));
+                    let recursive = self.build_query(&mut recursive_scope, *recursive)?;
+                    if recursive_scope.len() != labels.len() {
+                        return Err(Error::Value(format!(
+                            "Recursive query for {} returns {} columns, expected {}",
+                            name,
+                            recursive_scope.len(),
+                            labels.len()
+                        )));
+                    }
+                    // If the CTE isn't referenced, this is just a plain UNION.
+                    node = match recursive.references_work_table(&name) {
+                        true => Node::RecursiveUnion {
+                            name: name.clone(),
+                            anchor: Box::new(node),
+                            recursive: Box::new(recursive),
+                            distinct: !all,
+                            limit: None,
+                        },
+                        false => Node::Union {
+                            left: Box::new(node),
+                            right: Box::new(recursive),
+                            distinct: !all,
+                        },
+                    };
+                }
+                // Rename the output columns if column names were given.
+                if columns.is_some() {
+                    node = Node::Projection {
+                        source: Box::new(node),
+                        expressions: labels
+                            .iter()
+                            .enumerate()
+                            .map(|(i, l)| (Expression::Field(i, None), l.clone()))
+                            .collect(),
+                    };
+                }
+                (name, labels, node)
+            }
+        };
+        scope.add_relation(alias.unwrap_or(name), labels)?;
+        Ok(node)
+    }
+
+    /// Builds a window node for the given window functions, which appends a column with the
+    /// result of each window function to the source rows.
+    fn build_window(
+        &self,
+        scope: &mut Scope,
+        source: Node,
+        windows: Vec<(String, Vec<ast::Expression>, ast::Window)>,
+    ) -> Result<Node> {
+        let mut built = Vec::new();
+        for (name, args, window) in windows {
+            let function = self
+                .window_from_name(&name)
+                .ok_or_else(|| Error::Value(format!("Unknown window function {}", name)))?;
+            let valid_args = match function {
+                WindowFunction::Aggregate(_) => args.len() == 1,
+                WindowFunction::Lag | WindowFunction::Lead => (1..=3).contains(&args.len()),
+                _ => args.is_empty(),
+            };
+            if !valid_args {
+                return Err(Error::Value(format!(
+                    "Invalid number of arguments for window function {}",
+                    name
+                )));
+            }
+            built.push(Window {
+                function,
+                args: args
+                    .into_iter()
+                    .map(|e| self.build_expression(scope, e))
+                    .collect::<Result<_>>()?,
+                partition: window
+                    .partition
+                    .into_iter()
+                    .map(|e| self.build_expression(scope, e))
+                    .collect::<Result<_>>()?,
+                order: window
+                    .order
+                    .into_iter()
+                    .map(|(e, o)| {
+                        Ok((
+                            self.build_expression(scope, e)?,
+                            match o {
+                                ast::Order::Ascending => Direction::Ascending,
+                                ast::Order::Descending => Direction::Descending,
+                            },
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+                frame: window.frame.map(|f| self.build_window_frame(f)).transpose()?,
+            });
+        }
+        if !scope.subqueries.is_empty() {
+            return Err(Error::Value("Subqueries are not supported in window functions".into()));
+        }
+        for _ in &built {
+            scope.add_column(None, None);
+        }
+        Ok(Node::Window { source: Box::new(source), windows: built })
+    }
+
+    /// Builds a window frame from the given start and end bounds.
+    fn build_window_frame(
+        &self,
+        (start, end): (ast::FrameBound, ast::FrameBound),
+    ) -> Result<(FrameBound, FrameBound)> {
+        let offset = |expr: Box<ast::Expression>| match self.evaluate_constant(*expr)? {
+            Value::Integer(i) if i >= 0 => Ok(i as u64),
+            v => Err(Error::Value(format!("Invalid frame offset {}", v))),
+        };
+        let build = |bound| -> Result<FrameBound> {
+            Ok(match bound {
+                ast::FrameBound::UnboundedPreceding => FrameBound::UnboundedPreceding,
+                ast::FrameBound::Preceding(expr) => FrameBound::Preceding(offset(expr)?),
+                ast::FrameBound::CurrentRow => FrameBound::CurrentRow,
+                ast::FrameBound::Following(expr) => FrameBound::Following(offset(expr)?),
+                ast::FrameBound::UnboundedFollowing => FrameBound::UnboundedFollowing,
+            })
+        };
+        // Order the bounds by position, to check that the frame doesn't start after it ends.
+        let position = |bound: &FrameBound| match bound {
+            FrameBound::UnboundedPreceding => 0,
+            FrameBound::Preceding(_) => 1,
+            FrameBound::CurrentRow => 2,
+            FrameBound::Following(_) => 3,
+            FrameBound::UnboundedFollowing => 4,
+        };
+        let (start, end) = (build(start)?, build(end)?);
+        if start == FrameBound::UnboundedFollowing
+            || end == FrameBound::UnboundedPreceding
+            || position(&start) > position(&end)
+        {
+            return Err(Error::Value(format!("Invalid window frame {} to {}", start, end)));
+        }
+        Ok((start, end))
+    }
+
     /// Builds an aggregation node. All aggregate parameters and GROUP BY expressions are evaluated
     /// in a pre-projection, whose results are fed into an Aggregate node. This node computes the
     /// aggregates for the given groups, passing the group values through directly.
@@ -341,6 +694,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         for (expr, label) in groups {
             expressions.push((self.build_expression(scope, expr)?, label));
         }
+        let source = self.build_apply(scope, source);
         scope.project(
             &expressions
                 .iter()
@@ -487,7 +841,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     hidden += 1;
                     Ok(ast::Expression::Column(select.len() - 1))
                 }
-                ast::Expression::Field(_, _) => {
+                ast::Expression::Field(_, _) | ast::Expression::Window(_, _, _) => {
                     select.push((e, None));
                     hidden += 1;
                     Ok(ast::Expression::Column(select.len() - 1))
@@ -511,6 +865,44 @@ impl<'a, C: Catalog> Planner<'a, C> {
         }
     }
 
+    /// Looks up a window function by name, which can also be an aggregate function.
+    fn window_from_name(&self, name: &str) -> Option<WindowFunction> {
+        if let Some(aggregate) = self.aggregate_from_name(name) {
+            return Some(WindowFunction::Aggregate(aggregate));
+        }
+        match name {
+            "dense_rank" => Some(WindowFunction::DenseRank),
+            "lag" => Some(WindowFunction::Lag),
+            "lead" => Some(WindowFunction::Lead),
+            "rank" => Some(WindowFunction::Rank),
+            "row_number" => Some(WindowFunction::RowNumber),
+            _ => None,
+        }
+    }
+
+    /// Extracts window functions from a set of expressions, replacing them with Column references
+    /// starting at the given offset.
+    fn extract_windows(
+        &self,
+        exprs: &mut [(ast::Expression, Option<String>)],
+        offset: usize,
+    ) -> Result<Vec<(String, Vec<ast::Expression>, ast::Window)>> {
+        let mut windows = Vec::new();
+        for (expr, _) in exprs {
+            expr.transform_mut(
+                &mut |e| match e {
+                    ast::Expression::Window(name, args, window) => {
+                        windows.push((name, args, window));
+                        Ok(ast::Expression::Column(offset + windows.len() - 1))
+                    }
+                    e => Ok(e),
+                },
+                &mut |e| Ok(e),
+            )?;
+        }
+        Ok(windows)
+    }
+
     /// Checks whether a given expression is an aggregate expression.
     fn is_aggregate(&self, expr: &ast::Expression) -> bool {
         expr.contains(&|e| match e {
@@ -523,20 +915,31 @@ impl<'a, C: Catalog> Planner<'a, C> {
     fn build_expression(&self, scope: &mut Scope, expr: ast::Expression) -> Result<Expression> {
         use Expression::*;
         Ok(match expr {
-            ast::Expression::Literal(l) => Constant(match l {
-                ast::Literal::Null => Value::Null,
-                ast::Literal::Boolean(b) => Value::Boolean(b),
-                ast::Literal::Integer(i) => Value::Integer(i),
-                ast::Literal::Float(f) => Value::Float(f),
-                ast::Literal::String(s) => Value::String(s),
-            }),
+            ast::Expression::Literal(l) => Constant(l.into()),
             ast::Expression::Column(i) => Field(i, scope.get_label(i)?),
             ast::Expression::Field(table, name) => {
-                Field(scope.resolve(table.as_deref(), &name)?, Some((table, name)))
+                match scope.resolve_any(table.as_deref(), &name)? {
+                    (level, i) if level == scope.level => Field(i, Some((table, name))),
+                    (level, i) => Outer(level, i, Some((table, name))),
+                }
+            }
+            ast::Expression::Exists(query) => {
+                let (node, _) = self.build_subquery(scope, *query)?;
+                scope.add_subquery(node, Subquery::Exists)
+            }
+            ast::Expression::Subquery(query) => {
+                let (node, columns) = self.build_subquery(scope, *query)?;
+                if columns != 1 {
+                    return Err(Error::Value("Subquery must return a single column".into()));
+                }
+                scope.add_subquery(node, Subquery::Scalar)
             }
             ast::Expression::Function(name, _) => {
                 return Err(Error::Value(format!("Unknown function {}", name,)))
             }
+            ast::Expression::Window(name, _, _) => {
+                return Err(Error::Value(format!("Window function {} not allowed here", name)))
+            }
             ast::Expression::Operation(op) => match op {
                 // Logical operators
                 ast::Operation::And(lhs, rhs) => And(
@@ -558,35 +961,48 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
                 ),
-                ast::Operation::GreaterThanOrEqual(lhs, rhs) => Or(
-                    GreaterThan(
-                        self.build_expression(scope, *lhs.clone())?.into(),
-                        self.build_expression(scope, *rhs.clone())?.into(),
-                    )
-                    .into(),
-                    Equal(
-                        self.build_expression(scope, *lhs)?.into(),
-                        self.build_expression(scope, *rhs)?.into(),
+                ast::Operation::GreaterThanOrEqual(lhs, rhs) => {
+                    let lhs = self.build_expression(scope, *lhs)?;
+                    let rhs = self.build_expression(scope, *rhs)?;
+                    Or(
+                        GreaterThan(lhs.clone().into(), rhs.clone().into()).into(),
+                        Equal(lhs.into(), rhs.into()).into(),
                     )
-                    .into(),
-                ),
+                }
+                ast::Operation::In(lhs, list) => {
+                    let lhs = self.build_expression(scope, *lhs)?;
+                    let mut expr: Option<Expression> = None;
+                    for rhs in list {
+                        let equal =
+                            Equal(lhs.clone().into(), self.build_expression(scope, rhs)?.into());
+                        expr = Some(match expr {
+                            Some(expr) => Or(expr.into(), equal.into()),
+                            None => equal,
+                        });
+                    }
+                    expr.ok_or_else(|| Error::Value("IN list can't be empty".into()))?
+                }
+                ast::Operation::InSubquery(lhs, query) => {
+                    let lhs = self.build_expression(scope, *lhs)?;
+                    let (node, columns) = self.build_subquery(scope, *query)?;
+                    if columns != 1 {
+                        return Err(Error::Value("Subquery must return a single column".into()));
+                    }
+                    scope.add_subquery(node, Subquery::In(lhs))
+                }
                 ast::Operation::IsNull(expr) => IsNull(self.build_expression(scope, *expr)?.into()),
                 ast::Operation::LessThan(lhs, rhs) => LessThan(
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
                 ),
-                ast::Operation::LessThanOrEqual(lhs, rhs) => Or(
-                    LessThan(
-                        self.build_expression(scope, *lhs.clone())?.into(),
-                        self.build_expression(scope, *rhs.clone())?.into(),
-                    )
-                    .into(),
-                    Equal(
-                        self.build_expression(scope, *lhs)?.into(),
-                        self.build_expression(scope, *rhs)?.into(),
+                ast::Operation::LessThanOrEqual(lhs, rhs) => {
+                    let lhs = self.build_expression(scope, *lhs)?;
+                    let rhs = self.build_expression(scope, *rhs)?;
+                    Or(
+                        LessThan(lhs.clone().into(), rhs.clone().into()).into(),
+                        Equal(lhs.into(), rhs.into()).into(),
                     )
-                    .into(),
-                ),
+                }
                 ast::Operation::Like(lhs, rhs) => Like(
                     self.build_expression(scope, *lhs)?.into(),
                     self.build_expression(scope, *rhs)?.into(),
@@ -631,19 +1047,68 @@ impl<'a, C: Catalog> Planner<'a, C> {
         })
     }
 
+    /// Builds a column schema from an AST column. Columns of a composite primary key are not
+    /// unique by themselves, so single_pk gives whether this is a single-column primary key.
+    fn build_column(&self, c: ast::Column, single_pk: bool) -> Result<Column> {
+        let nullable = c.nullable.unwrap_or(!c.primary_key);
+        let default = match c.default {
+            Some(expr) => Some(self.evaluate_constant(expr)?),
+            None if nullable => Some(Value::Null),
+            None => None,
+        };
+        Ok(Column {
+            name: c.name,
+            datatype: c.datatype,
+            primary_key: c.primary_key,
+            nullable,
+            default,
+            index: c.index && !(c.primary_key && single_pk),
+            unique: c.unique || (c.primary_key && single_pk),
+            references: c.references,
+        })
+    }
+
     /// Builds and evaluates a constant AST expression.
     fn evaluate_constant(&self, expr: ast::Expression) -> Result<Value> {
         self.build_expression(&mut Scope::constant(), expr)?.evaluate(None)
     }
 }
 
+/// A common table expression visible in a scope.
+#[derive(Clone, Debug)]
+enum Cte {
+    /// A named query from a WITH clause.
+    Query(ast::CommonTableExpression),
+    /// The work table of a recursive query being built, with the given column labels.
+    WorkTable(String, Vec<Option<String>>),
+}
+
+impl Cte {
+    /// Returns the name of the CTE.
+    fn name(&self) -> &str {
+        match self {
+            Self::Query(cte) => &cte.name,
+            Self::WorkTable(name, _) => name,
+        }
+    }
+}
+
 /// Manages names available to expressions and executors, and maps them onto columns/fields.
 #[derive(Clone, Debug)]
 pub struct Scope {
     // If true, the scope is constant and cannot contain any variables.
     constant: bool,
+    // The scope of the enclosing query, for subqueries.
+    outer: Option<Box<Scope>>,
+    // The query nesting level, used to resolve fields of enclosing queries.
+    level: usize,
+    // Subqueries used by expressions built in this scope, which must be applied before the
+    // expressions are evaluated. Their results are appended as columns after the scope's columns.
+    subqueries: Vec<(Node, Subquery)>,
+    // Visible common table expressions, in definition order. Later CTEs shadow earlier ones.
+    ctes: Vec<Cte>,
     // Currently visible tables, by query name (i.e. alias or actual name).
-    tables: HashMap<String, Table>,
+    tables: HashSet<String>,
     // Column labels, if any (qualified by table name when available)
     columns: Vec<(Option<String>, Option<String>)>,
     // Qualified names to column indexes.
@@ -659,7 +1124,11 @@ impl Scope {
     fn new() -> Self {
         Self {
             constant: false,
-            tables: HashMap::new(),
+            outer: None,
+            level: 0,
+            subqueries: Vec::new(),
+            ctes: Vec::new(),
+            tables: HashSet::new(),
             columns: Vec::new(),
             qualified: HashMap::new(),
             unqualified: HashMap::new(),
@@ -674,6 +1143,36 @@ impl Scope {
         scope
     }
 
+    /// Creates a scope for a subquery nested in this scope.
+    fn nested(&self) -> Self {
+        let mut outer = self.clone();
+        outer.subqueries.clear();
+        let mut scope = Self::new();
+        scope.outer = Some(Box::new(outer));
+        scope.level = self.level + 1;
+        scope.ctes = self.ctes.clone();
+        scope
+    }
+
+    /// Creates a scope for a subquery in the FROM clause. These can't reference the query
+    /// they're in, only its enclosing queries, so they share its outer scope and level.
+    fn derived(&self) -> Self {
+        let mut scope = Self::new();
+        scope.outer = self.outer.clone();
+        scope.level = self.level;
+        scope.ctes = self.ctes.clone();
+        scope
+    }
+
+    /// Creates a scope for a common table expression, which can only reference the given CTEs.
+    /// It has the same level as this scope, since it's built as part of its query.
+    fn for_cte(&self, ctes: Vec<Cte>) -> Self {
+        let mut scope = Self::new();
+        scope.level = self.level;
+        scope.ctes = ctes;
+        scope
+    }
+
     /// Creates a scope from a table.
     fn from_table(table: Table) -> Result<Self> {
         let mut scope = Self::new();
@@ -702,19 +1201,37 @@ impl Scope {
 
     /// Adds a table to the scope.
     fn add_table(&mut self, label: String, table: Table) -> Result<()> {
+        self.add_relation(label, table.columns.into_iter().map(|c| Some(c.name)).collect())
+    }
+
+    /// Adds a relation with the given column labels to the scope, i.e. a table or subquery.
+    fn add_relation(&mut self, label: String, columns: Vec<Option<String>>) -> Result<()> {
         if self.constant {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
-        if self.tables.contains_key(&label) {
+        if self.tables.contains(&label) {
             return Err(Error::Value(format!("Duplicate table name {}", label)));
         }
-        for column in &table.columns {
-            self.add_column(Some(label.clone()), Some(column.name.clone()));
+        for column in columns {
+            self.add_column(Some(label.clone()), column);
         }
-        self.tables.insert(label, table);
+        self.tables.insert(label);
         Ok(())
     }
 
+    /// Looks up a common table expression by name, returning it along with the CTEs visible to it,
+    /// i.e. those defined before it.
+    fn get_cte(&self, name: &str) -> Option<(Cte, Vec<Cte>)> {
+        let i = self.ctes.iter().rposition(|cte| cte.name() == name)?;
+        Some((self.ctes[i].clone(), self.ctes[..i].to_vec()))
+    }
+
+    /// Adds a subquery to the scope, returning a field reference to its result column.
+    fn add_subquery(&mut self, node: Node, kind: Subquery) -> Expression {
+        self.subqueries.push((node, kind));
+        Expression::Field(self.len() + self.subqueries.len() - 1, None)
+    }
+
     /// Fetches a column from the scope by index.
     fn get_column(&self, index: usize) -> Result<(Option<String>, Option<String>)> {
         if self.constant {
@@ -742,11 +1259,11 @@ impl Scope {
         if self.constant {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
-        for (label, table) in scope.tables {
-            if self.tables.contains_key(&label) {
+        for label in scope.tables {
+            if self.tables.contains(&label) {
                 return Err(Error::Value(format!("Duplicate table name {}", label)));
             }
-            self.tables.insert(label, table);
+            self.tables.insert(label);
         }
         for (table, label) in scope.columns {
             self.add_column(table, label);
@@ -763,7 +1280,7 @@ impl Scope {
             )));
         }
         if let Some(table) = table {
-            if !self.tables.contains_key(table) {
+            if !self.tables.contains(table) {
                 return Err(Error::Value(format!("Unknown table {}", table)));
             }
             self.qualified
@@ -780,6 +1297,21 @@ impl Scope {
         }
     }
 
+    /// Resolves a name in this scope or the scopes of enclosing queries, returning the query
+    /// level and column index. Names are resolved in the innermost scope where they're visible.
+    fn resolve_any(&self, table: Option<&str>, name: &str) -> Result<(usize, usize)> {
+        let visible = match table {
+            Some(table) => self.tables.contains(table),
+            None => self.unqualified.contains_key(name) || self.ambiguous.contains(name),
+        };
+        if let (false, Some(outer)) = (visible, &self.outer) {
+            if let Ok(resolved) = outer.resolve_any(table, name) {
+                return Ok(resolved);
+            }
+        }
+        Ok((self.level, self.resolve(table, name)?))
+    }
+
     /// Number of columns in the current scope.
     fn len(&self) -> usize {
         self.columns.len()
@@ -792,6 +1324,10 @@ impl Scope {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
         let mut new = Self::new();
+        new.outer = self.outer.take();
+        new.level = self.level;
+        new.subqueries = take(&mut self.subqueries);
+        new.ctes = take(&mut self.ctes);
         new.tables = self.tables.clone();
         for (expr, label) in projection {
             match (expr, label) {
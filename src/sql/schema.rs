@@ -12,10 +12,18 @@ pub trait Catalog {
     fn create_table(&mut self, table: Table) -> Result<()>;
     /// Deletes an existing table, or errors if it does not exist
     fn delete_table(&mut self, table: &str) -> Result<()>;
+    /// Alters an existing table, rewriting any existing rows, indexes, and references as needed
+    fn alter_table(&mut self, table: &str, alteration: Alteration) -> Result<()>;
     /// Reads a table, if it exists
     fn read_table(&self, table: &str) -> Result<Option<Table>>;
     /// Iterates over all tables
     fn scan_tables(&self) -> Result<Tables>;
+    /// Creates a new index on an existing table column, indexing any existing rows
+    fn create_index(&mut self, index: Index) -> Result<()>;
+    /// Deletes an existing index, or errors if it does not exist
+    fn delete_index(&mut self, index: &str) -> Result<()>;
+    /// Iterates over all indexes created with CREATE INDEX
+    fn scan_indexes(&self) -> Result<Indexes>;
 
     /// Reads a table, and errors if it does not exist
     fn must_read_table(&self, table: &str) -> Result<Table> {
@@ -74,24 +82,34 @@ impl Table {
         })
     }
 
-    /// Returns the primary key column of the table
+    /// Returns the primary key column of the table, or errors if it has a composite primary key
     pub fn get_primary_key(&self) -> Result<&Column> {
-        self.columns
-            .iter()
-            .find(|c| c.primary_key)
-            .ok_or_else(|| Error::Value(format!("Primary key not found in table {}", self.name)))
+        match self.get_primary_key_indexes()[..] {
+            [i] => Ok(&self.columns[i]),
+            [] => Err(Error::Value(format!("Primary key not found in table {}", self.name))),
+            _ => Err(Error::Value(format!("Table {} has a composite primary key", self.name))),
+        }
     }
 
-    /// Returns the primary key value of a row
-    pub fn get_row_key(&self, row: &[Value]) -> Result<Value> {
-        row.get(
-            self.columns
-                .iter()
-                .position(|c| c.primary_key)
-                .ok_or_else(|| Error::Value("Primary key not found".into()))?,
-        )
-        .cloned()
-        .ok_or_else(|| Error::Value("Primary key value not found for row".into()))
+    /// Returns the column indexes of the primary key, in key order (i.e. table column order)
+    pub fn get_primary_key_indexes(&self) -> Vec<usize> {
+        self.columns.iter().enumerate().filter(|(_, c)| c.primary_key).map(|(i, _)| i).collect()
+    }
+
+    /// Returns the primary key of a row, as a tuple of the primary key column values
+    pub fn get_row_key(&self, row: &[Value]) -> Result<Vec<Value>> {
+        let indexes = self.get_primary_key_indexes();
+        if indexes.is_empty() {
+            return Err(Error::Value("Primary key not found".into()));
+        }
+        indexes
+            .into_iter()
+            .map(|i| {
+                row.get(i)
+                    .cloned()
+                    .ok_or_else(|| Error::Value("Primary key value not found for row".into()))
+            })
+            .collect()
     }
 
     /// Validates the table schema
@@ -99,11 +117,9 @@ impl Table {
         if self.columns.is_empty() {
             return Err(Error::Value(format!("Table {} has no columns", self.name)));
         }
-        match self.columns.iter().filter(|c| c.primary_key).count() {
-            1 => {}
-            0 => return Err(Error::Value(format!("No primary key in table {}", self.name))),
-            _ => return Err(Error::Value(format!("Multiple primary keys in table {}", self.name))),
-        };
+        if !self.columns.iter().any(|c| c.primary_key) {
+            return Err(Error::Value(format!("No primary key in table {}", self.name)));
+        }
         for column in &self.columns {
             column.validate(self, txn)?;
         }
@@ -124,12 +140,84 @@ impl Table {
 }
 
 impl Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pk = self.get_primary_key_indexes();
+        let mut lines: Vec<String> = if pk.len() > 1 {
+            // Composite primary keys are given as a table constraint.
+            self.columns
+                .iter()
+                .map(|c| format!("  {}", Column { primary_key: false, ..c.clone() }))
+                .collect()
+        } else {
+            self.columns.iter().map(|c| format!("  {}", c)).collect()
+        };
+        if pk.len() > 1 {
+            lines.push(format!(
+                "  PRIMARY KEY ({})",
+                pk.into_iter()
+                    .map(|i| format_ident(&self.columns[i].name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        write!(f, "CREATE TABLE {} (\n{}\n)", format_ident(&self.name), lines.join(",\n"))
+    }
+}
+
+/// A table schema change, made with ALTER TABLE
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Alteration {
+    /// Adds a column, filling existing rows with its default value
+    AddColumn(Column),
+    /// Drops a column, along with any indexes on it
+    DropColumn(String),
+    /// Renames a column
+    RenameColumn { from: String, to: String },
+    /// Renames the table
+    RenameTable(String),
+}
+
+impl Display for Alteration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddColumn(column) => write!(f, "ADD COLUMN {}", column),
+            Self::DropColumn(name) => write!(f, "DROP COLUMN {}", format_ident(name)),
+            Self::RenameColumn { from, to } => {
+                write!(f, "RENAME COLUMN {} TO {}", format_ident(from), format_ident(to))
+            }
+            Self::RenameTable(name) => write!(f, "RENAME TO {}", format_ident(name)),
+        }
+    }
+}
+
+/// An index scan iterator
+pub type Indexes = Box<dyn DoubleEndedIterator<Item = Index> + Send>;
+
+/// A named index on a table column, created with CREATE INDEX. These are stored separately from
+/// the table schema, which only records whether a column is indexed.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Index {
+    /// Index name
+    pub name: String,
+    /// The indexed table
+    pub table: String,
+    /// The indexed columns. Indexes on multiple columns can be used for lookups on a prefix of
+    /// the columns.
+    pub columns: Vec<String>,
+    /// Whether the index enforces unique values. For single-column indexes, this is only set if the
+    /// index added a UNIQUE constraint to the column.
+    pub unique: bool,
+}
+
+impl Display for Index {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "CREATE TABLE {} (\n{}\n)",
+            "CREATE {}INDEX {} ON {} ({})",
+            if self.unique { "UNIQUE " } else { "" },
             format_ident(&self.name),
-            self.columns.iter().map(|c| format!("  {}", c)).collect::<Vec<String>>().join(",\n")
+            format_ident(&self.table),
+            self.columns.iter().map(|c| format_ident(c)).collect::<Vec<_>>().join(", ")
         )
     }
 }
@@ -162,7 +250,7 @@ impl Column {
         if self.primary_key && self.nullable {
             return Err(Error::Value(format!("Primary key {} cannot be nullable", self.name)));
         }
-        if self.primary_key && !self.unique {
+        if self.primary_key && !self.unique && table.get_primary_key_indexes().len() == 1 {
             return Err(Error::Value(format!("Primary key {} must be unique", self.name)));
         }
 
@@ -218,7 +306,7 @@ impl Column {
     pub fn validate_value(
         &self,
         table: &Table,
-        pk: &Value,
+        pk: &[Value],
         value: &Value,
         txn: &mut dyn Transaction,
     ) -> Result<()> {
@@ -246,11 +334,10 @@ impl Column {
             match value {
                 Value::Null => Ok(()),
                 Value::Float(f) if f.is_nan() => Ok(()),
-                v if target == &table.name && v == pk => Ok(()),
-                v if txn.read(target, v)?.is_none() => Err(Error::Value(format!(
-                    "Referenced primary key {} in table {} does not exist",
-                    v, target,
-                ))),
+                v if target == &table.name && pk == std::slice::from_ref(v) => Ok(()),
+                v if txn.read(target, std::slice::from_ref(v))?.is_none() => Err(Error::Value(
+                    format!("Referenced primary key {} in table {} does not exist", v, target,),
+                )),
                 _ => Ok(()),
             }?;
         }
@@ -260,8 +347,7 @@ impl Column {
             let index = table.get_column_index(&self.name)?;
             let mut scan = txn.scan(&table.name, None)?;
             while let Some(row) = scan.next().transpose()? {
-                if row.get(index).unwrap_or(&Value::Null) == value
-                    && &table.get_row_key(&row)? != pk
+                if row.get(index).unwrap_or(&Value::Null) == value && table.get_row_key(&row)? != pk
                 {
                     return Err(Error::Value(format!(
                         "Unique value {} already exists for column {}",
@@ -12,6 +12,9 @@ pub enum Expression {
     // Values
     Constant(Value),
     Field(usize, Option<(Option<String>, String)>),
+    // A field of an enclosing query, by query nesting level and field index. These are bound to
+    // constants by the subquery executor before evaluation.
+    Outer(usize, usize, Option<(Option<String>, String)>),
 
     // Logical operations
     And(Box<Expression>, Box<Expression>),
@@ -47,6 +50,9 @@ impl Expression {
             // Constant values
             Self::Constant(c) => c.clone(),
             Self::Field(i, _) => row.and_then(|row| row.get(*i).cloned()).unwrap_or(Null),
+            Self::Outer(..) => {
+                return Err(Error::Internal(format!("Unbound outer field {}", self)))
+            }
 
             // Logical operations
             Self::And(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
@@ -304,7 +310,7 @@ impl Expression {
             | Self::Negate(expr)
             | Self::Not(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
 
-            Self::Constant(_) | Self::Field(_, _) => {}
+            Self::Constant(_) | Self::Field(_, _) | Self::Outer(..) => {}
         };
         after(self)
     }
@@ -332,7 +338,7 @@ impl Expression {
                 | Self::Negate(expr)
                 | Self::Not(expr) => expr.walk(visitor),
 
-                Self::Constant(_) | Self::Field(_, _) => true,
+                Self::Constant(_) | Self::Field(_, _) | Self::Outer(..) => true,
             }
     }
 
@@ -524,6 +530,9 @@ impl Display for Expression {
             Self::Field(i, None) => format!("#{}", i),
             Self::Field(_, Some((None, name))) => name.to_string(),
             Self::Field(_, Some((Some(table), name))) => format!("{}.{}", table, name),
+            Self::Outer(level, i, None) => format!("#{}.{}", level, i),
+            Self::Outer(_, _, Some((None, name))) => name.to_string(),
+            Self::Outer(_, _, Some((Some(table), name))) => format!("{}.{}", table, name),
 
             Self::And(lhs, rhs) => format!("{} AND {}", lhs, rhs),
             Self::Or(lhs, rhs) => format!("{} OR {}", lhs, rhs),
@@ -45,9 +45,11 @@ impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.datatype().hash(state);
         match self {
-            Value::Null => self.hash(state),
+            Value::Null => {}
             Value::Boolean(v) => v.hash(state),
             Value::Integer(v) => v.hash(state),
+            // 0.0 and -0.0 are equal, so they must hash equally too.
+            Value::Float(v) if *v == 0.0 => 0.0_f64.to_be_bytes().hash(state),
             Value::Float(v) => v.to_be_bytes().hash(state),
             Value::String(v) => v.hash(state),
         }
@@ -109,6 +111,17 @@ impl Value {
             v => Err(Error::Value(format!("Not a string: {:?}", v))),
         }
     }
+
+    /// Formats a tuple of values, e.g. a primary key. Single values are formatted as is, while
+    /// multiple values are formatted as (a, b).
+    pub fn format_tuple(values: &[Value]) -> String {
+        match values {
+            [value] => value.to_string(),
+            values => {
+                format!("({})", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -0,0 +1,274 @@
+use super::{Range, Scan, Store};
+use crate::error::{Error, Result};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// A key/value store wrapper which can buffer writes and then apply them to the underlying store
+/// as a single atomic write batch. While buffering, reads see the buffered writes, and any flushes
+/// are deferred until the batch is committed.
+pub struct Batch {
+    /// The underlying store.
+    inner: Box<dyn Store>,
+    /// Buffered writes, if buffering, with None for deletes.
+    writes: Option<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// Whether a flush was requested while buffering.
+    flush: bool,
+}
+
+impl Display for Batch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl Batch {
+    /// Creates a new batch wrapper for the given store, which passes writes through until
+    /// start() is called.
+    pub fn new(inner: Box<dyn Store>) -> Self {
+        Self { inner, writes: None, flush: false }
+    }
+
+    /// Starts buffering writes.
+    pub fn start(&mut self) -> Result<()> {
+        if self.writes.is_some() {
+            return Err(Error::Internal("Write batch already started".into()));
+        }
+        self.writes = Some(BTreeMap::new());
+        Ok(())
+    }
+
+    /// Writes the buffered writes to the underlying store as an atomic batch, flushing it if
+    /// requested while buffering, and stops buffering.
+    pub fn commit(&mut self) -> Result<()> {
+        match self.writes.take() {
+            Some(writes) => {
+                self.inner.write_batch(writes.into_iter().collect())?;
+                if std::mem::take(&mut self.flush) {
+                    self.inner.flush()?;
+                }
+                Ok(())
+            }
+            None => Err(Error::Internal("No write batch started".into())),
+        }
+    }
+
+    /// Discards the buffered writes, and stops buffering.
+    pub fn discard(&mut self) {
+        self.writes = None;
+        self.flush = false;
+    }
+}
+
+impl Store for Batch {
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        match &mut self.writes {
+            Some(writes) => {
+                writes.insert(key.to_vec(), None);
+                Ok(())
+            }
+            None => self.inner.delete(key),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &self.writes {
+            Some(_) => {
+                self.flush = true;
+                Ok(())
+            }
+            None => self.inner.flush(),
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.as_ref().and_then(|writes| writes.get(key)) {
+            Some(value) => Ok(value.clone()),
+            None => self.inner.get(key),
+        }
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        let writes = match &self.writes {
+            Some(writes) => writes
+                .range(Range { start: range.start.clone(), end: range.end.clone() })
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let scan = self.inner.scan(range);
+        if writes.is_empty() {
+            return scan;
+        }
+        Box::new(MergeScan { scan: Peek::new(scan), writes: Peek::new(writes.into_iter()) })
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match &mut self.writes {
+            Some(writes) => {
+                writes.insert(key.to_vec(), Some(value));
+                Ok(())
+            }
+            None => self.inner.set(key, value),
+        }
+    }
+}
+
+/// A double-ended iterator wrapper which can peek at both ends.
+struct Peek<I: DoubleEndedIterator> {
+    iter: I,
+    front: Option<I::Item>,
+    back: Option<I::Item>,
+}
+
+impl<I: DoubleEndedIterator> Peek<I> {
+    fn new(iter: I) -> Self {
+        Self { iter, front: None, back: None }
+    }
+
+    fn peek_front(&mut self) -> Option<&I::Item> {
+        if self.front.is_none() {
+            self.front = self.iter.next().or_else(|| self.back.take());
+        }
+        self.front.as_ref()
+    }
+
+    fn peek_back(&mut self) -> Option<&I::Item> {
+        if self.back.is_none() {
+            self.back = self.iter.next_back().or_else(|| self.front.take());
+        }
+        self.back.as_ref()
+    }
+}
+
+/// A buffered write, with None for deletes.
+type Write = (Vec<u8>, Option<Vec<u8>>);
+
+/// A scan over the underlying store merged with buffered writes, which take precedence.
+struct MergeScan {
+    scan: Peek<Scan>,
+    writes: Peek<std::vec::IntoIter<Write>>,
+}
+
+impl MergeScan {
+    /// Returns the next item from either end. When reverse, the greater key is taken first.
+    fn next_from(&mut self, reverse: bool) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let (scan, write) = match reverse {
+                false => (self.scan.peek_front(), self.writes.peek_front()),
+                true => (self.scan.peek_back(), self.writes.peek_back()),
+            };
+            let ordering = match (scan, write) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(Ok(_)), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((key, _))), Some((write_key, _))) => match reverse {
+                    false => key.cmp(write_key),
+                    true => write_key.cmp(key),
+                },
+            };
+            let (scan, writes) = match reverse {
+                false => (&mut self.scan.front, &mut self.writes.front),
+                true => (&mut self.scan.back, &mut self.writes.back),
+            };
+            if ordering == Ordering::Less {
+                return scan.take();
+            }
+            if ordering == Ordering::Equal {
+                scan.take();
+            }
+            if let Some((key, Some(value))) = writes.take() {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+impl Iterator for MergeScan {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_from(false)
+    }
+}
+
+impl DoubleEndedIterator for MergeScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_from(true)
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<Batch> for Batch {
+    fn setup() -> Result<Self> {
+        let mut batch = Batch::new(Box::new(super::Memory::new()));
+        batch.start()?;
+        Ok(batch)
+    }
+}
+
+#[test]
+fn tests() -> Result<()> {
+    use super::TestSuite;
+    Batch::test()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Memory;
+    use super::*;
+
+    #[test]
+    fn merge() -> Result<()> {
+        let mut inner = Memory::new();
+        for key in [b"a", b"b", b"c", b"d", b"e"] {
+            inner.set(key, vec![0x01])?;
+        }
+        let mut batch = Batch::new(Box::new(inner));
+        batch.start()?;
+        batch.set(b"0", vec![0x02])?;
+        batch.delete(b"b")?;
+        batch.set(b"c", vec![0x02])?;
+        batch.set(b"cc", vec![0x02])?;
+        batch.delete(b"e")?;
+        batch.delete(b"f")?;
+
+        let expect = vec![
+            (b"0".to_vec(), vec![0x02]),
+            (b"a".to_vec(), vec![0x01]),
+            (b"c".to_vec(), vec![0x02]),
+            (b"cc".to_vec(), vec![0x02]),
+            (b"d".to_vec(), vec![0x01]),
+        ];
+        assert_eq!(expect, batch.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+        assert_eq!(
+            expect.iter().rev().cloned().collect::<Vec<_>>(),
+            batch.scan(Range::from(..)).rev().collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            expect[1..4].to_vec(),
+            batch.scan(Range::from(b"a".to_vec()..b"d".to_vec())).collect::<Result<Vec<_>>>()?
+        );
+
+        // Iterating from both ends should meet in the middle.
+        let mut scan = batch.scan(Range::from(..));
+        assert_eq!(Some(expect[0].clone()), scan.next().transpose()?);
+        assert_eq!(Some(expect[4].clone()), scan.next_back().transpose()?);
+        assert_eq!(Some(expect[3].clone()), scan.next_back().transpose()?);
+        assert_eq!(Some(expect[1].clone()), scan.next().transpose()?);
+        assert_eq!(Some(expect[2].clone()), scan.next().transpose()?);
+        assert_eq!(None, scan.next_back().transpose()?);
+
+        // Discarded writes are not applied, committed writes are.
+        batch.discard();
+        assert_eq!(Some(vec![0x01]), batch.get(b"b")?);
+        batch.start()?;
+        batch.delete(b"b")?;
+        assert_eq!(Some(vec![0x01]), batch.inner.get(b"b")?);
+        batch.commit()?;
+        assert_eq!(None, batch.inner.get(b"b")?);
+        assert_eq!(Err(Error::Internal("No write batch started".into())), batch.commit());
+        Ok(())
+    }
+}
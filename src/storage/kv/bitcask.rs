@@ -0,0 +1,673 @@
+use super::{Range, Scan, Store};
+use crate::error::{Error, Result};
+use crate::storage::{check_torn, FileCheck};
+
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek as _, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The fraction of garbage in the data file which triggers compaction on startup.
+const COMPACT_GARBAGE_RATIO: f64 = 0.5;
+
+/// The size of an entry header: a u32 checksum, a u32 key length, and an i32 value length.
+const HEADER_SIZE: u64 = 12;
+
+/// The maximum combined size of an entry's key and value. Larger lengths in the data file are
+/// considered corrupt.
+const MAX_ENTRY_SIZE: u64 = 1 << 30;
+
+/// The value length of a batch entry, whose key data holds a sequence of regular entries that are
+/// written atomically.
+const BATCH: i32 = -2;
+
+/// Maps live keys to the position and length of their value in the data file.
+type KeyDir = BTreeMap<Vec<u8>, (u64, u32)>;
+
+/// A disk-based key-value store using a log-structured hash table, based on BitCask (see
+/// https://riak.com/assets/bitcask-intro.pdf). All writes are appended to a single data file, and
+/// an in-memory index (the keydir) maps each live key to the position of its value in the file.
+/// Keys must therefore fit in memory, but values are only read from disk when accessed.
+///
+/// Each entry consists of a big-endian u32 CRC32 checksum of the rest of the entry, a big-endian
+/// u32 key length, a big-endian i32 value length (-1 for deletion tombstones), the key, and the
+/// value. The keydir is rebuilt on startup by scanning the file and verifying checksums. A torn
+/// entry at the end of the file, i.e. an incomplete entry or a checksum mismatch in the last
+/// entry after a crash during a write, is truncated. A checksum mismatch anywhere else is
+/// returned as Error::Corruption, as is a length above MAX_ENTRY_SIZE. A length that points
+/// beyond the end of the file is only considered a torn entry if no valid entry follows it,
+/// otherwise it's a corrupt length field in the middle of the file and also returned as
+/// Error::Corruption rather than truncating the valid entries after it.
+///
+/// Write batches are stored as a single batch entry, with a value length of BATCH and the batch's
+/// encoded entries as its key. The batch checksum covers all of its entries, such that a torn
+/// batch is truncated as a whole and a batch is either fully applied on startup or not at all.
+///
+/// Replaced and deleted values remain in the file as garbage. If the garbage exceeds
+/// COMPACT_GARBAGE_RATIO of the file on startup, the live entries are written to a new file which
+/// atomically replaces the old one.
+pub struct BitCask {
+    /// The path of the data file.
+    path: PathBuf,
+    /// The append-only data file. Protected by a mutex for interior mutability (i.e. read seeks),
+    /// and shared with scan iterators, which read values lazily.
+    file: Arc<Mutex<File>>,
+    /// Value locations of all live keys.
+    keydir: KeyDir,
+    /// The size of the data file, in bytes.
+    size: u64,
+    /// The total size of live entries in the data file, in bytes.
+    live_size: u64,
+    /// If true, fsync writes on flush.
+    sync: bool,
+}
+
+impl Display for BitCask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bitcask")
+    }
+}
+
+impl BitCask {
+    /// Creates or opens a BitCask store using the given data file, compacting it if it contains
+    /// too much garbage.
+    pub fn new(path: PathBuf, sync: bool) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let (keydir, size, _) = Self::scan_file(&file, &path)?;
+        if file.metadata()?.len() > size {
+            warn!("Truncating torn BitCask entry at offset {}", size);
+            file.set_len(size)?;
+            file.sync_all()?;
+        }
+        let live_size = keydir.iter().map(|(k, (_, len))| Self::entry_size(k, *len)).sum();
+        let mut bitcask =
+            Self { path, file: Arc::new(Mutex::new(file)), keydir, size, live_size, sync };
+        if bitcask.garbage() as f64 > bitcask.size as f64 * COMPACT_GARBAGE_RATIO {
+            bitcask.compact()?;
+        }
+        Ok(bitcask)
+    }
+
+    /// Verifies the given data file without modifying it. Errors with Error::Corruption if the
+    /// file is corrupt.
+    pub fn verify(path: &Path) -> Result<FileCheck> {
+        let file = File::open(path)?;
+        let (_, size, entries) = Self::scan_file(&file, path)?;
+        let torn = file.metadata()?.len() - size;
+        Ok(FileCheck { path: path.to_path_buf(), entries, size, torn })
+    }
+
+    /// Builds the keydir by scanning the data file and verifying checksums, returning it along
+    /// with the size of the valid part of the file, excluding any torn entry at the end, and the
+    /// number of entries.
+    fn scan_file(file: &File, path: &Path) -> Result<(KeyDir, u64, u64)> {
+        let filesize = file.metadata()?.len();
+        let mut bufreader = BufReader::new(file);
+        let mut keydir = KeyDir::new();
+        let mut header = [0; HEADER_SIZE as usize];
+        let mut pos = 0;
+        let mut entries = 0;
+        while pos + HEADER_SIZE <= filesize {
+            bufreader.read_exact(&mut header)?;
+            let (checksum, key_len, value_len) = Self::decode_header(&header);
+            let data_len = Self::data_len(key_len, value_len).ok_or_else(|| {
+                Error::Corruption(format!(
+                    "Invalid length for entry at offset {} in {}",
+                    pos,
+                    path.display()
+                ))
+            })?;
+            let end = pos + HEADER_SIZE + data_len;
+            if end > filesize {
+                let mut tail = header.to_vec();
+                bufreader.read_to_end(&mut tail)?;
+                // A torn batch may contain complete entries, which don't follow it.
+                let mut offset = 0;
+                if value_len == BATCH {
+                    offset = HEADER_SIZE as usize;
+                    while Self::valid_entry(&tail[offset..]) {
+                        let (_, key_len, value_len) = Self::decode_header(&tail[offset..]);
+                        offset += (HEADER_SIZE + key_len as u64 + value_len.max(0) as u64) as usize;
+                    }
+                }
+                check_torn(path, pos + offset as u64, &tail[offset..], Self::valid_entry)?;
+                break;
+            }
+            let mut data = vec![0; data_len as usize];
+            bufreader.read_exact(&mut data)?;
+            if Self::checksum(&header, &data) != checksum {
+                if end == filesize {
+                    break;
+                }
+                return Err(Error::Corruption(format!(
+                    "Checksum mismatch for entry at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            if value_len == BATCH {
+                let batch = Self::decode_batch(&data).ok_or_else(|| {
+                    Error::Corruption(format!(
+                        "Invalid batch entry at offset {} in {}",
+                        pos,
+                        path.display()
+                    ))
+                })?;
+                for (offset, key_len, value_len) in batch {
+                    let key_pos = offset + HEADER_SIZE as usize;
+                    let key = data[key_pos..key_pos + key_len as usize].to_vec();
+                    let value_pos = pos + HEADER_SIZE + (key_pos + key_len as usize) as u64;
+                    Self::apply_entry(&mut keydir, key, value_pos, value_len);
+                    entries += 1;
+                }
+            } else {
+                let value_pos = pos + HEADER_SIZE + key_len as u64;
+                data.truncate(key_len as usize);
+                Self::apply_entry(&mut keydir, data, value_pos, value_len);
+                entries += 1;
+            }
+            pos = end;
+        }
+        Ok((keydir, pos, entries))
+    }
+
+    /// Applies a scanned entry to the keydir, given the position of its value.
+    fn apply_entry(keydir: &mut KeyDir, key: Vec<u8>, value_pos: u64, value_len: i32) {
+        if value_len >= 0 {
+            keydir.insert(key, (value_pos, value_len as u32));
+        } else {
+            keydir.remove(&key);
+        }
+    }
+
+    /// Decodes the entries of a batch entry's data, returning the offset, key length, and value
+    /// length of each, or None if the batch is malformed.
+    fn decode_batch(data: &[u8]) -> Option<Vec<(usize, u32, i32)>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            if offset + HEADER_SIZE as usize > data.len() {
+                return None;
+            }
+            let (_, key_len, value_len) = Self::decode_header(&data[offset..]);
+            let len = match Self::data_len(key_len, value_len) {
+                Some(len) if value_len != BATCH => len as usize,
+                _ => return None,
+            };
+            if offset + HEADER_SIZE as usize + len > data.len() {
+                return None;
+            }
+            entries.push((offset, key_len, value_len));
+            offset += HEADER_SIZE as usize + len;
+        }
+        Some(entries)
+    }
+
+    /// Decodes an entry header into its checksum, key length, and value length.
+    fn decode_header(header: &[u8]) -> (u32, u32, i32) {
+        (
+            u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            i32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+        )
+    }
+
+    /// Returns the length of an entry's key and value data given their header lengths, or None
+    /// if the lengths are invalid.
+    fn data_len(key_len: u32, value_len: i32) -> Option<u64> {
+        let len = key_len as u64 + value_len.max(0) as u64;
+        if value_len >= BATCH && len <= MAX_ENTRY_SIZE {
+            Some(len)
+        } else {
+            None
+        }
+    }
+
+    /// Computes the checksum of an entry, given its header and data.
+    fn checksum(header: &[u8], data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..HEADER_SIZE as usize]);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Returns true if the given data starts with a complete, valid entry.
+    fn valid_entry(data: &[u8]) -> bool {
+        if data.len() < HEADER_SIZE as usize {
+            return false;
+        }
+        let (checksum, key_len, value_len) = Self::decode_header(data);
+        match Self::data_len(key_len, value_len) {
+            Some(len) if HEADER_SIZE + len <= data.len() as u64 => {
+                let end = (HEADER_SIZE + len) as usize;
+                Self::checksum(data, &data[HEADER_SIZE as usize..end]) == checksum
+            }
+            _ => false,
+        }
+    }
+
+    /// Compacts the data file by writing all live entries to a new file, which atomically
+    /// replaces the old one.
+    pub fn compact(&mut self) -> Result<()> {
+        let compact_path = self.path.with_extension("compact");
+        let mut compact_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&compact_path)?;
+        let mut compact_keydir = KeyDir::new();
+        let mut bufwriter = BufWriter::new(&mut compact_file);
+        let mut pos = 0;
+        for (key, (value_pos, value_len)) in self.keydir.iter() {
+            let value = Self::read_value(&self.file, *value_pos, *value_len)?;
+            bufwriter.write_all(&Self::encode_entry(key, Some(&value)))?;
+            compact_keydir.insert(key.clone(), (pos + HEADER_SIZE + key.len() as u64, *value_len));
+            pos += Self::entry_size(key, *value_len);
+        }
+        bufwriter.flush()?;
+        drop(bufwriter);
+        compact_file.sync_all()?;
+        rename(&compact_path, &self.path)?;
+        // Sync the directory too, otherwise the rename may be lost on a crash.
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all()?,
+            _ => File::open(".")?.sync_all()?,
+        }
+
+        self.file = Arc::new(Mutex::new(compact_file));
+        self.keydir = compact_keydir;
+        self.size = pos;
+        self.live_size = pos;
+        Ok(())
+    }
+
+    /// Returns the number of garbage bytes in the data file, i.e. replaced or deleted entries.
+    pub fn garbage(&self) -> u64 {
+        self.size - self.live_size
+    }
+
+    /// Encodes an entry, with None as a deletion tombstone.
+    fn encode_entry(key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+        let value_len = value.map(|v| v.len() as i32).unwrap_or(-1);
+        Self::encode(key, value_len, value.unwrap_or_default())
+    }
+
+    /// Encodes a batch entry, given its encoded entries.
+    fn encode_batch(entries: &[u8]) -> Vec<u8> {
+        Self::encode(entries, BATCH, &[])
+    }
+
+    /// Encodes an entry with the given key, value length field, and value.
+    fn encode(key: &[u8], value_len: i32, value: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(HEADER_SIZE as usize + key.len() + value.len());
+        entry.extend_from_slice(&[0; 4]); // checksum placeholder
+        entry.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        entry.extend_from_slice(&value_len.to_be_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(value);
+        let checksum = crc32fast::hash(&entry[4..]);
+        entry[..4].copy_from_slice(&checksum.to_be_bytes());
+        entry
+    }
+
+    /// Returns the size of a live entry in the data file.
+    fn entry_size(key: &[u8], value_len: u32) -> u64 {
+        HEADER_SIZE + key.len() as u64 + value_len as u64
+    }
+
+    /// Reads a value from the data file.
+    fn read_value(file: &Mutex<File>, pos: u64, len: u32) -> Result<Vec<u8>> {
+        let mut value = vec![0; len as usize];
+        let mut file = file.lock()?;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    /// Appends an entry to the data file, returning its position.
+    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<u64> {
+        self.append(&Self::encode_entry(key, value))
+    }
+
+    /// Appends an encoded entry to the data file, returning its position.
+    fn append(&mut self, entry: &[u8]) -> Result<u64> {
+        let mut file = self.file.lock()?;
+        let pos = file.seek(SeekFrom::Start(self.size))?;
+        file.write_all(entry)?;
+        self.size += entry.len() as u64;
+        Ok(pos)
+    }
+}
+
+impl Store for BitCask {
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if let Some((_, value_len)) = self.keydir.remove(key) {
+            self.write_entry(key, None)?;
+            self.live_size -= Self::entry_size(key, value_len);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.sync {
+            self.file.lock()?.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.keydir.get(key) {
+            Some((pos, len)) => Ok(Some(Self::read_value(&self.file, *pos, *len)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        // The keys and value locations are buffered, since the keydir may change during
+        // iteration. Values are read lazily. The data file is append-only and compaction replaces
+        // the file handle, so buffered locations remain valid.
+        Box::new(Iter {
+            file: self.file.clone(),
+            entries: self
+                .keydir
+                .range(range)
+                .map(|(k, v)| (k.clone(), *v))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        })
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if (key.len() + value.len()) as u64 > MAX_ENTRY_SIZE {
+            return Err(Error::Value(format!("Entry size {} too large", key.len() + value.len())));
+        }
+        let pos = self.write_entry(key, Some(&value))?;
+        let value_len = value.len() as u32;
+        let value_pos = pos + HEADER_SIZE + key.len() as u64;
+        if let Some((_, old_len)) = self.keydir.insert(key.to_vec(), (value_pos, value_len)) {
+            self.live_size -= Self::entry_size(key, old_len);
+        }
+        self.live_size += Self::entry_size(key, value_len);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut entries = Vec::new();
+        let mut offsets = Vec::with_capacity(batch.len());
+        for (key, value) in &batch {
+            offsets.push(entries.len() as u64);
+            entries.extend(Self::encode_entry(key, value.as_deref()));
+        }
+        if entries.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(Error::Value(format!("Batch size {} too large", entries.len())));
+        }
+        let pos = self.append(&Self::encode_batch(&entries))?;
+        for ((key, value), offset) in batch.into_iter().zip(offsets) {
+            let old = match value {
+                Some(value) => {
+                    let value_len = value.len() as u32;
+                    let value_pos = pos + HEADER_SIZE + offset + HEADER_SIZE + key.len() as u64;
+                    self.live_size += Self::entry_size(&key, value_len);
+                    self.keydir.insert(key.clone(), (value_pos, value_len))
+                }
+                None => self.keydir.remove(&key),
+            };
+            if let Some((_, old_len)) = old {
+                self.live_size -= Self::entry_size(&key, old_len);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A BitCask key/value scan iterator.
+struct Iter {
+    /// The data file to read values from.
+    file: Arc<Mutex<File>>,
+    /// The keys and value locations to iterate over.
+    entries: std::vec::IntoIter<(Vec<u8>, (u64, u32))>,
+}
+
+impl Iter {
+    /// Reads the value for an entry.
+    fn read(&self, (key, (pos, len)): (Vec<u8>, (u64, u32))) -> Result<(Vec<u8>, Vec<u8>)> {
+        Ok((key, BitCask::read_value(&self.file, pos, len)?))
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| self.read(entry))
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries.next_back().map(|entry| self.read(entry))
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<BitCask> for BitCask {
+    fn setup() -> Result<Self> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        BitCask::new(dir.path().join("bitcask"), false)
+    }
+}
+
+#[test]
+fn tests() -> Result<()> {
+    use super::TestSuite;
+    BitCask::test()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Range;
+    use super::*;
+
+    #[test]
+    fn write_batch() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), true)?;
+        s.set(b"a", vec![0x01])?;
+        s.write_batch(vec![(b"a".to_vec(), None), (b"b".to_vec(), Some(vec![0x02]))])?;
+        s.flush()?;
+        let size = std::fs::metadata(&path)?.len();
+        s.write_batch(vec![(b"b".to_vec(), Some(vec![0x03])), (b"c".to_vec(), Some(vec![0x04]))])?;
+        s.flush()?;
+        assert_eq!(Some(vec![0x03]), s.get(b"b")?);
+        drop(s);
+
+        // A torn batch is truncated as a whole, even if some of its entries are complete.
+        let torn_path = dir.path().join("torn");
+        std::fs::copy(&path, &torn_path)?;
+        let file = OpenOptions::new().write(true).open(&torn_path)?;
+        file.set_len(file.metadata()?.len() - 1)?;
+        drop(file);
+        assert_eq!(size, BitCask::verify(&torn_path)?.size);
+        let s = BitCask::new(torn_path, true)?;
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x02])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+
+        let s = BitCask::new(path, true)?;
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x03]), (b"c".to_vec(), vec![0x04])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reopen() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), true)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.set(b"c", vec![])?;
+        s.set(b"a", vec![0x03])?;
+        s.delete(b"b")?;
+        s.flush()?;
+        drop(s);
+
+        let s = BitCask::new(path, true)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x03]), (b"c".to_vec(), vec![])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), false)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        assert_eq!(0, s.garbage());
+        s.set(b"a", vec![0x03])?;
+        s.delete(b"b")?;
+        assert_eq!(41, s.garbage());
+
+        // Scans started before compaction still see their entries.
+        let scan = s.scan(Range::from(..));
+        s.compact()?;
+        assert_eq!(0, s.garbage());
+        assert_eq!(14, std::fs::metadata(&path)?.len());
+        assert_eq!(vec![(b"a".to_vec(), vec![0x03])], scan.collect::<Result<Vec<_>>>()?);
+
+        s.set(b"c", vec![0x04])?;
+        assert_eq!(Some(vec![0x03]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x04]), s.get(b"c")?);
+        drop(s);
+
+        let s = BitCask::new(path, false)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x03]), (b"c".to_vec(), vec![0x04])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact_on_open() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), false)?;
+        for i in 0..10_u8 {
+            s.set(b"a", vec![i])?;
+        }
+        drop(s);
+
+        let s = BitCask::new(path.clone(), false)?;
+        assert_eq!(0, s.garbage());
+        assert_eq!(14, std::fs::metadata(&path)?.len());
+        assert_eq!(Some(vec![9]), s.get(b"a")?);
+        Ok(())
+    }
+
+    #[test]
+    fn torn_tail() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), false)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02, 0x03])?;
+        drop(s);
+
+        // Chop off the last byte of the last entry, which should be truncated on open.
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(file.metadata()?.len() - 1)?;
+        drop(file);
+
+        let mut s = BitCask::new(path.clone(), false)?;
+        assert_eq!(14, std::fs::metadata(&path)?.len());
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+
+        s.set(b"c", vec![0x04])?;
+        drop(s);
+        let s = BitCask::new(path, false)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"c".to_vec(), vec![0x04])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn corruption() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), false)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        drop(s);
+        assert_eq!(
+            FileCheck { path: path.clone(), entries: 2, size: 28, torn: 0 },
+            BitCask::verify(&path)?
+        );
+
+        // A checksum mismatch in the last entry is a torn write.
+        let mut data = std::fs::read(&path)?;
+        data[27] ^= 0x01;
+        std::fs::write(&path, &data)?;
+        assert_eq!(
+            FileCheck { path: path.clone(), entries: 1, size: 14, torn: 14 },
+            BitCask::verify(&path)?
+        );
+
+        // Elsewhere, it's corruption.
+        data[13] ^= 0x01;
+        std::fs::write(&path, &data)?;
+        assert!(matches!(BitCask::verify(&path), Err(Error::Corruption(_))));
+        assert!(matches!(BitCask::new(path.clone(), false), Err(Error::Corruption(_))));
+        assert_eq!(28, std::fs::metadata(&path)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_length() -> Result<()> {
+        let dir = tempdir::TempDir::new("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut s = BitCask::new(path.clone(), false)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.set(b"c", vec![0x03])?;
+        drop(s);
+
+        // A value length in the middle entry that points beyond the end of the file is not a
+        // torn write, since a valid entry follows it. It must not be truncated.
+        let mut data = std::fs::read(&path)?;
+        data[14 + 8..14 + 12].copy_from_slice(&1000_i32.to_be_bytes());
+        std::fs::write(&path, &data)?;
+        assert!(matches!(BitCask::verify(&path), Err(Error::Corruption(_))));
+        assert!(matches!(BitCask::new(path.clone(), false), Err(Error::Corruption(_))));
+        assert_eq!(42, std::fs::metadata(&path)?.len());
+
+        // Lengths beyond the maximum entry size are corrupt too.
+        data[14 + 8..14 + 12].copy_from_slice(&i32::MAX.to_be_bytes());
+        std::fs::write(&path, &data)?;
+        assert!(matches!(BitCask::new(path.clone(), false), Err(Error::Corruption(_))));
+        assert_eq!(42, std::fs::metadata(&path)?.len());
+        Ok(())
+    }
+}
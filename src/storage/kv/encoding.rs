@@ -7,6 +7,7 @@
 //! i64:     Big-endian binary representation, with sign bit flipped.
 //! f64:     Big-endian binary representation, with sign bit flipped if +, all flipped if -.
 //! Value:   Like above, with type prefix 0x00=Null 0x01=Boolean 0x02=Float 0x03=Integer 0x04=String
+//! [Value]: Concatenation of the encoded values, such that a tuple prefix is also a key prefix.
 
 use crate::error::{Error, Result};
 use crate::sql::types::Value;
@@ -196,6 +197,22 @@ pub fn take_value(bytes: &mut &[u8]) -> Result<Value> {
     }
 }
 
+/// Encodes a tuple of values by concatenating their encodings. Since value encodings are
+/// self-delimiting, this preserves the lexicographical order of the tuple, and the encoding of a
+/// tuple prefix is a prefix of the tuple's encoding. A single-value tuple encodes like the value.
+pub fn encode_values(values: &[Value]) -> Vec<u8> {
+    values.iter().flat_map(encode_value).collect()
+}
+
+/// Decodes a tuple of values from the remainder of a slice, emptying the slice.
+pub fn take_values(bytes: &mut &[u8]) -> Result<Vec<Value>> {
+    let mut values = Vec::new();
+    while !bytes.is_empty() {
+        values.push(take_value(bytes)?);
+    }
+    Ok(values)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -501,4 +518,47 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn encode_values() -> Result<()> {
+        use super::encode_values;
+
+        assert_eq!(encode_values(&[]), Vec::<u8>::new());
+        assert_eq!(
+            encode_values(&[Value::Integer(1024)]),
+            super::encode_value(&Value::Integer(1024))
+        );
+        assert_eq!(
+            encode_values(&[Value::Boolean(true), Value::String("a".into()), Value::Null]),
+            vec![0x01, 0x01, 0x04, 0x61, 0x00, 0x00, 0x00]
+        );
+
+        // Tuple order and prefixes are preserved.
+        let a = encode_values(&[Value::String("a".into()), Value::Integer(2)]);
+        let b = encode_values(&[Value::String("ab".into()), Value::Integer(1)]);
+        assert!(a < b);
+        assert!(a.starts_with(&encode_values(&[Value::String("a".into())])));
+        assert!(!b.starts_with(&encode_values(&[Value::String("a".into())])));
+        Ok(())
+    }
+
+    #[test]
+    fn take_values() -> Result<()> {
+        use super::take_values;
+
+        let mut bytes: &[u8] = &[];
+        assert_eq!(take_values(&mut bytes)?, vec![]);
+
+        let mut bytes: &[u8] = &[0x01, 0x01, 0x04, 0x61, 0x00, 0x00, 0x00];
+        assert_eq!(
+            take_values(&mut bytes)?,
+            vec![Value::Boolean(true), Value::String("a".into()), Value::Null]
+        );
+        assert!(bytes.is_empty());
+
+        let mut bytes: &[u8] = &[0x00, 0xaf];
+        assert!(take_values(&mut bytes).is_err());
+
+        Ok(())
+    }
 }
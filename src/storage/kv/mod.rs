@@ -1,3 +1,5 @@
+mod batch;
+mod bitcask;
 pub mod encoding;
 mod memory;
 pub mod mvcc;
@@ -5,6 +7,7 @@ mod std_memory;
 #[cfg(test)]
 mod test;
 
+pub use bitcask::BitCask;
 pub use memory::Memory;
 pub use mvcc::MVCC;
 pub use std_memory::StdMemory;
@@ -31,6 +34,18 @@ pub trait Store: Display + Send + Sync {
 
     /// Sets a value for a key, replacing the existing value if any.
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()>;
+
+    /// Writes a batch of keys in order, with None deleting the key. Persistent stores must write
+    /// the batch atomically, such that either all or none of it is visible after a crash.
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.set(&key, value)?,
+                None => self.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A scan range.
@@ -102,6 +117,7 @@ trait TestSuite<S: Store> {
         Self::test_get()?;
         Self::test_scan()?;
         Self::test_set()?;
+        Self::test_write_batch()?;
         Self::test_random()?;
         Ok(())
     }
@@ -230,4 +246,23 @@ trait TestSuite<S: Store> {
         assert_eq!(Some(vec![0x02]), s.get(b"a")?);
         Ok(())
     }
+
+    fn test_write_batch() -> Result<()> {
+        let mut s = Self::setup()?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.write_batch(vec![
+            (b"a".to_vec(), None),
+            (b"c".to_vec(), Some(vec![0x03])),
+            (b"b".to_vec(), Some(vec![0x04])),
+            (b"c".to_vec(), Some(vec![0x05])),
+            (b"d".to_vec(), None),
+        ])?;
+        s.write_batch(vec![])?;
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x04]), (b"c".to_vec(), vec![0x05])],
+            s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
 }
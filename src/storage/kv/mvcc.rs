@@ -1,3 +1,4 @@
+use super::batch::Batch;
 use super::{encoding, Range, Store};
 use crate::error::{Error, Result};
 
@@ -15,13 +16,14 @@ pub struct Status {
     pub txns: u64,
     pub txns_active: u64,
     pub storage: String,
+    pub reclaimed_bytes: u64,
 }
 
 /// An MVCC-based transactional key-value store.
 pub struct MVCC {
     /// The underlying KV store. It is protected by a mutex so it can be shared between txns.
     /// 存储
-    store: Arc<RwLock<Box<dyn Store>>>,
+    store: Arc<RwLock<Batch>>,
 }
 
 impl Clone for MVCC {
@@ -33,7 +35,26 @@ impl Clone for MVCC {
 impl MVCC {
     /// Creates a new MVCC key-value store with the given key-value store for storage.
     pub fn new(store: Box<dyn Store>) -> Self {
-        Self { store: Arc::new(RwLock::new(store)) }
+        Self { store: Arc::new(RwLock::new(Batch::new(store))) }
+    }
+
+    /// Runs the given closure with all writes to the underlying store buffered, then writes them
+    /// as a single atomic batch. If the closure fails, its writes are discarded.
+    pub fn batch<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        self.store.write()?.start()?;
+        match f() {
+            Ok(result) => {
+                self.store.write()?.commit()?;
+                Ok(result)
+            }
+            Err(err) => {
+                self.store.write()?.discard();
+                Err(err)
+            }
+        }
     }
 
     /// Begins a new transaction in read-write mode.
@@ -64,6 +85,118 @@ impl MVCC {
         session.set(&Key::Metadata(key.into()).encode(), value)
     }
 
+    /// Exports all key/value pairs from the underlying store, including MVCC metadata, e.g. for
+    /// state machine snapshots.
+    pub fn export(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.store.read()?.scan(Range::from(..)).collect()
+    }
+
+    /// Imports key/value pairs previously exported with export(), replacing all existing data.
+    pub fn import(&self, data: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let mut store = self.store.write()?;
+        let keys =
+            store.scan(Range::from(..)).map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            store.delete(&key)?;
+        }
+        for (key, value) in data {
+            store.set(&key, value)?;
+        }
+        store.flush()
+    }
+
+    /// Garbage collects obsolete data, returning the number of bytes reclaimed. All versions below
+    /// the oldest version visible to an active transaction are obsolete, except the latest live
+    /// version of each key, as are the snapshots and update markers of finished transactions below
+    /// it. Snapshot transactions can't be started for versions that have been garbage collected.
+    pub fn vacuum(&self) -> Result<u64> {
+        let mut session = self.store.write()?;
+
+        // Find the oldest version visible to any active transaction, defaulting to the next
+        // transaction ID. Versions below it are committed and visible to all active transactions
+        // (unless replaced by another version below it).
+        let mut horizon = match session.get(&Key::TxnNext.encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => 1,
+        };
+        let mut scan = session
+            .scan(Range::from(Key::TxnActive(0).encode()..Key::TxnActive(u64::MAX).encode()));
+        let mut versions = Vec::new();
+        while let Some((key, value)) = scan.next().transpose()? {
+            match (Key::decode(&key)?, deserialize(&value)?) {
+                (Key::TxnActive(_), Mode::Snapshot { version }) => versions.push(version),
+                (Key::TxnActive(id), _) => versions.push(id),
+                (k, _) => return Err(Error::Internal(format!("Expected TxnActive, got {:?}", k))),
+            };
+        }
+        std::mem::drop(scan);
+        for version in versions {
+            horizon = horizon.min(version);
+            if let Some(v) = session.get(&Key::TxnSnapshot(version).encode())? {
+                let invisible: HashSet<u64> = deserialize(&v)?;
+                horizon = invisible.into_iter().fold(horizon, u64::min);
+            }
+        }
+
+        // Collect obsolete snapshots and update markers of finished transactions, and record
+        // versions below the horizon that are either replaced or deleted.
+        let mut garbage = Vec::new();
+        let mut scan = session
+            .scan(Range::from(Key::TxnSnapshot(0).encode()..Key::TxnSnapshot(horizon).encode()));
+        while let Some((key, value)) = scan.next().transpose()? {
+            garbage.push((key, value.len()));
+        }
+        std::mem::drop(scan);
+        let mut scan = session.scan(Range::from(
+            Key::TxnUpdate(0, vec![].into()).encode()
+                ..Key::TxnUpdate(horizon, vec![].into()).encode(),
+        ));
+        while let Some((key, value)) = scan.next().transpose()? {
+            garbage.push((key, value.len()));
+        }
+        std::mem::drop(scan);
+        let mut scan = session.scan(Range::from(Key::Record(vec![].into(), 0).encode()..));
+        let mut latest: Option<(Vec<u8>, Vec<u8>, Vec<u8>)> = None; // (key, record, value)
+        while let Some((record, value)) = scan.next().transpose()? {
+            let (key, version) = match Key::decode(&record)? {
+                Key::Record(key, version) => (key.into_owned(), version),
+                k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+            };
+            if version >= horizon {
+                continue;
+            }
+            // Versions are ordered, so any previous version of the key is replaced by this one.
+            // Deleted keys are garbage once all previous versions are.
+            match latest.take() {
+                Some((k, r, v)) if k == key => garbage.push((r, v.len())),
+                Some((_, r, v)) if deserialize::<Option<Vec<u8>>>(&v)?.is_none() => {
+                    garbage.push((r, v.len()))
+                }
+                _ => {}
+            }
+            latest = Some((key, record, value));
+        }
+        std::mem::drop(scan);
+        if let Some((_, r, v)) = latest {
+            if deserialize::<Option<Vec<u8>>>(&v)?.is_none() {
+                garbage.push((r, v.len()))
+            }
+        }
+
+        let mut reclaimed = 0;
+        for (key, size) in garbage {
+            reclaimed += (key.len() + size) as u64;
+            session.delete(&key)?;
+        }
+        let total: u64 = match session.get(&Key::Reclaimed.encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => 0,
+        };
+        session.set(&Key::Reclaimed.encode(), serialize(&(total + reclaimed))?)?;
+        session.flush()?;
+        Ok(reclaimed)
+    }
+
     /// Returns engine status
     //
     // Bizarrely, the return statement is in fact necessary - see:
@@ -82,6 +215,10 @@ impl MVCC {
                 ))
                 .try_fold(0, |count, r| r.map(|_| count + 1))?,
             storage: store.to_string(),
+            reclaimed_bytes: match store.get(&Key::Reclaimed.encode())? {
+                Some(ref v) => deserialize(v)?,
+                None => 0,
+            },
         });
     }
 }
@@ -99,18 +236,24 @@ fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
 /// An MVCC transaction.
 pub struct Transaction {
     /// The underlying store for the transaction. Shared between transactions using a mutex.
-    store: Arc<RwLock<Box<dyn Store>>>,
+    store: Arc<RwLock<Batch>>,
     /// The unique transaction ID.
     id: u64,
     /// The transaction mode.
     mode: Mode,
     /// The snapshot that the transaction is running in.
     snapshot: Snapshot,
+    /// Undo records for writes made since the current savepoint, if any.
+    savepoint: Option<Vec<Undo>>,
 }
 
+/// An undo record for a savepoint: the written key and its previous raw value, or None if the key
+/// had not been written by the transaction before.
+type Undo = (Vec<u8>, Option<Vec<u8>>);
+
 impl Transaction {
     /// Begins a new transaction in the given mode.
-    fn begin(store: Arc<RwLock<Box<dyn Store>>>, mode: Mode) -> Result<Self> {
+    fn begin(store: Arc<RwLock<Batch>>, mode: Mode) -> Result<Self> {
         let mut session = store.write()?;
 
         let id = match session.get(&Key::TxnNext.encode())? {
@@ -126,14 +269,23 @@ impl Transaction {
         let mut snapshot = Snapshot::take(&mut session, id)?;
         std::mem::drop(session);
         if let Mode::Snapshot { version } = &mode {
-            snapshot = Snapshot::restore(&store.read()?, *version)?
+            // Don't leave the transaction active if the snapshot is missing, since that would
+            // hold back garbage collection.
+            let restored = Snapshot::restore(&store.read()?, *version);
+            snapshot = match restored {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    store.write()?.delete(&Key::TxnActive(id).encode())?;
+                    return Err(err);
+                }
+            }
         }
 
-        Ok(Self { store, id, mode, snapshot })
+        Ok(Self { store, id, mode, snapshot, savepoint: None })
     }
 
     /// Resumes an active transaction with the given ID. Errors if the transaction is not active.
-    fn resume(store: Arc<RwLock<Box<dyn Store>>>, id: u64) -> Result<Self> {
+    fn resume(store: Arc<RwLock<Batch>>, id: u64) -> Result<Self> {
         let session = store.read()?;
         let mode = match session.get(&Key::TxnActive(id).encode())? {
             Some(v) => deserialize(&v)?,
@@ -144,7 +296,7 @@ impl Transaction {
             _ => Snapshot::restore(&session, id)?,
         };
         std::mem::drop(session);
-        Ok(Self { store, id, mode, snapshot })
+        Ok(Self { store, id, mode, snapshot, savepoint: None })
     }
 
     /// Returns the transaction ID.
@@ -188,6 +340,36 @@ impl Transaction {
         session.delete(&Key::TxnActive(self.id).encode())
     }
 
+    /// Sets a savepoint, replacing any existing one. Writes made after it can be undone with
+    /// rollback_to_savepoint(), without rolling back the rest of the transaction.
+    pub fn savepoint(&mut self) {
+        self.savepoint = Some(Vec::new());
+    }
+
+    /// Releases the current savepoint, keeping any writes made since it was set.
+    pub fn release_savepoint(&mut self) {
+        self.savepoint = None;
+    }
+
+    /// Undoes all writes made since the current savepoint, and releases it.
+    pub fn rollback_to_savepoint(&mut self) -> Result<()> {
+        let undo = match self.savepoint.take() {
+            Some(undo) => undo,
+            None => return Err(Error::Internal("No active savepoint".into())),
+        };
+        let mut session = self.store.write()?;
+        for (key, value) in undo.into_iter().rev() {
+            match value {
+                Some(value) => session.set(&key, value)?,
+                None => {
+                    session.delete(&Key::TxnUpdate(self.id, (&key).into()).encode())?;
+                    session.delete(&key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Deletes a key.
     pub fn delete(&mut self, key: &[u8]) -> Result<()> {
         self.write(key, None)
@@ -232,6 +414,16 @@ impl Transaction {
 
     /// Scans keys under a given prefix.
     pub fn scan_prefix(&self, prefix: &[u8]) -> Result<super::Scan> {
+        self.scan_prefix_range(prefix, (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Scans keys under a given prefix, within the given key range. Unbounded range ends are
+    /// limited to the prefix, while bounded ends must be keys under the prefix.
+    pub fn scan_prefix_range(
+        &self,
+        prefix: &[u8],
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Result<super::Scan> {
         if prefix.is_empty() {
             return Err(Error::Internal("Scan prefix cannot be empty".into()));
         }
@@ -251,7 +443,15 @@ impl Transaction {
                 }
             }
         }
-        self.scan(start..end)
+        let start = match range.0 {
+            Bound::Unbounded => Bound::Included(start),
+            bound => bound,
+        };
+        let end = match range.1 {
+            Bound::Unbounded => Bound::Excluded(end),
+            bound => bound,
+        };
+        self.scan((start, end))
     }
 
     /// Sets a key.
@@ -260,7 +460,7 @@ impl Transaction {
     }
 
     /// Writes a value for a key. None is used for deletion.
-    fn write(&self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
         if !self.mode.mutable() {
             return Err(Error::ReadOnly);
         }
@@ -287,8 +487,11 @@ impl Transaction {
         }
         std::mem::drop(scan);
 
-        // Write the key and its update record.
+        // Write the key and its update record, remembering any previous value for the savepoint.
         let key = Key::Record(key.into(), self.id).encode();
+        if let Some(undo) = self.savepoint.as_mut() {
+            undo.push((key.clone(), session.get(&key)?));
+        }
         let update = Key::TxnUpdate(self.id, (&key).into()).encode();
         session.set(&update, vec![])?;
         session.set(&key, serialize(&value)?)
@@ -343,7 +546,7 @@ struct Snapshot {
 
 impl Snapshot {
     /// Takes a new snapshot, persisting it as `Key::TxnSnapshot(version)`.
-    fn take(session: &mut RwLockWriteGuard<Box<dyn Store>>, version: u64) -> Result<Self> {
+    fn take(session: &mut RwLockWriteGuard<Batch>, version: u64) -> Result<Self> {
         let mut snapshot = Self { version, invisible: HashSet::new() };
         let mut scan =
             session.scan(Range::from(Key::TxnActive(0).encode()..Key::TxnActive(version).encode()));
@@ -359,7 +562,7 @@ impl Snapshot {
     }
 
     /// Restores an existing snapshot from `Key::TxnSnapshot(version)`, or errors if not found.
-    fn restore(session: &RwLockReadGuard<Box<dyn Store>>, version: u64) -> Result<Self> {
+    fn restore(session: &RwLockReadGuard<Batch>, version: u64) -> Result<Self> {
         match session.get(&Key::TxnSnapshot(version).encode())? {
             Some(ref v) => Ok(Self { version, invisible: deserialize(v)? }),
             None => Err(Error::Value(format!("Snapshot not found for version {}", version))),
@@ -388,6 +591,8 @@ enum Key<'a> {
     Record(Cow<'a, [u8]>, u64),
     /// Arbitrary unversioned metadata.
     Metadata(Cow<'a, [u8]>),
+    /// The total number of bytes reclaimed by vacuuming.
+    Reclaimed,
 }
 
 impl<'a> Key<'a> {
@@ -402,6 +607,7 @@ impl<'a> Key<'a> {
                 [&[0x04][..], &encode_u64(id), &encode_bytes(&key)].concat()
             }
             Self::Metadata(key) => [&[0x05][..], &encode_bytes(&key)].concat(),
+            Self::Reclaimed => vec![0x06],
             Self::Record(key, version) => {
                 [&[0xff][..], &encode_bytes(&key), &encode_u64(version)].concat()
             }
@@ -418,6 +624,7 @@ impl<'a> Key<'a> {
             0x03 => Self::TxnSnapshot(take_u64(bytes)?),
             0x04 => Self::TxnUpdate(take_u64(bytes)?, take_bytes(bytes)?.into()),
             0x05 => Self::Metadata(take_bytes(bytes)?.into()),
+            0x06 => Self::Reclaimed,
             0xff => Self::Record(take_bytes(bytes)?.into(), take_u64(bytes)?),
             b => return Err(Error::Internal(format!("Unknown MVCC key prefix {:x?}", b))),
         };
@@ -958,6 +1165,27 @@ pub mod tests {
         assert_eq!(None, scan.next_back().transpose()?);
         std::mem::drop(scan);
 
+        // Range scans within the prefix
+        assert_eq!(
+            vec![(b"bb".to_vec(), vec![0x02, 0x02]), (b"bc".to_vec(), vec![0x02, 0x03])],
+            txn.scan_prefix_range(b"b", (Bound::Excluded(b"ba".to_vec()), Bound::Unbounded))?
+                .collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x02]), (b"ba".to_vec(), vec![0x02, 0x01])],
+            txn.scan_prefix_range(b"b", (Bound::Unbounded, Bound::Excluded(b"bb".to_vec())))?
+                .collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            vec![(b"bb".to_vec(), vec![0x02, 0x02]), (b"ba".to_vec(), vec![0x02, 0x01])],
+            txn.scan_prefix_range(
+                b"b",
+                (Bound::Included(b"ba".to_vec()), Bound::Included(b"bb".to_vec()))
+            )?
+            .rev()
+            .collect::<Result<Vec<_>>>()?
+        );
+
         txn.commit()?;
         Ok(())
     }
@@ -1016,6 +1244,49 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_txn_savepoint() -> Result<()> {
+        let mvcc = setup();
+
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x00])?;
+        txn.commit()?;
+
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        txn.set(b"b", vec![0x01])?;
+        txn.savepoint();
+        txn.set(b"a", vec![0x02])?;
+        txn.set(b"a", vec![0x03])?;
+        txn.delete(b"b")?;
+        txn.set(b"c", vec![0x02])?;
+        txn.rollback_to_savepoint()?;
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        assert_eq!(Some(vec![0x01]), txn.get(b"b")?);
+        assert_eq!(None, txn.get(b"c")?);
+        assert_eq!(Err(Error::Internal("No active savepoint".into())), txn.rollback_to_savepoint());
+
+        // Writes after a released savepoint are kept.
+        txn.savepoint();
+        txn.set(b"c", vec![0x03])?;
+        txn.release_savepoint();
+        txn.savepoint();
+        txn.set(b"d", vec![0x03])?;
+        txn.rollback_to_savepoint()?;
+        assert_eq!(Some(vec![0x03]), txn.get(b"c")?);
+        assert_eq!(None, txn.get(b"d")?);
+
+        // A full rollback must still undo everything, including keys undone by the savepoint.
+        txn.rollback()?;
+        let txn = mvcc.begin()?;
+        assert_eq!(Some(vec![0x00]), txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        assert_eq!(None, txn.get(b"c")?);
+        assert_eq!(None, txn.get(b"d")?);
+
+        Ok(())
+    }
+
     #[test]
     // A dirty write is when t2 overwrites an uncommitted value written by t1.
     fn test_txn_anomaly_dirty_write() -> Result<()> {
@@ -1160,6 +1431,45 @@ pub mod tests {
         Ok(())
     }*/
 
+    #[test]
+    fn test_export_import() -> Result<()> {
+        let mvcc = setup();
+        mvcc.set_metadata(b"foo", b"bar".to_vec())?;
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x01])?;
+        txn.commit()?;
+        let export = mvcc.export()?;
+
+        let mut txn = mvcc.begin()?;
+        txn.set(b"a", vec![0x02])?;
+        txn.set(b"b", vec![0x02])?;
+        txn.commit()?;
+        mvcc.set_metadata(b"foo", b"baz".to_vec())?;
+
+        // Importing into a different store should replace its data with the exported data.
+        let other = setup();
+        let mut txn = other.begin()?;
+        txn.set(b"c", vec![0x03])?;
+        txn.commit()?;
+        other.import(export.clone())?;
+        assert_eq!(export, other.export()?);
+        assert_eq!(Some(b"bar".to_vec()), other.get_metadata(b"foo")?);
+
+        let txn = other.begin()?;
+        assert_eq!(2, txn.id());
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        assert_eq!(None, txn.get(b"c")?);
+        txn.commit()?;
+
+        // Importing into the original store should roll it back to the exported data.
+        mvcc.import(export)?;
+        let txn = mvcc.begin()?;
+        assert_eq!(Some(vec![0x01]), txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        Ok(())
+    }
+
     #[test]
     fn test_metadata() -> Result<()> {
         let mvcc = setup();
@@ -1173,4 +1483,67 @@ pub mod tests {
         assert_eq!(Some(b"baz".to_vec()), mvcc.get_metadata(b"foo")?);
         Ok(())
     }
+
+    #[test]
+    fn test_vacuum() -> Result<()> {
+        let mvcc = setup();
+        // Counts the number of raw records of a key in the underlying store.
+        let records = |key: &[u8]| -> Result<usize> {
+            Ok(mvcc
+                .export()?
+                .into_iter()
+                .filter(|(k, _)| {
+                    matches!(Key::decode(k), Ok(Key::Record(ref rk, _)) if rk.as_ref() == key)
+                })
+                .count())
+        };
+
+        let mut t1 = mvcc.begin()?;
+        t1.set(b"a", vec![0x01])?;
+        t1.set(b"b", vec![0x01])?;
+        t1.commit()?;
+        let mut t2 = mvcc.begin()?;
+        t2.set(b"a", vec![0x02])?;
+        t2.delete(b"b")?;
+        t2.commit()?;
+
+        // An active transaction retains the versions visible to it, and its own writes.
+        let mut t3 = mvcc.begin()?;
+        t3.set(b"c", vec![0x03])?;
+        let mut t4 = mvcc.begin()?;
+        t4.set(b"a", vec![0x04])?;
+        t4.commit()?;
+
+        let reclaimed = mvcc.vacuum()?;
+        assert!(reclaimed > 0);
+        assert_eq!(reclaimed, mvcc.status()?.reclaimed_bytes);
+        assert_eq!(2, records(b"a")?);
+        assert_eq!(0, records(b"b")?);
+        assert_eq!(1, records(b"c")?);
+        assert_eq!(Some(vec![0x02]), t3.get(b"a")?);
+        assert_eq!(None, t3.get(b"b")?);
+
+        // Snapshots below the oldest visible version are no longer available.
+        assert_eq!(
+            mvcc.begin_with_mode(Mode::Snapshot { version: 1 }).err(),
+            Some(Error::Value("Snapshot not found for version 1".into()))
+        );
+
+        // Rolling back the active transaction still works, and once finished its versions are
+        // collected too.
+        t3.rollback()?;
+        let more = mvcc.vacuum()?;
+        assert!(more > 0);
+        assert_eq!(reclaimed + more, mvcc.status()?.reclaimed_bytes);
+        assert_eq!(1, records(b"a")?);
+        assert_eq!(0, records(b"c")?);
+
+        // Vacuuming again without any new transactions is a noop.
+        assert_eq!(0, mvcc.vacuum()?);
+        let txn = mvcc.begin()?;
+        assert_eq!(Some(vec![0x04]), txn.get(b"a")?);
+        assert_eq!(None, txn.get(b"b")?);
+        assert_eq!(None, txn.get(b"c")?);
+        Ok(())
+    }
 }
@@ -1,40 +1,135 @@
-use super::{Range, Scan, Store};
+use super::{Range, Scan, Store, SyncStats};
 use crate::error::{Error, Result};
+use crate::storage::{check_torn, FileCheck};
 
+use log::{info, warn};
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt::Display;
-use std::fs::{create_dir_all, File, OpenOptions};
+use std::fs::{create_dir_all, rename, File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek as _, SeekFrom, Write};
 use std::ops::Bound;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The size of a log entry header: a big-endian u32 entry length followed by a big-endian u32
+/// CRC32 checksum of the length and entry data.
+const HEADER_SIZE: u64 = 8;
+
+/// A length marking a compaction header at the start of the log file, whose data is the
+/// big-endian u64 index of the last compacted entry. It can't be confused with an entry length,
+/// since entries are never this large.
+const COMPACTED_HEADER: u32 = u32::MAX;
+
+/// A length marking the version header at the start of the log file, whose data is the big-endian
+/// u32 file format version. Log files without it use the legacy format of the original hybrid log,
+/// where entries are only prefixed by a big-endian u32 length, and are migrated on startup.
+const VERSION_HEADER: u32 = u32::MAX - 1;
+
+/// The size of the version header, including its data.
+const VERSION_HEADER_SIZE: u64 = HEADER_SIZE + 4;
+
+/// The current log file format version.
+const VERSION: u32 = 1;
+
+/// The maximum size of a log entry. Larger lengths in the log files are considered corrupt.
+const MAX_ENTRY_SIZE: u64 = 1 << 30;
+
+/// Commits that have been written to the log file but not yet fsynced, for group commit.
+#[derive(Clone, Copy, Debug)]
+struct Unsynced {
+    /// The time of the first unsynced commit.
+    since: Instant,
+    /// The number of unsynced commits.
+    commits: u64,
+    /// The number of unsynced entries.
+    entries: u64,
+}
+
+/// An index of committed entry positions and sizes in the log file, keyed by entry index.
+type Index = BTreeMap<u64, (u64, u32)>;
+
+/// Metadata key/value pairs.
+type Metadata = HashMap<Vec<u8>, Vec<u8>>;
+
+/// The size of a write-ahead log record header: a big-endian u64 entry index, a big-endian u32
+/// entry length, and a big-endian u32 CRC32 checksum of the index, length and entry data.
+const WAL_HEADER_SIZE: u64 = 16;
 
 /// A hybrid log store, storing committed entries in an append-only file, uncommitted entries
-/// in memory, and metadata in a separate file (should be an on-disk key-value store).
+/// in memory backed by a write-ahead log, and metadata in a separate file.
+///
+/// The log file starts with a version header, followed by sequential binary log entries, each
+/// prefixed with a header containing its length and a CRC32 checksum. Entries are only written to
+/// it when they are committed and permanent, thus the file is written append-only. A log file in
+/// the legacy format, without a version header or checksums, is rewritten in the current format
+/// on startup.
 ///
-/// The log file contains sequential binary log entries, length-prefixed with a big-endian u32.
-/// Entries are only flushed to disk when they are committed and permanent, thus the file is
-/// written append-only.
+/// Checksums are verified when reading entries, and when scanning the file on startup. A torn
+/// write at the end of the file, i.e. an incomplete entry or a checksum mismatch in the last
+/// entry, is truncated on startup, since it can only be caused by a crash during an unsynced
+/// write whose entries are also in the write-ahead log. A checksum mismatch anywhere else is
+/// returned as Error::Corruption, as is a length above MAX_ENTRY_SIZE. A length that points beyond
+/// the end of the file is only considered a torn write if no valid entry follows it, otherwise
+/// it's a corrupt length field and the valid entries after it must not be truncated.
+///
+/// Uncommitted entries must also be durable, since Raft counts a node's acknowledged entries
+/// towards a quorum (and its votes depend on them). They're appended to a separate write-ahead
+/// log file (and fsynced with sync enabled) before append() returns, as records containing the
+/// entry index, length, checksum and data. Truncation truncates the file at the first removed record, and
+/// once all entries have been committed and fsynced to the log file, the write-ahead log is
+/// emptied. On startup, records for entries already in the log file are skipped and the rest
+/// are restored as uncommitted entries, discarding any partially written record at the end (with
+/// the same checks as the log file).
+///
+/// Metadata is cached in memory, and the entire metadata file is replaced on every write: the
+/// metadata is written to a temporary file after a version header and a CRC32 checksum, which is
+/// fsynced and then atomically renamed over the old file, such that a crash leaves either the old
+/// or the new metadata. The checksum is verified on startup. A metadata file in the legacy format,
+/// i.e. plain Bincode without a version header, is rewritten in the current format on startup.
+/// This is fine since there are very few metadata items (i.e. the current term and vote), but a
+/// larger set would need an on-disk B-tree.
+///
+/// When the log is compacted, the remaining committed entries are written to a new file which
+/// atomically replaces the old one. The new file has a compaction header after the version header,
+/// containing the index of the last compacted entry, so that entry indexes are retained across
+/// restarts.
 ///
 /// An index of entry positions and sizes is maintained in memory. This is rebuilt on startup by
 /// scanning the file, since maintaining the index in a separate file requires additional fsyncing
 /// which is expensive. Since datasets are expected to be small, scanning the file on startup is
 /// reasonably cheap.
+///
+/// With sync enabled, commits can be grouped to amortize the fsync cost: given a sync delay,
+/// commits are written to the file right away, but only fsynced once the delay since the first
+/// unsynced commit has passed, either by a later commit or by flush(). Until then, flushed()
+/// excludes them, such that they aren't applied to the state machine.
 pub struct Hybrid {
+    /// The directory containing the log files.
+    dir: PathBuf,
     /// The append-only log file. Protected by a mutex for interior mutability (i.e. read seeks).
     file: Mutex<File>,
     /// Index of entry locations and sizes in the log file.
-    index: BTreeMap<u64, (u64, u32)>,
+    index: Index,
+    /// The index of the last compacted entry.
+    compacted: u64,
     /// Uncommitted log entries.
     uncommitted: VecDeque<Vec<u8>>,
-    /// Metadata cache. Flushed to disk on changes.
-    metadata: HashMap<Vec<u8>, Vec<u8>>,
-    /// The file used to store metadata.
-    /// FIXME Should be an on-disk B-tree key-value store.
-    metadata_file: File,
+    /// The write-ahead log file, persisting uncommitted entries.
+    wal: File,
+    /// The position of each uncommitted entry's record in the write-ahead log.
+    wal_positions: VecDeque<u64>,
+    /// Metadata cache. Written to the metadata file on changes.
+    metadata: Metadata,
     /// If true, fsync writes.
     sync: bool,
+    /// The maximum time to delay fsyncs of commits by, for group commit.
+    sync_delay: Duration,
+    /// Commits awaiting an fsync, if any.
+    unsynced: Option<Unsynced>,
+    /// Fsync statistics.
+    sync_stats: SyncStats,
 }
 
 impl Display for Hybrid {
@@ -48,135 +143,678 @@ impl Hybrid {
     pub fn new(dir: &Path, sync: bool) -> Result<Self> {
         create_dir_all(dir)?;
 
-        let file =
-            OpenOptions::new().read(true).write(true).create(true).open(dir.join("raft-log"))?;
+        let path = dir.join("raft-log");
+        let mut file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        if Self::is_legacy(&file)? {
+            file = Self::migrate_legacy(dir, &file, &path)?;
+        }
+        let (compacted, index, size) = Self::scan_log(&file, &path)?;
+        Self::truncate_torn(&file, &path, size)?;
+        if size == 0 {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&Self::encode_entry(VERSION_HEADER, &VERSION.to_be_bytes()))?;
+            if sync {
+                file.sync_data()?;
+            }
+        }
 
-        let metadata_file = OpenOptions::new()
+        let wal_path = dir.join("raft-wal");
+        let wal = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(dir.join("raft-metadata"))?;
+            .truncate(false)
+            .open(&wal_path)?;
+        let (uncommitted, wal_positions, wal_size) =
+            Self::scan_wal(&wal, &wal_path, compacted + index.len() as u64)?;
+        Self::truncate_torn(&wal, &wal_path, wal_size)?;
 
-        Ok(Self {
-            index: Self::build_index(&file)?,
+        let (metadata, legacy_metadata) = Self::load_metadata(&dir.join("raft-metadata"))?;
+        let log = Self {
+            dir: dir.to_path_buf(),
+            index,
+            compacted,
             file: Mutex::new(file),
-            uncommitted: VecDeque::new(),
-            metadata: Self::load_metadata(&metadata_file)?,
-            metadata_file,
+            uncommitted,
+            wal,
+            wal_positions,
+            metadata,
             sync,
-        })
+            sync_delay: Duration::from_secs(0),
+            unsynced: None,
+            sync_stats: SyncStats::default(),
+        };
+        if legacy_metadata {
+            info!("Migrating metadata to log format version {}", VERSION);
+            log.save_metadata()?;
+        }
+        Ok(log)
+    }
+
+    /// Enables group commit, delaying fsyncs of commits by up to the given duration such that
+    /// concurrent commits can share an fsync. Has no effect without sync.
+    pub fn with_sync_delay(mut self, delay: Duration) -> Self {
+        self.sync_delay = delay;
+        self
     }
 
-    /// Builds the index by scanning the log file.
-    fn build_index(file: &File) -> Result<BTreeMap<u64, (u64, u32)>> {
+    /// Fsyncs the log file, recording statistics for the given unsynced commits.
+    fn sync_file(file: &File, stats: &mut SyncStats, unsynced: Unsynced) -> Result<()> {
+        let start = Instant::now();
+        file.sync_data()?;
+        let micros = start.elapsed().as_micros() as u64;
+        stats.syncs += 1;
+        stats.commits += unsynced.commits;
+        stats.entries += unsynced.entries;
+        stats.max_commits = max(stats.max_commits, unsynced.commits);
+        stats.sync_micros += micros;
+        stats.max_sync_micros = max(stats.max_sync_micros, micros);
+        Ok(())
+    }
+
+    /// Verifies the files of a hybrid log in the given directory without modifying them,
+    /// returning the status of each file that exists. Errors with Error::Corruption if a file is
+    /// corrupt.
+    pub fn verify(dir: &Path) -> Result<Vec<FileCheck>> {
+        let mut checks = Vec::new();
+        let mut committed = 0;
+
+        let path = dir.join("raft-log");
+        if path.exists() {
+            let file = File::open(&path)?;
+            let (entries, size) = if Self::is_legacy(&file)? {
+                let (entries, size) = Self::scan_legacy_log(&file, &path)?;
+                (entries.len() as u64, size)
+            } else {
+                let (compacted, index, size) = Self::scan_log(&file, &path)?;
+                committed = compacted;
+                (index.len() as u64, size)
+            };
+            committed += entries;
+            let torn = file.metadata()?.len() - size;
+            checks.push(FileCheck { path, entries, size, torn });
+        }
+
+        let path = dir.join("raft-wal");
+        if path.exists() {
+            let file = File::open(&path)?;
+            let (entries, _, size) = Self::scan_wal(&file, &path, committed)?;
+            let torn = file.metadata()?.len() - size;
+            checks.push(FileCheck { path, entries: entries.len() as u64, size, torn });
+        }
+
+        let path = dir.join("raft-metadata");
+        if path.exists() {
+            let entries = Self::load_metadata(&path)?.0.len() as u64;
+            let size = std::fs::metadata(&path)?.len();
+            checks.push(FileCheck { path, entries, size, torn: 0 });
+        }
+        Ok(checks)
+    }
+
+    /// Returns true if the log file is in the legacy format, i.e. it doesn't start with a version
+    /// header. An empty or partially written version header is not considered legacy, since it
+    /// contains no entries either way.
+    fn is_legacy(mut file: &File) -> Result<bool> {
+        let mut lenbuf = [0; 4];
+        file.seek(SeekFrom::Start(0))?;
+        let legacy = match file.read_exact(&mut lenbuf) {
+            Ok(()) => u32::from_be_bytes(lenbuf) != VERSION_HEADER,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err.into()),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        Ok(legacy)
+    }
+
+    /// Scans a log file in the legacy format, where entries are only prefixed by a big-endian u32
+    /// length, and returns the entries along with the size of the complete entries. An incomplete
+    /// entry at the end is considered a torn write, and is discarded.
+    fn scan_legacy_log(file: &File, path: &Path) -> Result<(Vec<Vec<u8>>, u64)> {
+        let filesize = file.metadata()?.len();
+        let mut bufreader = BufReader::new(file);
+        let mut entries = Vec::new();
+        let mut lenbuf = [0; 4];
+        let mut pos = 0;
+        while pos + 4 <= filesize {
+            bufreader.read_exact(&mut lenbuf)?;
+            let size = u32::from_be_bytes(lenbuf) as u64;
+            if size > MAX_ENTRY_SIZE {
+                return Err(Error::Corruption(format!(
+                    "Invalid length for entry at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            if pos + 4 + size > filesize {
+                break;
+            }
+            let mut entry = vec![0; size as usize];
+            bufreader.read_exact(&mut entry)?;
+            entries.push(entry);
+            pos += 4 + size;
+        }
+        Ok((entries, pos))
+    }
+
+    /// Migrates a log file in the legacy format to the current format. The entries are written
+    /// to a new file which atomically replaces the old one, and which is returned. The new file
+    /// is always fsynced, since the old one is gone once replaced.
+    fn migrate_legacy(dir: &Path, file: &File, path: &Path) -> Result<File> {
+        let (entries, size) = Self::scan_legacy_log(file, path)?;
+        if file.metadata()?.len() > size {
+            warn!("Discarding torn write at offset {} in {}", size, path.display());
+        }
+        info!(
+            "Migrating {} entries in {} to log format version {}",
+            entries.len(),
+            path.display(),
+            VERSION
+        );
+        let migrate_path = dir.join("raft-log.migrate");
+        let mut migrate_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&migrate_path)?;
+        let mut bufwriter = BufWriter::new(&mut migrate_file);
+        bufwriter.write_all(&Self::encode_entry(VERSION_HEADER, &VERSION.to_be_bytes()))?;
+        for entry in entries {
+            bufwriter.write_all(&Self::encode_entry(entry.len() as u32, &entry))?;
+        }
+        bufwriter.flush()?;
+        drop(bufwriter);
+        migrate_file.sync_data()?;
+        rename(&migrate_path, path)?;
+        File::open(dir)?.sync_all()?;
+        migrate_file.seek(SeekFrom::Start(0))?;
+        Ok(migrate_file)
+    }
+
+    /// Scans the log file, verifying checksums, and returns the compacted index and an index of
+    /// entry positions along with the size of the valid part of the file, excluding any torn
+    /// write at the end. The file must start with a version header, unless it's empty.
+    fn scan_log(file: &File, path: &Path) -> Result<(u64, Index, u64)> {
         let filesize = file.metadata()?.len();
         let mut bufreader = BufReader::new(file);
         let mut index = BTreeMap::new();
-        let mut sizebuf = [0; 4];
+        let mut header = [0; HEADER_SIZE as usize];
+        let mut compacted = 0;
         let mut pos = 0;
         let mut i = 1;
-        while pos < filesize {
-            bufreader.read_exact(&mut sizebuf)?;
-            pos += 4;
-            let size = u32::from_be_bytes(sizebuf);
-            index.insert(i, (pos, size));
-            let mut buf = vec![0; size as usize];
-            bufreader.read_exact(&mut buf)?;
-            pos += size as u64;
-            i += 1;
+        while pos + HEADER_SIZE <= filesize {
+            bufreader.read_exact(&mut header)?;
+            let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let is_version = pos == 0;
+            if is_version && len != VERSION_HEADER {
+                return Err(Error::Corruption(format!(
+                    "Missing version header in {}",
+                    path.display()
+                )));
+            }
+            let is_compacted = pos == VERSION_HEADER_SIZE && len == COMPACTED_HEADER;
+            let size = match (is_version, is_compacted) {
+                (true, _) => 4,
+                (_, true) => 8,
+                _ => len as u64,
+            };
+            if size > MAX_ENTRY_SIZE {
+                return Err(Error::Corruption(format!(
+                    "Invalid length for entry at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            let end = pos + HEADER_SIZE + size;
+            if end > filesize {
+                let mut tail = header.to_vec();
+                bufreader.read_to_end(&mut tail)?;
+                check_torn(path, pos, &tail, Self::valid_entry)?;
+                break;
+            }
+            let mut data = vec![0; size as usize];
+            bufreader.read_exact(&mut data)?;
+            if checksum(&[&header[..4], &data])
+                != u32::from_be_bytes([header[4], header[5], header[6], header[7]])
+            {
+                if end == filesize {
+                    break;
+                }
+                return Err(Error::Corruption(format!(
+                    "Checksum mismatch for entry at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            if is_version {
+                let version = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                if version != VERSION {
+                    return Err(Error::Corruption(format!(
+                        "Unsupported log format version {} in {}",
+                        version,
+                        path.display()
+                    )));
+                }
+            } else if is_compacted {
+                compacted = u64::from_be_bytes([
+                    data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+                ]);
+                i = compacted + 1;
+            } else {
+                index.insert(i, (pos + HEADER_SIZE, len));
+                i += 1;
+            }
+            pos = end;
         }
-        Ok(index)
+        Ok((compacted, index, pos))
     }
 
-    /// Loads metadata from a file.
-    fn load_metadata(file: &File) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
-        match bincode::deserialize_from(file) {
-            Ok(metadata) => Ok(metadata),
-            Err(err) => {
-                if let bincode::ErrorKind::Io(err) = &*err {
-                    if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                        return Ok(HashMap::new());
-                    }
+    /// Returns true if the given data starts with a complete, valid log entry.
+    fn valid_entry(data: &[u8]) -> bool {
+        if data.len() < HEADER_SIZE as usize {
+            return false;
+        }
+        let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64;
+        let end = HEADER_SIZE + len;
+        len <= MAX_ENTRY_SIZE
+            && end <= data.len() as u64
+            && checksum(&[&data[..4], &data[HEADER_SIZE as usize..end as usize]])
+                == u32::from_be_bytes([data[4], data[5], data[6], data[7]])
+    }
+
+    /// Scans the write-ahead log, verifying checksums, and returns the uncommitted entries after
+    /// the given committed index along with their record positions and the size of the valid part
+    /// of the file, excluding any torn write at the end. Records for entries at or below the
+    /// committed index are skipped.
+    fn scan_wal(
+        wal: &File,
+        path: &Path,
+        committed: u64,
+    ) -> Result<(VecDeque<Vec<u8>>, VecDeque<u64>, u64)> {
+        let filesize = wal.metadata()?.len();
+        let mut bufreader = BufReader::new(wal);
+        let mut entries = VecDeque::new();
+        let mut positions = VecDeque::new();
+        let mut header = [0; WAL_HEADER_SIZE as usize];
+        let mut pos = 0;
+        while pos + WAL_HEADER_SIZE <= filesize {
+            bufreader.read_exact(&mut header)?;
+            let size = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as u64;
+            if size > MAX_ENTRY_SIZE {
+                return Err(Error::Corruption(format!(
+                    "Invalid length for record at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            let end = pos + WAL_HEADER_SIZE + size;
+            if end > filesize {
+                let mut tail = header.to_vec();
+                bufreader.read_to_end(&mut tail)?;
+                check_torn(path, pos, &tail, Self::valid_wal_record)?;
+                break;
+            }
+            let mut entry = vec![0; size as usize];
+            bufreader.read_exact(&mut entry)?;
+            if checksum(&[&header[..12], &entry])
+                != u32::from_be_bytes([header[12], header[13], header[14], header[15]])
+            {
+                if end == filesize {
+                    break;
                 }
-                Err(err.into())
+                return Err(Error::Corruption(format!(
+                    "Checksum mismatch for record at offset {} in {}",
+                    pos,
+                    path.display()
+                )));
+            }
+            let index = u64::from_be_bytes([
+                header[0], header[1], header[2], header[3], header[4], header[5], header[6],
+                header[7],
+            ]);
+            if index > committed {
+                let expect = committed + entries.len() as u64 + 1;
+                if index != expect {
+                    return Err(Error::Corruption(format!(
+                        "Expected entry {} at offset {} in {}, found {}",
+                        expect,
+                        pos,
+                        path.display(),
+                        index
+                    )));
+                }
+                entries.push_back(entry);
+                positions.push_back(pos);
+            }
+            pos = end;
+        }
+        Ok((entries, positions, pos))
+    }
+
+    /// Returns true if the given data starts with a complete, valid write-ahead log record.
+    fn valid_wal_record(data: &[u8]) -> bool {
+        if data.len() < WAL_HEADER_SIZE as usize {
+            return false;
+        }
+        let size = u32::from_be_bytes([data[8], data[9], data[10], data[11]]) as u64;
+        let end = WAL_HEADER_SIZE + size;
+        size <= MAX_ENTRY_SIZE
+            && end <= data.len() as u64
+            && checksum(&[&data[..12], &data[WAL_HEADER_SIZE as usize..end as usize]])
+                == u32::from_be_bytes([data[12], data[13], data[14], data[15]])
+    }
+
+    /// Truncates a torn write at the end of a file, given the size of its valid part.
+    fn truncate_torn(file: &File, path: &Path, size: u64) -> Result<()> {
+        if file.metadata()?.len() > size {
+            warn!("Truncating torn write at offset {} in {}", size, path.display());
+            file.set_len(size)?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Reads and verifies a log entry at the given entry position (i.e. its header), returning
+    /// the entry data.
+    fn read_entry(reader: &mut impl Read, pos: u64) -> Result<Vec<u8>> {
+        let mut lenbuf = [0; 4];
+        let mut crcbuf = [0; 4];
+        reader.read_exact(&mut lenbuf)?;
+        reader.read_exact(&mut crcbuf)?;
+        let mut entry = vec![0; u32::from_be_bytes(lenbuf) as usize];
+        reader.read_exact(&mut entry)?;
+        if checksum(&[&lenbuf, &entry]) != u32::from_be_bytes(crcbuf) {
+            return Err(Error::Corruption(format!(
+                "Checksum mismatch for log entry at offset {}",
+                pos
+            )));
+        }
+        Ok(entry)
+    }
+
+    /// Encodes a log entry with the given length field, prefixed by its header.
+    fn encode_entry(len: u32, entry: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE as usize + entry.len());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(&checksum(&[&len.to_be_bytes(), entry]).to_be_bytes());
+        buf.extend_from_slice(entry);
+        buf
+    }
+
+    /// Empties the write-ahead log once it no longer contains any entries that aren't durably
+    /// stored in the log file. The truncation itself needn't be fsynced, since stale records are
+    /// skipped on recovery.
+    fn clear_wal(&mut self) -> Result<()> {
+        if self.uncommitted.is_empty() && self.unsynced.is_none() {
+            self.wal.set_len(0)?;
+            self.wal_positions.clear();
+        }
+        Ok(())
+    }
+
+    /// Rewrites the log file such that it only contains committed entries after the given
+    /// compacted index, preceded by the version and compaction headers. The entries are written to a new file
+    /// which atomically replaces the old one.
+    fn rewrite(&mut self, compacted: u64) -> Result<()> {
+        let path = self.dir.join("raft-log");
+        let compact_path = self.dir.join("raft-log.compact");
+        let mut file = self.file.lock()?;
+        let mut compact_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&compact_path)?;
+        let mut compact_index = BTreeMap::new();
+        let mut bufwriter = BufWriter::new(&mut compact_file);
+        let mut header = Self::encode_entry(VERSION_HEADER, &VERSION.to_be_bytes());
+        header.extend(Self::encode_entry(COMPACTED_HEADER, &compacted.to_be_bytes()));
+        bufwriter.write_all(&header)?;
+        let mut pos = header.len() as u64;
+        for (i, (entry_pos, size)) in self.index.range((compacted + 1)..) {
+            file.seek(SeekFrom::Start(*entry_pos - HEADER_SIZE))?;
+            let entry = Self::read_entry(&mut *file, *entry_pos - HEADER_SIZE)?;
+            compact_index.insert(*i, (pos + HEADER_SIZE, *size));
+            let encoded = Self::encode_entry(*size, &entry);
+            bufwriter.write_all(&encoded)?;
+            pos += encoded.len() as u64;
+        }
+        bufwriter.flush()?;
+        drop(bufwriter);
+        if self.sync {
+            compact_file.sync_data()?;
+        }
+        rename(&compact_path, &path)?;
+        if self.sync {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        *file = compact_file;
+        drop(file);
+        // The new file has been fsynced along with any unsynced commits.
+        self.unsynced = None;
+
+        self.index = compact_index;
+        self.compacted = compacted;
+        Ok(())
+    }
+
+    /// Loads metadata from the given file, verifying its version header and checksum. A missing
+    /// or empty file yields empty metadata. Also returns true if the file is in the legacy
+    /// format, i.e. plain Bincode without a version header or checksum, which should be rewritten.
+    fn load_metadata(path: &Path) -> Result<(Metadata, bool)> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((HashMap::new(), false))
             }
+            Err(err) => return Err(err.into()),
+        };
+        if data.is_empty() {
+            return Ok((HashMap::new(), false));
+        }
+        // A legacy file starts with the u64 map length, which can't match the version header.
+        if !data.starts_with(&VERSION_HEADER.to_be_bytes()) {
+            return match bincode::deserialize(&data) {
+                Ok(metadata) => Ok((metadata, true)),
+                Err(err) => Err(Error::Corruption(format!(
+                    "Invalid legacy metadata file {}: {}",
+                    path.display(),
+                    err
+                ))),
+            };
+        }
+        if data.len() < VERSION_HEADER_SIZE as usize + 4 {
+            return Err(Error::Corruption(format!("Truncated metadata file {}", path.display())));
+        }
+        let (header, data) = data.split_at(VERSION_HEADER_SIZE as usize);
+        if checksum(&[&header[..4], &header[8..]])
+            != u32::from_be_bytes([header[4], header[5], header[6], header[7]])
+        {
+            return Err(Error::Corruption(format!(
+                "Checksum mismatch for version header in metadata file {}",
+                path.display()
+            )));
+        }
+        let version = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        if version != VERSION {
+            return Err(Error::Corruption(format!(
+                "Unsupported log format version {} in {}",
+                version,
+                path.display()
+            )));
         }
+        let (checksum, data) = data.split_at(4);
+        if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]])
+            != crc32fast::hash(data)
+        {
+            return Err(Error::Corruption(format!(
+                "Checksum mismatch in metadata file {}",
+                path.display()
+            )));
+        }
+        Ok((bincode::deserialize(data)?, false))
+    }
+
+    /// Atomically replaces the metadata file with the cached metadata, by writing it to a
+    /// temporary file and renaming it over the old one.
+    fn save_metadata(&self) -> Result<()> {
+        let path = self.dir.join("raft-metadata");
+        let tmp_path = self.dir.join("raft-metadata.new");
+        let data = bincode::serialize(&self.metadata)?;
+        let mut file =
+            OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        file.write_all(&Self::encode_entry(VERSION_HEADER, &VERSION.to_be_bytes()))?;
+        file.write_all(&crc32fast::hash(&data).to_be_bytes())?;
+        file.write_all(&data)?;
+        if self.sync {
+            file.sync_data()?;
+        }
+        rename(&tmp_path, &path)?;
+        if self.sync {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
     }
 }
 
 impl Store for Hybrid {
     fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
+        if entry.len() as u64 > MAX_ENTRY_SIZE {
+            return Err(Error::Value(format!("Log entry size {} too large", entry.len())));
+        }
+        let index = self.len() + 1;
+        let pos = self.wal.seek(SeekFrom::End(0))?;
+        let mut record = Vec::with_capacity(WAL_HEADER_SIZE as usize + entry.len());
+        let (indexbuf, sizebuf) = (index.to_be_bytes(), (entry.len() as u32).to_be_bytes());
+        record.extend_from_slice(&indexbuf);
+        record.extend_from_slice(&sizebuf);
+        record.extend_from_slice(&checksum(&[&indexbuf, &sizebuf, &entry]).to_be_bytes());
+        record.extend_from_slice(&entry);
+        self.wal.write_all(&record)?;
+        if self.sync {
+            self.wal.sync_data()?;
+        }
+        self.wal_positions.push_back(pos);
         self.uncommitted.push_back(entry);
-        Ok(self.len())
+        Ok(index)
     }
 
     fn commit(&mut self, index: u64) -> Result<()> {
+        let committed = self.committed();
         if index > self.len() {
             return Err(Error::Internal(format!("Cannot commit non-existant index {}", index)));
         }
-        if index < self.index.len() as u64 {
+        if index < committed {
             return Err(Error::Internal(format!(
                 "Cannot commit below current committed index {}",
-                self.index.len() as u64
+                committed
             )));
         }
-        if index == self.index.len() as u64 {
+        if index == committed {
             return Ok(());
         }
 
         let mut file = self.file.lock()?;
         let mut pos = file.seek(SeekFrom::End(0))?;
         let mut bufwriter = BufWriter::new(&mut *file);
-        for i in (self.index.len() as u64 + 1)..=index {
+        for i in (committed + 1)..=index {
             let entry = self
                 .uncommitted
                 .pop_front()
                 .ok_or_else(|| Error::Internal("Unexpected end of uncommitted entries".into()))?;
-            bufwriter.write_all(&(entry.len() as u32).to_be_bytes())?;
-            pos += 4;
-            self.index.insert(i, (pos, entry.len() as u32));
-            bufwriter.write_all(&entry)?;
-            pos += entry.len() as u64;
+            self.wal_positions.pop_front();
+            let encoded = Self::encode_entry(entry.len() as u32, &entry);
+            bufwriter.write_all(&encoded)?;
+            self.index.insert(i, (pos + HEADER_SIZE, entry.len() as u32));
+            pos += encoded.len() as u64;
         }
         bufwriter.flush()?;
         drop(bufwriter);
         if self.sync {
-            file.sync_data()?;
+            let mut unsynced = self.unsynced.take().unwrap_or(Unsynced {
+                since: Instant::now(),
+                commits: 0,
+                entries: 0,
+            });
+            unsynced.commits += 1;
+            unsynced.entries += index - committed;
+            if unsynced.since.elapsed() >= self.sync_delay {
+                Self::sync_file(&file, &mut self.sync_stats, unsynced)?;
+            } else {
+                self.unsynced = Some(unsynced);
+            }
         }
-        Ok(())
+        drop(file);
+        self.clear_wal()
     }
 
     fn committed(&self) -> u64 {
-        self.index.len() as u64
+        self.compacted + self.index.len() as u64
+    }
+
+    fn compact(&mut self, index: u64) -> Result<()> {
+        if index > self.committed() {
+            return Err(Error::Internal(format!("Cannot compact uncommitted index {}", index)));
+        }
+        if index <= self.compacted {
+            return Ok(());
+        }
+        self.rewrite(index)
+    }
+
+    fn compacted(&self) -> u64 {
+        self.compacted
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(unsynced) = self.unsynced.take() {
+            let file = self.file.lock()?;
+            Self::sync_file(&file, &mut self.sync_stats, unsynced)?;
+        }
+        self.clear_wal()
+    }
+
+    fn flushed(&self) -> u64 {
+        self.committed() - self.unsynced.map(|u| u.entries).unwrap_or(0)
+    }
+
+    fn flush_deadline(&self) -> Option<Instant> {
+        self.unsynced.map(|u| u.since + self.sync_delay)
     }
 
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         match index {
-            0 => Ok(None),
-            i if i <= self.index.len() as u64 => {
-                let (pos, size) = self.index.get(&i).copied().ok_or_else(|| {
+            i if i <= self.compacted => Ok(None),
+            i if i <= self.committed() => {
+                let (pos, _) = self.index.get(&i).copied().ok_or_else(|| {
                     Error::Internal(format!("Indexed position not found for entry {}", i))
                 })?;
-                let mut entry = vec![0; size as usize];
                 let mut file = self.file.lock()?;
-                file.seek(SeekFrom::Start(pos))?;
-                file.read_exact(&mut entry)?;
-                Ok(Some(entry))
+                file.seek(SeekFrom::Start(pos - HEADER_SIZE))?;
+                Ok(Some(Self::read_entry(&mut *file, pos - HEADER_SIZE)?))
             }
-            i => Ok(self.uncommitted.get(i as usize - self.index.len() - 1).cloned()),
+            i => Ok(self.uncommitted.get((i - self.committed()) as usize - 1).cloned()),
         }
     }
 
     fn len(&self) -> u64 {
-        self.index.len() as u64 + self.uncommitted.len() as u64
+        self.committed() + self.uncommitted.len() as u64
+    }
+
+    fn reset(&mut self, index: u64) -> Result<()> {
+        self.uncommitted.clear();
+        self.index.clear();
+        self.rewrite(index)?;
+        self.clear_wal()
     }
 
     fn scan(&self, range: Range) -> Scan {
         let start = match range.start {
-            Bound::Included(0) => 1,
-            Bound::Included(n) => n,
-            Bound::Excluded(n) => n + 1,
-            Bound::Unbounded => 1,
+            Bound::Included(n) => max(n, self.compacted + 1),
+            Bound::Excluded(n) => max(n + 1, self.compacted + 1),
+            Bound::Unbounded => self.compacted + 1,
         };
         let end = match range.end {
             Bound::Included(n) => n,
@@ -193,26 +831,23 @@ impl Store for Hybrid {
         // Scan committed entries in file
         if let Some((offset, _)) = self.index.get(&start) {
             let mut file = self.file.lock().unwrap();
-            file.seek(SeekFrom::Start(*offset - 4)).unwrap(); // seek to length prefix
+            file.seek(SeekFrom::Start(*offset - HEADER_SIZE)).unwrap(); // seek to entry header
             let mut bufreader = BufReader::new(MutexReader(file)); // FIXME Avoid MutexReader
             scan =
-                Box::new(scan.chain(self.index.range(start..=end).map(move |(_, (_, size))| {
-                    let mut sizebuf = vec![0; 4];
-                    bufreader.read_exact(&mut sizebuf)?;
-                    let mut entry = vec![0; *size as usize];
-                    bufreader.read_exact(&mut entry)?;
-                    Ok(entry)
+                Box::new(scan.chain(self.index.range(start..=end).map(move |(_, (pos, _))| {
+                    Self::read_entry(&mut bufreader, *pos - HEADER_SIZE)
                 })));
         }
 
         // Scan uncommitted entries in memory
-        if end > self.index.len() as u64 {
+        let committed = self.committed();
+        if end > committed {
             scan = Box::new(
                 scan.chain(
                     self.uncommitted
                         .iter()
-                        .skip(start as usize - min(start as usize, self.index.len() + 1))
-                        .take(end as usize - max(start as usize, self.index.len()) + 1)
+                        .skip((start - min(start, committed + 1)) as usize)
+                        .take((end - max(start, committed + 1) + 1) as usize)
                         .cloned()
                         .map(Ok),
                 ),
@@ -226,14 +861,27 @@ impl Store for Hybrid {
         self.index.iter().next_back().map(|(_, (pos, size))| *pos + *size as u64).unwrap_or(0)
     }
 
+    fn sync_stats(&self) -> SyncStats {
+        self.sync_stats.clone()
+    }
+
     fn truncate(&mut self, index: u64) -> Result<u64> {
-        if index < self.index.len() as u64 {
+        let committed = self.committed();
+        if index < committed {
             return Err(Error::Internal(format!(
                 "Cannot truncate below committed index {}",
-                self.index.len() as u64
+                committed
             )));
         }
-        self.uncommitted.truncate(index as usize - self.index.len());
+        let keep = (index - committed) as usize;
+        if let Some(pos) = self.wal_positions.get(keep).copied() {
+            self.wal.set_len(pos)?;
+            if self.sync {
+                self.wal.sync_data()?;
+            }
+            self.wal_positions.truncate(keep);
+        }
+        self.uncommitted.truncate(keep);
         Ok(self.len())
     }
 
@@ -243,24 +891,27 @@ impl Store for Hybrid {
 
     fn set_metadata(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.metadata.insert(key.to_vec(), value);
-        self.metadata_file.set_len(0)?;
-        self.metadata_file.seek(SeekFrom::Start(0))?;
-        bincode::serialize_into(&mut self.metadata_file, &self.metadata)?;
-        if self.sync {
-            self.metadata_file.sync_data()?;
-        }
-        Ok(())
+        self.save_metadata()
     }
 }
 
 impl Drop for Hybrid {
     /// Attempt to fsync data on drop, in case we're running without sync.
     fn drop(&mut self) {
-        self.metadata_file.sync_all().ok();
+        self.wal.sync_all().ok();
         self.file.lock().map(|f| f.sync_all()).ok();
     }
 }
 
+/// Computes a CRC32 checksum of the given byte slices.
+fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
 struct MutexReader<'a>(MutexGuard<'a, File>);
 
 impl<'a> Read for MutexReader<'a> {
@@ -272,8 +923,9 @@ impl<'a> Read for MutexReader<'a> {
 #[cfg(test)]
 impl super::TestSuite<Hybrid> for Hybrid {
     fn setup() -> Result<Self> {
-        let dir = tempdir::TempDir::new("toydb")?;
-        Hybrid::new(dir.as_ref(), false)
+        // Compaction replaces the log file, so the directory must outlive the store.
+        let dir = tempdir::TempDir::new("toydb")?.into_path();
+        Hybrid::new(&dir, false)
     }
 }
 
@@ -295,12 +947,450 @@ fn test_persistent() -> Result<()> {
     l.append(vec![0x05])?;
     l.commit(3)?;
 
+    // Uncommitted entries are retained via the write-ahead log.
     let l = Hybrid::new(dir.as_ref(), true)?;
 
+    assert_eq!(3, l.committed());
+    assert_eq!(5, l.len());
     assert_eq!(
-        vec![vec![1], vec![2], vec![3]],
+        vec![vec![1], vec![2], vec![3], vec![4], vec![5]],
         l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
     );
 
     Ok(())
 }
+
+#[test]
+fn test_wal_truncate() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03])?;
+    l.commit(1)?;
+    l.truncate(2)?;
+    l.append(vec![0x13])?;
+    l.append(vec![0x14])?;
+
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(1, l.committed());
+    assert_eq!(
+        vec![vec![0x01], vec![0x02], vec![0x13], vec![0x14]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    // Truncating recovered entries works too, and committing all entries empties the WAL.
+    l.truncate(3)?;
+    l.commit(3)?;
+    assert_eq!(0, std::fs::metadata(dir.as_ref().join("raft-wal"))?.len());
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(3, l.committed());
+    assert_eq!(3, l.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_torn_write() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02, 0x02])?;
+    l.append(vec![0x03, 0x03, 0x03])?;
+    drop(l);
+
+    // Simulate a crash while writing the last record, by truncating it.
+    let path = dir.as_ref().join("raft-wal");
+    let size = std::fs::metadata(&path)?.len();
+    OpenOptions::new().write(true).open(&path)?.set_len(size - 2)?;
+
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(0, l.committed());
+    assert_eq!(
+        vec![vec![0x01], vec![0x02, 0x02]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+    assert_eq!(size - 19, std::fs::metadata(&path)?.len());
+
+    // The torn record is gone, so new entries are appended after the recovered ones.
+    l.append(vec![0x13])?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(
+        vec![vec![0x01], vec![0x02, 0x02], vec![0x13]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_unsynced_commits() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?.with_sync_delay(Duration::from_secs(3600));
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.commit(1)?;
+    l.flush()?;
+    l.append(vec![0x03])?;
+    l.commit(3)?;
+    drop(l);
+
+    // Simulate a crash losing the unsynced commits from the log file. The entries are still in
+    // the WAL, and are recovered as uncommitted entries.
+    let path = dir.as_ref().join("raft-log");
+    OpenOptions::new().write(true).open(&path)?.set_len(21)?;
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(1, l.committed());
+    assert_eq!(
+        vec![vec![0x01], vec![0x02], vec![0x03]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_persistent_compact() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03])?;
+    l.append(vec![0x04])?;
+    l.commit(4)?;
+    l.compact(2)?;
+    l.append(vec![0x05])?;
+    l.commit(5)?;
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+
+    assert_eq!(2, l.compacted());
+    assert_eq!(5, l.committed());
+    assert_eq!(None, l.get(2)?);
+    assert_eq!(Some(vec![0x03]), l.get(3)?);
+    assert_eq!(
+        vec![vec![3], vec![4], vec![5]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_persistent_reset() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.commit(1)?;
+    l.reset(3)?;
+    l.append(vec![0x04])?;
+    l.commit(4)?;
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+
+    assert_eq!(3, l.compacted());
+    assert_eq!(4, l.committed());
+    assert_eq!(vec![vec![4]], l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_group_commit() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?.with_sync_delay(Duration::from_secs(3600));
+
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03])?;
+    l.commit(1)?;
+    l.commit(3)?;
+    assert_eq!(3, l.committed());
+    assert_eq!(0, l.flushed());
+    assert!(l.flush_deadline().is_some());
+    assert_eq!(SyncStats::default(), l.sync_stats());
+
+    // Unsynced commits are readable, and retained across restarts unless the machine crashes.
+    assert_eq!(Some(vec![0x03]), l.get(3)?);
+    assert_eq!(3, Hybrid::new(dir.as_ref(), true)?.committed());
+
+    // Flushing fsyncs both commits at once.
+    l.flush()?;
+    assert_eq!(3, l.flushed());
+    assert_eq!(None, l.flush_deadline());
+    let stats = l.sync_stats();
+    assert_eq!((stats.syncs, stats.commits, stats.entries, stats.max_commits), (1, 2, 3, 2));
+
+    // Without a delay, every commit is fsynced right away.
+    let mut l = l.with_sync_delay(Duration::from_secs(0));
+    l.append(vec![0x04])?;
+    l.commit(4)?;
+    assert_eq!(4, l.flushed());
+    assert_eq!(None, l.flush_deadline());
+    let stats = l.sync_stats();
+    assert_eq!((stats.syncs, stats.commits, stats.entries, stats.max_commits), (2, 3, 4, 2));
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.set_metadata(b"a", vec![0x01])?;
+    l.set_metadata(b"b", vec![0x02])?;
+    l.set_metadata(b"a", vec![0x03])?;
+    drop(l);
+
+    // A leftover temporary file, e.g. from a crash before the rename, is ignored.
+    std::fs::write(dir.as_ref().join("raft-metadata.new"), b"garbage")?;
+
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(Some(vec![0x03]), l.get_metadata(b"a")?);
+    assert_eq!(Some(vec![0x02]), l.get_metadata(b"b")?);
+    assert_eq!(None, l.get_metadata(b"c")?);
+
+    // Writes replace the temporary file.
+    l.set_metadata(b"c", vec![0x04])?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(Some(vec![0x04]), l.get_metadata(b"c")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_corrupt() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.set_metadata(b"a", vec![0x01])?;
+    drop(l);
+
+    let path = dir.as_ref().join("raft-metadata");
+    let mut data = std::fs::read(&path)?;
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    std::fs::write(&path, &data)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+
+    std::fs::write(&path, &data[..2])?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_legacy_metadata() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let path = dir.as_ref().join("raft-metadata");
+
+    // A metadata file in the legacy format, i.e. plain Bincode, is loaded and rewritten.
+    let mut metadata: Metadata = HashMap::new();
+    metadata.insert(b"term".to_vec(), vec![0x01]);
+    metadata.insert(b"vote".to_vec(), vec![0x02]);
+    let data = bincode::serialize(&metadata)?;
+    std::fs::write(&path, &data)?;
+    assert_eq!(2, Hybrid::verify(dir.as_ref())?[0].entries);
+    assert_eq!(data, std::fs::read(&path)?);
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(Some(vec![0x01]), l.get_metadata(b"term")?);
+    assert_eq!(Some(vec![0x02]), l.get_metadata(b"vote")?);
+    assert!(std::fs::read(&path)?.starts_with(&VERSION_HEADER.to_be_bytes()));
+    drop(l);
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(Some(vec![0x01]), l.get_metadata(b"term")?);
+    assert_eq!(Some(vec![0x02]), l.get_metadata(b"vote")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_torn_write() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let path = dir.as_ref().join("raft-log");
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.append(vec![0x01])?;
+    l.append(vec![0x02, 0x02])?;
+    l.commit(2)?;
+    drop(l);
+    std::fs::write(dir.as_ref().join("raft-wal"), b"")?;
+
+    // An incomplete last entry is truncated on startup, but not by verify.
+    let size = std::fs::metadata(&path)?.len();
+    OpenOptions::new().write(true).open(&path)?.set_len(size - 1)?;
+    let checks = Hybrid::verify(dir.as_ref())?;
+    assert_eq!(FileCheck { path: path.clone(), entries: 1, size: 21, torn: 9 }, checks[0]);
+    assert_eq!(size - 1, std::fs::metadata(&path)?.len());
+
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(1, l.committed());
+    assert_eq!(21, std::fs::metadata(&path)?.len());
+
+    // As is a last entry with a checksum mismatch, e.g. with unwritten data.
+    l.append(vec![0x03, 0x03])?;
+    l.commit(2)?;
+    drop(l);
+    let mut data = std::fs::read(&path)?;
+    let last = data.len() - 1;
+    data[last] = 0x00;
+    std::fs::write(&path, &data)?;
+
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(1, l.committed());
+    assert_eq!(vec![vec![0x01]], l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_corruption() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let path = dir.as_ref().join("raft-log");
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03])?;
+    l.commit(3)?;
+    l.append(vec![0x04])?;
+
+    // Flip a bit in the data of the second entry.
+    let mut data = std::fs::read(&path)?;
+    data[29] ^= 0x01;
+    std::fs::write(&path, &data)?;
+
+    // Reads detect the corruption.
+    assert_eq!(Some(vec![0x01]), l.get(1)?);
+    assert!(matches!(l.get(2), Err(Error::Corruption(_))));
+    assert!(matches!(
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>(),
+        Err(Error::Corruption(_))
+    ));
+    drop(l);
+
+    // As do startup and verify, without truncating the file.
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+    assert!(matches!(Hybrid::verify(dir.as_ref()), Err(Error::Corruption(_))));
+    assert_eq!(data.len() as u64, std::fs::metadata(&path)?.len());
+
+    // A corrupt write-ahead log record is detected too.
+    data[29] ^= 0x01;
+    std::fs::write(&path, &data)?;
+    let checks = Hybrid::verify(dir.as_ref())?;
+    assert_eq!(2, checks.len());
+    assert_eq!((3, 39, 0), (checks[0].entries, checks[0].size, checks[0].torn));
+    assert_eq!((1, 17, 0), (checks[1].entries, checks[1].size, checks[1].torn));
+
+    let wal_path = dir.as_ref().join("raft-wal");
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.append(vec![0x05])?;
+    drop(l);
+    let mut wal = std::fs::read(&wal_path)?;
+    wal[16] ^= 0x01;
+    std::fs::write(&wal_path, &wal)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupt_length() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let path = dir.as_ref().join("raft-log");
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    l.append(vec![0x01])?;
+    l.append(vec![0x02])?;
+    l.append(vec![0x03])?;
+    l.commit(3)?;
+    l.append(vec![0x04])?;
+    l.append(vec![0x05])?;
+    drop(l);
+
+    // A length in the middle of the log file that points beyond the end of the file is followed
+    // by a valid entry, so it's corruption rather than a torn write, and isn't truncated.
+    let mut data = std::fs::read(&path)?;
+    data[21..25].copy_from_slice(&1000_u32.to_be_bytes());
+    std::fs::write(&path, &data)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+    assert!(matches!(Hybrid::verify(dir.as_ref()), Err(Error::Corruption(_))));
+    assert_eq!(39, std::fs::metadata(&path)?.len());
+
+    // As is a length beyond the maximum entry size.
+    data[21..25].copy_from_slice(&u32::MAX.to_be_bytes());
+    std::fs::write(&path, &data)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+    assert_eq!(39, std::fs::metadata(&path)?.len());
+
+    // The same goes for write-ahead log records.
+    data[21..25].copy_from_slice(&1_u32.to_be_bytes());
+    std::fs::write(&path, &data)?;
+    let wal_path = dir.as_ref().join("raft-wal");
+    let mut wal = std::fs::read(&wal_path)?;
+    let wal_size = wal.len() as u64;
+    wal[8..12].copy_from_slice(&1000_u32.to_be_bytes());
+    std::fs::write(&wal_path, &wal)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+    assert_eq!(wal_size, std::fs::metadata(&wal_path)?.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_legacy_log() -> Result<()> {
+    let dir = tempdir::TempDir::new("toydb")?;
+    let path = dir.as_ref().join("raft-log");
+
+    // A log file in the legacy format, with length-prefixed entries and a torn write at the end.
+    let mut data = Vec::new();
+    for entry in [vec![0x01], vec![0x02, 0x02], vec![0x03, 0x03, 0x03]] {
+        data.extend_from_slice(&(entry.len() as u32).to_be_bytes());
+        data.extend_from_slice(&entry);
+    }
+    data.extend_from_slice(&[0x00, 0x00, 0x00, 0x04, 0x04]);
+    std::fs::write(&path, &data)?;
+
+    // Verify reads it without migrating it.
+    let checks = Hybrid::verify(dir.as_ref())?;
+    assert_eq!(FileCheck { path: path.clone(), entries: 3, size: 18, torn: 5 }, checks[0]);
+    assert_eq!(data, std::fs::read(&path)?);
+
+    // Opening it migrates it to the current format, discarding the torn write.
+    let mut l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(3, l.committed());
+    assert_eq!(
+        vec![vec![0x01], vec![0x02, 0x02], vec![0x03, 0x03, 0x03]],
+        l.scan(Range::from(..)).collect::<Result<Vec<_>>>()?
+    );
+    assert!(!dir.as_ref().join("raft-log.migrate").exists());
+    let data = std::fs::read(&path)?;
+    assert_eq!(VERSION_HEADER.to_be_bytes(), data[..4]);
+    assert_eq!(VERSION_HEADER_SIZE + 3 * HEADER_SIZE + 6, data.len() as u64);
+
+    // The migrated log is used as normal.
+    l.append(vec![0x04])?;
+    l.commit(4)?;
+    drop(l);
+    let l = Hybrid::new(dir.as_ref(), true)?;
+    assert_eq!(4, l.committed());
+    assert_eq!(Some(vec![0x04]), l.get(4)?);
+
+    // A log file with an unknown version is rejected.
+    let mut data = std::fs::read(&path)?;
+    data[8..12].copy_from_slice(&2_u32.to_be_bytes());
+    let crc = checksum(&[&data[..4], &data[8..12]]);
+    data[4..8].copy_from_slice(&crc.to_be_bytes());
+    std::fs::write(&path, &data)?;
+    assert!(matches!(Hybrid::new(dir.as_ref(), true), Err(Error::Corruption(_))));
+
+    Ok(())
+}
@@ -7,15 +7,18 @@ use std::ops::Bound;
 
 // An in-memory log store.
 pub struct Memory {
+    /// Log entries following the compacted entries.
     log: Vec<Vec<u8>>,
     committed: u64,
+    /// The number of compacted entries, i.e. the index of the last compacted entry.
+    compacted: u64,
     metadata: HashMap<Vec<u8>, Vec<u8>>,
 }
 
 impl Memory {
     /// Creates a new in-memory log.
     pub fn new() -> Self {
-        Self { log: Vec::new(), committed: 0, metadata: HashMap::new() }
+        Self { log: Vec::new(), committed: 0, compacted: 0, metadata: HashMap::new() }
     }
 }
 
@@ -29,7 +32,7 @@ impl Display for Memory {
 impl Store for Memory {
     fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
         self.log.push(entry);
-        Ok(self.log.len() as u64)
+        Ok(self.len())
     }
 
     // store 提交
@@ -52,35 +55,59 @@ impl Store for Memory {
         self.committed
     }
 
+    fn compact(&mut self, index: u64) -> Result<()> {
+        if index > self.committed {
+            return Err(Error::Internal(format!("Cannot compact uncommitted index {}", index)));
+        }
+        if index <= self.compacted {
+            return Ok(());
+        }
+        self.log.drain(..(index - self.compacted) as usize);
+        self.compacted = index;
+        Ok(())
+    }
+
+    fn compacted(&self) -> u64 {
+        self.compacted
+    }
+
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         match index {
-            0 => Ok(None),
+            i if i <= self.compacted => Ok(None),
             // 创建一个复制
-            i => Ok(self.log.get(i as usize - 1).cloned()),
+            i => Ok(self.log.get((i - self.compacted) as usize - 1).cloned()),
         }
     }
 
     // 获取当前log
     fn len(&self) -> u64 {
         // vector的size
-        self.log.len() as u64
+        self.compacted + self.log.len() as u64
+    }
+
+    fn reset(&mut self, index: u64) -> Result<()> {
+        self.log.clear();
+        self.committed = index;
+        self.compacted = index;
+        Ok(())
     }
 
     fn scan(&self, range: Range) -> super::Scan {
+        let compacted = self.compacted as usize;
         Box::new(
             self.log
                 .iter()
                 .take(match range.end {
                     // 取多个
-                    Bound::Included(n) => n as usize,
+                    Bound::Included(n) => (n as usize).saturating_sub(compacted),
                     Bound::Excluded(0) => 0,
-                    Bound::Excluded(n) => n as usize - 1,
+                    Bound::Excluded(n) => (n as usize - 1).saturating_sub(compacted),
                     Bound::Unbounded => std::usize::MAX,
                 })
                 .skip(match range.start {
                     Bound::Included(0) => 0,
-                    Bound::Included(n) => n as usize - 1,
-                    Bound::Excluded(n) => n as usize,
+                    Bound::Included(n) => (n as usize - 1).saturating_sub(compacted),
+                    Bound::Excluded(n) => (n as usize).saturating_sub(compacted),
                     Bound::Unbounded => 0,
                 })
                 .cloned()
@@ -100,8 +127,8 @@ impl Store for Memory {
                 self.committed
             )));
         }
-        self.log.truncate(index as usize);
-        Ok(self.log.len() as u64)
+        self.log.truncate((index - self.compacted) as usize);
+        Ok(self.len())
     }
 
     fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
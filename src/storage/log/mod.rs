@@ -10,8 +10,10 @@ pub use test::Test;
 
 use crate::error::Result;
 
+use serde_derive::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::ops::{Bound, RangeBounds};
+use std::time::Instant;
 
 /// A log store. Entry indexes are 1-based, to match Raft semantics.
 /// store 实现trait;
@@ -25,18 +27,52 @@ pub trait Store: Display + Sync + Send {
     /// Returns the committed index, if any.
     fn committed(&self) -> u64;
 
+    /// Compacts the log by removing committed entries up to and including the given index, e.g.
+    /// once they are covered by a state machine snapshot. Compacted entries can no longer be
+    /// fetched, but the indexes of the remaining entries are unchanged. Errors if asked to compact
+    /// uncommitted entries.
+    fn compact(&mut self, index: u64) -> Result<()>;
+
+    /// Returns the index of the last compacted entry, if any.
+    fn compacted(&self) -> u64;
+
+    /// Flushes any commits buffered for group commit to durable storage.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the last committed index that has been flushed to durable storage.
+    fn flushed(&self) -> u64 {
+        self.committed()
+    }
+
+    /// Returns the time by which buffered commits must be flushed, if any.
+    fn flush_deadline(&self) -> Option<Instant> {
+        None
+    }
+
     /// Fetches a log entry, if it exists.
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>>;
 
-    /// Returns the number of entries in the log.
+    /// Returns the number of entries in the log, including compacted entries.
     fn len(&self) -> u64;
 
+    /// Removes all entries, including uncommitted ones, and resets the log such that it continues
+    /// after the given index, which is considered compacted. Used e.g. when replacing the log with
+    /// a state machine snapshot.
+    fn reset(&mut self, index: u64) -> Result<()>;
+
     /// Scans the log between the given indexes.
     fn scan(&self, range: Range) -> Scan;
 
     /// Returns the size of the log, in bytes.
     fn size(&self) -> u64;
 
+    /// Returns fsync statistics for the log.
+    fn sync_stats(&self) -> SyncStats {
+        SyncStats::default()
+    }
+
     /// Truncates the log be removing any entries above the given index, and returns the
     /// highest index. Errors if asked to truncate any committed entries.
     fn truncate(&mut self, index: u64) -> Result<u64>;
@@ -53,6 +89,23 @@ pub trait Store: Display + Sync + Send {
     }
 }
 
+/// Log fsync statistics. With group commit, each fsync may cover several commits.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncStats {
+    /// The number of fsyncs of committed entries.
+    pub syncs: u64,
+    /// The number of commits covered by the fsyncs.
+    pub commits: u64,
+    /// The number of entries covered by the fsyncs.
+    pub entries: u64,
+    /// The largest number of commits covered by a single fsync.
+    pub max_commits: u64,
+    /// The total fsync latency, in microseconds.
+    pub sync_micros: u64,
+    /// The largest fsync latency, in microseconds.
+    pub max_sync_micros: u64,
+}
+
 /// A scan range.
 pub struct Range {
     start: Bound<u64>,
@@ -92,8 +145,10 @@ trait TestSuite<S: Store> {
     fn test() -> Result<()> {
         Self::test_append()?;
         Self::test_commit_truncate()?;
+        Self::test_compact()?;
         Self::test_get()?;
         Self::test_metadata()?;
+        Self::test_reset()?;
         Self::test_scan()?;
         Ok(())
     }
@@ -146,6 +201,54 @@ trait TestSuite<S: Store> {
         Ok(())
     }
 
+    fn test_compact() -> Result<()> {
+        let mut s = Self::setup()?;
+
+        // Compacting an empty store should be fine.
+        assert_eq!(0, s.compacted());
+        s.compact(0)?;
+
+        s.append(vec![0x01])?;
+        s.append(vec![0x02])?;
+        s.append(vec![0x03])?;
+        s.append(vec![0x04])?;
+        s.commit(3)?;
+
+        // Compacting uncommitted entries should error.
+        assert_eq!(Err(Error::Internal("Cannot compact uncommitted index 4".into())), s.compact(4));
+
+        // Compacting committed entries should remove them, but retain indexes.
+        s.compact(2)?;
+        assert_eq!(2, s.compacted());
+        assert_eq!(3, s.committed());
+        assert_eq!(4, s.len());
+        assert_eq!(None, s.get(1)?);
+        assert_eq!(None, s.get(2)?);
+        assert_eq!(Some(vec![0x03]), s.get(3)?);
+        assert_eq!(Some(vec![0x04]), s.get(4)?);
+        assert_eq!(vec![vec![3], vec![4]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+        assert_eq!(vec![vec![3]], s.scan(Range::from(1..=3)).collect::<Result<Vec<_>>>()?);
+
+        // Compacting below the compacted index should be a noop.
+        s.compact(1)?;
+        assert_eq!(2, s.compacted());
+
+        // Appending, committing and truncating should work as before.
+        assert_eq!(5, s.append(vec![0x05])?);
+        s.commit(4)?;
+        assert_eq!(4, s.truncate(4)?);
+        assert_eq!(vec![vec![3], vec![4]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+        // Compacting all committed entries should leave an empty log with retained indexes.
+        s.compact(4)?;
+        assert_eq!(4, s.compacted());
+        assert_eq!(4, s.len());
+        assert!(s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?.is_empty());
+        assert_eq!(5, s.append(vec![0x05])?);
+        assert_eq!(Some(vec![0x05]), s.get(5)?);
+        Ok(())
+    }
+
     fn test_get() -> Result<()> {
         let mut s = Self::setup()?;
         s.append(vec![0x01])?;
@@ -165,6 +268,37 @@ trait TestSuite<S: Store> {
         Ok(())
     }
 
+    fn test_reset() -> Result<()> {
+        let mut s = Self::setup()?;
+        s.append(vec![0x01])?;
+        s.append(vec![0x02])?;
+        s.append(vec![0x03])?;
+        s.commit(2)?;
+        s.set_metadata(b"a", vec![0x01])?;
+
+        // Resetting should remove all entries, and continue after the given index.
+        s.reset(5)?;
+        assert_eq!(5, s.len());
+        assert_eq!(5, s.committed());
+        assert_eq!(5, s.compacted());
+        assert_eq!(None, s.get(2)?);
+        assert_eq!(None, s.get(3)?);
+        assert!(s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?.is_empty());
+        assert_eq!(Some(vec![0x01]), s.get_metadata(b"a")?);
+
+        assert_eq!(6, s.append(vec![0x06])?);
+        s.commit(6)?;
+        assert_eq!(Some(vec![0x06]), s.get(6)?);
+        assert_eq!(vec![vec![6]], s.scan(Range::from(..)).collect::<Result<Vec<_>>>()?);
+
+        // Resetting to a lower index should also work.
+        s.reset(1)?;
+        assert_eq!(1, s.len());
+        assert_eq!(1, s.committed());
+        assert_eq!(None, s.get(6)?);
+        Ok(())
+    }
+
     #[allow(clippy::reversed_empty_ranges)]
     fn test_scan() -> Result<()> {
         let mut s = Self::setup()?;
@@ -37,6 +37,14 @@ impl Store for Test {
         self.store.read().unwrap().committed()
     }
 
+    fn compact(&mut self, index: u64) -> Result<()> {
+        self.store.write()?.compact(index)
+    }
+
+    fn compacted(&self) -> u64 {
+        self.store.read().unwrap().compacted()
+    }
+
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         self.store.read()?.get(index)
     }
@@ -45,6 +53,10 @@ impl Store for Test {
         self.store.read().unwrap().len()
     }
 
+    fn reset(&mut self, index: u64) -> Result<()> {
+        self.store.write()?.reset(index)
+    }
+
     fn scan(&self, range: Range) -> Scan {
         // Since the mutex guard is scoped to this method, we simply buffer the result.
         Box::new(self.store.read().unwrap().scan(range).collect::<Vec<Result<_>>>().into_iter())
@@ -1,2 +1,36 @@
 pub mod kv;
-pub mod log;
\ No newline at end of file
+pub mod log;
+
+use crate::error::{Error, Result};
+
+use std::path::{Path, PathBuf};
+
+/// The result of verifying a storage file, e.g. via `toydb verify`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileCheck {
+    /// The file path.
+    pub path: PathBuf,
+    /// The number of valid entries in the file.
+    pub entries: u64,
+    /// The size of the valid part of the file, in bytes.
+    pub size: u64,
+    /// The size of a torn write at the end of the file, in bytes, which is truncated on startup.
+    pub torn: u64,
+}
+
+/// Checks that an entry whose length extends beyond the end of its file is a torn write, i.e. an
+/// incomplete final entry, rather than a corrupt length field in the middle of the file. This is
+/// the case if no valid entry starts anywhere after its offset. The tail contains the file data
+/// from the entry's offset onwards, and valid returns whether a valid entry starts at the
+/// beginning of the given data.
+fn check_torn(path: &Path, pos: u64, tail: &[u8], valid: impl Fn(&[u8]) -> bool) -> Result<()> {
+    match (1..tail.len()).find(|i| valid(&tail[*i..])) {
+        Some(i) => Err(Error::Corruption(format!(
+            "Invalid length for entry at offset {} in {}, followed by a valid entry at offset {}",
+            pos,
+            path.display(),
+            pos + i as u64
+        ))),
+        None => Ok(()),
+    }
+}
@@ -2,6 +2,7 @@ mod pool;
 
 use super::{assert_row, assert_rows, setup};
 
+use toydb::client::StreamResultSet;
 use toydb::error::{Error, Result};
 use toydb::raft;
 use toydb::sql::engine::{Mode, Status};
@@ -11,6 +12,7 @@ use toydb::sql::types::{Column, DataType, Value};
 use toydb::storage::kv;
 use toydb::Client;
 
+use futures::stream::TryStreamExt as _;
 use pretty_assertions::assert_eq;
 use serial_test::serial;
 
@@ -125,18 +127,130 @@ async fn status() -> Result<()> {
                 server: "test".into(),
                 leader: "test".into(),
                 term: 0,
-                node_last_index: vec![("test".to_string(), 26)].into_iter().collect(),
-                commit_index: 26,
-                apply_index: 26,
+                node_last_index: vec![("test".to_string(), 10)].into_iter().collect(),
+                commit_index: 10,
+                apply_index: 10,
                 storage: "hybrid".into(),
-                storage_size: 3239,
+                storage_size: 2444,
+                storage_sync: Default::default(),
+            },
+            mvcc: kv::mvcc::Status {
+                txns: 1,
+                txns_active: 0,
+                storage: "memory".into(),
+                reclaimed_bytes: 0,
             },
-            mvcc: kv::mvcc::Status { txns: 1, txns_active: 0, storage: "memory".into() },
         }
     );
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn vacuum() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    c.execute("UPDATE genres SET name = 'Drama' WHERE id = 3").await?;
+    c.execute("INSERT INTO genres VALUES (4, 'Horror')").await?;
+    c.execute("DELETE FROM genres WHERE id = 4").await?;
+    let reclaimed = c.vacuum().await?;
+    assert!(reclaimed > 0);
+    assert_eq!(c.status().await?.mvcc.reclaimed_bytes, reclaimed);
+
+    assert_rows(
+        c.execute("SELECT * FROM genres").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("Science Fiction".into())],
+            vec![Value::Integer(2), Value::String("Action".into())],
+            vec![Value::Integer(3), Value::String("Drama".into())],
+        ],
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_batch() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Each statement's writes should be submitted as a single Raft command, in addition to the
+    // implicit transaction's begin and commit commands.
+    let index = c.status().await?.raft.commit_index;
+    let result =
+        c.execute("INSERT INTO genres VALUES (4, 'Horror'), (5, 'Comedy'), (6, 'War')").await?;
+    assert_eq!(result, ResultSet::Create { count: 3 });
+    assert_eq!(c.status().await?.raft.commit_index, index + 3);
+
+    let result = c.execute("UPDATE genres SET name = 'Other' WHERE id > 3").await?;
+    assert_eq!(result, ResultSet::Update { count: 3 });
+    assert_eq!(c.status().await?.raft.commit_index, index + 6);
+
+    let result = c.execute("DELETE FROM genres WHERE id > 4").await?;
+    assert_eq!(result, ResultSet::Delete { count: 2 });
+    assert_eq!(c.status().await?.raft.commit_index, index + 9);
+
+    assert_rows(
+        c.execute("SELECT * FROM genres").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("Science Fiction".into())],
+            vec![Value::Integer(2), Value::String("Action".into())],
+            vec![Value::Integer(3), Value::String("Comedy".into())],
+            vec![Value::Integer(4), Value::String("Other".into())],
+        ],
+    );
+
+    // A failing batch is rolled back along with the implicit transaction.
+    assert_eq!(
+        c.execute("INSERT INTO genres VALUES (5, 'Comedy'), (1, 'Drama')").await,
+        Err(Error::Value("Primary key 1 already exists for table genres".into()))
+    );
+    assert_row(c.execute("SELECT COUNT(*) FROM genres").await?, vec![Value::Integer(4)]);
+
+    // In an explicit transaction, a failing batch leaves none of its rows behind, while earlier
+    // writes in the transaction are kept.
+    c.execute("BEGIN").await?;
+    c.execute("INSERT INTO genres VALUES (5, 'Drama')").await?;
+    assert_eq!(
+        c.execute("INSERT INTO genres VALUES (6, 'Western'), (1, 'Drama'), (7, 'Musical')").await,
+        Err(Error::Value("Primary key 1 already exists for table genres".into()))
+    );
+    assert_rows(
+        c.execute("SELECT * FROM genres WHERE id > 4").await?,
+        vec![vec![Value::Integer(5), Value::String("Drama".into())]],
+    );
+    c.execute("COMMIT").await?;
+    assert_row(c.execute("SELECT COUNT(*) FROM genres").await?, vec![Value::Integer(5)]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_scan_pages() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(vec![]).await?;
+
+    // Scans larger than a page should be fetched in several pages, in either direction.
+    c.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER INDEX)").await?;
+    let values = (1..=2500).map(|i| format!("({}, {})", i, i % 10)).collect::<Vec<_>>();
+    c.execute(&format!("INSERT INTO test VALUES {}", values.join(", "))).await?;
+
+    match c.execute("SELECT id FROM test").await? {
+        ResultSet::Query { rows, .. } => assert_eq!(
+            rows.collect::<Result<Vec<_>>>()?,
+            (1..=2500).map(|i| vec![Value::Integer(i)]).collect::<Vec<_>>()
+        ),
+        r => panic!("Unexpected result {:?}", r),
+    }
+    assert_row(
+        c.execute("SELECT COUNT(*), MIN(id), MAX(id) FROM test WHERE value = 3").await?,
+        vec![Value::Integer(250), Value::Integer(3), Value::Integer(2493)],
+    );
+    assert_row(
+        c.execute("SELECT id FROM test WHERE id > 2000 ORDER BY id DESC LIMIT 1").await?,
+        vec![Value::Integer(2500)],
+    );
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn execute() -> Result<()> {
@@ -220,6 +334,67 @@ async fn execute() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn execute_stream() -> Result<()> {
+    let (c, _teardown) = setup::server_with_client(setup::movies()).await?;
+
+    // Query rows are streamed from the server.
+    match c.execute_stream("SELECT id, title FROM movies WHERE id < 3").await? {
+        StreamResultSet::Query { columns, mut rows } => {
+            assert_eq!(
+                columns,
+                vec![Column { name: Some("id".into()) }, Column { name: Some("title".into()) }]
+            );
+            assert_eq!(
+                rows.try_next().await?,
+                Some(vec![Value::Integer(1), Value::String("Stalker".into())])
+            );
+            assert_eq!(
+                rows.try_next().await?,
+                Some(vec![Value::Integer(2), Value::String("Sicario".into())])
+            );
+            assert_eq!(rows.try_next().await?, None);
+            assert_eq!(rows.try_next().await?, None);
+        }
+        StreamResultSet::Other(r) => panic!("Unexpected result {:?}", r),
+    }
+
+    // Other results are returned as is, and update the transaction state.
+    match c.execute_stream("BEGIN READ ONLY").await? {
+        StreamResultSet::Other(ResultSet::Begin { mode: Mode::ReadOnly, .. }) => {}
+        StreamResultSet::Other(r) => panic!("Unexpected result {:?}", r),
+        StreamResultSet::Query { .. } => panic!("Unexpected query result"),
+    }
+    assert!(c.txn().is_some());
+
+    // Dropping a partially consumed stream discards the remaining rows, such that the client
+    // can be used for further requests.
+    match c.execute_stream("SELECT * FROM movies").await? {
+        StreamResultSet::Query { mut rows, .. } => {
+            assert!(rows.try_next().await?.is_some());
+        }
+        StreamResultSet::Other(r) => panic!("Unexpected result {:?}", r),
+    }
+    assert_row(c.execute("SELECT COUNT(*) FROM movies").await?, vec![Value::Integer(10)]);
+    c.execute("COMMIT").await?;
+    assert_eq!(c.txn(), None);
+
+    // Errors are returned via the stream, ending it.
+    match c.execute_stream("SELECT 1 / (10 - id) FROM movies").await? {
+        StreamResultSet::Query { mut rows, .. } => {
+            for _ in 1..10 {
+                assert!(rows.try_next().await?.is_some());
+            }
+            assert!(rows.try_next().await.is_err());
+            assert_eq!(rows.try_next().await?, None);
+        }
+        StreamResultSet::Other(r) => panic!("Unexpected result {:?}", r),
+    }
+    assert_row(c.execute("SELECT COUNT(*) FROM movies").await?, vec![Value::Integer(10)]);
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn execute_txn() -> Result<()> {
@@ -0,0 +1,50 @@
+use super::super::{assert_row, setup};
+
+use toydb::error::{Error, Result};
+use toydb::sql::types::Value;
+
+use serial_test::serial;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Leadership can be transferred between nodes of a running cluster.
+async fn transfer_leader() -> Result<()> {
+    let (a, _, _, _teardown) = setup::cluster_simple().await?;
+    a.execute("INSERT INTO test VALUES (1, 'a')").await?;
+
+    assert_eq!(
+        a.transfer_leader("toydb9").await,
+        Err(Error::Value("Node toydb9 is not a cluster member".into()))
+    );
+
+    // Transfer leadership to each of the other nodes in turn.
+    for i in 1..=3 {
+        let leader = a.status().await?.raft.leader;
+        let target = if leader == "toydb0" { "toydb1" } else { "toydb0" };
+        a.transfer_leader(target).await?;
+        // Requests may be aborted while the target takes over.
+        for _ in 0..20 {
+            match a.status().await {
+                Ok(status) if status.raft.leader == target => break,
+                Ok(_) | Err(Error::Abort) => {}
+                Err(err) => return Err(err),
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(a.status().await?.raft.leader, target);
+
+        a.execute(&format!("INSERT INTO test VALUES ({}, 'b')", i + 1)).await?;
+        assert_row(
+            a.execute(&format!("SELECT * FROM test WHERE id = {}", i + 1)).await?,
+            vec![Value::Integer(i + 1), Value::String("b".into())],
+        );
+    }
+
+    // Transferring to the current leader is a noop.
+    let leader = a.status().await?.raft.leader;
+    a.transfer_leader(&leader).await?;
+    assert_eq!(a.status().await?.raft.leader, leader);
+
+    Ok(())
+}
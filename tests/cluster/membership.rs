@@ -0,0 +1,56 @@
+use super::super::{assert_row, setup};
+
+use toydb::client::Client;
+use toydb::error::{Error, Result};
+use toydb::sql::types::Value;
+
+use serial_test::serial;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Nodes can be added to and removed from a running cluster.
+async fn add_remove_node() -> Result<()> {
+    let (a, _, _, _teardown) = setup::cluster_simple().await?;
+    a.execute("INSERT INTO test VALUES (1, 'a')").await?;
+
+    // Adding a node already in the cluster, or removing an unknown one, should error.
+    assert_eq!(
+        a.add_node("toydb1", "127.0.0.1:9706").await,
+        Err(Error::Value("Node toydb1 is already a cluster member".into()))
+    );
+    assert_eq!(
+        a.remove_node("toydb3").await,
+        Err(Error::Value("Node toydb3 is not a cluster member".into()))
+    );
+
+    // Add a new node and start it, after which it should catch up with the leader.
+    a.add_node("toydb3", "127.0.0.1:9708").await?;
+    let peers: HashMap<String, String> =
+        (0..3).map(|i| (format!("toydb{}", i), format!("127.0.0.1:{}", 9705 + i))).collect();
+    let _teardown_d = setup::server("toydb3", "127.0.0.1:9608", "127.0.0.1:9708", peers).await?;
+    let d = Client::new("127.0.0.1:9608").await?;
+    assert_eq!(a.status().await?.raft.node_last_index.len(), 4);
+
+    let mut result = d.execute("SELECT * FROM test WHERE id = 1").await;
+    for _ in 0..20 {
+        if result.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        result = d.execute("SELECT * FROM test WHERE id = 1").await;
+    }
+    assert_row(result?, vec![Value::Integer(1), Value::String("a".into())]);
+
+    // Writes should still go through once the node is removed again.
+    a.remove_node("toydb3").await?;
+    assert_eq!(a.status().await?.raft.node_last_index.len(), 3);
+    a.execute("INSERT INTO test VALUES (2, 'b')").await?;
+    assert_row(
+        a.execute("SELECT * FROM test WHERE id = 2").await?,
+        vec![Value::Integer(2), Value::String("b".into())],
+    );
+
+    Ok(())
+}
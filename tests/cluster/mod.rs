@@ -1,2 +1,6 @@
 mod isolation;
+mod leadership;
+mod membership;
+mod partition;
+mod reads;
 mod recovery;
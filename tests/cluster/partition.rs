@@ -0,0 +1,100 @@
+use super::super::{assert_row, setup};
+
+use toydb::client::Client;
+use toydb::error::{Error, Result};
+use toydb::sql::types::Value;
+
+use serial_test::serial;
+use std::time::Duration;
+
+/// Executes a query, retrying it while it's aborted e.g. due to leader elections.
+async fn execute_retry(client: &Client, query: &str) -> Result<()> {
+    for _ in 0..50 {
+        match client.execute(query).await {
+            Err(Error::Abort) => tokio::time::sleep(Duration::from_millis(100)).await,
+            result => return result.map(|_| ()),
+        }
+    }
+    Err(Error::Abort)
+}
+
+/// Waits for the client's node to return the given row for a query.
+async fn wait_row(client: &Client, query: &str, expect: Vec<Value>) -> Result<()> {
+    let mut result = client.execute(query).await;
+    for _ in 0..50 {
+        if result.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        result = client.execute(query).await;
+    }
+    assert_row(result?, expect);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A partitioned follower doesn't inflate its term, and doesn't disrupt the leader when it rejoins.
+async fn partition_follower() -> Result<()> {
+    let (clients, partitions, _teardown) =
+        setup::cluster_with_partitions(3, setup::simple()).await?;
+    let status = clients[0].status().await?;
+    let (leader, term) = (status.raft.leader, status.raft.term);
+    let i = (0..3).find(|i| format!("toydb{}", i) != leader).unwrap();
+    let j = (0..3).find(|j| format!("toydb{}", j) == leader).unwrap();
+
+    // Partition the follower for several election timeouts, while writing to the leader.
+    partitions.isolate(&format!("toydb{}", i));
+    clients[j].execute("INSERT INTO test VALUES (1, 'a')").await?;
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    clients[j].execute("INSERT INTO test VALUES (2, 'b')").await?;
+
+    // Once healed and reconnected, the follower catches up without disrupting the leader.
+    partitions.heal();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let select = "SELECT * FROM test WHERE id = 2";
+    wait_row(&clients[i], select, vec![Value::Integer(2), Value::String("b".into())]).await?;
+    let status = clients[i].status().await?;
+    assert_eq!(status.raft.leader, leader);
+    assert_eq!(status.raft.term, term);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A partitioned leader steps down once it loses contact with the majority, which elects a new
+// leader. Once healed, the old leader follows the new one.
+async fn partition_leader() -> Result<()> {
+    let (clients, partitions, _teardown) =
+        setup::cluster_with_partitions(3, setup::simple()).await?;
+    let status = clients[0].status().await?;
+    let (leader, term) = (status.raft.leader, status.raft.term);
+    let i = (0..3).find(|i| format!("toydb{}", i) == leader).unwrap();
+    let j = (0..3).find(|j| format!("toydb{}", j) != leader).unwrap();
+
+    // The majority elects a new leader, and accepts writes.
+    partitions.isolate(&leader);
+    execute_retry(&clients[j], "INSERT INTO test VALUES (1, 'a')").await?;
+    let status = clients[j].status().await?;
+    let new_leader = status.raft.leader;
+    assert_ne!(new_leader, leader);
+    assert!(status.raft.term > term);
+
+    // The old leader steps down, and no longer responds to status requests itself. We use a
+    // separate client, since the request remains queued until the partition heals.
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let probe = Client::new(format!("127.0.0.1:{}", 9605 + i)).await?;
+    assert!(tokio::time::timeout(Duration::from_millis(500), probe.status()).await.is_err());
+    std::mem::drop(probe);
+
+    // Once healed, the old leader follows the new leader.
+    partitions.heal();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(clients[i].status().await?.raft.leader, new_leader);
+    clients[i].execute("INSERT INTO test VALUES (2, 'b')").await?;
+    let select = "SELECT * FROM test WHERE id = 2";
+    wait_row(&clients[j], select, vec![Value::Integer(2), Value::String("b".into())]).await?;
+
+    Ok(())
+}
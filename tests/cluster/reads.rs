@@ -0,0 +1,109 @@
+use super::super::{assert_row, assert_rows, setup};
+
+use toydb::error::{Error, Result};
+use toydb::sql::engine::Mode;
+use toydb::sql::execution::ResultSet;
+use toydb::sql::types::Value;
+
+use serial_test::serial;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// Follower reads are served by the follower, but still see all writes committed before them.
+async fn follower_reads() -> Result<()> {
+    let (clients, _partitions, _teardown) =
+        setup::cluster_with_partitions(3, setup::simple()).await?;
+    let leader = clients[0].status().await?.raft.leader;
+    let i = (0..3).find(|i| format!("toydb{}", i) != leader).unwrap();
+    let j = (0..3).find(|j| format!("toydb{}", j) == leader).unwrap();
+    let (follower, leader) = (&clients[i], &clients[j]);
+
+    assert_eq!(
+        follower.execute("SET follower_reads = TRUE").await?,
+        ResultSet::Set { name: "follower_reads".into(), value: Value::Boolean(true) }
+    );
+    assert_eq!(
+        follower.execute("SET follower_reads = 1").await,
+        Err(Error::Value("Invalid value 1 for setting follower_reads".into()))
+    );
+    assert_eq!(
+        follower.execute("SET unknown = 1").await,
+        Err(Error::Value("Unknown setting unknown".into()))
+    );
+
+    // Writes on the leader are immediately visible to follower reads.
+    leader.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    assert_row(
+        follower.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+
+    // Read-only transactions see a consistent snapshot.
+    let version = match follower.execute("BEGIN READ ONLY").await? {
+        ResultSet::Begin { id, mode: Mode::ReadOnly } => id,
+        result => panic!("Unexpected result {:?}", result),
+    };
+    leader.execute("INSERT INTO test VALUES (2, 'b')").await?;
+    assert_row(
+        follower.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    follower.execute("COMMIT").await?;
+    assert_rows(
+        follower.execute("SELECT * FROM test").await?,
+        vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(2), Value::String("b".into())],
+        ],
+    );
+
+    // As do snapshot transactions.
+    follower.execute(&format!("BEGIN READ ONLY AS OF SYSTEM TIME {}", version)).await?;
+    assert_row(
+        follower.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    follower.execute("COMMIT").await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+// A partitioned follower keeps serving reads within the staleness bound, but can't serve reads
+// that need a read index from the leader.
+async fn follower_reads_stale() -> Result<()> {
+    let (clients, partitions, _teardown) =
+        setup::cluster_with_partitions(3, setup::simple()).await?;
+    let leader = clients[0].status().await?.raft.leader;
+    let i = (0..3).find(|i| format!("toydb{}", i) != leader).unwrap();
+    let j = (0..3).find(|j| format!("toydb{}", j) == leader).unwrap();
+    let (follower, leader) = (&clients[i], &clients[j]);
+    let other = toydb::client::Client::new(format!("127.0.0.1:{}", 9605 + i)).await?;
+
+    follower.execute("SET follower_reads = TRUE").await?;
+    follower.execute("SET max_staleness = 60000").await?;
+    other.execute("SET follower_reads = TRUE").await?;
+
+    leader.execute("INSERT INTO test VALUES (1, 'a')").await?;
+    follower.execute("BEGIN READ ONLY").await?;
+    other.execute("BEGIN READ ONLY").await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Once partitioned, the stale read is served locally, while the read which needs a read index
+    // waits for the leader until the follower starts campaigning and aborts it.
+    partitions.isolate(&format!("toydb{}", i));
+    assert_row(
+        follower.execute("SELECT * FROM test").await?,
+        vec![Value::Integer(1), Value::String("a".into())],
+    );
+    assert_eq!(other.execute("SELECT * FROM test").await, Err(Error::Abort));
+
+    // Once healed, the transactions can be committed, so that the sessions don't hang on close.
+    partitions.heal();
+    follower.execute("COMMIT").await?;
+    other.execute("COMMIT").await?;
+
+    Ok(())
+}
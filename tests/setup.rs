@@ -9,6 +9,8 @@ use futures_util::future::FutureExt as _;
 use pretty_assertions::assert_eq;
 use std::collections::HashMap;
 use tempdir::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
 
 // Movie data
 pub fn movies() -> Vec<&'static str> {
@@ -154,6 +156,103 @@ pub async fn cluster_with_clients(
     Ok((clients, teardown))
 }
 
+/// Sets up a server cluster with clients, where Raft traffic between nodes is routed via a TCP proxy
+/// for each pair of nodes, such that nodes can be partitioned from the cluster.
+pub async fn cluster_with_partitions(
+    size: u64,
+    queries: Vec<&str>,
+) -> Result<(Vec<Client>, Partitions, Teardown)> {
+    let mut teardown = Teardown::empty();
+    let mut partitions = Partitions { links: HashMap::new() };
+    for i in 0..size {
+        let id = format!("toydb{}", i);
+        let mut peers = HashMap::new();
+        for j in (0..size).filter(|j| *j != i) {
+            let peer = format!("toydb{}", j);
+            let addr = format!("127.0.0.1:{}", 9800 + 10 * i + j);
+            let listener = TcpListener::bind(&addr).await?;
+            let (blocked_tx, blocked_rx) = watch::channel(false);
+            let (task, abort) =
+                proxy(listener, format!("127.0.0.1:{}", 9705 + j), blocked_rx).remote_handle();
+            tokio::spawn(task);
+            teardown.on_drop(move || std::mem::drop(abort));
+            partitions.links.insert((id.clone(), peer.clone()), blocked_tx);
+            peers.insert(peer, addr);
+        }
+        let addr_sql = format!("127.0.0.1:{}", 9605 + i);
+        let addr_raft = format!("127.0.0.1:{}", 9705 + i);
+        teardown.merge(server(&id, &addr_sql, &addr_raft, peers).await?);
+    }
+
+    let mut clients = Vec::<Client>::new();
+    for i in 0..size {
+        let client = Client::new(format!("127.0.0.1:{}", 9605 + i)).await?;
+        assert_eq!(format!("toydb{}", i), client.status().await?.raft.server);
+        clients.push(client);
+    }
+
+    if !queries.is_empty() {
+        let c = clients.get_mut(0).unwrap();
+        c.execute("BEGIN").await?;
+        for query in queries {
+            c.execute(query).await?;
+        }
+        c.execute("COMMIT").await?;
+    }
+
+    Ok((clients, partitions, teardown))
+}
+
+/// Controls network partitions between cluster nodes, by blocking their Raft proxies.
+pub struct Partitions {
+    links: HashMap<(String, String), watch::Sender<bool>>,
+}
+
+impl Partitions {
+    /// Partitions the given node from all other nodes.
+    pub fn isolate(&self, id: &str) {
+        for ((from, to), blocked) in self.links.iter() {
+            if from == id || to == id {
+                blocked.send(true).unwrap();
+            }
+        }
+    }
+
+    /// Heals all partitions.
+    pub fn heal(&self) {
+        for blocked in self.links.values() {
+            blocked.send(false).unwrap();
+        }
+    }
+}
+
+/// Proxies TCP connections to the given address, dropping them while blocked.
+async fn proxy(listener: TcpListener, addr: String, blocked: watch::Receiver<bool>) {
+    while let Ok((mut inbound, _)) = listener.accept().await {
+        if *blocked.borrow() {
+            continue;
+        }
+        let addr = addr.clone();
+        let mut blocked = blocked.clone();
+        tokio::spawn(async move {
+            let mut outbound = match TcpStream::connect(&addr).await {
+                Ok(outbound) => outbound,
+                Err(_) => return,
+            };
+            tokio::select! {
+                _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => {},
+                _ = async {
+                    while !*blocked.borrow() {
+                        if blocked.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                } => {},
+            }
+        });
+    }
+}
+
 /// Sets up a server cluster with a client pool
 pub async fn cluster_with_pool(
     cluster_size: u64,
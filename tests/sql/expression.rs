@@ -438,6 +438,22 @@ test_expr! {
     op_subtract_error_bool: "TRUE - FALSE" => Err(Error::Value("Can't subtract TRUE and FALSE".into())),
     op_subtract_error_strings: "'a' - 'b'" => Err(Error::Value("Can't subtract a and b".into())),
 
+    // List operators
+    op_in: "2 IN (1, 2, 3)" => Ok(Boolean(true)),
+    op_in_not: "4 IN (1, 2, 3)" => Ok(Boolean(false)),
+    op_in_single: "1 IN (1)" => Ok(Boolean(true)),
+    op_in_expr: "1 + 1 IN (1, 1 + 1)" => Ok(Boolean(true)),
+    op_in_mixed: "3.0 IN (1, 3)" => Ok(Boolean(true)),
+    op_in_null: "NULL IN (1, 2)" => Ok(Null),
+    op_in_null_list: "3 IN (1, NULL)" => Ok(Null),
+    op_in_null_list_match: "1 IN (1, NULL)" => Ok(Boolean(true)),
+    op_in_empty: "1 IN ()" => Err(Error::Parse("Expected expression atom, found )".into())),
+    op_not_in: "4 NOT IN (1, 2, 3)" => Ok(Boolean(true)),
+    op_not_in_match: "2 NOT IN (1, 2, 3)" => Ok(Boolean(false)),
+    op_not_in_null_list: "3 NOT IN (1, NULL)" => Ok(Null),
+    op_prec_in_and: "1 IN (1) AND 2 IN (3)" => Ok(Boolean(false)),
+    op_prec_in_add: "1 + 1 IN (2)" => Ok(Boolean(true)),
+
     // String operators
     op_like_percent: "'abcde' LIKE 'a%e'" => Ok(Boolean(true)),
     op_like_percent_escape: "'ab%de' LIKE 'ab%%de'" => Ok(Boolean(true)),
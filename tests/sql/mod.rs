@@ -5,8 +5,11 @@ mod schema;
 
 use toydb::error::Result;
 use toydb::sql::engine::{Engine, KV};
+use toydb::sql::types::Value;
 use toydb::storage::kv;
 
+use std::collections::HashSet;
+
 /// Sets up a basic in-memory SQL engine with an initial dataset.
 fn setup(queries: Vec<&str>) -> Result<KV> {
     let engine = KV::new(kv::MVCC::new(Box::new(kv::Memory::new())));
@@ -18,3 +21,14 @@ fn setup(queries: Vec<&str>) -> Result<KV> {
     session.execute("COMMIT")?;
     Ok(engine)
 }
+
+/// Formats a set of index entry primary keys in sorted order, showing single-value keys as values.
+fn format_pks(pks: HashSet<Vec<Value>>) -> String {
+    let mut pks = pks.into_iter().collect::<Vec<_>>();
+    pks.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    if pks.iter().all(|pk| pk.len() == 1) {
+        format!("{:?}", pks.into_iter().flatten().collect::<Vec<_>>())
+    } else {
+        format!("{:?}", pks)
+    }
+}
@@ -41,9 +41,7 @@ macro_rules! test_mutation {
                         write!(f, "\nIndex {}.{}\n", table.name, column.name)?;
                         let mut scan = txn.scan_index(&table.name, &column.name)?;
                         while let Some((value, pks)) = scan.next().transpose()? {
-                            let mut pks = pks.into_iter().collect::<Vec<_>>();
-                            pks.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-                            write!(f, "{:?} => {:?}\n", value, pks)?;
+                            write!(f, "{:?} => {}\n", value, super::format_pks(pks))?;
                         }
                     }
                 }
@@ -76,6 +74,7 @@ test_mutation! { with [
     delete_where_float: "DELETE FROM test WHERE 3.14",
     delete_where_integer: "DELETE FROM test WHERE 1",
     delete_where_string: "DELETE FROM test WHERE 'a'",
+    delete_where_subquery: "DELETE FROM test WHERE id IN (SELECT id FROM other WHERE id > 1)",
     delete_case: "DELETE FROM TeSt WHERE ID = 1",
     delete_missing_column_where: "DELETE FROM test WHERE missing = TRUE",
     delete_missing_table: "DELETE FROM missing",
@@ -140,6 +139,8 @@ test_mutation! { with [
     update_where_integer: "UPDATE test SET name = 'x' WHERE 1",
     update_where_string: "UPDATE test SET name = 'x' WHERE 'a'",
     update_where_full: "UPDATE test SET id = 9, name = 'x', value = 999 WHERE id = 1",
+    update_where_subquery: "UPDATE test SET name = 'x' WHERE EXISTS (SELECT * FROM other WHERE other.id = test.id + 1)",
+    update_subquery: "UPDATE test SET value = (SELECT MAX(id) FROM other) WHERE id = 1",
     update_case: "UPDATE TeSt SET Name = 'x' WHERE ID = 1",
     update_missing_column_set: "UPDATE test SET missing = 0",
     update_missing_column_where: "UPDATE test SET name = 'x' WHERE missing = TRUE",
@@ -410,3 +410,279 @@ test_query! {
     having_noselect: "SELECT studio_id FROM movies GROUP BY studio_id HAVING MAX(rating) > 8 ORDER BY studio_id",
     having_noaggr: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING studio_id >= 3 ORDER BY studio_id",
 }
+test_query! { with [
+        "CREATE TABLE releases (
+            country_id STRING,
+            movie_id INTEGER,
+            released INTEGER NOT NULL,
+            format STRING NOT NULL,
+            PRIMARY KEY (country_id, movie_id)
+        )",
+        "INSERT INTO releases VALUES
+            ('fr', 1, 1980, 'dvd'),
+            ('fr', 3, 2004, 'dvd'),
+            ('ru', 1, 1979, 'film'),
+            ('ru', 6, 1972, 'film'),
+            ('us', 1, 1981, 'dvd'),
+            ('us', 2, 2015, 'bluray'),
+            ('us', 3, 2004, 'dvd'),
+            ('us', 4, 1995, 'bluray')",
+        "CREATE INDEX releases_format ON releases (format, released)",
+    ];
+    where_pk_composite: "SELECT * FROM releases WHERE country_id = 'us' AND movie_id = 2",
+    where_pk_composite_or: "SELECT * FROM releases WHERE (country_id = 'fr' OR country_id = 'us') AND (movie_id = 1 OR movie_id = 3)",
+    where_pk_composite_prefix: "SELECT * FROM releases WHERE country_id = 'us'",
+    where_pk_composite_prefix_partial: "SELECT * FROM releases WHERE country_id = 'us' AND released > 2000",
+    where_pk_composite_suffix: "SELECT * FROM releases WHERE movie_id = 1",
+    where_index_composite: "SELECT * FROM releases WHERE format = 'dvd' AND released = 2004 ORDER BY country_id",
+    where_index_composite_prefix: "SELECT * FROM releases WHERE format = 'bluray' ORDER BY country_id, movie_id",
+    where_index_composite_suffix: "SELECT * FROM releases WHERE released = 2004 ORDER BY country_id",
+}
+test_query! {
+    subquery_scalar: "SELECT title, rating, (SELECT MAX(rating) FROM movies) AS best FROM movies WHERE id <= 3",
+    subquery_scalar_where: "SELECT title FROM movies WHERE rating = (SELECT MAX(rating) FROM movies)",
+    subquery_scalar_correlated: r#"
+        SELECT s.name, (SELECT COUNT(*) FROM movies m WHERE m.studio_id = s.id) AS movies
+        FROM studios s ORDER BY s.id"#,
+    subquery_scalar_correlated_where: r#"
+        SELECT title, rating FROM movies m
+        WHERE rating > (SELECT AVG(rating) FROM movies WHERE studio_id = m.studio_id)
+        ORDER BY id"#,
+    subquery_scalar_empty: "SELECT (SELECT id FROM movies WHERE FALSE)",
+    subquery_scalar_multirow: "SELECT (SELECT id FROM movies)",
+    subquery_scalar_multicolumn: "SELECT (SELECT id, title FROM movies WHERE id = 1)",
+    subquery_scalar_order: "SELECT id, title, genre_id FROM movies m ORDER BY (SELECT name FROM genres g WHERE g.id = m.genre_id), id",
+    subquery_scalar_having: r#"
+        SELECT studio_id, COUNT(*) FROM movies GROUP BY studio_id
+        HAVING COUNT(*) > (SELECT COUNT(*) FROM genres) - 2 ORDER BY studio_id"#,
+
+    subquery_exists: "SELECT name FROM genres WHERE EXISTS (SELECT * FROM movies WHERE released > 2015)",
+    subquery_exists_correlated: r#"
+        SELECT name FROM studios s
+        WHERE EXISTS (SELECT 1 FROM movies m WHERE m.studio_id = s.id AND m.rating >= 8)
+        ORDER BY name"#,
+    subquery_exists_correlated_and: r#"
+        SELECT name FROM studios s
+        WHERE s.id > 1 AND EXISTS (SELECT 1 FROM movies m WHERE m.studio_id = s.id AND m.rating >= 8)"#,
+    subquery_exists_not: r#"
+        SELECT name FROM countries c
+        WHERE NOT EXISTS (SELECT * FROM studios s WHERE s.country_id = c.id AND s.name LIKE '%Bros%')"#,
+    subquery_exists_or: r#"
+        SELECT id FROM genres g
+        WHERE id = 3 OR EXISTS (SELECT * FROM movies m WHERE m.genre_id = g.id AND m.released < 1990)"#,
+    subquery_exists_select: "SELECT id, EXISTS (SELECT * FROM movies WHERE genre_id = genres.id AND rating > 8) AS good FROM genres",
+    subquery_exists_nested: r#"
+        SELECT name FROM countries c WHERE EXISTS (
+            SELECT * FROM studios s WHERE s.country_id = c.id AND EXISTS (
+                SELECT * FROM movies m WHERE m.studio_id = s.id AND m.genre_id = 3))"#,
+    subquery_exists_nested_outer: r#"
+        SELECT name FROM countries c WHERE EXISTS (
+            SELECT * FROM studios s WHERE EXISTS (
+                SELECT * FROM movies m WHERE m.studio_id = s.id AND s.country_id = c.id AND m.rating > 8))"#,
+
+    subquery_in: "SELECT title FROM movies WHERE studio_id IN (SELECT id FROM studios WHERE country_id = 'us') ORDER BY id",
+    subquery_in_not: "SELECT title FROM movies WHERE studio_id NOT IN (SELECT id FROM studios WHERE country_id = 'us') ORDER BY id",
+    subquery_in_correlated: r#"
+        SELECT title FROM movies m
+        WHERE rating IN (SELECT rating FROM movies WHERE genre_id != m.genre_id)
+        ORDER BY id"#,
+    subquery_in_correlated_aggregate: r#"
+        SELECT title FROM movies m
+        WHERE rating IN (SELECT MAX(rating) FROM movies WHERE genre_id = m.genre_id)
+        ORDER BY id"#,
+    subquery_in_select: "SELECT id, id IN (SELECT genre_id FROM movies WHERE rating > 8) AS good FROM genres",
+    subquery_in_multicolumn: "SELECT * FROM genres WHERE id IN (SELECT id, name FROM genres)",
+    subquery_in_expr: "SELECT title FROM movies WHERE studio_id + 1 IN (SELECT id FROM studios) AND id <= 3",
+
+    subquery_from: "SELECT * FROM (SELECT id, title FROM movies WHERE rating > 8) AS good ORDER BY id",
+    subquery_from_alias_missing: "SELECT * FROM (SELECT id FROM movies)",
+    subquery_from_qualified: "SELECT good.title FROM (SELECT id, title FROM movies WHERE rating > 8) good WHERE good.id > 1",
+    subquery_from_join: r#"
+        SELECT g.name, stats.best FROM genres g
+        JOIN (SELECT genre_id, MAX(rating) AS best FROM movies GROUP BY genre_id) stats
+            ON stats.genre_id = g.id
+        ORDER BY g.id"#,
+    subquery_from_nested: "SELECT * FROM (SELECT * FROM (SELECT id, name FROM genres) a WHERE id > 1) b",
+    subquery_from_correlated: "SELECT * FROM genres g WHERE EXISTS (SELECT * FROM (SELECT * FROM movies m WHERE m.genre_id = g.id) s WHERE s.rating > 8.5)",
+    subquery_from_unknown: "SELECT * FROM genres g, (SELECT * FROM movies WHERE genre_id = g.id) m",
+
+    subquery_join_predicate: "SELECT * FROM movies m JOIN genres g ON g.id = (SELECT 1)",
+    subquery_constant: "SELECT * FROM movies LIMIT (SELECT 1)",
+}
+test_query! { with [
+        "CREATE TABLE nulls (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO nulls VALUES (1, 1), (2, NULL)",
+    ];
+    subquery_in_null: "SELECT id, id IN (SELECT value FROM nulls), id NOT IN (SELECT value FROM nulls) FROM genres",
+    subquery_in_not_null: "SELECT id FROM genres WHERE id NOT IN (SELECT value FROM nulls)",
+    subquery_in_not_null_lhs: "SELECT id FROM nulls WHERE value NOT IN (SELECT id FROM genres WHERE id > 1)",
+    subquery_in_not_empty: "SELECT id FROM nulls WHERE value NOT IN (SELECT id FROM genres WHERE FALSE)",
+}
+test_query! {
+    cte: "WITH good AS (SELECT id, title FROM movies WHERE rating > 8) SELECT * FROM good ORDER BY id",
+    cte_alias: "WITH good AS (SELECT id, title FROM movies WHERE rating > 8) SELECT g.title FROM good g WHERE g.id > 5",
+    cte_columns: "WITH best (genre, rating) AS (SELECT genre_id, MAX(rating) FROM movies GROUP BY genre_id) SELECT * FROM best ORDER BY genre",
+    cte_columns_mismatch: "WITH best (genre, rating) AS (SELECT genre_id FROM movies) SELECT * FROM best",
+    cte_multiple: r#"
+        WITH a AS (SELECT id, name FROM genres WHERE id > 1),
+             b AS (SELECT * FROM a WHERE id < 3)
+        SELECT * FROM b"#,
+    cte_forward: "WITH a AS (SELECT * FROM b), b AS (SELECT 1) SELECT * FROM a",
+    cte_duplicate: "WITH a AS (SELECT 1), a AS (SELECT 2) SELECT * FROM a",
+    cte_shadow: "WITH movies AS (SELECT * FROM genres WHERE id = 1) SELECT * FROM movies",
+    cte_join: r#"
+        WITH us AS (SELECT id, name FROM studios WHERE country_id = 'us')
+        SELECT m.title, us.name FROM movies m JOIN us ON m.studio_id = us.id ORDER BY m.id"#,
+    cte_self_join: "WITH g AS (SELECT id, name FROM genres) SELECT a.name, b.name FROM g a JOIN g b ON a.id = b.id - 1",
+    cte_subquery: r#"
+        WITH good AS (SELECT genre_id FROM movies WHERE rating > 8)
+        SELECT name FROM genres WHERE id IN (SELECT genre_id FROM good)"#,
+    cte_nested: "SELECT * FROM (WITH a AS (SELECT id FROM genres) SELECT * FROM a WHERE id > 1) x",
+    cte_nested_shadow: r#"
+        WITH a AS (SELECT id FROM genres)
+        SELECT * FROM a WHERE id IN (WITH a AS (SELECT 1) SELECT * FROM a)"#,
+    cte_bare: "WITH a AS (SELECT 1)",
+    cte_missing_as: "WITH a (SELECT 1) SELECT * FROM a",
+    cte_union: "WITH a AS (SELECT 1 UNION SELECT 2) SELECT * FROM a",
+}
+test_query! { with [
+        "CREATE TABLE employees (id INTEGER PRIMARY KEY, name STRING NOT NULL, manager_id INTEGER)",
+        "INSERT INTO employees VALUES (1, 'Alice', NULL), (2, 'Bob', 1), (3, 'Carol', 1), (4, 'Dave', 2), (5, 'Eve', 4)",
+        "CREATE TABLE edges (id INTEGER PRIMARY KEY, src INTEGER NOT NULL, dst INTEGER NOT NULL)",
+        "INSERT INTO edges VALUES (1, 1, 2), (2, 2, 3), (3, 3, 1), (4, 3, 4), (5, 5, 1)",
+    ];
+    cte_recursive: "WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5) SELECT * FROM n",
+    cte_recursive_labels: "WITH RECURSIVE n AS (SELECT 1 AS i UNION ALL SELECT i * 2 FROM n WHERE i < 8) SELECT * FROM n",
+    cte_recursive_tree: r#"
+        WITH RECURSIVE reports (id, name, depth) AS (
+            SELECT id, name, 0 FROM employees WHERE manager_id IS NULL
+            UNION ALL
+            SELECT e.id, e.name, r.depth + 1 FROM employees e JOIN reports r ON e.manager_id = r.id
+        )
+        SELECT * FROM reports ORDER BY depth, id"#,
+    cte_recursive_ancestors: r#"
+        WITH RECURSIVE chain (id, manager_id) AS (
+            SELECT id, manager_id FROM employees WHERE name = 'Eve'
+            UNION ALL
+            SELECT e.id, e.manager_id FROM chain c, employees e WHERE e.id = c.manager_id
+        )
+        SELECT e.name FROM chain c JOIN employees e ON e.id = c.id"#,
+    cte_recursive_aggregate: r#"
+        WITH RECURSIVE reports (id, depth) AS (
+            SELECT id, 0 FROM employees WHERE id = 2
+            UNION ALL
+            SELECT e.id, r.depth + 1 FROM employees e JOIN reports r ON e.manager_id = r.id
+        )
+        SELECT COUNT(*), MAX(depth) FROM reports"#,
+    cte_recursive_cycle: r#"
+        WITH RECURSIVE reachable (node) AS (
+            SELECT 1 UNION SELECT e.dst FROM edges e JOIN reachable r ON e.src = r.node
+        )
+        SELECT * FROM reachable ORDER BY node"#,
+    cte_recursive_cycle_all: r#"
+        WITH RECURSIVE reachable (node) AS (
+            SELECT 1 UNION ALL SELECT e.dst FROM edges e JOIN reachable r ON e.src = r.node
+        )
+        SELECT * FROM reachable ORDER BY node"#,
+    cte_recursive_subquery: r#"
+        WITH RECURSIVE n (i) AS (
+            SELECT 1 UNION ALL SELECT id FROM employees WHERE manager_id IN (SELECT i FROM n)
+        )
+        SELECT * FROM n"#,
+    cte_recursive_multiple: r#"
+        WITH RECURSIVE managers (id) AS (SELECT id FROM employees WHERE FALSE),
+             n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3),
+             m (i) AS (SELECT i * 10 FROM n UNION ALL SELECT i + 1 FROM m WHERE i % 10 = 0)
+        SELECT * FROM m ORDER BY i"#,
+    cte_recursive_plain: "WITH RECURSIVE a (i) AS (SELECT 1) SELECT * FROM a",
+    cte_recursive_unreferenced: "WITH RECURSIVE a (i) AS (SELECT 1 UNION ALL SELECT 2) SELECT * FROM a",
+    cte_recursive_columns_mismatch: "WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i, i FROM n) SELECT * FROM n",
+    cte_recursive_anchor: "WITH RECURSIVE n (i) AS (SELECT i FROM n UNION ALL SELECT i FROM n) SELECT * FROM n",
+    cte_recursive_limit: "WITH RECURSIVE n AS (SELECT 1 AS n UNION ALL SELECT n + 1 FROM n) SELECT * FROM n LIMIT 10",
+    cte_recursive_limit_offset: "WITH RECURSIVE n AS (SELECT 1 AS n UNION ALL SELECT n + 1 FROM n) SELECT n * 2 FROM n LIMIT 3 OFFSET 5",
+    cte_recursive_limit_order: "WITH RECURSIVE n AS (SELECT 1 AS n UNION ALL SELECT n + 1 FROM n) SELECT * FROM n ORDER BY n DESC LIMIT 3",
+}
+test_query! {
+    union: "SELECT id, name FROM genres UNION SELECT id, name FROM countries",
+    union_all: "SELECT genre_id FROM movies WHERE released < 2000 UNION ALL SELECT genre_id FROM movies WHERE rating > 8",
+    union_distinct: "SELECT genre_id FROM movies WHERE released < 2000 UNION SELECT genre_id FROM movies WHERE rating > 8",
+    union_star: "SELECT * FROM genres UNION SELECT * FROM genres",
+    union_labels: "SELECT id AS a, name FROM genres UNION SELECT id, name AS b FROM studios",
+    union_order: "SELECT name FROM genres UNION SELECT name FROM studios ORDER BY name DESC",
+    union_order_qualified: "SELECT genres.name FROM genres UNION SELECT name FROM studios ORDER BY genres.name",
+    union_order_unknown: "SELECT name FROM genres UNION SELECT name FROM studios ORDER BY id",
+    union_limit: "SELECT id FROM genres UNION ALL SELECT id FROM studios ORDER BY id LIMIT 3 OFFSET 2",
+    union_chain: "SELECT 1 UNION SELECT 2 UNION ALL SELECT 1",
+    union_columns_mismatch: "SELECT id, name FROM genres UNION SELECT id FROM studios",
+    union_missing_select: "SELECT 1 UNION 2",
+    union_trailing: "SELECT 1 UNION",
+    union_subquery: "SELECT title FROM movies WHERE id IN (SELECT 1 UNION SELECT 3)",
+    union_derived: "SELECT COUNT(*) FROM (SELECT genre_id FROM movies UNION SELECT id FROM genres) AS g",
+    union_with: "WITH a AS (SELECT 1) SELECT * FROM a UNION SELECT 2",
+}
+test_query! { with [
+        "CREATE TABLE a (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO a VALUES (1, 1), (2, 1), (3, 2), (4, 2), (5, 3), (6, NULL), (7, NULL)",
+        "CREATE TABLE b (id INTEGER PRIMARY KEY, value INTEGER)",
+        "INSERT INTO b VALUES (1, 1), (2, 2), (3, 2), (4, 4), (5, NULL)",
+        "CREATE TABLE f (id INTEGER PRIMARY KEY, value FLOAT)",
+        "INSERT INTO f VALUES (1, 0.0), (2, -0.0), (3, 1.0)",
+    ];
+    union_null: "SELECT value FROM a UNION SELECT value FROM b ORDER BY value",
+    union_float_zero: "SELECT value FROM f UNION SELECT 1.0",
+    union_mixed_types: "SELECT value FROM f UNION SELECT 1",
+    intersect: "SELECT value FROM a INTERSECT SELECT value FROM b ORDER BY value",
+    intersect_all: "SELECT value FROM a INTERSECT ALL SELECT value FROM b ORDER BY value",
+    except: "SELECT value FROM a EXCEPT SELECT value FROM b ORDER BY value",
+    except_all: "SELECT value FROM a EXCEPT ALL SELECT value FROM b ORDER BY value",
+    except_chain: "SELECT value FROM a EXCEPT SELECT 1 EXCEPT SELECT 2",
+    set_precedence: "SELECT value FROM a EXCEPT SELECT value FROM a INTERSECT SELECT value FROM b UNION SELECT 5",
+    set_empty: "SELECT value FROM a WHERE FALSE INTERSECT SELECT value FROM b",
+}
+test_query! {
+    window_row_number: "SELECT id, title, ROW_NUMBER() OVER (ORDER BY released) AS n FROM movies",
+    window_row_number_empty: "SELECT id, ROW_NUMBER() OVER () FROM movies",
+    window_rank: "SELECT title, rating, RANK() OVER (ORDER BY rating DESC) AS r, DENSE_RANK() OVER (ORDER BY rating DESC) AS d FROM movies ORDER BY r, title",
+    window_partition: "SELECT genre_id, title, ROW_NUMBER() OVER (PARTITION BY genre_id ORDER BY rating DESC) AS n FROM movies ORDER BY genre_id, n",
+    window_partition_multiple: "SELECT studio_id, genre_id, title, COUNT(*) OVER (PARTITION BY studio_id, genre_id) FROM movies ORDER BY studio_id, genre_id, id",
+    window_lag_lead: "SELECT title, released, LAG(title) OVER (ORDER BY released) AS prev, LEAD(title) OVER (ORDER BY released) AS next FROM movies ORDER BY released",
+    window_lag_offset: "SELECT id, LAG(id, 2) OVER (ORDER BY id), LEAD(id, 3, 0) OVER (ORDER BY id), LAG(id, 0) OVER (ORDER BY id) FROM movies",
+    window_lag_partition: "SELECT genre_id, title, LAG(released) OVER (PARTITION BY genre_id ORDER BY released) FROM movies ORDER BY genre_id, released",
+    window_lag_negative: "SELECT LAG(id, -1) OVER (ORDER BY id) FROM movies",
+    window_sum_running: "SELECT id, released, SUM(released) OVER (ORDER BY id) FROM movies",
+    window_sum_peers: "SELECT id, rating, COUNT(*) OVER (ORDER BY rating) FROM movies ORDER BY rating, id",
+    window_sum_partition: "SELECT genre_id, id, SUM(id) OVER (PARTITION BY genre_id) FROM movies ORDER BY genre_id, id",
+    window_rows: "SELECT id, SUM(id) OVER (ORDER BY id ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) FROM movies",
+    window_rows_shorthand: "SELECT id, SUM(id) OVER (ORDER BY id ROWS 2 PRECEDING) FROM movies",
+    window_rows_unbounded: "SELECT id, MIN(id) OVER (ORDER BY id DESC ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING), MAX(id) OVER (ORDER BY id ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING) FROM movies",
+    window_rows_empty: "SELECT id, COUNT(id) OVER (ORDER BY id ROWS BETWEEN 3 PRECEDING AND 2 PRECEDING), SUM(id) OVER (ORDER BY id ROWS BETWEEN 3 PRECEDING AND 2 PRECEDING) FROM movies",
+    window_rows_running: "SELECT id, COUNT(id) OVER (ORDER BY id ROWS BETWEEN UNBOUNDED PRECEDING AND 2 PRECEDING), SUM(id) OVER (ORDER BY id ROWS BETWEEN 2 FOLLOWING AND UNBOUNDED FOLLOWING) FROM movies",
+    window_rows_avg: "SELECT id, rating, AVG(rating) OVER (PARTITION BY genre_id ORDER BY id ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) FROM movies ORDER BY id",
+    window_rows_invalid: "SELECT SUM(id) OVER (ORDER BY id ROWS BETWEEN CURRENT ROW AND 1 PRECEDING) FROM movies",
+    window_rows_unbounded_invalid: "SELECT SUM(id) OVER (ROWS UNBOUNDED FOLLOWING) FROM movies",
+    window_rows_offset_invalid: "SELECT SUM(id) OVER (ROWS BETWEEN id PRECEDING AND CURRENT ROW) FROM movies",
+    window_rows_offset_negative: "SELECT SUM(id) OVER (ROWS BETWEEN -1 PRECEDING AND CURRENT ROW) FROM movies",
+    window_expr: "SELECT id, 100 * rating / MAX(rating) OVER (PARTITION BY genre_id) AS pct FROM movies",
+    window_expr_args: "SELECT id, SUM(rating * 10) OVER (ORDER BY id % 3, id) FROM movies",
+    window_aggregate: "SELECT genre_id, COUNT(*), RANK() OVER (ORDER BY COUNT(*) DESC), SUM(COUNT(*)) OVER (ORDER BY genre_id) FROM movies GROUP BY genre_id ORDER BY genre_id",
+    window_aggregate_group: "SELECT studio_id, MAX(rating) AS best, ROW_NUMBER() OVER (ORDER BY MAX(rating) DESC) FROM movies GROUP BY studio_id ORDER BY best DESC",
+    window_where: "SELECT id, ROW_NUMBER() OVER (ORDER BY id) FROM movies WHERE genre_id = 1 ORDER BY id",
+    window_order: "SELECT title FROM movies ORDER BY ROW_NUMBER() OVER (ORDER BY rating DESC) LIMIT 3",
+    window_order_alias: "SELECT title, RANK() OVER (ORDER BY released) AS r FROM movies ORDER BY r DESC LIMIT 3",
+    window_join: "SELECT g.name, m.title, ROW_NUMBER() OVER (PARTITION BY g.name ORDER BY m.released) FROM movies m JOIN genres g ON m.genre_id = g.id ORDER BY g.name, m.released",
+    window_nothing: "SELECT ROW_NUMBER() OVER (), SUM(1) OVER ()",
+    window_in_where: "SELECT * FROM movies WHERE ROW_NUMBER() OVER () = 1",
+    window_in_group: "SELECT COUNT(*) FROM movies GROUP BY ROW_NUMBER() OVER ()",
+    window_in_aggregate: "SELECT SUM(ROW_NUMBER() OVER ()) FROM movies",
+    window_nested: "SELECT SUM(ROW_NUMBER() OVER ()) OVER () FROM movies",
+    window_having: "SELECT genre_id, RANK() OVER (ORDER BY COUNT(*)) FROM movies GROUP BY genre_id HAVING COUNT(*) > 1",
+    window_unknown: "SELECT foo() OVER () FROM movies",
+    window_no_over: "SELECT ROW_NUMBER() FROM movies",
+    window_args: "SELECT ROW_NUMBER(id) OVER () FROM movies",
+    window_args_lag: "SELECT LAG() OVER () FROM movies",
+    window_args_sum: "SELECT SUM(id, id) OVER () FROM movies",
+    window_subquery: "SELECT SUM((SELECT 1)) OVER () FROM movies",
+    window_missing_paren: "SELECT ROW_NUMBER() OVER ORDER BY id FROM movies",
+    window_bound_missing: "SELECT SUM(id) OVER (ROWS BETWEEN 1 AND CURRENT ROW) FROM movies",
+    window_union: "SELECT id, ROW_NUMBER() OVER (ORDER BY id DESC) FROM genres UNION ALL SELECT id, RANK() OVER (ORDER BY id) FROM genres",
+}
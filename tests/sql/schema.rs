@@ -39,12 +39,16 @@ macro_rules! test_schema {
                         write!(f, "\nIndex {}.{}\n", table.name, column.name)?;
                         let mut scan = txn.scan_index(&table.name, &column.name)?;
                         while let Some((value, pks)) = scan.next().transpose()? {
-                            let mut pks = pks.into_iter().collect::<Vec<_>>();
-                            pks.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-                            write!(f, "{:?} => {:?}\n", value, pks)?;
+                            write!(f, "{:?} => {}\n", value, super::format_pks(pks))?;
                         }
                     }
                 }
+                for index in txn.scan_indexes()? {
+                    write!(f, "\n{}\n", index)?;
+                    if index.columns.len() > 1 {
+                        write!(f, "{}\n", super::format_pks(txn.read_index_prefix(&index.name, &[])?))?;
+                    }
+                }
                 txn.rollback()?;
 
                 Ok(())
@@ -444,3 +448,123 @@ test_schema! { with [
     update_index_pk: "UPDATE test SET id = 4 WHERE id = 1",
     update_index_null: "UPDATE test SET name = NULL WHERE id = 3",
 }
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING, value INTEGER UNIQUE, code STRING UNIQUE)",
+        "INSERT INTO test VALUES (1, 'a', 101, 'x'), (2, 'b', 102, 'y'), (3, 'b', NULL, NULL)",
+    ];
+
+    create_index: "CREATE INDEX test_name ON test (name)",
+    create_index_bare: "CREATE INDEX",
+    create_index_column_missing: "CREATE INDEX test_name ON test (missing)",
+    create_index_pk: "CREATE INDEX test_id ON test (id)",
+    create_index_table_missing: "CREATE INDEX test_name ON missing (name)",
+    create_index_unique: "CREATE UNIQUE INDEX test_value ON test (value)",
+    create_index_unique_column: "CREATE UNIQUE INDEX test_code ON test (code)",
+    create_index_unique_conflict: "CREATE UNIQUE INDEX test_name ON test (name)",
+    drop_index_missing: "DROP INDEX missing",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING, value INTEGER, code STRING INDEX)",
+        "INSERT INTO test VALUES (1, 'a', 101, 'x'), (2, 'b', 102, 'y'), (3, 'b', NULL, 'y')",
+        "CREATE INDEX test_name ON test (name)",
+        "CREATE UNIQUE INDEX test_value ON test (value)",
+    ];
+
+    create_index_exists: "CREATE INDEX test_name ON test (value)",
+    create_index_indexed: "CREATE INDEX test_code ON test (code)",
+    drop_index: "DROP INDEX test_name",
+    drop_index_unique: "DROP INDEX test_value",
+    drop_table_index: "DROP TABLE test",
+    explain_index_unique: "EXPLAIN SELECT * FROM test WHERE name = 'a' AND value = 101",
+    insert_index_created: "INSERT INTO test VALUES (4, 'a', 104, 'z')",
+    insert_index_unique_conflict: "INSERT INTO test VALUES (4, 'a', 101, 'z')",
+    update_index_created: "UPDATE test SET name = 'c', value = 103 WHERE id = 3",
+}
+
+test_schema! {
+    create_table_pk_composite: "CREATE TABLE name (a STRING, b INTEGER, c STRING, PRIMARY KEY (a, b))",
+    create_table_pk_composite_bare: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY)",
+    create_table_pk_composite_duplicate: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY (a, a))",
+    create_table_pk_composite_inline: "CREATE TABLE name (a STRING PRIMARY KEY, b INTEGER, PRIMARY KEY (a, b))",
+    create_table_pk_composite_missing: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY (a, c))",
+    create_table_pk_composite_multiple: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY (a), PRIMARY KEY (b))",
+    create_table_pk_composite_nullable: "CREATE TABLE name (a STRING NULL, b INTEGER, PRIMARY KEY (a, b))",
+    create_table_pk_composite_order: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY (b, a))",
+    create_table_pk_composite_single: "CREATE TABLE name (a STRING, b INTEGER, PRIMARY KEY (b))",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (a STRING, b INTEGER, x INTEGER, y STRING, PRIMARY KEY (a, b))",
+        "INSERT INTO test VALUES ('a', 1, 1, 'x'), ('a', 2, 1, 'y'), ('b', 1, 2, 'x'), ('b', 2, NULL, 'x')",
+    ];
+    create_index_composite: "CREATE INDEX test_xy ON test (x, y)",
+    create_index_composite_duplicate: "CREATE INDEX test_xy ON test (x, x)",
+    create_index_composite_pk: "CREATE INDEX test_ba ON test (b, a)",
+    create_index_composite_unique: "CREATE UNIQUE INDEX test_xy ON test (x, y)",
+    create_index_composite_unique_conflict: "CREATE UNIQUE INDEX test_ay ON test (a, y)",
+    create_table_ref_composite: "CREATE TABLE ref (id INTEGER PRIMARY KEY, test_a STRING REFERENCES test)",
+    delete_pk_composite: "DELETE FROM test WHERE a = 'a' AND b = 2",
+    delete_pk_composite_prefix: "DELETE FROM test WHERE a = 'b'",
+    insert_pk_composite: "INSERT INTO test VALUES ('a', 3, 3, 'z')",
+    insert_pk_composite_conflict: "INSERT INTO test VALUES ('a', 2, 3, 'z')",
+    insert_pk_composite_null: "INSERT INTO test VALUES ('a', NULL, 3, 'z')",
+    update_pk_composite: "UPDATE test SET b = 3 WHERE a = 'a' AND b = 2",
+    update_pk_composite_conflict: "UPDATE test SET b = 1 WHERE a = 'a' AND b = 2",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (a STRING, b INTEGER, x INTEGER, y STRING, PRIMARY KEY (a, b))",
+        "INSERT INTO test VALUES ('a', 1, 1, 'x'), ('a', 2, 1, 'y'), ('b', 1, 2, 'x'), ('b', 2, NULL, 'x')",
+        "CREATE UNIQUE INDEX test_xy ON test (x, y)",
+    ];
+    create_index_composite_exists: "CREATE INDEX test_yx ON test (x, y)",
+    delete_index_composite: "DELETE FROM test WHERE x = 1",
+    drop_index_composite: "DROP INDEX test_xy",
+    explain_index_composite: "EXPLAIN SELECT * FROM test WHERE x = 1 AND y = 'x'",
+    explain_index_composite_prefix: "EXPLAIN SELECT * FROM test WHERE x = 1",
+    explain_pk_composite_prefix: "EXPLAIN SELECT * FROM test WHERE x = 1 AND a = 'a'",
+    insert_index_composite: "INSERT INTO test VALUES ('c', 1, 2, 'y')",
+    insert_index_composite_conflict: "INSERT INTO test VALUES ('c', 1, 2, 'x')",
+    insert_index_composite_null: "INSERT INTO test VALUES ('c', 1, NULL, 'x')",
+    update_index_composite: "UPDATE test SET y = 'z' WHERE a = 'a' AND b = 1",
+    update_index_composite_conflict: "UPDATE test SET x = 2 WHERE a = 'a' AND b = 1",
+}
+
+test_schema! { with [
+        "CREATE TABLE target (id INTEGER PRIMARY KEY, value STRING)",
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING INDEX, value INTEGER, target_id INTEGER REFERENCES target, self_id INTEGER REFERENCES test)",
+        "INSERT INTO target VALUES (1, 'a'), (2, 'b')",
+        "INSERT INTO test VALUES (1, 'a', 101, 1, NULL), (2, 'b', 102, 2, 1), (3, 'b', NULL, NULL, 3)",
+        "CREATE INDEX test_value ON test (value)",
+        "CREATE UNIQUE INDEX test_name_value ON test (name, value)",
+    ];
+    alter_table_add_column: "ALTER TABLE test ADD COLUMN extra STRING",
+    alter_table_add_column_bare: "ALTER TABLE test ADD extra BOOLEAN NOT NULL DEFAULT TRUE",
+    alter_table_add_column_default: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 7",
+    alter_table_add_column_exists: "ALTER TABLE test ADD COLUMN value INTEGER",
+    alter_table_add_column_index: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 7 INDEX",
+    alter_table_add_column_not_null: "ALTER TABLE test ADD COLUMN extra INTEGER NOT NULL",
+    alter_table_add_column_pk: "ALTER TABLE test ADD COLUMN extra INTEGER PRIMARY KEY",
+    alter_table_add_column_ref: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 1 REFERENCES target",
+    alter_table_add_column_ref_missing: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 9 REFERENCES target",
+    alter_table_add_column_unique: "ALTER TABLE test ADD COLUMN extra INTEGER DEFAULT 7 UNIQUE",
+    alter_table_bare: "ALTER TABLE test",
+    alter_table_drop_column: "ALTER TABLE test DROP COLUMN target_id",
+    alter_table_drop_column_bare: "ALTER TABLE test DROP self_id",
+    alter_table_drop_column_index: "ALTER TABLE test DROP COLUMN name",
+    alter_table_drop_column_index_created: "ALTER TABLE test DROP COLUMN value",
+    alter_table_drop_column_missing: "ALTER TABLE test DROP COLUMN missing",
+    alter_table_drop_column_pk: "ALTER TABLE test DROP COLUMN id",
+    alter_table_missing: "ALTER TABLE missing DROP COLUMN value",
+    alter_table_rename_column: "ALTER TABLE test RENAME COLUMN value TO number",
+    alter_table_rename_column_bare: "ALTER TABLE test RENAME name TO label",
+    alter_table_rename_column_exists: "ALTER TABLE test RENAME COLUMN name TO value",
+    alter_table_rename_column_missing: "ALTER TABLE test RENAME COLUMN missing TO other",
+    alter_table_rename_column_pk: "ALTER TABLE test RENAME COLUMN id TO pk",
+    alter_table_rename_table: "ALTER TABLE test RENAME TO renamed",
+    alter_table_rename_table_exists: "ALTER TABLE test RENAME TO target",
+    alter_table_rename_table_ref: "ALTER TABLE target RENAME TO renamed",
+    explain_alter_table: "EXPLAIN ALTER TABLE test RENAME COLUMN value TO number",
+}